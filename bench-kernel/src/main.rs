@@ -180,6 +180,31 @@ static mut WAIT_COUNT: AtomicU64 = AtomicU64::new(0);
 
 const SUSPENDED: SbiRet = SbiRet::success(hart_state::SUSPENDED);
 
+fn get_cycle() -> u64 {
+    const CSR_CYCLE: u32 = 0xc00;
+    let mut cycle: u64;
+    unsafe {
+        asm!("csrr {}, {CSR_CYCLE}", out(reg) cycle, CSR_CYCLE = const CSR_CYCLE);
+    }
+
+    cycle
+}
+
+/// Calls of each trivial ecall measured.
+const ECALL_ROUNDS: u64 = 10_000;
+/// Cycles a trivial ecall should take at most.
+const ECALL_BUDGET: u64 = 200;
+
+/// Average cycles of `call` over `ECALL_ROUNDS` calls, after a warm-up call.
+fn ecall_cycles(call: fn()) -> u64 {
+    call();
+    let start = get_cycle();
+    for _ in 0..ECALL_ROUNDS {
+        call();
+    }
+    (get_cycle() - start) / ECALL_ROUNDS
+}
+
 fn get_time() -> u64 {
     const CSR_TIME: u32 = 0xc01;
     let mut low_time: u64;
@@ -289,6 +314,31 @@ extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
         let end_time = get_time();
         println!("Test #{}: {}", i, end_time - start_time);
     }
+    info!("Starting trivial ecall test");
+    let calls: [(&str, fn()); 4] = [
+        ("get_spec_version", || {
+            sbi::get_spec_version();
+        }),
+        ("get_sbi_impl_id", || {
+            sbi::get_sbi_impl_id();
+        }),
+        ("get_mvendorid", || {
+            sbi::get_mvendorid();
+        }),
+        // Not cached, for comparison.
+        ("probe_extension", || {
+            sbi::probe_extension(sbi::Timer);
+        }),
+    ];
+    for (name, call) in calls {
+        let cycles = ecall_cycles(call);
+        let verdict = if cycles <= ECALL_BUDGET {
+            "ok"
+        } else {
+            "over budget"
+        };
+        println!("Ecall {name}: {cycles} cycles, {verdict}");
+    }
     sbi::system_reset(sbi::Shutdown, sbi::NoReason);
     unreachable!()
 }
@@ -0,0 +1,986 @@
+//! In-place device tree fixups applied before handing over to the next stage.
+//!
+//! Like other firmwares, the blob is grown in place and memory right after
+//! `totalsize` is assumed to be free.
+
+use core::ops::Range;
+
+/// Most NUMA nodes told apart.
+pub const MAX_NUMA_NODES: usize = 8;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+const HEADER_TOTALSIZE: usize = 4;
+const HEADER_OFF_DT_STRUCT: usize = 8;
+const HEADER_OFF_DT_STRINGS: usize = 12;
+const HEADER_OFF_MEM_RSVMAP: usize = 16;
+const HEADER_VERSION: usize = 20;
+const HEADER_SIZE_DT_STRINGS: usize = 32;
+const HEADER_SIZE_DT_STRUCT: usize = 36;
+
+/// Errors that can occur while editing the device tree.
+#[derive(Debug)]
+pub enum FixupError {
+    /// The blob is not a device tree of version 17 or later.
+    BadHeader,
+    /// The structure block ended unexpectedly.
+    BadStructure,
+}
+
+#[inline]
+const fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+struct Fdt {
+    base: *mut u8,
+}
+
+impl Fdt {
+    fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        unsafe { core::ptr::copy_nonoverlapping(self.base.add(offset), bytes.as_mut_ptr(), 4) };
+        u32::from_be_bytes(bytes)
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        let bytes = value.to_be_bytes();
+        unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.base.add(offset), 4) };
+    }
+
+    fn header(&self, field: usize) -> usize {
+        self.read_u32(field) as usize
+    }
+
+    fn set_header(&mut self, field: usize, value: usize) {
+        self.write_u32(field, value as u32);
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.base.add(offset), len) }
+    }
+
+    fn write_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.base.add(offset), bytes.len())
+        };
+    }
+
+    /// Length of the NUL terminated string at `offset`, without the NUL.
+    fn strlen(&self, offset: usize) -> usize {
+        let mut len = 0;
+        while unsafe { *self.base.add(offset + len) } != 0 {
+            len += 1;
+        }
+        len
+    }
+
+    /// Open a zeroed gap of `len` bytes at `offset`, moving the rest of the blob up.
+    fn insert(&mut self, offset: usize, len: usize) {
+        let total = self.header(HEADER_TOTALSIZE);
+        unsafe {
+            core::ptr::copy(
+                self.base.add(offset),
+                self.base.add(offset + len),
+                total - offset,
+            );
+            core::ptr::write_bytes(self.base.add(offset), 0, len);
+        }
+        for field in [
+            HEADER_OFF_DT_STRUCT,
+            HEADER_OFF_DT_STRINGS,
+            HEADER_OFF_MEM_RSVMAP,
+        ] {
+            let value = self.header(field);
+            if value >= offset {
+                self.set_header(field, value + len);
+            }
+        }
+        self.set_header(HEADER_TOTALSIZE, total + len);
+    }
+
+    /// Grow the structure block by `len` bytes at `offset`.
+    fn insert_struct(&mut self, offset: usize, len: usize) {
+        self.insert(offset, len);
+        let size = self.header(HEADER_SIZE_DT_STRUCT);
+        self.set_header(HEADER_SIZE_DT_STRUCT, size + len);
+    }
+
+    /// Offset of `name` in the strings block, appending it if missing.
+    fn string_offset(&mut self, name: &str) -> usize {
+        let strings = self.header(HEADER_OFF_DT_STRINGS);
+        let size = self.header(HEADER_SIZE_DT_STRINGS);
+        let mut offset = 0;
+        while offset < size {
+            let len = self.strlen(strings + offset);
+            if self.bytes(strings + offset, len) == name.as_bytes() {
+                return offset;
+            }
+            offset += len + 1;
+        }
+        self.insert(strings + size, name.len() + 1);
+        self.write_bytes(strings + size, name.as_bytes());
+        self.set_header(HEADER_SIZE_DT_STRINGS, size + name.len() + 1);
+        size
+    }
+
+    /// Offset of the token following the one at `offset`.
+    fn next_token(&self, offset: usize) -> Result<usize, FixupError> {
+        match self.read_u32(offset) {
+            FDT_BEGIN_NODE => Ok(offset + 4 + align4(self.strlen(offset + 4) + 1)),
+            FDT_PROP => Ok(offset + 12 + align4(self.read_u32(offset + 4) as usize)),
+            FDT_END_NODE | FDT_NOP => Ok(offset + 4),
+            _ => Err(FixupError::BadStructure),
+        }
+    }
+
+    /// Find the top level node `name`, as `(begin_node_offset, end_node_offset)`.
+    fn find_root_child(&self, name: &str) -> Result<Option<(usize, usize)>, FixupError> {
+        let mut offset = self.header(HEADER_OFF_DT_STRUCT);
+        let mut depth = 0;
+        let mut found = None;
+        loop {
+            match self.read_u32(offset) {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    if depth == 2 {
+                        let len = self.strlen(offset + 4);
+                        if self.bytes(offset + 4, len) == name.as_bytes() {
+                            found = Some(offset);
+                        }
+                    }
+                }
+                FDT_END_NODE => {
+                    if depth == 2 {
+                        if let Some(begin) = found {
+                            return Ok(Some((begin, offset)));
+                        }
+                    }
+                    if depth == 1 {
+                        return Ok(None);
+                    }
+                    depth -= 1;
+                }
+                FDT_END => return Err(FixupError::BadStructure),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Offset of the root node's `FDT_END_NODE` token.
+    fn root_end(&self) -> Result<usize, FixupError> {
+        let mut offset = self.header(HEADER_OFF_DT_STRUCT);
+        let mut depth = 0;
+        loop {
+            match self.read_u32(offset) {
+                FDT_BEGIN_NODE => depth += 1,
+                FDT_END_NODE => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(offset);
+                    }
+                }
+                FDT_END => return Err(FixupError::BadStructure),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Find or create the top level node `name`.
+    fn root_child(&mut self, name: &str) -> Result<(usize, usize), FixupError> {
+        if let Some(node) = self.find_root_child(name)? {
+            return Ok(node);
+        }
+        let at = self.root_end()?;
+        let name_len = align4(name.len() + 1);
+        self.insert_struct(at, 8 + name_len);
+        self.write_u32(at, FDT_BEGIN_NODE);
+        self.write_bytes(at + 4, name.as_bytes());
+        self.write_u32(at + 4 + name_len, FDT_END_NODE);
+        Ok((at, at + 4 + name_len))
+    }
+
+    /// Find property `name` directly inside the node spanning `begin..end`.
+    fn find_prop(&self, begin: usize, end: usize, name: &str) -> Result<Option<usize>, FixupError> {
+        let strings = self.header(HEADER_OFF_DT_STRINGS);
+        let mut offset = self.next_token(begin)?;
+        while offset < end {
+            match self.read_u32(offset) {
+                FDT_PROP => {
+                    let name_offset = strings + self.read_u32(offset + 8) as usize;
+                    let len = self.strlen(name_offset);
+                    if self.bytes(name_offset, len) == name.as_bytes() {
+                        return Ok(Some(offset));
+                    }
+                }
+                // Properties always precede subnodes.
+                FDT_BEGIN_NODE | FDT_END_NODE => return Ok(None),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+        Ok(None)
+    }
+
+    /// Value of property `name` of the node at `begin`, as `(offset, len)`.
+    fn prop(&self, begin: usize, name: &str) -> Result<Option<(usize, usize)>, FixupError> {
+        let end = self.header(HEADER_OFF_DT_STRUCT) + self.header(HEADER_SIZE_DT_STRUCT);
+        Ok(self
+            .find_prop(begin, end, name)?
+            .map(|prop| (prop + 12, self.read_u32(prop + 4) as usize)))
+    }
+
+    /// Offset of the `FDT_END_NODE` token closing the node at `begin`.
+    fn node_end(&self, begin: usize) -> Result<usize, FixupError> {
+        let mut offset = begin;
+        let mut depth = 0;
+        loop {
+            match self.read_u32(offset) {
+                FDT_BEGIN_NODE => depth += 1,
+                FDT_END_NODE => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(offset);
+                    }
+                }
+                FDT_END => return Err(FixupError::BadStructure),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Offset of the `index`th node, in tree order, whose `compatible` lists `compatible`.
+    fn find_compatible(&self, compatible: &str, index: usize) -> Result<Option<usize>, FixupError> {
+        let mut offset = self.header(HEADER_OFF_DT_STRUCT);
+        let mut seen = 0;
+        loop {
+            match self.read_u32(offset) {
+                FDT_BEGIN_NODE => {
+                    if let Some((value, len)) = self.prop(offset, "compatible")? {
+                        if self
+                            .bytes(value, len)
+                            .split(|&byte| byte == 0)
+                            .any(|id| id == compatible.as_bytes())
+                        {
+                            if seen == index {
+                                return Ok(Some(offset));
+                            }
+                            seen += 1;
+                        }
+                    }
+                }
+                FDT_END => return Ok(None),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Largest `phandle` in the tree, 0 if there is none.
+    fn max_phandle(&self) -> Result<u32, FixupError> {
+        let strings = self.header(HEADER_OFF_DT_STRINGS);
+        let mut offset = self.header(HEADER_OFF_DT_STRUCT);
+        let mut max = 0;
+        loop {
+            match self.read_u32(offset) {
+                FDT_PROP => {
+                    let name_offset = strings + self.read_u32(offset + 8) as usize;
+                    let len = self.strlen(name_offset);
+                    if self.bytes(name_offset, len) == b"phandle" {
+                        max = max.max(self.read_u32(offset + 12));
+                    }
+                }
+                FDT_END => return Ok(max),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Add property `name` to the node at `begin`.
+    fn add_prop(&mut self, begin: usize, name: &str, value: &[u8]) -> Result<(), FixupError> {
+        // Adding the name moves the structure block if strings come first.
+        let struct_before = self.header(HEADER_OFF_DT_STRUCT);
+        let name_offset = self.string_offset(name);
+        let begin = begin + self.header(HEADER_OFF_DT_STRUCT) - struct_before;
+        let at = self.next_token(begin)?;
+        self.insert_struct(at, 12 + align4(value.len()));
+        self.write_u32(at, FDT_PROP);
+        self.write_u32(at + 4, value.len() as u32);
+        self.write_u32(at + 8, name_offset as u32);
+        self.write_bytes(at + 12, value);
+        Ok(())
+    }
+
+    /// `phandle` of the node at `begin`, giving it a new one if it has none.
+    ///
+    /// Adding a `phandle` moves everything after `begin`.
+    fn phandle(&mut self, begin: usize) -> Result<u32, FixupError> {
+        if let Some((value, 4)) = self.prop(begin, "phandle")? {
+            return Ok(self.read_u32(value));
+        }
+        let phandle = self.max_phandle()? + 1;
+        self.add_prop(begin, "phandle", &phandle.to_be_bytes())?;
+        Ok(phandle)
+    }
+}
+
+fn open(fdt_address: usize) -> Result<Fdt, FixupError> {
+    let fdt = Fdt {
+        base: fdt_address as *mut u8,
+    };
+    if fdt.read_u32(0) != FDT_MAGIC || fdt.header(HEADER_VERSION) < 17 {
+        return Err(FixupError::BadHeader);
+    }
+    Ok(fdt)
+}
+
+/// Size of the device tree at `fdt_address`, if it has a valid header.
+pub fn total_size(fdt_address: usize) -> Option<usize> {
+    let fdt = Fdt {
+        base: fdt_address as *mut u8,
+    };
+    (fdt.read_u32(0) == FDT_MAGIC).then(|| fdt.header(HEADER_TOTALSIZE))
+}
+
+/// Append `extra` to `/chosen/bootargs` of the device tree at `fdt_address`,
+/// creating the node and property as needed.
+///
+/// Nothing is changed if `bootargs` already contains `extra`.
+pub fn append_bootargs(fdt_address: usize, extra: &str) -> Result<(), FixupError> {
+    const BOOTARGS: &str = "bootargs";
+    let mut fdt = open(fdt_address)?;
+    // Add the name first, it may move the structure block.
+    let name_offset = fdt.string_offset(BOOTARGS);
+    let (begin, end) = fdt.root_child("chosen")?;
+
+    match fdt.find_prop(begin, end, BOOTARGS)? {
+        Some(prop) => {
+            let len = fdt.read_u32(prop + 4) as usize;
+            let value = prop + 12;
+            let old_len = fdt.strlen(value).min(len);
+            let old = fdt.bytes(value, old_len);
+            if old
+                .windows(extra.len())
+                .any(|window| window == extra.as_bytes())
+            {
+                return Ok(());
+            }
+            let separator = if old_len == 0 { 0 } else { 1 };
+            let new_len = old_len + separator + extra.len() + 1;
+            let grow = align4(new_len) - align4(len);
+            if grow > 0 {
+                fdt.insert_struct(value + align4(len), grow);
+            }
+            // Clear the old terminator and padding before writing the new tail.
+            unsafe {
+                core::ptr::write_bytes(fdt.base.add(value + old_len), 0, align4(new_len) - old_len)
+            };
+            if separator != 0 {
+                fdt.write_bytes(value + old_len, b" ");
+            }
+            fdt.write_bytes(value + old_len + separator, extra.as_bytes());
+            fdt.write_u32(prop + 4, new_len as u32);
+        }
+        None => {
+            let at = fdt.next_token(begin)?;
+            let len = extra.len() + 1;
+            fdt.insert_struct(at, 12 + align4(len));
+            fdt.write_u32(at, FDT_PROP);
+            fdt.write_u32(at + 4, len as u32);
+            fdt.write_u32(at + 8, name_offset as u32);
+            fdt.write_bytes(at + 12, extra.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+/// Set `/cpus/timebase-frequency` of the device tree at `fdt_address` to `freq`.
+///
+/// A 64-bit value is rewritten in place; the property is created if missing.
+pub fn fixup_timebase(fdt_address: usize, freq: u32) -> Result<(), FixupError> {
+    const TIMEBASE_FREQUENCY: &str = "timebase-frequency";
+    let mut fdt = open(fdt_address)?;
+    let (begin, end) = fdt.root_child("cpus")?;
+    match fdt.find_prop(begin, end, TIMEBASE_FREQUENCY)? {
+        Some(prop) => match fdt.read_u32(prop + 4) {
+            4 => fdt.write_u32(prop + 12, freq),
+            8 => {
+                fdt.write_u32(prop + 12, 0);
+                fdt.write_u32(prop + 16, freq);
+            }
+            _ => return Err(FixupError::BadStructure),
+        },
+        None => fdt.add_prop(begin, TIMEBASE_FREQUENCY, &freq.to_be_bytes())?,
+    }
+    Ok(())
+}
+
+/// Property marking a device the firmware keeps for itself, such as a UART
+/// dedicated to the firmware console.
+pub const FIRMWARE_RESERVED: &str = "rustsbi,firmware-reserved";
+const STATUS: &str = "status";
+const FAIL: &[u8] = b"fail\0";
+const DISABLED: &[u8] = b"disabled\0";
+
+impl Fdt {
+    /// Offset of the cpu node of `hart_id`, if the tree has one.
+    fn find_cpu(&self, hart_id: usize) -> Result<Option<usize>, FixupError> {
+        let mut index = 0;
+        while let Some(cpu) = self.find_compatible("riscv", index)? {
+            if self.cpu_hart(cpu)? == hart_id as u64 {
+                return Ok(Some(cpu));
+            }
+            index += 1;
+        }
+        Ok(None)
+    }
+
+    /// Hart ID of the cpu node at `cpu`, its `reg`.
+    fn cpu_hart(&self, cpu: usize) -> Result<u64, FixupError> {
+        match self.prop(cpu, "reg")? {
+            Some((value, 4)) => Ok(self.read_u32(value) as u64),
+            Some((value, 8)) => {
+                Ok(((self.read_u32(value) as u64) << 32) | self.read_u32(value + 4) as u64)
+            }
+            _ => Err(FixupError::BadStructure),
+        }
+    }
+
+    /// Offset of the node whose `phandle` is `phandle`.
+    fn find_phandle(&self, phandle: u32) -> Result<Option<usize>, FixupError> {
+        let mut offset = self.header(HEADER_OFF_DT_STRUCT);
+        loop {
+            match self.read_u32(offset) {
+                FDT_BEGIN_NODE => {
+                    if let Some((value, 4)) = self.prop(offset, "phandle")? {
+                        if self.read_u32(value) == phandle {
+                            return Ok(Some(offset));
+                        }
+                    }
+                }
+                FDT_END => return Ok(None),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Offset of the `index`th node, in tree order, with property `name`.
+    fn find_with_prop(&self, name: &str, index: usize) -> Result<Option<usize>, FixupError> {
+        let mut offset = self.header(HEADER_OFF_DT_STRUCT);
+        let mut seen = 0;
+        loop {
+            match self.read_u32(offset) {
+                FDT_BEGIN_NODE => {
+                    if self.prop(offset, name)?.is_some() {
+                        if seen == index {
+                            return Ok(Some(offset));
+                        }
+                        seen += 1;
+                    }
+                }
+                FDT_END => return Ok(None),
+                _ => {}
+            }
+            offset = self.next_token(offset)?;
+        }
+    }
+
+    /// Set `status` of the node at `node` to the NUL terminated `status`.
+    ///
+    /// Growing the property moves everything after `node`.
+    fn set_status(&mut self, node: usize, status: &[u8]) -> Result<(), FixupError> {
+        let end = self.header(HEADER_OFF_DT_STRUCT) + self.header(HEADER_SIZE_DT_STRUCT);
+        match self.find_prop(node, end, STATUS)? {
+            Some(prop) => {
+                let len = self.read_u32(prop + 4) as usize;
+                let (old, new) = (align4(len), align4(status.len()));
+                if new > old {
+                    self.insert_struct(prop + 12 + old, new - old);
+                }
+                // A longer old value leaves whole words behind, turn them into nops.
+                for word in (prop + 12 + new..prop + 12 + old).step_by(4) {
+                    self.write_u32(word, FDT_NOP);
+                }
+                unsafe { core::ptr::write_bytes(self.base.add(prop + 12), 0, new) };
+                self.write_bytes(prop + 12, status);
+                self.write_u32(prop + 4, status.len() as u32);
+                Ok(())
+            }
+            None => self.add_prop(node, STATUS, status),
+        }
+    }
+}
+
+/// Set `status = "fail"` on the cpu node of `hart_id` in the device tree at
+/// `fdt_address`, so the next stage does not wait for it.
+///
+/// Returns false if the tree has no cpu node for the hart.
+pub fn mark_cpu_failed(fdt_address: usize, hart_id: usize) -> Result<bool, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let Some(cpu) = fdt.find_cpu(hart_id)? else {
+        return Ok(false);
+    };
+    fdt.set_status(cpu, FAIL)?;
+    Ok(true)
+}
+
+/// Set `status = "fail"` on the cpu nodes of harts `first_hart` and above in
+/// the device tree at `fdt_address`, the harts the firmware parks.
+///
+/// Returns the number of cpu nodes marked.
+pub fn mark_cpus_failed_from(fdt_address: usize, first_hart: usize) -> Result<usize, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let mut marked = 0;
+    let mut index = 0;
+    while let Some(cpu) = fdt.find_compatible("riscv", index)? {
+        if fdt.cpu_hart(cpu)? >= first_hart as u64 {
+            fdt.set_status(cpu, FAIL)?;
+            marked += 1;
+        }
+        index += 1;
+    }
+    Ok(marked)
+}
+
+/// Disable the cpu node of `hart_id` in the device tree at `fdt_address`.
+///
+/// Returns false if the tree has no cpu node for the hart.
+pub fn disable_cpu(fdt_address: usize, hart_id: usize) -> Result<bool, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let Some(cpu) = fdt.find_cpu(hart_id)? else {
+        return Ok(false);
+    };
+    fdt.set_status(cpu, DISABLED)?;
+    Ok(true)
+}
+
+/// Set property `name` of the cpu node of `hart_id` in the device tree at
+/// `fdt_address` to `value`, adding it if missing.
+///
+/// Returns false if the tree has no cpu node for the hart.
+pub fn set_cpu_prop(
+    fdt_address: usize,
+    hart_id: usize,
+    name: &str,
+    value: &[u8],
+) -> Result<bool, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let Some(cpu) = fdt.find_cpu(hart_id)? else {
+        return Ok(false);
+    };
+    match fdt.prop(cpu, name)? {
+        Some((offset, len)) if len == value.len() => fdt.write_bytes(offset, value),
+        Some(_) => return Err(FixupError::BadStructure),
+        None => fdt.add_prop(cpu, name, value)?,
+    }
+    Ok(true)
+}
+
+/// Disable the node with `phandle` in the device tree at `fdt_address`.
+///
+/// Returns false if no node has the phandle.
+pub fn disable_phandle(fdt_address: usize, phandle: u32) -> Result<bool, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let Some(node) = fdt.find_phandle(phandle)? else {
+        return Ok(false);
+    };
+    fdt.set_status(node, DISABLED)?;
+    Ok(true)
+}
+
+/// Disable every node compatible with `compatible` in the device tree at
+/// `fdt_address`, returning how many there were.
+pub fn disable_compatible(fdt_address: usize, compatible: &str) -> Result<usize, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let mut index = 0;
+    while let Some(node) = fdt.find_compatible(compatible, index)? {
+        fdt.set_status(node, DISABLED)?;
+        index += 1;
+    }
+    Ok(index)
+}
+
+/// Disable every node marked `rustsbi,firmware-reserved` in the device tree
+/// at `fdt_address`, returning how many there were.
+pub fn disable_firmware_reserved(fdt_address: usize) -> Result<usize, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let mut index = 0;
+    while let Some(node) = fdt.find_with_prop(FIRMWARE_RESERVED, index)? {
+        fdt.set_status(node, DISABLED)?;
+        index += 1;
+    }
+    Ok(index)
+}
+
+/// Copy the device tree at `fdt_address` to `to`, returning its size.
+///
+/// # Safety
+///
+/// `to` must point to free memory large enough for the tree and whatever
+/// later fixups add to the copy.
+pub unsafe fn copy(fdt_address: usize, to: usize) -> Result<usize, FixupError> {
+    let fdt = open(fdt_address)?;
+    let size = fdt.header(HEADER_TOTALSIZE);
+    unsafe { core::ptr::copy(fdt.base, to as *mut u8, size) };
+    Ok(size)
+}
+
+/// Add `range` to the memory reservation block of the device tree at `fdt_address`.
+pub fn add_mem_reserve(fdt_address: usize, range: Range<usize>) -> Result<(), FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let mut entry = fdt.header(HEADER_OFF_MEM_RSVMAP);
+    // The block ends with an all zero entry.
+    while fdt.read_u32(entry)
+        | fdt.read_u32(entry + 4)
+        | fdt.read_u32(entry + 8)
+        | fdt.read_u32(entry + 12)
+        != 0
+    {
+        entry += 16;
+    }
+    // Open the gap after the terminator, so the block itself never moves.
+    fdt.insert(entry + 16, 16);
+    fdt.write_bytes(entry, &(range.start as u64).to_be_bytes());
+    fdt.write_bytes(entry + 8, &(range.len() as u64).to_be_bytes());
+    Ok(())
+}
+
+const IOMMU_COMPATIBLE: &str = "riscv,iommu";
+const PCI_IOMMU_COMPATIBLE: &str = "riscv,pci-iommu";
+const IMSIC_COMPATIBLE: &str = "riscv,imsics";
+const PCI_HOST_COMPATIBLE: &str = "pci-host-ecam-generic";
+/// Supervisor external interrupt, the one S-level IMSICs deliver.
+const IRQ_S_EXT: u32 = 9;
+/// Requester IDs of a PCI segment.
+const REQUESTER_IDS: u32 = 0x1_0000;
+
+/// Offset of the IMSIC node delivering supervisor interrupts, if any.
+fn supervisor_imsic(fdt: &Fdt) -> Result<Option<usize>, FixupError> {
+    let mut index = 0;
+    while let Some(imsic) = fdt.find_compatible(IMSIC_COMPATIBLE, index)? {
+        if let Some((value, len)) = fdt.prop(imsic, "interrupts-extended")? {
+            // Pairs of interrupt parent phandle and interrupt number.
+            if len >= 8 && fdt.read_u32(value + 4) == IRQ_S_EXT {
+                return Ok(Some(imsic));
+            }
+        }
+        index += 1;
+    }
+    Ok(None)
+}
+
+/// The PCI IOMMU on the bus of the host bridge at `host`, with its requester ID.
+fn host_iommu(fdt: &Fdt, host: usize) -> Result<Option<(usize, u32)>, FixupError> {
+    let end = fdt.node_end(host)?;
+    let mut index = 0;
+    while let Some(iommu) = fdt.find_compatible(PCI_IOMMU_COMPATIBLE, index)? {
+        index += 1;
+        if !(host..end).contains(&iommu) {
+            continue;
+        }
+        // The first cell of a PCI `reg` holds bus, device and function in bits 8 to 23.
+        let Some((value, len)) = fdt.prop(iommu, "reg")? else {
+            return Err(FixupError::BadStructure);
+        };
+        if len < 4 {
+            return Err(FixupError::BadStructure);
+        }
+        return Ok(Some((iommu, (fdt.read_u32(value) >> 8) & 0xffff)));
+    }
+    Ok(None)
+}
+
+/// Complete RISC-V IOMMU nodes the way Linux expects them, returning the
+/// number of properties added.
+///
+/// A platform IOMMU without wired interrupts gets the supervisor IMSIC as its
+/// `msi-parent`. Generic ECAM host bridges that name no IOMMU get an
+/// `iommu-map` to the IOMMU translating for them: a PCI IOMMU on their own
+/// bus, which is left out of the map, or else the platform IOMMU if there is
+/// only one.
+pub fn fixup_iommu(fdt_address: usize) -> Result<usize, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let mut added = 0;
+
+    let mut iommus = 0;
+    while let Some(iommu) = fdt.find_compatible(IOMMU_COMPATIBLE, iommus)? {
+        iommus += 1;
+        if fdt.prop(iommu, "msi-parent")?.is_some()
+            || fdt.prop(iommu, "interrupts")?.is_some()
+            || fdt.prop(iommu, "interrupts-extended")?.is_some()
+        {
+            continue;
+        }
+        let Some(imsic) = supervisor_imsic(&fdt)? else {
+            continue;
+        };
+        let phandle = fdt.phandle(imsic)?;
+        // Offsets moved if the IMSIC got a new phandle.
+        let Some(iommu) = fdt.find_compatible(IOMMU_COMPATIBLE, iommus - 1)? else {
+            return Err(FixupError::BadStructure);
+        };
+        fdt.add_prop(iommu, "msi-parent", &phandle.to_be_bytes())?;
+        added += 1;
+    }
+
+    let mut hosts = 0;
+    while let Some(host) = fdt.find_compatible(PCI_HOST_COMPATIBLE, hosts)? {
+        hosts += 1;
+        if fdt.prop(host, "iommu-map")?.is_some() || fdt.prop(host, "iommus")?.is_some() {
+            continue;
+        }
+        let (iommu, bypass) = match host_iommu(&fdt, host)? {
+            Some((iommu, requester_id)) => (iommu, Some(requester_id)),
+            None if iommus == 1 => match fdt.find_compatible(IOMMU_COMPATIBLE, 0)? {
+                Some(iommu) => (iommu, None),
+                None => return Err(FixupError::BadStructure),
+            },
+            None => continue,
+        };
+        let phandle = fdt.phandle(iommu)?;
+        // Entries of `rid-base iommu iommu-base length`, around the IOMMU itself.
+        let ranges = match bypass {
+            Some(rid) => [(0, rid), (rid + 1, REQUESTER_IDS - rid - 1)],
+            None => [(0, REQUESTER_IDS), (0, 0)],
+        };
+        let mut map = [0u8; 32];
+        let mut len = 0;
+        for (base, count) in ranges.into_iter().filter(|&(_, count)| count != 0) {
+            for (cell, value) in map[len..len + 16]
+                .chunks_exact_mut(4)
+                .zip([base, phandle, base, count])
+            {
+                cell.copy_from_slice(&value.to_be_bytes());
+            }
+            len += 16;
+        }
+        // Offsets moved if the IOMMU got a new phandle.
+        let Some(host) = fdt.find_compatible(PCI_HOST_COMPATIBLE, hosts - 1)? else {
+            return Err(FixupError::BadStructure);
+        };
+        fdt.add_prop(host, "iommu-map", &map[..len])?;
+        added += 1;
+    }
+    Ok(added)
+}
+
+const DISTANCE_MAP_COMPATIBLE: &str = "numa-distance-map-v1";
+const DISTANCE_MATRIX: &str = "distance-matrix";
+/// Distance of a node to itself, the only one Linux accepts for it.
+const LOCAL_DISTANCE: u32 = 10;
+/// Distance between two nodes Linux uses without a map.
+const REMOTE_DISTANCE: u32 = 20;
+
+/// Make the NUMA distance map of the device tree at `fdt_address` one Linux
+/// accepts for `nodes`, returning the number of entries written.
+///
+/// A tree of several nodes without a map gets one of the distances Linux
+/// would assume. Linux turns NUMA off for a map with a node not at the local
+/// distance from itself, or another node at the local distance or nearer;
+/// such entries are corrected in place and everything else is kept.
+pub fn fixup_numa(fdt_address: usize, nodes: &[u32]) -> Result<usize, FixupError> {
+    let mut fdt = open(fdt_address)?;
+    let map = match fdt.find_compatible(DISTANCE_MAP_COMPATIBLE, 0)? {
+        Some(map) => map,
+        None if nodes.len() < 2 => return Ok(0),
+        None => {
+            // Add the names first, they may move the structure block.
+            fdt.string_offset("compatible");
+            fdt.string_offset(DISTANCE_MATRIX);
+            let (map, _) = fdt.root_child("distance-map")?;
+            let mut compatible = [0u8; DISTANCE_MAP_COMPATIBLE.len() + 1];
+            compatible[..DISTANCE_MAP_COMPATIBLE.len()]
+                .copy_from_slice(DISTANCE_MAP_COMPATIBLE.as_bytes());
+            fdt.add_prop(map, "compatible", &compatible)?;
+            map
+        }
+    };
+    let Some((value, len)) = fdt.prop(map, DISTANCE_MATRIX)? else {
+        // Entries of `node_a node_b distance`, for every ordered pair.
+        let mut matrix = [0u8; MAX_NUMA_NODES * MAX_NUMA_NODES * 12];
+        let mut entries = 0;
+        for &a in nodes {
+            for &b in nodes {
+                let distance = if a == b {
+                    LOCAL_DISTANCE
+                } else {
+                    REMOTE_DISTANCE
+                };
+                for (cell, value) in matrix[entries * 12..(entries + 1) * 12]
+                    .chunks_exact_mut(4)
+                    .zip([a, b, distance])
+                {
+                    cell.copy_from_slice(&value.to_be_bytes());
+                }
+                entries += 1;
+            }
+        }
+        fdt.add_prop(map, DISTANCE_MATRIX, &matrix[..entries * 12])?;
+        return Ok(entries);
+    };
+    let mut corrected = 0;
+    for entry in (value..value + len - len % 12).step_by(12) {
+        let (a, b) = (fdt.read_u32(entry), fdt.read_u32(entry + 4));
+        let distance = fdt.read_u32(entry + 8);
+        let valid = if a == b {
+            distance == LOCAL_DISTANCE
+        } else {
+            distance > LOCAL_DISTANCE
+        };
+        if !valid {
+            let fixed = if a == b {
+                LOCAL_DISTANCE
+            } else {
+                REMOTE_DISTANCE
+            };
+            fdt.write_u32(entry + 8, fixed);
+            corrected += 1;
+        }
+    }
+    Ok(corrected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fdt::Builder;
+
+    fn cells(blob: &[u8], compatible: &str, index: usize, name: &str) -> Option<Vec<u32>> {
+        let fdt = open(blob.as_ptr() as usize).unwrap();
+        let node = fdt.find_compatible(compatible, index).unwrap().unwrap();
+        let (value, len) = fdt.prop(node, name).unwrap()?;
+        Some((0..len / 4).map(|i| fdt.read_u32(value + i * 4)).collect())
+    }
+
+    fn platform_iommu(tree: &mut Builder, name: &str) {
+        tree.begin(name)
+            .prop_strs("compatible", &[IOMMU_COMPATIBLE])
+            .end();
+    }
+
+    fn host<'a>(tree: &'a mut Builder, name: &str) -> &'a mut Builder {
+        tree.begin(name)
+            .prop_strs("compatible", &[PCI_HOST_COMPATIBLE])
+    }
+
+    #[test]
+    fn lone_iommu_gets_msi_parent_and_map() {
+        let mut tree = Builder::new();
+        tree.begin("");
+        tree.begin("imsics")
+            .prop_strs("compatible", &[IMSIC_COMPATIBLE])
+            .prop_cells("interrupts-extended", &[1, IRQ_S_EXT])
+            .end();
+        platform_iommu(&mut tree, "iommu");
+        host(&mut tree, "pci").end();
+        let mut blob = tree.end().build(256);
+
+        assert_eq!(fixup_iommu(blob.as_mut_ptr() as usize).unwrap(), 2);
+        let imsic = cells(&blob, IMSIC_COMPATIBLE, 0, "phandle").unwrap()[0];
+        let iommu = cells(&blob, IOMMU_COMPATIBLE, 0, "phandle").unwrap()[0];
+        assert_eq!(
+            cells(&blob, IOMMU_COMPATIBLE, 0, "msi-parent"),
+            Some(vec![imsic])
+        );
+        assert_eq!(
+            cells(&blob, PCI_HOST_COMPATIBLE, 0, "iommu-map"),
+            Some(vec![0, iommu, 0, REQUESTER_IDS])
+        );
+    }
+
+    #[test]
+    fn several_iommus_without_imsic_map_no_host() {
+        let mut tree = Builder::new();
+        tree.begin("");
+        platform_iommu(&mut tree, "iommu@0");
+        platform_iommu(&mut tree, "iommu@1");
+        host(&mut tree, "pci").end();
+        let mut blob = tree.end().build(256);
+
+        // Neither IOMMU can get an MSI parent, both must still be counted.
+        assert_eq!(fixup_iommu(blob.as_mut_ptr() as usize).unwrap(), 0);
+        assert_eq!(cells(&blob, PCI_HOST_COMPATIBLE, 0, "iommu-map"), None);
+    }
+
+    #[test]
+    fn hosts_map_to_their_own_pci_iommu() {
+        let mut tree = Builder::new();
+        tree.begin("");
+        for (name, device) in [("pci@0", 1), ("pci@1", 2)] {
+            host(&mut tree, name)
+                .begin("iommu")
+                .prop_strs("compatible", &[PCI_IOMMU_COMPATIBLE])
+                .prop_cells("reg", &[device << 11, 0, 0, 0, 0])
+                .end()
+                .end();
+        }
+        let mut blob = tree.end().build(256);
+
+        assert_eq!(fixup_iommu(blob.as_mut_ptr() as usize).unwrap(), 2);
+        for (index, rid) in [(0, 0x8), (1, 0x10)] {
+            let iommu = cells(&blob, PCI_IOMMU_COMPATIBLE, index, "phandle").unwrap()[0];
+            assert_eq!(
+                cells(&blob, PCI_HOST_COMPATIBLE, index, "iommu-map"),
+                Some(vec![
+                    0,
+                    iommu,
+                    0,
+                    rid,
+                    rid + 1,
+                    iommu,
+                    rid + 1,
+                    REQUESTER_IDS - rid - 1
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn hosts_naming_an_iommu_are_kept() {
+        let mut tree = Builder::new();
+        tree.begin("");
+        platform_iommu(&mut tree, "iommu");
+        host(&mut tree, "pci").prop_cells("iommus", &[7]).end();
+        let mut blob = tree.end().build(256);
+
+        assert_eq!(fixup_iommu(blob.as_mut_ptr() as usize).unwrap(), 0);
+        assert_eq!(cells(&blob, PCI_HOST_COMPATIBLE, 0, "iommu-map"), None);
+    }
+
+    #[test]
+    fn marks_harts_past_the_limit_failed() {
+        let mut tree = Builder::new();
+        tree.begin("").begin("cpus");
+        for hart in [3, 0, 5, 1] {
+            tree.begin(&format!("cpu@{hart}"))
+                .prop_strs("compatible", &["thead,c910", "riscv"])
+                .prop_cells("reg", &[hart])
+                .prop_strs("status", &["okay"])
+                .end();
+        }
+        let mut blob = tree.end().end().build(256);
+
+        assert_eq!(
+            mark_cpus_failed_from(blob.as_mut_ptr() as usize, 2).unwrap(),
+            2
+        );
+        let fdt = open(blob.as_ptr() as usize).unwrap();
+        for hart in [0, 1, 3, 5] {
+            let cpu = fdt.find_cpu(hart).unwrap().unwrap();
+            let (value, len) = fdt.prop(cpu, "status").unwrap().unwrap();
+            let status = unsafe { core::slice::from_raw_parts(fdt.base.add(value), len) };
+            let expected = if hart >= 2 { FAIL } else { b"okay\0" };
+            assert_eq!(status, expected, "hart {hart}");
+        }
+    }
+}
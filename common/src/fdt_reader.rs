@@ -0,0 +1,448 @@
+//! Bounds checked reading of device trees the firmware does not own.
+//!
+//! `fdt_fixup` trusts the tree it edits, which the firmware or the previous
+//! stage built. A tree the supervisor may have written to is read through
+//! `FdtReader` instead: the header is checked against the blob before any
+//! block is touched, every token is checked against its block, and names
+//! longer than `MAX_NAME_LEN` are refused.
+
+use core::ops::Range;
+
+use crate::fdt_fixup::FixupError;
+
+/// Longest node or property name read, without the NUL.
+pub const MAX_NAME_LEN: usize = 256;
+/// Size of the version 17 header.
+pub const HEADER_SIZE: usize = 40;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn be64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some((be32(bytes, offset)? as u64) << 32 | be32(bytes, offset + 4)? as u64)
+}
+
+/// `totalsize` of the tree whose header is `header`, if it has the magic.
+pub fn total_size(header: &[u8]) -> Option<usize> {
+    (be32(header, 0)? == FDT_MAGIC).then_some(be32(header, 4)? as usize)
+}
+
+/// Node of the root a tree walk is in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TopNode {
+    Chosen,
+    ReservedMemory,
+    Other,
+}
+
+/// Big endian number of one or two cells.
+fn number(value: &[u8]) -> Result<u64, FixupError> {
+    match value.len() {
+        4 => Ok(be32(value, 0).unwrap() as u64),
+        8 => Ok(be64(value, 0).unwrap()),
+        _ => Err(FixupError::BadStructure),
+    }
+}
+
+/// A device tree blob whose header has been checked.
+#[derive(Clone)]
+pub struct FdtReader<'a> {
+    blob: &'a [u8],
+    structure: Range<usize>,
+    strings: Range<usize>,
+    rsvmap: usize,
+}
+
+/// A token of the structure block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// Start of a node, with its name, empty for the root node.
+    BeginNode(&'a [u8]),
+    EndNode,
+    Prop {
+        name: &'a [u8],
+        value: &'a [u8],
+    },
+}
+
+impl<'a> FdtReader<'a> {
+    /// Check the header of the tree at the start of `blob`.
+    ///
+    /// The tree must fit in `blob`, and its blocks in the tree.
+    pub fn new(blob: &'a [u8]) -> Result<Self, FixupError> {
+        let header = |field| be32(blob, field).ok_or(FixupError::BadHeader);
+        if header(0)? != FDT_MAGIC || header(20)? < 17 {
+            return Err(FixupError::BadHeader);
+        }
+        let total = header(4)? as usize;
+        if total < HEADER_SIZE || total > blob.len() {
+            return Err(FixupError::BadHeader);
+        }
+        let block = |offset: u32, size: u32| {
+            let (offset, size) = (offset as usize, size as usize);
+            match offset.checked_add(size) {
+                Some(end) if offset >= HEADER_SIZE && end <= total => Ok(offset..end),
+                _ => Err(FixupError::BadHeader),
+            }
+        };
+        let structure = block(header(8)?, header(36)?)?;
+        let strings = block(header(12)?, header(32)?)?;
+        let rsvmap = header(16)? as usize;
+        if structure.start % 4 != 0 || rsvmap % 8 != 0 || rsvmap < HEADER_SIZE || rsvmap >= total {
+            return Err(FixupError::BadHeader);
+        }
+        Ok(Self {
+            blob: &blob[..total],
+            structure,
+            strings,
+            rsvmap,
+        })
+    }
+
+    /// Check the header of the tree at `fdt_address`.
+    ///
+    /// # Safety
+    ///
+    /// The `totalsize` bytes the header gives, and the header itself, must be
+    /// readable memory that is not written while the reader is in use.
+    pub unsafe fn from_address(fdt_address: usize) -> Result<Self, FixupError> {
+        let header = unsafe { core::slice::from_raw_parts(fdt_address as *const u8, HEADER_SIZE) };
+        let size = total_size(header).ok_or(FixupError::BadHeader)?;
+        Self::new(unsafe { core::slice::from_raw_parts(fdt_address as *const u8, size) })
+    }
+
+    /// The tree, `totalsize` bytes.
+    pub fn blob(&self) -> &'a [u8] {
+        self.blob
+    }
+
+    /// Tokens of the structure block, up to `FDT_END`, with `FDT_NOP` left out.
+    pub fn tokens(&self) -> Tokens<'a> {
+        Tokens {
+            reader: self.clone(),
+            offset: self.structure.start,
+            done: false,
+        }
+    }
+
+    /// Entries of the memory reservation block, as `(address, size)`.
+    pub fn reservations(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let blob = self.blob;
+        (self.rsvmap..)
+            .step_by(16)
+            .map(move |entry| Some((be64(blob, entry)?, be64(blob, entry + 8)?)))
+            .take_while(|entry| matches!(entry, Some(entry) if *entry != (0, 0)))
+            .flatten()
+    }
+
+    /// Call `f` with every range the tree keeps from the operating system:
+    /// the memory reservation block, `reg` of the `/reserved-memory` nodes,
+    /// and the initrd `/chosen` gives.
+    ///
+    /// Reserved memory nodes with only a `size`, placed by the operating
+    /// system, have no range yet.
+    pub fn reserved_ranges(&self, mut f: impl FnMut(Range<u64>)) -> Result<(), FixupError> {
+        for (address, size) in self.reservations() {
+            f(address..address.saturating_add(size));
+        }
+        let mut depth = 0;
+        let mut top = TopNode::Other;
+        let (mut address_cells, mut size_cells) = (2, 1);
+        let (mut initrd_start, mut initrd_end) = (None, None);
+        for token in self.tokens() {
+            match token? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+                    if depth == 2 {
+                        top = match name {
+                            b"chosen" => TopNode::Chosen,
+                            b"reserved-memory" => TopNode::ReservedMemory,
+                            _ => TopNode::Other,
+                        };
+                        (address_cells, size_cells) = (2, 1);
+                    }
+                }
+                Token::EndNode if depth == 0 => return Err(FixupError::BadStructure),
+                Token::EndNode => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                Token::Prop { name, value } => match (depth, top, name) {
+                    (2, TopNode::ReservedMemory, b"#address-cells") => {
+                        address_cells = number(value)? as usize;
+                    }
+                    (2, TopNode::ReservedMemory, b"#size-cells") => {
+                        size_cells = number(value)? as usize;
+                    }
+                    (3, TopNode::ReservedMemory, b"reg") => {
+                        if !(1..=2).contains(&address_cells) || !(1..=2).contains(&size_cells) {
+                            return Err(FixupError::BadStructure);
+                        }
+                        let split = address_cells * 4;
+                        for entry in value.chunks_exact(split + size_cells * 4) {
+                            let address = number(&entry[..split])?;
+                            let size = number(&entry[split..])?;
+                            f(address..address.saturating_add(size));
+                        }
+                    }
+                    (2, TopNode::Chosen, b"linux,initrd-start") => {
+                        initrd_start = Some(number(value)?);
+                    }
+                    (2, TopNode::Chosen, b"linux,initrd-end") => initrd_end = Some(number(value)?),
+                    _ => {}
+                },
+            }
+        }
+        if let (Some(start), Some(end)) = (initrd_start, initrd_end) {
+            if start < end {
+                f(start..end);
+            }
+        }
+        Ok(())
+    }
+
+    /// The NUL terminated name at `offset`, within `block`.
+    fn name(&self, block: &Range<usize>, offset: usize) -> Result<&'a [u8], FixupError> {
+        let end = block.end.min(offset.saturating_add(MAX_NAME_LEN + 1));
+        let bytes = self.blob.get(offset..end).ok_or(FixupError::BadStructure)?;
+        let len = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(FixupError::BadStructure)?;
+        Ok(&bytes[..len])
+    }
+}
+
+/// Iterator over the tokens of a tree, see [`FdtReader::tokens`].
+///
+/// Yields one error for a malformed structure block and stops.
+pub struct Tokens<'a> {
+    reader: FdtReader<'a>,
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Tokens<'a> {
+    fn read(&mut self) -> Result<Option<Token<'a>>, FixupError> {
+        let reader = self.reader.clone();
+        let structure = &reader.structure;
+        let word = |offset: usize| -> Result<u32, FixupError> {
+            match offset.checked_add(4) {
+                Some(end) if end <= structure.end => {
+                    be32(reader.blob, offset).ok_or(FixupError::BadStructure)
+                }
+                _ => Err(FixupError::BadStructure),
+            }
+        };
+        loop {
+            let offset = self.offset;
+            match word(offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = reader.name(structure, offset + 4)?;
+                    self.offset = offset + 4 + (name.len() + 4) / 4 * 4;
+                    return Ok(Some(Token::BeginNode(name)));
+                }
+                FDT_END_NODE => {
+                    self.offset = offset + 4;
+                    return Ok(Some(Token::EndNode));
+                }
+                FDT_PROP => {
+                    let len = word(offset + 4)? as usize;
+                    let name_offset = word(offset + 8)? as usize;
+                    let value = offset + 12;
+                    let end = value.checked_add(len).ok_or(FixupError::BadStructure)?;
+                    if end > structure.end {
+                        return Err(FixupError::BadStructure);
+                    }
+                    let name = reader.name(
+                        &reader.strings,
+                        reader
+                            .strings
+                            .start
+                            .checked_add(name_offset)
+                            .ok_or(FixupError::BadStructure)?,
+                    )?;
+                    self.offset = value + (len + 3) / 4 * 4;
+                    return Ok(Some(Token::Prop {
+                        name,
+                        value: &reader.blob[value..end],
+                    }));
+                }
+                FDT_NOP => self.offset = offset + 4,
+                FDT_END => return Ok(None),
+                _ => return Err(FixupError::BadStructure),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Token<'a>, FixupError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let token = self.read().transpose();
+        if !matches!(token, Some(Ok(_))) {
+            self.done = true;
+        }
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fdt::Builder;
+
+    fn sample() -> Vec<u8> {
+        Builder::new()
+            .begin("")
+            .prop_cells("#address-cells", &[2])
+            .begin("chosen")
+            .prop_strs("bootargs", &["console=ttyS0"])
+            .end()
+            .end()
+            .build(0)
+    }
+
+    fn set_header(blob: &mut [u8], field: usize, value: u32) {
+        blob[field..field + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    #[test]
+    fn reads_every_token() {
+        let blob = sample();
+        let reader = FdtReader::new(&blob).unwrap();
+        let tokens: Vec<_> = reader.tokens().map(Result::unwrap).collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::BeginNode(b""),
+                Token::Prop {
+                    name: b"#address-cells",
+                    value: &[0, 0, 0, 2]
+                },
+                Token::BeginNode(b"chosen"),
+                Token::Prop {
+                    name: b"bootargs",
+                    value: b"console=ttyS0\0"
+                },
+                Token::EndNode,
+                Token::EndNode,
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_headers_past_the_blob() {
+        let blob = sample();
+        assert!(FdtReader::new(&blob[..blob.len() - 1]).is_err());
+        assert!(FdtReader::new(&blob[..HEADER_SIZE - 1]).is_err());
+        for (field, value) in [
+            (0, 0xdead_beef),
+            (4, HEADER_SIZE as u32 - 1),
+            (8, u32::MAX - 3),
+            (12, blob.len() as u32),
+            (16, blob.len() as u32),
+            (20, 16),
+            (32, u32::MAX),
+            (36, blob.len() as u32),
+        ] {
+            let mut bad = blob.clone();
+            set_header(&mut bad, field, value);
+            assert!(FdtReader::new(&bad).is_err(), "field {field}");
+        }
+    }
+
+    #[test]
+    fn stops_at_tokens_past_their_block() {
+        let mut blob = sample();
+        // The first property's length, reaching past the structure block.
+        let structure = be32(&blob, 8).unwrap() as usize;
+        set_header(&mut blob, structure + 12, 0x1000);
+        let reader = FdtReader::new(&blob).unwrap();
+        let tokens: Vec<_> = reader.tokens().collect();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens[1].is_err());
+    }
+
+    #[test]
+    fn refuses_overlong_names() {
+        let name = "n".repeat(MAX_NAME_LEN + 1);
+        let blob = Builder::new().begin("").begin(&name).end().end().build(0);
+        let reader = FdtReader::new(&blob).unwrap();
+        assert!(reader.tokens().any(|token| token.is_err()));
+    }
+
+    #[test]
+    fn finds_every_reserved_range() {
+        let blob = Builder::new()
+            .reserve(0x8000_0000, 0x20_0000)
+            .begin("")
+            .begin("chosen")
+            .prop_cells("linux,initrd-start", &[0x8400_0000])
+            .prop_cells("linux,initrd-end", &[0, 0x8480_0000])
+            .end()
+            .begin("reserved-memory")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[2])
+            .begin("mmode_resv0@80000000")
+            .prop_cells("reg", &[0, 0x8000_0000, 0, 0x4_0000])
+            .end()
+            .begin("linux,cma")
+            .prop_cells("size", &[0, 0x100_0000])
+            .end()
+            .begin("framebuffer@fe000000")
+            .prop_cells("reg", &[0, 0xfe00_0000, 0, 0x80_0000, 1, 0, 0, 0x1000])
+            .end()
+            .end()
+            .begin("soc")
+            .begin("uart")
+            .prop_cells("reg", &[0x1000_0000, 0x100])
+            .end()
+            .end()
+            .end()
+            .build(0);
+        let reader = FdtReader::new(&blob).unwrap();
+        let mut ranges = Vec::new();
+        reader.reserved_ranges(|range| ranges.push(range)).unwrap();
+        assert_eq!(
+            ranges,
+            [
+                0x8000_0000..0x8020_0000,
+                0x8000_0000..0x8004_0000,
+                0xfe00_0000..0xfe80_0000,
+                0x1_0000_0000..0x1_0000_1000,
+                0x8400_0000..0x8480_0000,
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_reservations() {
+        let blob = Builder::new()
+            .reserve(0x8000_0000, 0x20_0000)
+            .reserve(0x9000_0000, 0x1000)
+            .begin("")
+            .end()
+            .build(0);
+        let reader = FdtReader::new(&blob).unwrap();
+        assert_eq!(
+            reader.reservations().collect::<Vec<_>>(),
+            [(0x8000_0000, 0x20_0000), (0x9000_0000, 0x1000)]
+        );
+    }
+}
@@ -0,0 +1,497 @@
+//! Gzip decompression of a bundled payload.
+//!
+//! A small inflater after zlib's `puff`: codes are decoded a bit at a time,
+//! needing no tables beyond two canonical Huffman codes on the stack, and
+//! back references are copied from the output itself, so no window is kept.
+//! The CRC-32 and size in the gzip trailer are checked against the output.
+
+use core::fmt::{self, Display, Formatter};
+
+/// Why a gzip stream was refused.
+#[derive(Debug)]
+pub enum GzipError {
+    /// No gzip header, or one with unknown flags or method.
+    Header,
+    /// The stream ended before its last block or trailer.
+    Truncated,
+    /// A block of reserved type, or a stored block with a bad length.
+    Block,
+    /// A Huffman code that is over-subscribed, incomplete or undecodable.
+    Code,
+    /// A back reference before the start of the output.
+    Distance,
+    /// The output does not fit the space given.
+    OutputFull,
+    /// The output does not match the trailer.
+    Checksum { expected: u32, found: u32 },
+    /// The output size does not match the trailer.
+    Size { expected: u32, found: u32 },
+}
+
+impl Display for GzipError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            GzipError::Header => write!(f, "not a gzip stream"),
+            GzipError::Truncated => write!(f, "stream truncated"),
+            GzipError::Block => write!(f, "invalid block"),
+            GzipError::Code => write!(f, "invalid Huffman code"),
+            GzipError::Distance => write!(f, "back reference out of the output"),
+            GzipError::OutputFull => write!(f, "output does not fit"),
+            GzipError::Checksum { expected, found } => {
+                write!(f, "CRC-32 {:#010x}, expected {:#010x}", found, expected)
+            }
+            GzipError::Size { expected, found } => {
+                write!(f, "{} bytes, expected {}", found, expected)
+            }
+        }
+    }
+}
+
+const FLAG_HCRC: u8 = 1 << 1;
+const FLAG_EXTRA: u8 = 1 << 2;
+const FLAG_NAME: u8 = 1 << 3;
+const FLAG_COMMENT: u8 = 1 << 4;
+const FLAG_RESERVED: u8 = 0xe0;
+
+/// Longest code of deflate, in bits.
+const MAX_BITS: usize = 15;
+/// Literal and length symbols, with the two unused ones of fixed codes.
+const MAX_LITERALS: usize = 288;
+const MAX_DISTANCES: usize = 30;
+
+/// Base and extra bits of lengths 257 to 285 and distances 0 to 29.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reflected CRC-32 of IEEE 802.3, as gzip uses.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Input bits, least significant first.
+struct Bits<'a> {
+    input: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    /// The next `need` bits, at most 16.
+    fn bits(&mut self, need: u32) -> Result<u32, GzipError> {
+        let mut value = self.buffer;
+        while self.count < need {
+            let byte = *self.input.get(self.position).ok_or(GzipError::Truncated)?;
+            self.position += 1;
+            value |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        self.buffer = value >> need;
+        self.count -= need;
+        Ok(value & ((1 << need) - 1))
+    }
+
+    /// The next `len` whole bytes, dropping bits left of the current byte.
+    fn bytes(&mut self, len: usize) -> Result<&[u8], GzipError> {
+        self.buffer = 0;
+        self.count = 0;
+        let bytes = self
+            .input
+            .get(self.position..self.position + len)
+            .ok_or(GzipError::Truncated)?;
+        self.position += len;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code: codes of each length and symbols by code.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: [u16; MAX_LITERALS],
+}
+
+impl Huffman {
+    const fn new() -> Self {
+        Self {
+            count: [0; MAX_BITS + 1],
+            symbol: [0; MAX_LITERALS],
+        }
+    }
+
+    /// Build the code of symbols with code lengths `lengths`.
+    ///
+    /// Returns 0 for a complete code, a positive number for an incomplete
+    /// one and a negative number for an over-subscribed one.
+    fn build(&mut self, lengths: &[u8]) -> i32 {
+        self.count = [0; MAX_BITS + 1];
+        for &len in lengths {
+            self.count[len as usize] += 1;
+        }
+        if self.count[0] as usize == lengths.len() {
+            return 0;
+        }
+        let mut left = 1;
+        for len in 1..=MAX_BITS {
+            left <<= 1;
+            left -= self.count[len] as i32;
+            if left < 0 {
+                return left;
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + self.count[len];
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                self.symbol[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        left
+    }
+
+    /// Decode one symbol from `bits`.
+    fn decode(&self, bits: &mut Bits) -> Result<usize, GzipError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= bits.bits(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(GzipError::Code)
+    }
+}
+
+/// Output of the inflater, filled from the start.
+struct Output<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Output<'_> {
+    #[inline]
+    fn push(&mut self, byte: u8) -> Result<(), GzipError> {
+        *self.buffer.get_mut(self.len).ok_or(GzipError::OutputFull)? = byte;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Decode the compressed data of one block.
+fn codes(
+    bits: &mut Bits,
+    out: &mut Output,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), GzipError> {
+    loop {
+        let symbol = literals.decode(bits)?;
+        match symbol {
+            0..=255 => out.push(symbol as u8)?,
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(GzipError::Code);
+                }
+                let len =
+                    LENGTH_BASE[symbol] as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+                let symbol = distances.decode(bits)?;
+                if symbol >= DISTANCE_BASE.len() {
+                    return Err(GzipError::Code);
+                }
+                let distance = DISTANCE_BASE[symbol] as usize
+                    + bits.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                if distance > out.len {
+                    return Err(GzipError::Distance);
+                }
+                // Byte by byte, the copy may overlap what it writes.
+                for _ in 0..len {
+                    out.push(out.buffer[out.len - distance])?;
+                }
+            }
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Output) -> Result<(), GzipError> {
+    let header = bits.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    if len != !u16::from_le_bytes([header[2], header[3]]) {
+        return Err(GzipError::Block);
+    }
+    let data = bits.bytes(len as usize)?;
+    let end = out.len + data.len();
+    out.buffer
+        .get_mut(out.len..end)
+        .ok_or(GzipError::OutputFull)?
+        .copy_from_slice(data);
+    out.len = end;
+    Ok(())
+}
+
+fn fixed(bits: &mut Bits, out: &mut Output) -> Result<(), GzipError> {
+    let mut lengths = [0u8; MAX_LITERALS];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let mut literals = Huffman::new();
+    literals.build(&lengths);
+    let mut distances = Huffman::new();
+    distances.build(&[5; MAX_DISTANCES]);
+    codes(bits, out, &literals, &distances)
+}
+
+fn dynamic(bits: &mut Bits, out: &mut Output) -> Result<(), GzipError> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > MAX_DISTANCES {
+        return Err(GzipError::Code);
+    }
+
+    let mut lengths = [0u8; MAX_LITERALS + MAX_DISTANCES];
+    for &symbol in &CODE_LENGTH_ORDER[..code_count] {
+        lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let mut literals = Huffman::new();
+    if literals.build(&lengths[..CODE_LENGTH_ORDER.len()]) != 0 {
+        return Err(GzipError::Code);
+    }
+
+    let total = literal_count + distance_count;
+    let mut index = 0;
+    while index < total {
+        let symbol = literals.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if index == 0 => return Err(GzipError::Code),
+            16 => (lengths[index - 1], 3 + bits.bits(2)? as usize),
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if index + repeat > total {
+            return Err(GzipError::Code);
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+    // A block must be able to end.
+    if lengths[256] == 0 {
+        return Err(GzipError::Code);
+    }
+
+    // Only codes of a single symbol may be incomplete.
+    let incomplete = |code: &Huffman, result: i32, count: usize| {
+        result < 0 || (result > 0 && count != (code.count[0] + code.count[1]) as usize)
+    };
+    let result = literals.build(&lengths[..literal_count]);
+    if incomplete(&literals, result, literal_count) {
+        return Err(GzipError::Code);
+    }
+    let mut distances = Huffman::new();
+    let result = distances.build(&lengths[literal_count..total]);
+    if incomplete(&distances, result, distance_count) {
+        return Err(GzipError::Code);
+    }
+    codes(bits, out, &literals, &distances)
+}
+
+/// Decompress the gzip stream `input` into `output`, returning the output size.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize, GzipError> {
+    let [0x1f, 0x8b, 8, flags, ..] = *input else {
+        return Err(GzipError::Header);
+    };
+    if flags & FLAG_RESERVED != 0 {
+        return Err(GzipError::Header);
+    }
+    let mut bits = Bits {
+        input,
+        position: 10,
+        buffer: 0,
+        count: 0,
+    };
+    if flags & FLAG_EXTRA != 0 {
+        let len = bits.bytes(2)?;
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        bits.bytes(len)?;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            while bits.bytes(1)?[0] != 0 {}
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        bits.bytes(2)?;
+    }
+
+    let mut out = Output {
+        buffer: output,
+        len: 0,
+    };
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => stored(&mut bits, &mut out)?,
+            1 => fixed(&mut bits, &mut out)?,
+            2 => dynamic(&mut bits, &mut out)?,
+            _ => return Err(GzipError::Block),
+        }
+        if last {
+            break;
+        }
+    }
+
+    let trailer = bits.bytes(8)?;
+    let expected = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    let found = crc32(&out.buffer[..out.len]);
+    if found != expected {
+        return Err(GzipError::Checksum { expected, found });
+    }
+    if out.len as u32 != size {
+        return Err(GzipError::Size {
+            expected: size,
+            found: out.len as u32,
+        });
+    }
+    Ok(out.len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `hello, hello, hello prototyper\n` from `gzip -9`, a fixed code block.
+    const FIXED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0x0a, 0x8a, 0xf2, 0x4b, 0xf2, 0x4b, 0x2a, 0x0b, 0x52,
+        0x8b, 0xb8, 0x00, 0x50, 0x31, 0x4c, 0xe8, 0x1f, 0x00, 0x00, 0x00,
+    ];
+
+    /// `text()` from `gzip -9`, a dynamic code block.
+    const DYNAMIC: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x95, 0xd1, 0xd9, 0x11, 0x83,
+        0x30, 0x10, 0x03, 0xd0, 0x7f, 0x55, 0xb1, 0x25, 0x78, 0x8d, 0x31, 0x38, 0xdd, 0x24, 0x40,
+        0xb8, 0x31, 0x81, 0x70, 0x24, 0xd5, 0x87, 0x49, 0x07, 0xfa, 0xd6, 0xbc, 0x19, 0xed, 0xca,
+        0xdc, 0xe4, 0xdd, 0x54, 0xf2, 0xda, 0xda, 0xa2, 0x97, 0xc7, 0x12, 0x8f, 0x49, 0x9e, 0xf1,
+        0x94, 0x6e, 0x1b, 0xe7, 0x55, 0xe2, 0x5e, 0x2d, 0xff, 0x78, 0xb8, 0x7f, 0x3f, 0x52, 0xc6,
+        0x5a, 0x0c, 0x94, 0x03, 0x0a, 0xcb, 0x01, 0x87, 0x84, 0x03, 0x01, 0x8e, 0xac, 0xe4, 0x91,
+        0x72, 0xc2, 0xa6, 0xf0, 0x9c, 0x48, 0x3c, 0x32, 0xf2, 0xee, 0x80, 0x9c, 0x13, 0xde, 0x21,
+        0x70, 0x22, 0x57, 0xa8, 0x21, 0x9f, 0x65, 0xae, 0xc9, 0xd9, 0xcd, 0xad, 0xe2, 0x07, 0x67,
+        0x66, 0xfe, 0x60, 0x58, 0x02, 0x00, 0x00,
+    ];
+
+    fn text() -> Vec<u8> {
+        (0..12)
+            .flat_map(|i| {
+                format!(
+                    "{}: the quick brown fox jumps over the lazy dog {}\n",
+                    i,
+                    i * i
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    /// `data` in a single stored block, with the gzip header and trailer.
+    fn stored_stream(data: &[u8]) -> Vec<u8> {
+        let mut stream = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+        let len = data.len() as u16;
+        stream.push(1);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(data);
+        stream.extend_from_slice(&crc32(data).to_le_bytes());
+        stream.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        stream
+    }
+
+    fn inflate(stream: &[u8]) -> Result<Vec<u8>, GzipError> {
+        let mut output = vec![0; 4096];
+        let size = decompress(stream, &mut output)?;
+        output.truncate(size);
+        Ok(output)
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        assert_eq!(inflate(&stored_stream(&data)).unwrap(), data);
+        assert_eq!(inflate(FIXED).unwrap(), b"hello, hello, hello prototyper\n");
+        assert_eq!(inflate(DYNAMIC).unwrap(), text());
+    }
+
+    #[test]
+    fn corrupt_crc() {
+        let mut stream = DYNAMIC.to_vec();
+        let crc = stream.len() - 8;
+        stream[crc] ^= 1;
+        assert!(matches!(inflate(&stream), Err(GzipError::Checksum { .. })));
+        // A flipped data byte is caught by the CRC, if not by the decoder.
+        let mut stream = stored_stream(b"payload");
+        stream[15] ^= 0x20;
+        assert!(matches!(inflate(&stream), Err(GzipError::Checksum { .. })));
+    }
+
+    #[test]
+    fn refused_streams() {
+        assert!(matches!(
+            inflate(&FIXED[..FIXED.len() - 3]),
+            Err(GzipError::Truncated)
+        ));
+        assert!(matches!(inflate(&FIXED[1..]), Err(GzipError::Header)));
+        let mut output = [0; 8];
+        assert!(matches!(
+            decompress(DYNAMIC, &mut output),
+            Err(GzipError::OutputFull)
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+//! Walking SBI hart masks.
+//!
+//! A hart mask addresses harts `hart_mask_base + i` for every set bit `i` of
+//! `hart_mask`, so it can reach hart IDs far beyond XLEN, and the addressed
+//! IDs do not need to be contiguous. A `hart_mask_base` of -1 is a special
+//! encoding that addresses every hart and ignores `hart_mask`.
+
+/// Hart ID reported for bits whose `hart_mask_base + i` overflows.
+///
+/// No platform hart can have this ID, so validity checks reject it.
+pub const OVERFLOW_HART_ID: usize = usize::MAX;
+
+/// Iterator over the hart IDs addressed by a hart mask, in ascending order.
+pub struct HartIds {
+    mask: usize,
+    base: usize,
+    /// Next hart to yield when walking every hart.
+    all: Option<usize>,
+    /// Hart slots yielded when walking every hart.
+    hart_count: usize,
+}
+
+impl HartIds {
+    /// Walk the harts addressed by `mask` and `base`.
+    ///
+    /// With `base` of -1 the slots `0..hart_count` are yielded.
+    pub fn new(mask: usize, base: usize, hart_count: usize) -> Self {
+        HartIds {
+            mask,
+            base,
+            all: (base == usize::MAX).then_some(0),
+            hart_count,
+        }
+    }
+}
+
+impl Iterator for HartIds {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if let Some(next) = self.all.as_mut() {
+            if *next >= self.hart_count {
+                return None;
+            }
+            *next += 1;
+            return Some(*next - 1);
+        }
+        if self.mask == 0 {
+            return None;
+        }
+        let idx = self.mask.trailing_zeros() as usize;
+        // Clear the lowest set bit.
+        self.mask &= self.mask - 1;
+        Some(self.base.checked_add(idx).unwrap_or(OVERFLOW_HART_ID))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+
+    const BITS: usize = usize::BITS as usize;
+
+    fn ids(mask: usize, base: usize, hart_count: usize) -> Vec<usize> {
+        HartIds::new(mask, base, hart_count).collect()
+    }
+
+    #[test]
+    fn sparse_mask() {
+        assert_eq!(ids(0b1010_0101, 0, 8), [0, 2, 5, 7]);
+        assert_eq!(ids(0b1010_0101, 3, 8), [3, 5, 8, 10]);
+        assert_eq!(ids(1 << (BITS - 1), 0, 8), [BITS - 1]);
+        assert!(ids(0, 5, 8).is_empty());
+    }
+
+    #[test]
+    fn base_past_xlen() {
+        assert_eq!(ids(0b11, 1000, 8), [1000, 1001]);
+        assert_eq!(ids(0b11, BITS, 8), [BITS, BITS + 1]);
+    }
+
+    #[test]
+    fn overflowing_base() {
+        let base = usize::MAX - 2;
+        assert_eq!(ids(0b1011, base, 8), [base, base + 1, OVERFLOW_HART_ID]);
+    }
+
+    #[test]
+    fn all_harts() {
+        assert_eq!(ids(0, usize::MAX, 4), [0, 1, 2, 3]);
+        assert_eq!(ids(!0, usize::MAX, 2), [0, 1]);
+        assert!(ids(1, usize::MAX, 0).is_empty());
+    }
+
+    /// The IDs `mask` and `base` address, bit by bit.
+    fn expected(mask: usize, base: usize) -> Vec<usize> {
+        (0..BITS)
+            .filter(|bit| mask >> bit & 1 != 0)
+            .map(|bit| base.checked_add(bit).unwrap_or(OVERFLOW_HART_ID))
+            .collect()
+    }
+
+    /// Bases around zero, XLEN multiples and the overflow boundary.
+    fn base() -> impl Strategy<Value = usize> {
+        let near = || select(vec![0, BITS, 2 * BITS, usize::MAX - BITS, usize::MAX - 1]);
+        prop_oneof![
+            near(),
+            (near(), 0..BITS).prop_map(|(near, offset)| near.saturating_add(offset)),
+            any::<usize>(),
+        ]
+        .prop_map(|base| base.min(usize::MAX - 1))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(4096))]
+
+        #[test]
+        fn yields_every_set_bit_once(mask: usize, base in base()) {
+            let ids: Vec<usize> = HartIds::new(mask, base, 8).collect();
+            prop_assert_eq!(&ids, &expected(mask, base));
+            prop_assert_eq!(ids.len(), mask.count_ones() as usize);
+            prop_assert!(ids.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+
+        #[test]
+        fn reports_overflowing_bits(mask: usize, below_max in 1..BITS) {
+            let base = usize::MAX - below_max;
+            let mask = mask | 1 << (BITS - 1);
+            let ids: Vec<usize> = HartIds::new(mask, base, 8).collect();
+            // `base + bit` reaching `usize::MAX` is the overflow ID as well.
+            let in_range = (0..BITS)
+                .filter(|&bit| mask >> bit & 1 != 0 && bit < usize::MAX - base)
+                .count();
+            prop_assert_eq!(
+                ids.iter().filter(|&&id| id != OVERFLOW_HART_ID).count(),
+                in_range
+            );
+            prop_assert_eq!(ids.last(), Some(&OVERFLOW_HART_ID));
+        }
+
+        #[test]
+        fn all_harts_ignores_the_mask(mask: usize, hart_count in 0..2 * BITS) {
+            let ids: Vec<usize> = HartIds::new(mask, usize::MAX, hart_count).collect();
+            prop_assert_eq!(ids, (0..hart_count).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Instruction decoding for the emulation paths.
+//!
+//! Covers what the firmware emulates or reports: the fields of any 32-bit
+//! encoding, and the classes of loads, stores, AMOs and CSR accesses.
+//! Compressed loads and stores are expanded to their 32-bit forms, so the
+//! emulation code sees a single encoding; other compressed instructions only
+//! keep their raw bits and length.
+
+use core::fmt;
+
+const OPCODE_LOAD: u32 = 0b000_0011;
+const OPCODE_LOAD_FP: u32 = 0b000_0111;
+const OPCODE_STORE: u32 = 0b010_0011;
+const OPCODE_STORE_FP: u32 = 0b010_0111;
+const OPCODE_AMO: u32 = 0b010_1111;
+const OPCODE_SYSTEM: u32 = 0b111_0011;
+
+/// `funct3` of `csrrs`.
+pub const CSRRS: u32 = 0b010;
+
+/// Class of an instruction, with the access width in bytes where it has one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Integer load, sign extended unless `unsigned`.
+    Load { width: usize, unsigned: bool },
+    /// Floating point load.
+    LoadFp { width: usize },
+    /// Integer store.
+    Store { width: usize },
+    /// Floating point store.
+    StoreFp { width: usize },
+    /// Atomic memory operation, including `lr` and `sc`, by its `funct5`.
+    Amo { width: usize, funct5: u32 },
+    /// CSR access, by its `funct3`.
+    Csr { funct3: u32 },
+    /// Anything else.
+    Other,
+}
+
+/// One decoded instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Insn {
+    /// Bits as fetched, the upper half unused for compressed instructions.
+    raw: u32,
+    /// 32-bit encoding, 0 for compressed instructions that are not expanded.
+    bits: u32,
+}
+
+/// Length in bytes of the instruction whose first 16-bit parcel is `low`.
+#[inline]
+pub const fn length(low: u16) -> usize {
+    if low & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+#[inline]
+const fn field(bits: u32, low: u32, len: u32) -> u32 {
+    (bits >> low) & ((1 << len) - 1)
+}
+
+const fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    imm << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+const fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    (imm >> 5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | opcode
+}
+
+/// 32-bit form of the compressed load or store `c`, or 0.
+const fn expand(c: u32) -> u32 {
+    // Registers `x8` to `x15` in the three bit fields of quadrant 0.
+    let rd_short = field(c, 2, 3) + 8;
+    let rs1_short = field(c, 7, 3) + 8;
+    let rd = field(c, 7, 5);
+    let rs2 = field(c, 2, 5);
+    // Offsets scaled by 4 and by 8, in quadrant 0 and from `sp` in quadrant 2.
+    let word = field(c, 10, 3) << 3 | field(c, 6, 1) << 2 | field(c, 5, 1) << 6;
+    let double = field(c, 10, 3) << 3 | field(c, 5, 2) << 6;
+    let word_sp_load = field(c, 12, 1) << 5 | field(c, 4, 3) << 2 | field(c, 2, 2) << 6;
+    let double_sp_load = field(c, 12, 1) << 5 | field(c, 5, 2) << 3 | field(c, 2, 3) << 6;
+    let word_sp_store = field(c, 9, 4) << 2 | field(c, 7, 2) << 6;
+    let double_sp_store = field(c, 10, 3) << 3 | field(c, 7, 3) << 6;
+    match (field(c, 0, 2), field(c, 13, 3)) {
+        // c.fld
+        (0b00, 0b001) => i_type(double, rs1_short, 3, rd_short, OPCODE_LOAD_FP),
+        // c.lw
+        (0b00, 0b010) => i_type(word, rs1_short, 2, rd_short, OPCODE_LOAD),
+        // c.ld
+        #[cfg(target_pointer_width = "64")]
+        (0b00, 0b011) => i_type(double, rs1_short, 3, rd_short, OPCODE_LOAD),
+        // c.flw
+        #[cfg(target_pointer_width = "32")]
+        (0b00, 0b011) => i_type(word, rs1_short, 2, rd_short, OPCODE_LOAD_FP),
+        // c.fsd
+        (0b00, 0b101) => s_type(double, rd_short, rs1_short, 3, OPCODE_STORE_FP),
+        // c.sw
+        (0b00, 0b110) => s_type(word, rd_short, rs1_short, 2, OPCODE_STORE),
+        // c.sd
+        #[cfg(target_pointer_width = "64")]
+        (0b00, 0b111) => s_type(double, rd_short, rs1_short, 3, OPCODE_STORE),
+        // c.fsw
+        #[cfg(target_pointer_width = "32")]
+        (0b00, 0b111) => s_type(word, rd_short, rs1_short, 2, OPCODE_STORE_FP),
+        // c.fldsp
+        (0b10, 0b001) => i_type(double_sp_load, 2, 3, rd, OPCODE_LOAD_FP),
+        // c.lwsp, reserved with `rd` zero
+        (0b10, 0b010) if rd != 0 => i_type(word_sp_load, 2, 2, rd, OPCODE_LOAD),
+        // c.ldsp, reserved with `rd` zero
+        #[cfg(target_pointer_width = "64")]
+        (0b10, 0b011) if rd != 0 => i_type(double_sp_load, 2, 3, rd, OPCODE_LOAD),
+        // c.flwsp
+        #[cfg(target_pointer_width = "32")]
+        (0b10, 0b011) => i_type(word_sp_load, 2, 2, rd, OPCODE_LOAD_FP),
+        // c.fsdsp
+        (0b10, 0b101) => s_type(double_sp_store, rs2, 2, 3, OPCODE_STORE_FP),
+        // c.swsp
+        (0b10, 0b110) => s_type(word_sp_store, rs2, 2, 2, OPCODE_STORE),
+        // c.sdsp
+        #[cfg(target_pointer_width = "64")]
+        (0b10, 0b111) => s_type(double_sp_store, rs2, 2, 3, OPCODE_STORE),
+        // c.fswsp
+        #[cfg(target_pointer_width = "32")]
+        (0b10, 0b111) => s_type(word_sp_store, rs2, 2, 2, OPCODE_STORE_FP),
+        _ => 0,
+    }
+}
+
+impl Insn {
+    /// Decode the instruction fetched as `raw`, its first parcel in the low half.
+    pub const fn new(raw: u32) -> Self {
+        if length(raw as u16) == 4 {
+            Self { raw, bits: raw }
+        } else {
+            let raw = raw & 0xffff;
+            Self {
+                raw,
+                bits: expand(raw),
+            }
+        }
+    }
+
+    /// Length in bytes, the distance to the next instruction.
+    #[inline]
+    pub const fn size(&self) -> usize {
+        length(self.raw as u16)
+    }
+
+    /// True for compressed instructions.
+    #[inline]
+    pub const fn is_compressed(&self) -> bool {
+        self.size() == 2
+    }
+
+    /// Opcode of the 32-bit form.
+    #[inline]
+    pub const fn opcode(&self) -> u32 {
+        field(self.bits, 0, 7)
+    }
+
+    #[inline]
+    pub const fn rd(&self) -> u32 {
+        field(self.bits, 7, 5)
+    }
+
+    #[inline]
+    pub const fn funct3(&self) -> u32 {
+        field(self.bits, 12, 3)
+    }
+
+    #[inline]
+    pub const fn rs1(&self) -> u32 {
+        field(self.bits, 15, 5)
+    }
+
+    #[inline]
+    pub const fn rs2(&self) -> u32 {
+        field(self.bits, 20, 5)
+    }
+
+    #[inline]
+    pub const fn funct7(&self) -> u32 {
+        field(self.bits, 25, 7)
+    }
+
+    /// CSR number of a CSR access.
+    #[inline]
+    pub const fn csr(&self) -> u32 {
+        field(self.bits, 20, 12)
+    }
+
+    /// Sign extended immediate of an I-type instruction.
+    #[inline]
+    pub const fn imm_i(&self) -> isize {
+        (self.bits as i32 >> 20) as isize
+    }
+
+    /// Sign extended immediate of an S-type instruction.
+    #[inline]
+    pub const fn imm_s(&self) -> isize {
+        ((self.bits as i32 >> 25) << 5 | field(self.bits, 7, 5) as i32) as isize
+    }
+
+    /// Address offset of a load or store, 0 for AMOs.
+    pub fn offset(&self) -> isize {
+        match self.op() {
+            Op::Load { .. } | Op::LoadFp { .. } => self.imm_i(),
+            Op::Store { .. } | Op::StoreFp { .. } => self.imm_s(),
+            _ => 0,
+        }
+    }
+
+    /// Class of the instruction.
+    pub fn op(&self) -> Op {
+        let width = 1 << (self.funct3() & 0b11);
+        match (self.opcode(), self.funct3()) {
+            (OPCODE_LOAD, 0b000..=0b011) => Op::Load {
+                width,
+                unsigned: false,
+            },
+            (OPCODE_LOAD, 0b100..=0b110) => Op::Load {
+                width,
+                unsigned: true,
+            },
+            (OPCODE_LOAD_FP, 0b001..=0b100) => Op::LoadFp {
+                width: 1 << self.funct3(),
+            },
+            (OPCODE_STORE, 0b000..=0b011) => Op::Store { width },
+            (OPCODE_STORE_FP, 0b001..=0b100) => Op::StoreFp {
+                width: 1 << self.funct3(),
+            },
+            (OPCODE_AMO, 0b010 | 0b011) => Op::Amo {
+                width,
+                funct5: self.funct7() >> 2,
+            },
+            (OPCODE_SYSTEM, funct3) if funct3 & 0b11 != 0 => Op::Csr { funct3 },
+            _ => Op::Other,
+        }
+    }
+}
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_compressed() {
+            write!(f, "{:#06x}", self.raw)?;
+        } else {
+            write!(f, "{:#010x}", self.raw)?;
+        }
+        match self.op() {
+            Op::Other => Ok(()),
+            op => write!(
+                f,
+                " ({:?}, rd x{}, rs1 x{}, rs2 x{}, offset {})",
+                op,
+                self.rd(),
+                self.rs1(),
+                self.rs2(),
+                self.offset()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An encoding from LLVM, with the fields it must decode to.
+    struct Case {
+        asm: &'static str,
+        raw: u32,
+        op: Op,
+        /// `rd`, `rs1` and `rs2`, `None` where the instruction has no such field.
+        regs: [Option<u32>; 3],
+        offset: isize,
+    }
+
+    const COMMON: &[Case] = &[
+        Case {
+            asm: "lw s1, -4(a5)",
+            raw: 0xffc7_a483,
+            op: Op::Load {
+                width: 4,
+                unsigned: false,
+            },
+            regs: [Some(9), Some(15), None],
+            offset: -4,
+        },
+        Case {
+            asm: "lbu t0, 2047(a1)",
+            raw: 0x7ff5_c283,
+            op: Op::Load {
+                width: 1,
+                unsigned: true,
+            },
+            regs: [Some(5), Some(11), None],
+            offset: 2047,
+        },
+        Case {
+            asm: "lh t6, -2048(sp)",
+            raw: 0x8001_1f83,
+            op: Op::Load {
+                width: 2,
+                unsigned: false,
+            },
+            regs: [Some(31), Some(2), None],
+            offset: -2048,
+        },
+        Case {
+            asm: "sd s11, 16(a0)",
+            raw: 0x01b5_3823,
+            op: Op::Store { width: 8 },
+            regs: [None, Some(10), Some(27)],
+            offset: 16,
+        },
+        Case {
+            asm: "sb zero, -1(t6)",
+            raw: 0xfe0f_8fa3,
+            op: Op::Store { width: 1 },
+            regs: [None, Some(31), Some(0)],
+            offset: -1,
+        },
+        Case {
+            asm: "fsw ft1, 12(a2)",
+            raw: 0x0016_2627,
+            op: Op::StoreFp { width: 4 },
+            regs: [None, Some(12), Some(1)],
+            offset: 12,
+        },
+        Case {
+            asm: "amoadd.w a0, a1, (a2)",
+            raw: 0x00b6_252f,
+            op: Op::Amo {
+                width: 4,
+                funct5: 0b00000,
+            },
+            regs: [Some(10), Some(12), Some(11)],
+            offset: 0,
+        },
+        Case {
+            asm: "lr.d t0, (s1)",
+            raw: 0x1004_b2af,
+            op: Op::Amo {
+                width: 8,
+                funct5: 0b00010,
+            },
+            regs: [Some(5), Some(9), Some(0)],
+            offset: 0,
+        },
+        Case {
+            asm: "sc.d t1, t2, (s1)",
+            raw: 0x1874_b32f,
+            op: Op::Amo {
+                width: 8,
+                funct5: 0b00011,
+            },
+            regs: [Some(6), Some(9), Some(7)],
+            offset: 0,
+        },
+        Case {
+            asm: "rdtime s3",
+            raw: 0xc010_29f3,
+            op: Op::Csr { funct3: CSRRS },
+            regs: [Some(19), Some(0), None],
+            offset: 0,
+        },
+        Case {
+            asm: "csrw sscratch, t0",
+            raw: 0x1402_9073,
+            op: Op::Csr { funct3: 0b001 },
+            regs: [Some(0), Some(5), None],
+            offset: 0,
+        },
+        Case {
+            asm: "add a0, a1, a2",
+            raw: 0x00c5_8533,
+            op: Op::Other,
+            regs: [Some(10), Some(11), Some(12)],
+            offset: 0,
+        },
+        Case {
+            asm: "c.lw s1, 124(a5)",
+            raw: 0x5fe4,
+            op: Op::Load {
+                width: 4,
+                unsigned: false,
+            },
+            regs: [Some(9), Some(15), None],
+            offset: 124,
+        },
+        Case {
+            asm: "c.sw a2, 0(a3)",
+            raw: 0xc290,
+            op: Op::Store { width: 4 },
+            regs: [None, Some(13), Some(12)],
+            offset: 0,
+        },
+        Case {
+            asm: "c.fld fa1, 16(a0)",
+            raw: 0x290c,
+            op: Op::LoadFp { width: 8 },
+            regs: [Some(11), Some(10), None],
+            offset: 16,
+        },
+        Case {
+            asm: "c.fld fa0, 24(s0)",
+            raw: 0x2c08,
+            op: Op::LoadFp { width: 8 },
+            regs: [Some(10), Some(8), None],
+            offset: 24,
+        },
+        Case {
+            asm: "c.lwsp t6, 252(sp)",
+            raw: 0x5ffe,
+            op: Op::Load {
+                width: 4,
+                unsigned: false,
+            },
+            regs: [Some(31), Some(2), None],
+            offset: 252,
+        },
+        Case {
+            asm: "c.swsp a7, 4(sp)",
+            raw: 0xc246,
+            op: Op::Store { width: 4 },
+            regs: [None, Some(2), Some(17)],
+            offset: 4,
+        },
+        Case {
+            asm: "c.fsdsp fs0, 0(sp)",
+            raw: 0xa022,
+            op: Op::StoreFp { width: 8 },
+            regs: [None, Some(2), Some(8)],
+            offset: 0,
+        },
+        Case {
+            asm: "c.addi a0, 1",
+            raw: 0x0505,
+            op: Op::Other,
+            regs: [None, None, None],
+            offset: 0,
+        },
+    ];
+
+    #[cfg(target_pointer_width = "64")]
+    const XLEN: &[Case] = &[
+        Case {
+            asm: "c.ld a0, 8(a1)",
+            raw: 0x6588,
+            op: Op::Load {
+                width: 8,
+                unsigned: false,
+            },
+            regs: [Some(10), Some(11), None],
+            offset: 8,
+        },
+        Case {
+            asm: "c.ldsp ra, 504(sp)",
+            raw: 0x70fe,
+            op: Op::Load {
+                width: 8,
+                unsigned: false,
+            },
+            regs: [Some(1), Some(2), None],
+            offset: 504,
+        },
+        Case {
+            asm: "c.sd a4, 248(s0)",
+            raw: 0xfc78,
+            op: Op::Store { width: 8 },
+            regs: [None, Some(8), Some(14)],
+            offset: 248,
+        },
+        Case {
+            asm: "c.sdsp s0, 8(sp)",
+            raw: 0xe422,
+            op: Op::Store { width: 8 },
+            regs: [None, Some(2), Some(8)],
+            offset: 8,
+        },
+    ];
+
+    #[cfg(target_pointer_width = "32")]
+    const XLEN: &[Case] = &[];
+
+    #[test]
+    fn decodes_the_encoding_table() {
+        for case in COMMON.iter().chain(XLEN) {
+            let insn = Insn::new(case.raw);
+            let size = if case.raw >> 16 == 0 { 2 } else { 4 };
+            assert_eq!(insn.size(), size, "{}", case.asm);
+            assert_eq!(insn.op(), case.op, "{}", case.asm);
+            let [rd, rs1, rs2] = case.regs;
+            assert_eq!(rd.map(|_| insn.rd()), rd, "{}", case.asm);
+            assert_eq!(rs1.map(|_| insn.rs1()), rs1, "{}", case.asm);
+            assert_eq!(rs2.map(|_| insn.rs2()), rs2, "{}", case.asm);
+            assert_eq!(insn.offset(), case.offset, "{}", case.asm);
+        }
+    }
+
+    #[test]
+    fn decodes_csr_numbers() {
+        // rdtime s3
+        assert_eq!(Insn::new(0xc010_29f3).csr(), 0xc01);
+        // csrw sscratch, t0
+        assert_eq!(Insn::new(0x1402_9073).csr(), 0x140);
+    }
+
+    #[test]
+    fn keeps_the_first_parcel_of_compressed_instructions() {
+        // c.lw s1, 124(a5), followed by the low half of another instruction.
+        let insn = Insn::new(0x1234_5fe4);
+        assert!(insn.is_compressed());
+        assert_eq!(
+            insn.op(),
+            Op::Load {
+                width: 4,
+                unsigned: false
+            }
+        );
+        // c.lwsp with `rd` zero is reserved.
+        assert_eq!(Insn::new(0x4002).op(), Op::Other);
+    }
+}
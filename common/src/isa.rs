@@ -0,0 +1,227 @@
+//! ISA extensions named in the device tree.
+
+/// Declare the ISA extensions probed from the device tree.
+///
+/// Each entry names the variant and its lower case device tree spelling,
+/// which is all a new extension needs.
+macro_rules! extension_table {
+    ($($name:ident => $isa:literal,)*) => {
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub enum Extension {
+            $($name,)*
+        }
+
+        impl Extension {
+            const ITER: &'static [Self] = &[$(Extension::$name,)*];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Extension::$name => $isa,)*
+                }
+            }
+        }
+    };
+}
+
+extension_table! {
+    Sstc => "sstc",
+    Sscofpmf => "sscofpmf",
+    Svpbmt => "svpbmt",
+    Svnapot => "svnapot",
+    Smcdeleg => "smcdeleg",
+    Ssccfg => "ssccfg",
+    Smstateen => "smstateen",
+    Smnpm => "smnpm",
+    Smrnmi => "smrnmi",
+    Smepmp => "smepmp",
+    Smaia => "smaia",
+    Zkr => "zkr",
+    Zicfilp => "zicfilp",
+    Zicfiss => "zicfiss",
+    Zicbom => "zicbom",
+    Zicboz => "zicboz",
+    Zawrs => "zawrs",
+}
+
+const _: () = assert!(Extension::ITER.len() <= u64::BITS as usize);
+
+impl Extension {
+    #[inline]
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// Look up an extension by name, ignoring case and a trailing version.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let find = |name: &str| {
+            Extension::ITER
+                .iter()
+                .copied()
+                .find(|ext| ext.as_str().eq_ignore_ascii_case(name))
+        };
+        find(name).or_else(|| find(trim_version(name)))
+    }
+}
+
+/// Strip a `<major>[p<minor>]` version suffix from an extension name.
+fn trim_version(name: &str) -> &str {
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let name = match name.strip_suffix(['p', 'P']) {
+        Some(major) if major.ends_with(|c: char| c.is_ascii_digit()) => major,
+        _ => name,
+    };
+    name.trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+/// Set of extensions a hart supports.
+#[derive(Copy, Clone, Default)]
+pub struct ExtensionSet(u64);
+
+impl ExtensionSet {
+    #[inline]
+    pub const fn empty() -> Self {
+        ExtensionSet(0)
+    }
+
+    #[inline]
+    pub fn insert(&mut self, ext: Extension) {
+        self.0 |= 1 << ext.index();
+    }
+
+    #[inline]
+    pub fn contains(&self, ext: Extension) -> bool {
+        self.0 & (1 << ext.index()) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Extension> + '_ {
+        Extension::ITER
+            .iter()
+            .copied()
+            .filter(|&ext| self.contains(ext))
+    }
+
+    /// Extensions named in a `riscv,isa-extensions` list.
+    pub fn from_names<'a>(names: impl Iterator<Item = &'a str>) -> Self {
+        let mut set = Self::empty();
+        names
+            .filter_map(Extension::from_name)
+            .for_each(|ext| set.insert(ext));
+        set
+    }
+
+    /// Extensions named in a `riscv,isa` string such as `rv64imac_zicbom_sstc`.
+    ///
+    /// Single letter extensions are not tracked. The first multi-letter one
+    /// may directly follow them, the others are separated by underscores.
+    pub fn from_isa(isa: &str) -> Self {
+        let mut segments = isa.split('_');
+        let base = segments.next().unwrap_or_default();
+        let letters = base
+            .get(..4)
+            .filter(|prefix| {
+                prefix.eq_ignore_ascii_case("rv32") || prefix.eq_ignore_ascii_case("rv64")
+            })
+            .map_or(base, |_| &base[4..]);
+        let first = letters
+            .find(['s', 'S', 'z', 'Z', 'x', 'X'])
+            .map(|at| &letters[at..]);
+        Self::from_names(first.into_iter().chain(segments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+
+    /// Segments no extension is named by.
+    const UNKNOWN: [&str; 6] = ["zfoo", "svendor", "xthead", "zba2", "smfoo1p0", ""];
+
+    fn names(set: ExtensionSet) -> Vec<Extension> {
+        set.iter().collect()
+    }
+
+    /// `name` in random case, with a random version suffix.
+    fn spelled(name: &'static str) -> impl Strategy<Value = String> {
+        let version = prop_oneof![
+            2 => Just(String::new()),
+            1 => (0..10u32).prop_map(|major| major.to_string()),
+            1 => (0..3u32, 0..10u32).prop_map(|(major, minor)| format!("{major}p{minor}")),
+        ];
+        (vec(prop::bool::weighted(0.25), name.len()), version).prop_map(move |(upper, version)| {
+            let spelled: String = name
+                .chars()
+                .zip(upper)
+                .map(|(c, upper)| match upper {
+                    true => c.to_ascii_uppercase(),
+                    false => c,
+                })
+                .collect();
+            spelled + &version
+        })
+    }
+
+    /// A segment of an ISA string, with the extension it names if any.
+    fn segment() -> impl Strategy<Value = (Option<Extension>, String)> {
+        prop_oneof![
+            1 => select(&UNKNOWN[..])
+                .prop_flat_map(|name| spelled(name).prop_map(|spelled| (None, spelled))),
+            2 => select(Extension::ITER).prop_flat_map(|ext| {
+                spelled(ext.as_str()).prop_map(move |spelled| (Some(ext), spelled))
+            }),
+        ]
+    }
+
+    /// The base ISA: an optional XLEN prefix and single letter extensions.
+    fn base() -> impl Strategy<Value = String> {
+        (
+            select(&["", "rv32", "rv64", "RV64"][..]),
+            vec(select(&['i', 'm', 'a', 'f', 'd', 'c', 'v', 'h'][..]), 0..6),
+        )
+            .prop_map(|(prefix, letters)| {
+                prefix.to_string() + &letters.into_iter().collect::<String>()
+            })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(4096))]
+
+        #[test]
+        fn finds_the_extensions_of_any_isa_string(
+            base in base(),
+            segments in vec(segment(), 0..8),
+            glued: bool,
+        ) {
+            let mut expected = ExtensionSet::empty();
+            for ext in segments.iter().filter_map(|(ext, _)| *ext) {
+                expected.insert(ext);
+            }
+            let segments: Vec<String> = segments.into_iter().map(|(_, spelled)| spelled).collect();
+            // The first multi-letter extension may follow the letters directly.
+            let isa = match segments.split_first() {
+                Some((first, rest)) if glued && !first.is_empty() => [base + first]
+                    .into_iter()
+                    .chain(rest.iter().cloned())
+                    .collect::<Vec<_>>(),
+                _ => [base].into_iter().chain(segments).collect(),
+            }
+            .join("_");
+            prop_assert_eq!(
+                names(ExtensionSet::from_isa(&isa)),
+                names(expected),
+                "{}",
+                isa
+            );
+        }
+
+        #[test]
+        fn names_round_trip(
+            (ext, spelled) in select(Extension::ITER)
+                .prop_flat_map(|ext| (Just(ext), spelled(ext.as_str())))
+        ) {
+            prop_assert_eq!(Extension::from_name(&spelled), Some(ext));
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Parts of RustSBI Prototyper that do not touch hardware.
+//!
+//! The firmware uses them as it does its own modules. Kept in a crate of
+//! their own, they are tested on the host with `cargo test -p prototyper-common`.
+#![cfg_attr(not(test), no_std)]
+
+pub mod fdt_fixup;
+pub mod fdt_reader;
+pub mod gzip;
+pub mod hart_mask;
+pub mod insn;
+pub mod isa;
+pub mod sbi_functions;
+#[cfg(test)]
+mod test_fdt;
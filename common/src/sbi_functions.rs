@@ -0,0 +1,109 @@
+//! Function IDs implemented by each SBI extension of the firmware.
+//!
+//! The firmware refuses calls past them before dispatch, and the test kernel
+//! checks that it does, both from this table.
+
+/// An extension with the number of function IDs implemented, from 0 up.
+pub struct Functions {
+    /// Name used in the disable lists, following the SBI specification.
+    pub name: &'static str,
+    pub eid: usize,
+    pub count: usize,
+}
+
+/// Every extension with function IDs, the legacy ones excepted.
+pub const IMPLEMENTED: [Functions; 12] = [
+    Functions {
+        name: "base",
+        eid: 0x10,
+        count: 7,
+    },
+    Functions {
+        name: "time",
+        eid: 0x5449_4D45,
+        count: 1,
+    },
+    Functions {
+        name: "spi",
+        eid: 0x0073_5049,
+        count: 1,
+    },
+    Functions {
+        name: "rfnc",
+        eid: 0x5246_4E43,
+        count: 7,
+    },
+    Functions {
+        name: "hsm",
+        eid: 0x0048_534D,
+        count: 4,
+    },
+    Functions {
+        name: "srst",
+        eid: 0x5352_5354,
+        count: 1,
+    },
+    Functions {
+        name: "susp",
+        eid: 0x5355_5350,
+        count: 1,
+    },
+    Functions {
+        name: "dbcn",
+        eid: 0x4442_434E,
+        count: 3,
+    },
+    Functions {
+        name: "fwft",
+        eid: 0x4657_4654,
+        count: 2,
+    },
+    Functions {
+        name: "debug",
+        eid: 0x0A52_5342,
+        count: 15,
+    },
+    Functions {
+        name: "update",
+        eid: 0x0A52_5355,
+        count: 4,
+    },
+    Functions {
+        name: "entropy",
+        eid: 0x0A52_5345,
+        count: 1,
+    },
+];
+
+/// Number of function IDs extension `eid` implements, `None` if it is not listed.
+pub const fn count(eid: usize) -> Option<usize> {
+    let mut index = 0;
+    while index < IMPLEMENTED.len() {
+        if IMPLEMENTED[index].eid == eid {
+            return Some(IMPLEMENTED[index].count);
+        }
+        index += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_each_extension_once() {
+        for (index, functions) in IMPLEMENTED.iter().enumerate() {
+            assert!(IMPLEMENTED[..index]
+                .iter()
+                .all(|other| other.eid != functions.eid));
+            assert_eq!(
+                count(functions.eid),
+                Some(functions.count),
+                "{}",
+                functions.name
+            );
+        }
+        assert_eq!(count(0x0A00_0000), None);
+    }
+}
@@ -0,0 +1,107 @@
+//! Device tree blobs built by tests.
+
+/// Builds a version 17 flattened device tree, node by node.
+pub struct Builder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    reserved: Vec<(u64, u64)>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            structure: Vec::new(),
+            strings: Vec::new(),
+            reserved: Vec::new(),
+        }
+    }
+
+    fn word(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        while self.structure.len() % 4 != 0 {
+            self.structure.push(0);
+        }
+    }
+
+    /// Open node `name`, the root node is named "".
+    pub fn begin(&mut self, name: &str) -> &mut Self {
+        self.word(0x1);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self
+    }
+
+    pub fn end(&mut self) -> &mut Self {
+        self.word(0x2);
+        self
+    }
+
+    pub fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        let name_offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.word(0x3);
+        self.word(value.len() as u32);
+        self.word(name_offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+        self
+    }
+
+    pub fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.prop(name, &value)
+    }
+
+    /// Add a property of NUL terminated strings.
+    pub fn prop_strs(&mut self, name: &str, strings: &[&str]) -> &mut Self {
+        let mut value = Vec::new();
+        for string in strings {
+            value.extend_from_slice(string.as_bytes());
+            value.push(0);
+        }
+        self.prop(name, &value)
+    }
+
+    /// Add an entry to the memory reservation block.
+    pub fn reserve(&mut self, address: u64, size: u64) -> &mut Self {
+        self.reserved.push((address, size));
+        self
+    }
+
+    /// The blob, followed by `spare` free bytes for fixups to grow into.
+    pub fn build(&mut self, spare: usize) -> Vec<u8> {
+        self.word(0x9);
+        let rsvmap = 40;
+        let structure = rsvmap + (self.reserved.len() + 1) * 16;
+        let strings = structure + self.structure.len();
+        let total = strings + self.strings.len();
+        let mut blob = Vec::with_capacity(total + spare);
+        for value in [
+            0xd00d_feed,
+            total as u32,
+            structure as u32,
+            strings as u32,
+            rsvmap as u32,
+            17,
+            16,
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            blob.extend_from_slice(&u32::to_be_bytes(value));
+        }
+        for &(address, size) in self.reserved.iter().chain([&(0, 0)]) {
+            blob.extend_from_slice(&address.to_be_bytes());
+            blob.extend_from_slice(&size.to_be_bytes());
+        }
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob.resize(total + spare, 0);
+        blob
+    }
+}
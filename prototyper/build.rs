@@ -1,25 +1,264 @@
-use std::{env, path::PathBuf};
+use std::{env, fmt::Write, path::PathBuf};
+
+/// Features a board manifest may switch on; none of them pull in dependencies.
+const MANIFEST_FEATURES: [&str; 8] = [
+    "legacy-sbi",
+    "timer-trace",
+    "sbi-trace",
+    "lockdep",
+    "boot-menu",
+    "memtest",
+    "fdt",
+    "no-lockdown",
+];
 
 fn main() {
     let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
     let ld = &out.join("rustsbi-prototyper.ld");
 
-    std::fs::write(ld, LINKER_SCRIPT).unwrap();
+    let config = PlatformConfig::load();
+    let script = LINKER_SCRIPT
+        .replace("{MEMORY_BASE}", &format!("{:#x}", config.memory_base))
+        .replace("{PAYLOAD_BASE}", &format!("{:#x}", config.payload_base()));
+    std::fs::write(ld, script).unwrap();
+    std::fs::write(out.join("platform_config.rs"), config.generate()).unwrap();
+    for feature in &config.features {
+        println!("cargo:rustc-cfg=feature=\"{}\"", feature);
+    }
 
     println!("cargo:rerun-if-env-changed=RUST_LOG,PROTOTYPER_FDT,PROTOTYPER_IMAGE");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_COUNTER_POLICY");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_DISABLE_EXTENSIONS");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_SBI_SPEC");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_FAULT_THRESHOLD");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_EARLY_UART");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_BOOTARGS");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_LOG_PREFIX");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_BOOT_PROTOCOL");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_SEED_POLICY");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_BOOT_DELAY_MS");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_IOMMU_MODE");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_PCI_BUS_MASTER_OFF");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_PLATFORM");
+    println!("cargo:rerun-if-env-changed=PROTOTYPER_SUPERVISOR_BOOT_HART");
     println!("cargo:rustc-link-arg=-T{}", ld.display());
     println!("cargo:rustc-link-search={}", out.display());
 }
 
-const LINKER_SCRIPT: &[u8] = b"OUTPUT_ARCH(riscv)
+/// Board settings from `platform.toml`, see `platform.example.toml`.
+struct PlatformConfig {
+    memory_base: usize,
+    max_harts: usize,
+    arrival_timeout_ms: usize,
+    uart16550u8: Vec<String>,
+    uart16550u32: Vec<String>,
+    uartlite: Vec<String>,
+    features: Vec<String>,
+}
+
+/// Offset of the payload from the start of the firmware, the most the image may take.
+const PAYLOAD_OFFSET: usize = 0x20_0000;
+
+impl PlatformConfig {
+    /// Address the payload is linked at.
+    fn payload_base(&self) -> usize {
+        self.memory_base + PAYLOAD_OFFSET
+    }
+
+    /// Read the manifest named by `PROTOTYPER_PLATFORM`, or `platform.toml`
+    /// next to this script if present, falling back to QEMU virt defaults.
+    fn load() -> Self {
+        let path = match env::var_os("PROTOTYPER_PLATFORM") {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("platform.toml"),
+        };
+        println!("cargo:rerun-if-changed={}", path.display());
+        let mut config = PlatformConfig {
+            memory_base: 0x8000_0000,
+            max_harts: 8,
+            arrival_timeout_ms: 100,
+            uart16550u8: Vec::new(),
+            uart16550u32: Vec::new(),
+            uartlite: Vec::new(),
+            features: Vec::new(),
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            if env::var_os("PROTOTYPER_PLATFORM").is_some() {
+                panic!("cannot read platform manifest {}", path.display());
+            }
+            return config;
+        };
+        let fail = |key: &str, message: &str| -> ! {
+            panic!("{}: `{}`: {}", path.display(), key, message)
+        };
+        for (key, value) in
+            parse_manifest(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+        {
+            match (key.as_str(), value) {
+                ("memory.base", Value::Integer(base)) => {
+                    if base % 0x1000 != 0 {
+                        fail(&key, "must be page aligned");
+                    }
+                    config.memory_base = base;
+                }
+                ("harts.max", Value::Integer(max)) => {
+                    if !(1..=64).contains(&max) {
+                        fail(&key, "must be between 1 and 64");
+                    }
+                    config.max_harts = max;
+                }
+                ("harts.arrival-timeout-ms", Value::Integer(ms)) => {
+                    if ms == 0 {
+                        fail(&key, "must not be 0");
+                    }
+                    config.arrival_timeout_ms = ms;
+                }
+                ("console.uart16550u8", Value::Strings(list)) => config.uart16550u8 = list,
+                ("console.uart16550u32", Value::Strings(list)) => config.uart16550u32 = list,
+                ("console.uartlite", Value::Strings(list)) => config.uartlite = list,
+                ("features.enable", Value::Strings(list)) => {
+                    if let Some(unknown) = list
+                        .iter()
+                        .find(|f| !MANIFEST_FEATURES.contains(&f.as_str()))
+                    {
+                        fail(&key, &format!("unknown feature `{}`", unknown));
+                    }
+                    config.features = list;
+                }
+                _ => fail(&key, "unknown key or wrong value type"),
+            }
+        }
+        config
+    }
+
+    /// Source of the `config` module.
+    fn generate(&self) -> String {
+        let list = |items: &[String]| {
+            let quoted: Vec<_> = items.iter().map(|item| format!("{:?}", item)).collect();
+            format!("&[{}]", quoted.join(", "))
+        };
+        let mut source = String::from("// Generated by build.rs from the platform manifest.\n");
+        writeln!(
+            source,
+            "pub const MEMORY_BASE: usize = {:#x};",
+            self.memory_base
+        )
+        .unwrap();
+        writeln!(
+            source,
+            "pub const PAYLOAD_BASE: usize = {:#x};",
+            self.payload_base()
+        )
+        .unwrap();
+        writeln!(source, "pub const MAX_HARTS: usize = {};", self.max_harts).unwrap();
+        writeln!(
+            source,
+            "pub const ARRIVAL_TIMEOUT_MS: u64 = {};",
+            self.arrival_timeout_ms
+        )
+        .unwrap();
+        for (name, items) in [
+            ("UART16550U8_EXTRA", &self.uart16550u8),
+            ("UART16550U32_EXTRA", &self.uart16550u32),
+            ("UARTAXILITE_EXTRA", &self.uartlite),
+        ] {
+            writeln!(source, "pub const {}: &[&str] = {};", name, list(items)).unwrap();
+        }
+        source
+    }
+}
+
+enum Value {
+    Integer(usize),
+    Strings(Vec<String>),
+}
+
+/// Parse the subset of TOML the manifest uses: `[table]` headers and
+/// `key = value` lines, values being integers or arrays of strings on one
+/// line. Keys are returned as `table.key`, in order.
+fn parse_manifest(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut table = String::new();
+    let mut entries: Vec<(String, Value)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            table = name.trim().to_string();
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(error("expected `key = value`"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let value = if let Some(items) = value
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            let mut list = Vec::new();
+            for item in items
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+            {
+                let Some(item) = item
+                    .strip_prefix('"')
+                    .and_then(|rest| rest.strip_suffix('"'))
+                else {
+                    return Err(error("array items must be strings"));
+                };
+                list.push(item.to_string());
+            }
+            Value::Strings(list)
+        } else {
+            let digits = value.replace('_', "");
+            let parsed = match digits.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => digits.parse(),
+            };
+            Value::Integer(parsed.map_err(|_| error("expected an integer or a string array"))?)
+        };
+        let key = if table.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", table, key)
+        };
+        if entries.iter().any(|(existing, _)| *existing == key) {
+            return Err(error(&format!("duplicate key `{}`", key)));
+        }
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+/// Drop a `#` comment, keeping `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+const LINKER_SCRIPT: &str = "OUTPUT_ARCH(riscv)
 ENTRY(_start) 
 SECTIONS {
-    . = 0x80000000;
+    . = {MEMORY_BASE};
 
     . = ALIGN(0x1000); /* Need this to create proper sections */
 
     sbi_start = .;
     .text : ALIGN(0x1000) { 
+        KEEP(*(.text.head))
         *(.text.entry)
         *(.text .text.*)
     }
@@ -41,6 +280,8 @@ SECTIONS {
         __rel_dyn_end = .;
     }
 
+    /* Nothing writable or executable may share a page with read-only data. */
+    . = ALIGN(0x1000);
     sbi_rodata_end = .;
 
 	/*
@@ -54,16 +295,25 @@ SECTIONS {
         sbi_data_start = .;
         *(.data .data.*)
         *(.sdata .sdata.*)
+        *(.htif)
         . = ALIGN(0x1000); 
         sbi_data_end = .;
     }
     sidata = LOADADDR(.data);
 
+    /* A compressed payload, inflated to the payload address at boot. */
+    .payload.packed : ALIGN(8) {
+        sbi_payload_packed_start = .;
+        KEEP(*(.payload.packed))
+        sbi_payload_packed_end = .;
+    }
+
     .bss (NOLOAD) : ALIGN(0x1000) {  
         *(.bss.uninit)
         sbi_bss_start = .;
         *(.bss .bss.*)
         *(.sbss .sbss.*)
+        . = ALIGN(8);
         sbi_bss_end = .;
     } 
     /DISCARD/ : {
@@ -73,7 +323,17 @@ SECTIONS {
 	. = ALIGN(0x1000); /* Need this to create proper sections */
     sbi_end = .;
 
-    .text 0x80200000 : ALIGN(0x1000) {
+    /* The PMP entries split the image at these boundaries, see `set_pmp`. */
+    ASSERT(sbi_start % 0x1000 == 0, \"firmware start is not page aligned\")
+    ASSERT(sbi_rodata_start % 0x1000 == 0, \"firmware text does not end on a page\")
+    ASSERT(sbi_rodata_end % 0x1000 == 0, \"firmware read-only data does not end on a page\")
+    ASSERT(sbi_data_start >= sbi_rodata_end, \"writable data overlaps read-only sections\")
+    ASSERT(ADDR(.bss) >= sbi_data_end, \"bss overlaps initialized data\")
+    ASSERT(sbi_end <= {PAYLOAD_BASE}, \"firmware image runs into the payload\")
+
+    .text {PAYLOAD_BASE} : ALIGN(0x1000) {
+        sbi_payload_start = .;
         *(.payload)
+        sbi_payload_end = .;
     }
 }";
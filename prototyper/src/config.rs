@@ -0,0 +1,8 @@
+//! Board constants from the platform manifest.
+//!
+//! `build.rs` reads `platform.toml`, or the file named by
+//! `PROTOTYPER_PLATFORM`, and generates this module; see
+//! `platform.example.toml` for the keys. Without a manifest every constant
+//! keeps its QEMU virt default.
+
+include!(concat!(env!("OUT_DIR"), "/platform_config.rs"));
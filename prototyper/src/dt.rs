@@ -24,13 +24,27 @@ pub struct Tree<'a> {
 #[serde(rename_all = "kebab-case")]
 pub struct Chosen<'a> {
     /// Path to stdout device.
-    pub stdout_path: StrSeq<'a>,
+    pub stdout_path: Option<StrSeq<'a>>,
+    /// SBI extensions the firmware should report as absent.
+    #[serde(rename = "rustsbi,disable-extensions")]
+    pub disable_extensions: Option<StrSeq<'a>>,
+    /// SBI specification version to advertise, `2.0` or `3.0`.
+    #[serde(rename = "rustsbi,sbi-spec-version")]
+    pub sbi_spec_version: Option<StrSeq<'a>>,
+    /// Test and scrub RAM at cold boot when present.
+    #[serde(rename = "rustsbi,memtest")]
+    pub memtest: Option<StrSeq<'a>>,
+    /// Hart the next stage must boot on.
+    #[serde(rename = "rustsbi,supervisor-boot-hart")]
+    pub supervisor_boot_hart: Option<u32>,
 }
 
 /// CPU information container.
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Cpus<'a> {
+    /// Frequency of the mtime counter in Hz.
+    pub timebase_frequency: Option<u32>,
     /// Sequence of CPU nodes.
     pub cpu: NodeSeq<'a>,
 }
@@ -43,8 +57,27 @@ pub struct Cpu<'a> {
     pub isa_extensions: Option<StrSeq<'a>>,
     #[serde(rename = "riscv,isa")]
     pub isa: Option<StrSeq<'a>>,
+    /// Size of Zicbom cache blocks in bytes.
+    #[serde(rename = "riscv,cbom-block-size")]
+    pub cbom_block_size: Option<u32>,
+    /// Size of Zicboz cache blocks in bytes.
+    #[cfg(feature = "fast-mem")]
+    #[serde(rename = "riscv,cboz-block-size")]
+    pub cboz_block_size: Option<u32>,
     /// CPU register information.
     pub reg: Reg<'a>,
+    /// NUMA node of this CPU.
+    #[serde(rename = "numa-node-id")]
+    pub numa_node_id: Option<u32>,
+    /// Local interrupt controller of this CPU.
+    #[serde(rename = "interrupt-controller")]
+    pub interrupt_controller: Option<CpuIntc>,
+}
+
+/// Local interrupt controller of a CPU, the parent of per-hart interrupts.
+#[derive(Deserialize, Debug)]
+pub struct CpuIntc {
+    pub phandle: Option<u32>,
 }
 
 /// Generic device node information.
@@ -60,9 +93,12 @@ pub struct Device<'a> {
 #[serde(rename_all = "kebab-case")]
 pub struct Memory<'a> {
     pub reg: Reg<'a>,
+    /// NUMA node of all ranges of this node.
+    pub numa_node_id: Option<u32>,
 }
 
 /// Errors that can occur during device tree parsing.
+#[derive(Debug)]
 pub enum ParseDeviceTreeError {
     /// Invalid device tree format.
     Format,
@@ -76,10 +112,13 @@ pub fn parse_device_tree(opaque: usize) -> Result<Dtb, ParseDeviceTreeError> {
     Ok(dtb)
 }
 
+pub fn get_compatible<'de>(node: &Node) -> Option<StrSeq<'de>> {
+    node.get_prop("compatible")
+        .map(|prop_item| prop_item.deserialize::<StrSeq<'de>>())
+}
+
 pub fn get_compatible_and_range<'de>(node: &Node) -> Option<(StrSeq<'de>, Range<usize>)> {
-    let compatible = node
-        .get_prop("compatible")
-        .map(|prop_item| prop_item.deserialize::<StrSeq<'de>>());
+    let compatible = get_compatible(node);
     let regs = node
         .get_prop("reg")
         .map(|prop_item| {
@@ -100,3 +139,79 @@ pub fn get_compatible_and_range<'de>(node: &Node) -> Option<(StrSeq<'de>, Range<
         None
     }
 }
+
+/// Hart whose CPU node holds the local interrupt controller `phandle`.
+pub fn intc_hart(cpus: &NodeSeq, phandle: u32) -> Option<usize> {
+    cpus.iter().find_map(|cpu| {
+        let cpu = cpu.deserialize::<Cpu>();
+        if cpu.interrupt_controller?.phandle? != phandle {
+            return None;
+        }
+        Some(cpu.reg.iter().next()?.0.start)
+    })
+}
+
+/// Call `f` with the hart and the interrupt number of each entry of the
+/// `interrupts-extended` property of `node`, in order. The hart is `None`
+/// where the entry's controller is not a CPU local one.
+///
+/// Entries are taken as one phandle and one cell, as CPU local controllers
+/// have. Returns false if `node` has no such property.
+pub fn for_each_hart_interrupt(
+    node: &Node,
+    cpus: &NodeSeq,
+    mut f: impl FnMut(Option<usize>, u32),
+) -> bool {
+    let Some(prop_item) = node.get_prop("interrupts-extended") else {
+        return false;
+    };
+    for entry in prop_item.deserialize::<&[u8]>().chunks_exact(8) {
+        let phandle = u32::from_be_bytes(entry[..4].try_into().unwrap());
+        let irq = u32::from_be_bytes(entry[4..].try_into().unwrap());
+        f(intc_hart(cpus, phandle), irq);
+    }
+    true
+}
+
+/// A single cell property of `node`.
+pub fn get_u32(node: &Node, name: &str) -> Option<u32> {
+    node.get_prop(name)
+        .map(|prop_item| prop_item.deserialize::<u32>())
+}
+
+/// An address property of `node` of one or two cells.
+pub fn get_address(node: &Node, name: &str) -> Option<usize> {
+    let cells = node
+        .get_prop(name)
+        .map(|prop_item| prop_item.deserialize::<&[u8]>())?;
+    match cells.len() {
+        4 => Some(u32::from_be_bytes(cells.try_into().unwrap()) as usize),
+        8 => Some(u64::from_be_bytes(cells.try_into().unwrap()) as usize),
+        _ => None,
+    }
+}
+
+/// Cell `index` of a property of `node`.
+pub fn get_cell(node: &Node, name: &str, index: usize) -> Option<u32> {
+    let cells = node
+        .get_prop(name)
+        .map(|prop_item| prop_item.deserialize::<&[u8]>())?;
+    cells
+        .chunks_exact(4)
+        .nth(index)
+        .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+}
+
+/// Controller phandle and request line of the DMA channel `name` of `node`,
+/// for controllers with one cell of `#dma-cells`.
+pub fn get_dma_request(node: &Node, name: &str) -> Option<(u32, u32)> {
+    let index = node
+        .get_prop("dma-names")
+        .map(|prop_item| prop_item.deserialize::<StrSeq>())?
+        .iter()
+        .position(|dma_name| dma_name == name)?;
+    Some((
+        get_cell(node, "dmas", index * 2)?,
+        get_cell(node, "dmas", index * 2 + 1)?,
+    ))
+}
@@ -0,0 +1,104 @@
+//! Errors stopping the firmware from booting.
+
+use core::fmt::{self, Display, Formatter};
+use core::ops::Range;
+
+use crate::dt;
+use crate::firmware::boot_profile::Phase;
+
+/// An error of firmware initialization, with what it was working on.
+#[derive(Debug)]
+pub enum FwError {
+    /// The blob at the device tree address is not a device tree.
+    DeviceTreeFormat(dt::ParseDeviceTreeError),
+    /// The device tree root does not deserialize.
+    DeviceTreeRoot(serde_device_tree::error::Error),
+    /// The device tree has no memory node, or one without `reg`.
+    NoMemory,
+    /// A cpu node has no `reg`.
+    CpuWithoutReg,
+    /// More than one hart is enabled, but no device can send them IPIs.
+    NoIpi { harts: usize },
+    /// The logger was already set.
+    Logger,
+    /// The firmware image is not split into sections at pages.
+    SectionLayout {
+        text: Range<usize>,
+        rodata: Range<usize>,
+        data: Range<usize>,
+    },
+    /// A PMP entry did not read back as programmed.
+    PmpReadback {
+        hart_id: usize,
+        index: usize,
+        found: (Option<u8>, Option<usize>),
+        cfg: u8,
+        addr: usize,
+    },
+    /// `mseccfg` did not take the Smepmp lockdown bits.
+    Lockdown { hart_id: usize, mseccfg: usize },
+    /// The compressed payload does not inflate.
+    #[cfg(feature = "payload-gzip")]
+    Payload(crate::firmware::gzip::GzipError),
+}
+
+/// Result of firmware initialization steps.
+pub type FwResult<T = ()> = Result<T, FwError>;
+
+impl FwError {
+    /// Boot phase the error kept the boot hart from reaching.
+    pub fn phase(&self) -> Phase {
+        match self {
+            FwError::DeviceTreeFormat(_)
+            | FwError::DeviceTreeRoot(_)
+            | FwError::NoMemory
+            | FwError::CpuWithoutReg
+            | FwError::NoIpi { .. }
+            | FwError::Logger => Phase::Platform,
+            FwError::SectionLayout { .. } | FwError::PmpReadback { .. } => Phase::Pmp,
+            FwError::Lockdown { .. } => Phase::Jump,
+            #[cfg(feature = "payload-gzip")]
+            FwError::Payload(_) => Phase::HartRelease,
+        }
+    }
+}
+
+impl Display for FwError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FwError::DeviceTreeFormat(err) => write!(f, "malformed device tree: {:?}", err),
+            FwError::DeviceTreeRoot(err) => {
+                write!(f, "cannot deserialize device tree root: {:?}", err)
+            }
+            FwError::NoMemory => write!(f, "device tree describes no memory"),
+            FwError::CpuWithoutReg => write!(f, "device tree has a cpu node without reg"),
+            FwError::NoIpi { harts } => {
+                write!(f, "device tree enables {} harts but no IPI device", harts)
+            }
+            FwError::Logger => write!(f, "logger already initialized"),
+            FwError::SectionLayout { text, rodata, data } => write!(
+                f,
+                "firmware sections are not split at pages: text {:#x?}, rodata {:#x?}, data {:#x?}",
+                text, rodata, data
+            ),
+            FwError::PmpReadback {
+                hart_id,
+                index,
+                found,
+                cfg,
+                addr,
+            } => write!(
+                f,
+                "PMP entry {} of hart {} reads {:x?}, expected configuration {:#x} and address {:#x}",
+                index, hart_id, found, cfg, addr
+            ),
+            FwError::Lockdown { hart_id, mseccfg } => write!(
+                f,
+                "mseccfg of hart {} reads {:#x}, machine mode lockdown not enabled",
+                hart_id, mseccfg
+            ),
+            #[cfg(feature = "payload-gzip")]
+            FwError::Payload(err) => write!(f, "cannot inflate payload: {}", err),
+        }
+    }
+}
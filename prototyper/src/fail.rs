@@ -1,6 +1,7 @@
-use serde_device_tree::Dtb;
-
-use crate::dt;
+use crate::error::FwError;
+use crate::firmware::boot_profile;
+use crate::platform;
+use crate::riscv_spec::current_hartid;
 
 #[cfg(not(feature = "payload"))]
 use crate::firmware::dynamic;
@@ -9,22 +10,17 @@ use crate::sbi::reset;
 #[cfg(not(feature = "payload"))]
 use riscv::register::mstatus;
 
-// TODO: Need a better way to handle device tree parsing errors
-
-/// Handles device tree format parsing errors by logging and resetting.
-#[cold]
-pub fn device_tree_format(_err: dt::ParseDeviceTreeError) -> Dtb {
-    loop {
-        core::hint::spin_loop()
-    }
-}
-
+/// Report the init error that stopped the boot, then reset the system as
+/// failed if a reset device is up, or stop the hart otherwise.
 #[cold]
-pub fn device_tree_deserialize_root<'a>(
-    _err: serde_device_tree::error::Error,
-) -> serde_device_tree::buildin::Node<'a> {
-    loop {
-        core::hint::spin_loop()
+pub fn boot(err: FwError) -> ! {
+    error!("Boot failed on hart {}: {}", current_hartid(), err);
+    boot_profile::print_failure(err.phase());
+    match platform::reset() {
+        Some(reset) => reset.fail(),
+        None => loop {
+            core::hint::spin_loop()
+        },
     }
 }
 
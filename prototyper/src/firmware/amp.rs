@@ -0,0 +1,74 @@
+//! Asymmetric boot of domains with their own next stage.
+//!
+//! The boot hart enters the usual next stage. Every other domain naming a
+//! next stage has its boot hart started there at the same time, as if by a
+//! `hart_start` call, so for example harts 0 to 3 may run Linux while hart 4
+//! runs an RTOS image.
+
+use crate::firmware::fdt_domain;
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::current_hartid;
+use crate::sbi::domain;
+use crate::sbi::hart_context::NextStage;
+use crate::sbi::hart_init;
+use crate::sbi::hsm::{check_entry_address, remote_hsm};
+
+/// Start the boot hart of every other domain with a next stage of its own.
+pub fn start_domains(fdt_address: usize) {
+    let current_hart = current_hartid();
+    let domains = unsafe { PLATFORM.info.domains };
+    for (index, domain) in domains.iter().enumerate() {
+        let Some(domain) = domain else {
+            continue;
+        };
+        let Some(boot) = domain.boot else {
+            continue;
+        };
+        if domain.contains(current_hart) {
+            warn!(
+                "Domain {} holds the boot hart, which boots the usual next stage",
+                index
+            );
+            continue;
+        }
+        if domain::of_hart(boot.hart_id) != Some(index) {
+            warn!(
+                "Domain {} boot hart {} belongs to another domain",
+                index, boot.hart_id
+            );
+            continue;
+        }
+        if check_entry_address(boot.next_addr).is_err() {
+            error!(
+                "Domain {} next stage at {:#x} is not in supervisor memory",
+                index, boot.next_addr
+            );
+            continue;
+        }
+        let started = !hart_init::failed(boot.hart_id)
+            && remote_hsm(boot.hart_id).is_some_and(|remote| {
+                remote.start(NextStage {
+                    start_addr: boot.next_addr,
+                    next_mode: boot.next_mode,
+                    opaque: boot
+                        .arg1
+                        .or(fdt_domain::address(index))
+                        .unwrap_or(fdt_address),
+                })
+            });
+        if !started {
+            error!(
+                "Domain {} boot hart {} is not available",
+                index, boot.hart_id
+            );
+            continue;
+        }
+        if let Some(ipi) = platform::ipi() {
+            ipi.set_msip(boot.hart_id);
+        }
+        info!(
+            "Redirecting hart {} of domain {} to 0x{:0>16x} in {:?} mode.",
+            boot.hart_id, index, boot.next_addr, boot.next_mode
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Interactive boot menu over the firmware console.
+//!
+//! With the `boot-menu` feature the boot hart waits `PROTOTYPER_BOOT_DELAY_MS`
+//! milliseconds (default 3000) for a key press before entering the next
+//! stage. Pressing a key opens a menu of firmware actions, Ctrl-C goes
+//! straight to the firmware shell.
+
+use log::LevelFilter;
+
+use crate::firmware::{fdt_dump, shell};
+use crate::sbi::console;
+use crate::time;
+
+const DEFAULT_BOOT_DELAY_MS: u64 = 3000;
+/// Ctrl-C, the break key entering the shell.
+const BREAK_KEY: u8 = 0x03;
+
+/// Boot delay selected at build time.
+fn boot_delay_ms() -> u64 {
+    option_env!("PROTOTYPER_BOOT_DELAY_MS")
+        .and_then(|delay| delay.trim().parse().ok())
+        .unwrap_or(DEFAULT_BOOT_DELAY_MS)
+}
+
+/// Returns a key pressed on the console, without waiting.
+#[inline]
+fn try_read_key() -> Option<u8> {
+    match console::getchar() {
+        usize::MAX => None,
+        c => Some(c as u8),
+    }
+}
+
+/// Wait for a key press on the console.
+pub(crate) fn read_key() -> u8 {
+    loop {
+        if let Some(key) = try_read_key() {
+            return key;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn change_log_level() {
+    println!("Log level: [e]rror [w]arn [i]nfo [d]ebug [t]race [o]ff");
+    let level = match read_key() {
+        b'e' => LevelFilter::Error,
+        b'w' => LevelFilter::Warn,
+        b'i' => LevelFilter::Info,
+        b'd' => LevelFilter::Debug,
+        b't' => LevelFilter::Trace,
+        b'o' => LevelFilter::Off,
+        _ => return,
+    };
+    log::set_max_level(level);
+    println!("Log level set to {}", level);
+}
+
+/// Offer the boot menu if a key is pressed during the boot delay.
+///
+/// Returns once the user chooses to continue booting, or at once if no key
+/// came in time.
+pub fn run(fdt_address: usize) {
+    let delay_ms = boot_delay_ms();
+    if delay_ms == 0 {
+        return;
+    }
+    println!("Press any key within {} ms for the boot menu", delay_ms);
+    let deadline = time::Deadline::after_us(delay_ms * 1000);
+    loop {
+        match try_read_key() {
+            Some(BREAK_KEY) => {
+                shell::run(fdt_address);
+                return;
+            }
+            Some(_) => break,
+            None if deadline.expired() => return,
+            None => core::hint::spin_loop(),
+        }
+    }
+    loop {
+        println!("\n\rRustSBI Prototyper boot menu");
+        println!("  1) Continue boot");
+        println!("  2) Firmware shell");
+        println!("  3) Dump device tree");
+        println!("  4) Change log level");
+        match read_key() {
+            b'1' | b'\r' | b'\n' => return,
+            b'2' => {
+                shell::run(fdt_address);
+                return;
+            }
+            b'3' => {
+                if let Err(err) = fdt_dump::dump(fdt_address) {
+                    println!("Bad device tree: {:?}", err);
+                }
+            }
+            b'4' => change_log_level(),
+            _ => {}
+        }
+    }
+}
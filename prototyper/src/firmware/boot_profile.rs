@@ -0,0 +1,117 @@
+//! Boot time profiling.
+//!
+//! The boot hart stamps each boot phase with mtime and `mcycle`. mtime
+//! counts from reset on most platforms, so its values include the stages before
+//! the firmware; the timer is only found with the device tree, so the entry is
+//! placed by cycles alone. The summary is printed before the jump and stays
+//! readable through the debug extension.
+
+use riscv::register::mcycle;
+
+use crate::sync::Mutex;
+use crate::time;
+
+/// Steps of the boot hart, in order.
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    /// The boot hart entered `rust_main`.
+    Entry = 0,
+    /// Device tree parsed and platform devices probed.
+    Platform = 1,
+    /// PMP programmed.
+    Pmp = 2,
+    /// Every enabled hart came up or was given up on.
+    HartRelease = 3,
+    /// About to enter the next stage.
+    Jump = 4,
+}
+
+pub const PHASES: usize = 5;
+
+const NAMES: [&str; PHASES] = [
+    "Boot Phase Entry",
+    "Boot Phase Platform",
+    "Boot Phase PMP",
+    "Boot Phase Hart Release",
+    "Boot Phase Jump",
+];
+
+/// mtime and `mcycle` of each phase, mtime 0 while the timer was not found.
+#[derive(Clone, Copy)]
+struct Stamp {
+    ticks: u64,
+    cycles: u64,
+}
+
+static STAMPS: Mutex<[Option<Stamp>; PHASES]> = Mutex::named("boot profile", [None; PHASES]);
+
+/// Record that the boot hart reached `phase`.
+pub fn mark(phase: Phase) {
+    STAMPS.lock()[phase as usize] = Some(Stamp {
+        ticks: time::current_ticks(),
+        cycles: mcycle::read64(),
+    });
+}
+
+/// Microseconds since reset at phase `index`, if it was reached with a timer.
+pub fn elapsed_us(index: usize) -> Option<u64> {
+    let stamp = (*STAMPS.lock().get(index)?)?;
+    (stamp.ticks != 0).then(|| time::ticks_to_us(stamp.ticks))
+}
+
+/// `mcycle` at phase `index`, if it was reached.
+pub fn cycles(index: usize) -> Option<u64> {
+    STAMPS
+        .lock()
+        .get(index)
+        .copied()
+        .flatten()
+        .map(|stamp| stamp.cycles)
+}
+
+/// Print which phases were reached before the boot failed on the way to `failed`.
+pub fn print_failure(failed: Phase) {
+    let stamps = *STAMPS.lock();
+    for (index, (name, stamp)) in NAMES.iter().zip(stamps).enumerate() {
+        let state = match stamp {
+            _ if index == failed as usize => "FAILED",
+            Some(_) => "done",
+            None => "not reached",
+        };
+        error!("{:<30}: {}", name, state);
+    }
+}
+
+/// Print the time of every phase reached and its distance to the previous one.
+pub fn print_summary() {
+    let stamps = *STAMPS.lock();
+    let mut previous: Option<Stamp> = None;
+    for (name, stamp) in NAMES.iter().zip(stamps) {
+        let Some(stamp) = stamp else {
+            continue;
+        };
+        match previous {
+            _ if stamp.ticks == 0 => {
+                info!("{:<30}: mcycle {}", name, stamp.cycles);
+            }
+            Some(previous) if previous.ticks != 0 => info!(
+                "{:<30}: {} us since reset, +{} us",
+                name,
+                time::ticks_to_us(stamp.ticks),
+                time::ticks_to_us(stamp.ticks - previous.ticks)
+            ),
+            Some(previous) => info!(
+                "{:<30}: {} us since reset, +{} cycles",
+                name,
+                time::ticks_to_us(stamp.ticks),
+                stamp.cycles.wrapping_sub(previous.cycles)
+            ),
+            None => info!(
+                "{:<30}: {} us since reset",
+                name,
+                time::ticks_to_us(stamp.ticks)
+            ),
+        }
+        previous = Some(stamp);
+    }
+}
@@ -0,0 +1,75 @@
+//! Register state handed to the next stage.
+//!
+//! Every supported protocol receives `a0 = hartid` and `a1 = opaque` (the
+//! device tree address for the boot hart), with `satp = 0`, `sstatus.SIE = 0`,
+//! the device tree written back from the data cache and the instruction cache
+//! synchronized with memory. Protocols differ in what the remaining registers
+//! may hold. The protocol is chosen at build time with
+//! `PROTOTYPER_BOOT_PROTOCOL`:
+//!
+//! - `linux` (default): `Documentation/arch/riscv/boot.rst` asks for the hart
+//!   ID in `a0`, the device tree physical address in `a1` and the MMU off;
+//!   remaining registers are undefined.
+//! - `freebsd`: `_start` in `sys/riscv/riscv/locore.S` takes the hart ID in
+//!   `a0` and the device tree physical address in `a1`, and secondary harts
+//!   started through HSM enter `mpentry` with their hart ID in `a0`. Nothing
+//!   else is read; remaining registers are cleared so no firmware value
+//!   reaches it.
+//! - `zephyr`: `__reset` in `arch/riscv/core/reset.S` defines no arguments
+//!   and reads its hart ID from `mhartid`, which traps in S-mode. Only images
+//!   built to take the hart ID and device tree from `a0` and `a1` run from
+//!   this firmware; remaining registers are cleared, as Zephyr expects
+//!   nothing in them.
+
+use riscv::register::{satp, sstatus};
+
+use crate::firmware::cache;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootProtocol {
+    Linux,
+    FreeBsd,
+    Zephyr,
+}
+
+/// How a protocol expects to be entered.
+#[derive(Clone, Copy, Debug)]
+pub struct Handoff {
+    /// Clear every general purpose register other than `a0` and `a1`.
+    pub zero_registers: bool,
+}
+
+impl BootProtocol {
+    /// Returns the protocol selected at build time.
+    pub fn current() -> Self {
+        match option_env!("PROTOTYPER_BOOT_PROTOCOL") {
+            Some("freebsd") => BootProtocol::FreeBsd,
+            Some("zephyr") => BootProtocol::Zephyr,
+            _ => BootProtocol::Linux,
+        }
+    }
+
+    #[inline]
+    pub const fn handoff(self) -> Handoff {
+        match self {
+            BootProtocol::Linux => Handoff {
+                zero_registers: false,
+            },
+            BootProtocol::FreeBsd | BootProtocol::Zephyr => Handoff {
+                zero_registers: true,
+            },
+        }
+    }
+}
+
+/// Put supervisor state and caches in the shape every protocol expects, for
+/// a hart entering the next stage with `opaque` in `a1`.
+#[inline]
+pub fn prepare(opaque: usize) -> Handoff {
+    unsafe {
+        sstatus::clear_sie();
+        satp::write(0);
+    }
+    cache::prepare_hart_entry(opaque);
+    BootProtocol::current().handoff()
+}
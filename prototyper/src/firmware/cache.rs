@@ -0,0 +1,93 @@
+//! Cache maintenance before entering the next stage.
+//!
+//! Harts are not required to keep instruction fetch coherent with stores, and
+//! on some silicon the data written by the firmware (the fixed up device tree,
+//! a bundled payload) is not visible to bus masters or a cold starting hart
+//! until it is cleaned from the data cache.
+
+use core::arch::asm;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rustsbi::Fence;
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::extensions::{hart_cbom_block_size, hart_extension_probe, Extension};
+use crate::sbi::hart_mask;
+use crate::sbi::hsm::remote_hsm;
+use crate::sbi::rfence::SbiRFence;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// `mvendorid` of T-Head cores, which clean caches with vendor instructions.
+const THEAD_VENDOR_ID: usize = 0x5b7;
+/// Block size assumed when the device tree does not give one.
+const DEFAULT_CBOM_BLOCK_SIZE: usize = 64;
+/// T-Head L1 cache line size.
+const THEAD_CACHE_LINE: usize = 64;
+
+/// Address and size of the device tree handed to the next stage.
+static HANDOFF_FDT: (AtomicUsize, AtomicUsize) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+/// Write back the data cache lines covering `range`, where the hart can.
+pub fn clean_dcache_range(range: Range<usize>) {
+    let hart_id = current_hartid();
+    if hart_extension_probe(hart_id, Extension::Zicbom) {
+        let block = match hart_cbom_block_size(hart_id) {
+            0 => DEFAULT_CBOM_BLOCK_SIZE,
+            size => size,
+        };
+        for addr in (range.start & !(block - 1)..range.end).step_by(block) {
+            // cbo.clean (addr), CBO function 1 in the immediate
+            unsafe { asm!(".insn i 0x0f, 2, x0, {}, 1", in(reg) addr, options(nostack)) };
+        }
+        unsafe { asm!("fence rw, rw", options(nostack)) };
+    } else if riscv::register::mvendorid::read().map(|id| id.bits()) == Some(THEAD_VENDOR_ID) {
+        for addr in (range.start & !(THEAD_CACHE_LINE - 1)..range.end).step_by(THEAD_CACHE_LINE) {
+            // th.dcache.cpa a0
+            unsafe { asm!(".long 0x0295000b", in("a0") addr, options(nostack)) };
+        }
+        // th.sync.s
+        unsafe { asm!(".long 0x0190000b", options(nostack)) };
+    }
+}
+
+/// Make instruction fetch coherent on this hart and every started hart.
+pub fn sync_icache_all() {
+    unsafe { asm!("fence.i", options(nostack)) };
+    let current_hart = current_hartid();
+    let started = (0..NUM_HART_MAX).filter(|&hart_id| {
+        hart_id != current_hart && remote_hsm(hart_id).is_some_and(|hsm| hsm.allow_ipi())
+    });
+    if started.clone().next().is_some() {
+        SbiRFence.remote_fence_i(hart_mask::from_hart_ids(started));
+    }
+}
+
+/// Clean what the firmware wrote for the next stage and sync instruction caches.
+pub fn prepare_next_stage(fdt_address: usize) {
+    if let Some(size) = super::fdt_fixup::total_size(fdt_address) {
+        clean_dcache_range(fdt_address..fdt_address + size);
+        HANDOFF_FDT.1.store(size, Ordering::Relaxed);
+        HANDOFF_FDT.0.store(fdt_address, Ordering::Release);
+    }
+    for range in super::fdt_domain::copies() {
+        clean_dcache_range(range);
+    }
+    #[cfg(feature = "payload")]
+    clean_dcache_range(super::payload::payload_range());
+    sync_icache_all();
+}
+
+/// Cache maintenance of a hart about to enter the next stage with `opaque`
+/// in `a1`.
+///
+/// A hart entered with the device tree writes it back once more, so the
+/// tree is in memory whichever hart the next stage boots on. The next stage
+/// may have been loaded through the data side only, so instruction fetch is
+/// synchronized last.
+pub fn prepare_hart_entry(opaque: usize) {
+    let fdt_address = HANDOFF_FDT.0.load(Ordering::Acquire);
+    if fdt_address != 0 && opaque == fdt_address {
+        clean_dcache_range(fdt_address..fdt_address + HANDOFF_FDT.1.load(Ordering::Relaxed));
+    }
+    unsafe { asm!("fence.i", options(nostack)) };
+}
@@ -0,0 +1,69 @@
+//! Counter access policy for S-mode and U-mode.
+//!
+//! The policy is chosen at build time with `PROTOTYPER_COUNTER_POLICY`:
+//!
+//! - `all`: every counter is readable from S-mode and U-mode.
+//! - `standard` (default): S-mode may read every counter, U-mode may read
+//!   `cycle`, `time` and `instret`. Hardware performance counters stay
+//!   hidden from U-mode unless the supervisor opts in through `scounteren`.
+//! - `time`: only `time` is readable from S-mode and U-mode. Use this when
+//!   cycle-accurate counters are considered a side channel.
+
+use core::arch::asm;
+
+/// `cycle` enable bit in `mcounteren` / `scounteren`.
+const CY: usize = 1 << 0;
+/// `time` enable bit in `mcounteren` / `scounteren`.
+const TM: usize = 1 << 1;
+/// `instret` enable bit in `mcounteren` / `scounteren`.
+const IR: usize = 1 << 2;
+/// `hpmcounter3` to `hpmcounter31` enable bits.
+const HPM: usize = 0xffff_fff8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CounterPolicy {
+    All,
+    Standard,
+    TimeOnly,
+}
+
+impl CounterPolicy {
+    /// Returns the policy selected at build time.
+    pub fn current() -> Self {
+        match option_env!("PROTOTYPER_COUNTER_POLICY") {
+            Some("all") => CounterPolicy::All,
+            Some("time") => CounterPolicy::TimeOnly,
+            _ => CounterPolicy::Standard,
+        }
+    }
+
+    /// Counters S-mode may access.
+    #[inline]
+    pub const fn mcounteren(self) -> usize {
+        match self {
+            CounterPolicy::All | CounterPolicy::Standard => CY | TM | IR | HPM,
+            CounterPolicy::TimeOnly => TM,
+        }
+    }
+
+    /// Counters U-mode may access until the supervisor reprograms `scounteren`.
+    #[inline]
+    pub const fn scounteren(self) -> usize {
+        match self {
+            CounterPolicy::All => CY | TM | IR | HPM,
+            CounterPolicy::Standard => CY | TM | IR,
+            CounterPolicy::TimeOnly => TM,
+        }
+    }
+}
+
+/// Program `mcounteren` and `scounteren` of the current hart.
+///
+/// Both registers are WARL, so bits for counters the hart lacks are dropped.
+pub fn init() {
+    let policy = CounterPolicy::current();
+    unsafe {
+        asm!("csrw mcounteren, {}", in(reg) policy.mcounteren());
+        asm!("csrw scounteren, {}", in(reg) policy.scounteren());
+    }
+}
@@ -0,0 +1,81 @@
+//! Boot work that need not finish before the next stage starts.
+//!
+//! Printing the boot report over a 115200 baud console costs the boot hart
+//! tens of milliseconds. The boot hart posts such work here instead. The
+//! first secondary hart done with its own setup runs it while the boot hart
+//! goes on to the next stage, and the boot hart runs whatever is still left
+//! right before the jump, so nothing is lost without secondary harts.
+//!
+//! Jobs run holding the console, so a report is not split by lines of other
+//! harts, and the boot hart waits for jobs still running before the jump, so
+//! none prints over the next stage.
+
+use crate::sbi::console;
+use crate::sync::{Backoff, Mutex};
+
+/// Jobs that can be pending at once; posting more runs them right away.
+const MAX_JOBS: usize = 4;
+
+struct Jobs {
+    jobs: [Option<fn()>; MAX_JOBS],
+    /// Next job to run.
+    head: usize,
+    /// Slot the next job posted goes to.
+    tail: usize,
+    /// Jobs taken and not finished yet.
+    running: usize,
+}
+
+static JOBS: Mutex<Jobs> = Mutex::named(
+    "deferred jobs",
+    Jobs {
+        jobs: [None; MAX_JOBS],
+        head: 0,
+        tail: 0,
+        running: 0,
+    },
+);
+
+/// Run `job` on some hart later during boot, in posting order.
+pub fn post(job: fn()) {
+    let mut jobs = JOBS.lock();
+    if jobs.tail == MAX_JOBS {
+        drop(jobs);
+        console::hold(job);
+        return;
+    }
+    let tail = jobs.tail;
+    jobs.jobs[tail] = Some(job);
+    jobs.tail += 1;
+}
+
+/// Run the jobs posted so far that no hart has taken yet.
+///
+/// Jobs are taken one at a time, so harts calling this together share them.
+pub fn run_pending() {
+    loop {
+        let job = {
+            let mut jobs = JOBS.lock();
+            if jobs.head == jobs.tail {
+                return;
+            }
+            let head = jobs.head;
+            jobs.head += 1;
+            jobs.running += 1;
+            jobs.jobs[head].take()
+        };
+        if let Some(job) = job {
+            console::hold(job);
+        }
+        JOBS.lock().running -= 1;
+    }
+}
+
+/// Run the jobs no hart has taken yet and wait for the others to finish.
+pub fn finish() {
+    run_pending();
+    let mut backoff = Backoff::new();
+    while JOBS.lock().running != 0 {
+        backoff.spin();
+    }
+}
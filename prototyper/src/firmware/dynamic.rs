@@ -6,6 +6,7 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use super::BootInfo;
 use crate::fail;
 use crate::riscv_spec::current_hartid;
+use crate::START_ADDRESS;
 
 use riscv::register::mstatus;
 
@@ -62,7 +63,7 @@ pub struct DynamicInfo {
 // https://github.com/riscv-software-src/opensbi/blob/019a8e69a1dc0c0f011fabd0372e1ba80e40dd7c/include/sbi/fw_dynamic.h#L75
 
 const DYNAMIC_INFO_INVALID_ADDRESSES: usize = 0x00000000;
-const NEXT_ADDR_VALID_ADDRESSES: Range<usize> = 0x80000000..0x90000000;
+const NEXT_ADDR_VALID_ADDRESSES: Range<usize> = START_ADDRESS..START_ADDRESS + 0x10000000;
 pub(crate) const MAGIC: usize = 0x4942534f;
 const SUPPORTED_VERSION: Range<usize> = 0..3;
 
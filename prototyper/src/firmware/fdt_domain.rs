@@ -0,0 +1,177 @@
+//! Device trees of each domain.
+//!
+//! Every supervisor is handed a device tree showing only the hardware it
+//! owns: cpus of other domains and devices another domain lists are
+//! disabled, as are the machine mode CLINT and nodes marked
+//! `rustsbi,firmware-reserved`, such as a UART dedicated to the firmware.
+//! The boot hart's domain keeps the boot device tree, pruned in place. Each
+//! other domain with a next stage of its own and no `rustsbi,next-arg1` gets
+//! a pruned copy, placed after the boot tree in memory nothing else uses and
+//! reserved in the boot tree. Without domains the tree is left as it is.
+
+use core::ops::Range;
+
+use prototyper_common::fdt_reader::FdtReader;
+
+use crate::firmware::fdt_fixup::{self, FixupError};
+use crate::firmware::{self, NEXT_STAGE_RESERVE};
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::current_hartid;
+use crate::sbi::domain::{self, MAX_DOMAINS};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Room left after each tree for the fixups that grow it.
+const FDT_GROWTH: usize = 0x1_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// Machine mode devices the firmware owns on every platform.
+const FIRMWARE_COMPATIBLE: [&str; 1] = ["riscv,clint0"];
+
+/// Pruned device tree copies of each domain.
+static mut DOMAIN_FDT: [Option<Range<usize>>; MAX_DOMAINS] = [const { None }; MAX_DOMAINS];
+
+#[inline]
+const fn page_align_up(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+#[inline]
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Whether the memory at `range` is free for a device tree copy: in memory
+/// and clear of the firmware, the next stages, and the memory the boot tree
+/// at `fdt_address` reserves, with the initrd.
+fn usable(range: &Range<usize>, fdt_address: usize, next_address: usize) -> bool {
+    let in_memory = platform::memory_range()
+        .is_some_and(|memory| memory.start <= range.start && range.end <= memory.end);
+    if !in_memory || overlaps(range, &firmware::private_range()) {
+        return false;
+    }
+    #[cfg(feature = "payload")]
+    if overlaps(range, &firmware::payload::payload_range()) {
+        return false;
+    }
+    let domains = unsafe { PLATFORM.info.domains };
+    let next_stages = domains
+        .iter()
+        .flatten()
+        .filter_map(|info| info.boot)
+        .map(|boot| boot.next_addr)
+        .chain([next_address]);
+    for entry in next_stages {
+        if overlaps(range, &(entry..entry.saturating_add(NEXT_STAGE_RESERVE))) {
+            return false;
+        }
+    }
+    let mut reserved = false;
+    let reader = unsafe { FdtReader::from_address(fdt_address) };
+    let known = reader.and_then(|fdt| {
+        fdt.reserved_ranges(|taken| {
+            let Ok(start) = usize::try_from(taken.start) else {
+                return;
+            };
+            let end = usize::try_from(taken.end).unwrap_or(usize::MAX);
+            reserved |= overlaps(range, &(start..end));
+        })
+    });
+    known.is_ok() && !reserved
+}
+
+/// Disable what the supervisor of domain `owner` does not own in the device
+/// tree at `fdt_address`, `None` standing for harts outside every domain.
+///
+/// Returns the number of nodes disabled.
+fn prune(fdt_address: usize, owner: Option<usize>) -> Result<usize, FixupError> {
+    let mut disabled = 0;
+    for hart_id in (0..NUM_HART_MAX).filter(|&hart_id| domain::of_hart(hart_id) != owner) {
+        if fdt_fixup::disable_cpu(fdt_address, hart_id)? {
+            disabled += 1;
+        }
+    }
+    let domains = unsafe { PLATFORM.info.domains };
+    for (index, info) in domains.iter().enumerate() {
+        let Some(info) = info.filter(|_| Some(index) != owner) else {
+            continue;
+        };
+        for phandle in info.devices() {
+            if fdt_fixup::disable_phandle(fdt_address, phandle)? {
+                disabled += 1;
+            }
+        }
+    }
+    for compatible in FIRMWARE_COMPATIBLE {
+        disabled += fdt_fixup::disable_compatible(fdt_address, compatible)?;
+    }
+    disabled += fdt_fixup::disable_firmware_reserved(fdt_address)?;
+    Ok(disabled)
+}
+
+/// Make the device tree of each domain from the boot device tree at
+/// `fdt_address`, keeping clear of the next stage at `next_address`.
+pub fn prepare(fdt_address: usize, next_address: usize) {
+    let domains = unsafe { PLATFORM.info.domains };
+    // A lone supervisor owns everything the tree shows.
+    if domains.iter().all(Option::is_none) {
+        return;
+    }
+    let Some(size) = fdt_fixup::total_size(fdt_address) else {
+        return;
+    };
+    let boot_domain = domain::of_hart(current_hartid());
+    let mut next = page_align_up(fdt_address + size + FDT_GROWTH);
+    for (index, info) in domains.iter().enumerate() {
+        let needs_copy = info
+            .and_then(|info| info.boot)
+            .is_some_and(|boot| boot.arg1.is_none());
+        if !needs_copy || Some(index) == boot_domain {
+            continue;
+        }
+        let range = next..page_align_up(next + size + FDT_GROWTH);
+        if !usable(&range, fdt_address, next_address) {
+            warn!(
+                "No room for the device tree of domain {} at {:#x}",
+                index, range.start
+            );
+            continue;
+        }
+        let pruned = unsafe { fdt_fixup::copy(fdt_address, range.start) }
+            .and_then(|_| prune(range.start, Some(index)));
+        match pruned {
+            Ok(disabled) => {
+                info!(
+                    "{:<30}: {} (0x{:x}, {} nodes disabled)",
+                    "Domain Device Tree", index, range.start, disabled
+                );
+                next = range.end;
+                unsafe { DOMAIN_FDT[index] = Some(range) };
+            }
+            Err(err) => warn!("Failed to make device tree of domain {}: {:?}", index, err),
+        }
+    }
+    match prune(fdt_address, boot_domain) {
+        Ok(0) => {}
+        Ok(disabled) => info!(
+            "{:<30}: {} nodes disabled",
+            "Boot Device Tree Pruning", disabled
+        ),
+        Err(err) => warn!("Failed to prune the boot device tree: {:?}", err),
+    }
+    // Keep the supervisor of the boot tree off the other trees.
+    for range in copies() {
+        if let Err(err) = fdt_fixup::add_mem_reserve(fdt_address, range) {
+            warn!("Failed to reserve a domain device tree: {:?}", err);
+        }
+    }
+}
+
+/// Device tree address of domain `index`, if it has a copy of its own.
+pub fn address(index: usize) -> Option<usize> {
+    unsafe { DOMAIN_FDT.get(index)?.as_ref().map(|range| range.start) }
+}
+
+/// Memory taken by the device tree copies of the domains.
+pub fn copies() -> impl Iterator<Item = Range<usize>> {
+    unsafe { DOMAIN_FDT.iter() }.flatten().cloned()
+}
@@ -0,0 +1,93 @@
+//! Print a flattened device tree in DTS-like form over the console.
+
+use prototyper_common::fdt_reader::{FdtReader, Token};
+
+use super::fdt_fixup::FixupError;
+
+/// Indentation printed per nesting level.
+const INDENT: &str = "    ";
+
+fn indent(depth: usize) {
+    for _ in 0..depth {
+        print!("{}", INDENT);
+    }
+}
+
+/// Whether `value` is a list of printable NUL terminated strings.
+fn is_string_list(value: &[u8]) -> bool {
+    value.last() == Some(&0)
+        && value[0] != 0
+        && value.windows(2).all(|pair| pair != [0, 0])
+        && value.iter().all(|&c| c == 0 || (0x20..0x7f).contains(&c))
+}
+
+fn print_value(value: &[u8]) {
+    if value.is_empty() {
+        return;
+    }
+    print!(" = ");
+    if is_string_list(value) {
+        let strings = value[..value.len() - 1].split(|&c| c == 0);
+        for (i, string) in strings.enumerate() {
+            if i != 0 {
+                print!(", ");
+            }
+            // Only printable ASCII passes `is_string_list`.
+            print!("\"{}\"", unsafe { core::str::from_utf8_unchecked(string) });
+        }
+    } else if value.len() % 4 == 0 {
+        print!("<");
+        for (i, cell) in value.chunks_exact(4).enumerate() {
+            if i != 0 {
+                print!(" ");
+            }
+            let cell = u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]);
+            print!("{:#x}", cell);
+        }
+        print!(">");
+    } else {
+        print!("[");
+        for (i, byte) in value.iter().enumerate() {
+            if i != 0 {
+                print!(" ");
+            }
+            print!("{:02x}", byte);
+        }
+        print!("]");
+    }
+}
+
+/// Print the device tree at `fdt_address`.
+///
+/// Only the `totalsize` bytes its header gives are read, and every token is
+/// checked against them. Callers dumping a tree they do not own check first
+/// that it lies in memory its owner may read.
+pub fn dump(fdt_address: usize) -> Result<(), FixupError> {
+    let fdt = unsafe { FdtReader::from_address(fdt_address) }?;
+    let mut depth = 0;
+    for token in fdt.tokens() {
+        match token? {
+            Token::BeginNode(name) => {
+                let name = core::str::from_utf8(name).unwrap_or("?");
+                indent(depth);
+                println!("{} {{", if name.is_empty() { "/" } else { name });
+                depth += 1;
+            }
+            Token::EndNode => {
+                if depth == 0 {
+                    return Err(FixupError::BadStructure);
+                }
+                depth -= 1;
+                indent(depth);
+                println!("}};");
+            }
+            Token::Prop { name, value } => {
+                indent(depth);
+                print!("{}", core::str::from_utf8(name).unwrap_or("?"));
+                print_value(value);
+                println!(";");
+            }
+        }
+    }
+    Ok(())
+}
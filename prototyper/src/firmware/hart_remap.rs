@@ -0,0 +1,72 @@
+//! Entering the next stage on another hart than the boot hart.
+//!
+//! Some supervisors assume they boot on the lowest hart ID, while some ROMs
+//! release another hart first. `/chosen/rustsbi,supervisor-boot-hart` names
+//! the hart the next stage must boot on; failing that,
+//! `PROTOTYPER_SUPERVISOR_BOOT_HART` at build time gives a hart ID or `lowest`,
+//! the lowest hart in the boot hart's domain. The boot hart then starts that
+//! hart as `hart_start` would and stops itself, left for the supervisor to
+//! start like any other.
+
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::current_hartid;
+use crate::sbi::domain;
+use crate::sbi::hart_context::NextStage;
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::hsm::{local_remote_hsm, remote_hsm};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Hart to boot the next stage on, as configured.
+fn configured() -> Option<usize> {
+    if let Some(hart_id) = unsafe { PLATFORM.info.supervisor_boot_hart } {
+        return Some(hart_id);
+    }
+    match option_env!("PROTOTYPER_SUPERVISOR_BOOT_HART")?.trim() {
+        "lowest" => {
+            let boot_domain = domain::of_hart(current_hartid());
+            (0..NUM_HART_MAX).find(|&hart_id| {
+                domain::of_hart(hart_id) == boot_domain
+                    && hart_init::reached(hart_id, InitState::SbiReady)
+            })
+        }
+        hart_id => hart_id.parse().ok(),
+    }
+}
+
+/// Whether the next stage can start on `hart_id` instead of the boot hart.
+fn usable(hart_id: usize) -> bool {
+    if hart_id >= NUM_HART_MAX || domain::of_hart(hart_id) != domain::of_hart(current_hartid()) {
+        warn!(
+            "Supervisor boot hart {} is not in the boot hart's domain",
+            hart_id
+        );
+        return false;
+    }
+    if hart_init::failed(hart_id) || !hart_init::reached(hart_id, InitState::SbiReady) {
+        warn!("Supervisor boot hart {} did not come up", hart_id);
+        return false;
+    }
+    true
+}
+
+/// Start `next_stage` on the configured hart, the boot hart by default,
+/// returning the hart it starts on.
+pub fn start_next_stage(next_stage: NextStage) -> usize {
+    let current_hart = current_hartid();
+    let target = configured()
+        .filter(|&hart_id| hart_id != current_hart)
+        .filter(|&hart_id| usable(hart_id));
+    if let (Some(hart_id), Some(ipi)) = (target, platform::ipi()) {
+        if remote_hsm(hart_id).is_some_and(|remote| remote.start(next_stage)) {
+            ipi.set_msip(hart_id);
+            info!(
+                "{:<30}: hart {} for boot hart {}",
+                "Supervisor Boot Hart", hart_id, current_hart
+            );
+            return hart_id;
+        }
+        warn!("Supervisor boot hart {} is not stopped", hart_id);
+    }
+    local_remote_hsm().start(next_stage);
+    current_hart
+}
@@ -0,0 +1,109 @@
+//! Header at the very start of the firmware image.
+//!
+//! Bootloaders and update tools can read it from the binary to learn which
+//! prototyper features a given image was built with. The first word jumps
+//! over the header, so the image can still be entered at its first byte.
+
+use core::arch::asm;
+use core::mem::size_of;
+
+use crate::START_ADDRESS;
+
+/// `RSBIPROT` in little endian.
+pub const IMAGE_MAGIC: u64 = u64::from_le_bytes(*b"RSBIPROT");
+/// Layout version of `ImageHeader`.
+pub const IMAGE_HEADER_VERSION: u32 = 1;
+
+/// Feature bits of `ImageHeader::features`.
+pub mod feature {
+    pub const LEGACY_SBI: u64 = 1 << 0;
+    pub const NEMU: u64 = 1 << 1;
+    pub const PAYLOAD: u64 = 1 << 2;
+    pub const FDT: u64 = 1 << 3;
+    pub const TIMER_TRACE: u64 = 1 << 4;
+}
+
+#[allow(unused)]
+#[repr(C)]
+pub struct ImageHeader {
+    /// `j` over the header to the entry point.
+    pub code0: u32,
+    /// `nop`.
+    pub code1: u32,
+    pub magic: u64,
+    pub header_version: u32,
+    /// Firmware version as `major << 16 | minor << 8 | patch`.
+    pub firmware_version: u32,
+    /// Enabled build features, see `feature`.
+    pub features: u64,
+    /// Address the image is linked at.
+    pub load_address: u64,
+    /// Address of the entry point, right after the header.
+    pub entry_address: u64,
+}
+
+const HEADER_SIZE: usize = size_of::<ImageHeader>();
+
+/// Encode `jal zero, offset`.
+const fn jump(offset: u32) -> u32 {
+    ((offset & 0x10_0000) << 11)
+        | ((offset & 0x7fe) << 20)
+        | ((offset & 0x800) << 9)
+        | (offset & 0xf_f000)
+        | 0x6f
+}
+
+const fn parse_version(s: &str) -> u32 {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    value
+}
+
+const fn features() -> u64 {
+    let mut features = 0;
+    if cfg!(feature = "legacy-sbi") {
+        features |= feature::LEGACY_SBI;
+    }
+    if cfg!(feature = "nemu") {
+        features |= feature::NEMU;
+    }
+    if cfg!(feature = "payload") {
+        features |= feature::PAYLOAD;
+    }
+    if cfg!(feature = "fdt") {
+        features |= feature::FDT;
+    }
+    if cfg!(feature = "timer-trace") {
+        features |= feature::TIMER_TRACE;
+    }
+    features
+}
+
+#[used]
+#[link_section = ".text.head"]
+static IMAGE_HEADER: ImageHeader = ImageHeader {
+    code0: jump(HEADER_SIZE as u32),
+    code1: 0x0000_0013,
+    magic: IMAGE_MAGIC,
+    header_version: IMAGE_HEADER_VERSION,
+    firmware_version: parse_version(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+        | parse_version(env!("CARGO_PKG_VERSION_MINOR")) << 8
+        | parse_version(env!("CARGO_PKG_VERSION_PATCH")),
+    features: features(),
+    load_address: START_ADDRESS as u64,
+    entry_address: (START_ADDRESS + HEADER_SIZE) as u64,
+};
+
+/// Locate the header of the running image, which may have been loaded
+/// somewhere other than its link address.
+pub fn image_header() -> Option<&'static ImageHeader> {
+    let start: usize;
+    unsafe { asm!("la {}, sbi_start", out(reg) start, options(nomem)) };
+    let header = unsafe { &*(start as *const ImageHeader) };
+    (header.magic == IMAGE_MAGIC).then_some(header)
+}
@@ -0,0 +1,75 @@
+//! Machine configuration structures.
+//!
+//! Harts of privileged specification 1.12 and later point `mconfigptr` at a
+//! structure describing them, or hold 0. Its unified discovery format is not
+//! settled, so the firmware does not read the structure; it checks the
+//! pointer and passes it on as `rustsbi,mconfigptr` of each cpu node, for
+//! supervisors that read the structure themselves.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::firmware::{self, fdt_fixup};
+use crate::riscv_spec::{current_hartid, mconfigptr};
+use crate::sbi::extensions::{hart_privileged_version, PrivilegedVersion};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Name of the cpu node property carrying the pointer.
+const MCONFIGPTR_PROP: &str = "rustsbi,mconfigptr";
+
+percpu! {
+    /// Checked `mconfigptr` of each hart, 0 for none.
+    static MCONFIGPTR: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Read and check `mconfigptr` of the current hart.
+pub fn probe() {
+    let hart_id = current_hartid();
+    if hart_privileged_version(hart_id) < PrivilegedVersion::Version1_12 {
+        return;
+    }
+    let pointer = mconfigptr::read();
+    if pointer == 0 {
+        return;
+    }
+    // The structure starts MXLEN aligned.
+    if pointer % core::mem::size_of::<usize>() != 0 {
+        warn!(
+            "Ignoring misaligned mconfigptr 0x{:x} of hart {}",
+            pointer, hart_id
+        );
+        return;
+    }
+    if firmware::firmware_range().contains(&pointer) {
+        warn!(
+            "Ignoring mconfigptr 0x{:x} of hart {} inside the firmware",
+            pointer, hart_id
+        );
+        return;
+    }
+    MCONFIGPTR.local().store(pointer, Ordering::Relaxed);
+}
+
+/// Checked `mconfigptr` of `hart_id`, if it has one.
+pub fn get(hart_id: usize) -> Option<usize> {
+    match MCONFIGPTR.get(hart_id)?.load(Ordering::Relaxed) {
+        0 => None,
+        pointer => Some(pointer),
+    }
+}
+
+/// Record the pointer of every hart that has one in its cpu node of the
+/// device tree at `fdt_address`.
+pub fn mirror(fdt_address: usize) {
+    for (hart_id, pointer) in (0..NUM_HART_MAX).filter_map(|hart_id| Some((hart_id, get(hart_id)?)))
+    {
+        let value = (pointer as u64).to_be_bytes();
+        match fdt_fixup::set_cpu_prop(fdt_address, hart_id, MCONFIGPTR_PROP, &value) {
+            Ok(true) => info!(
+                "{:<30}: 0x{:x} (Hart {})",
+                "Machine Config Pointer", pointer, hart_id
+            ),
+            Ok(false) => {}
+            Err(err) => warn!("Failed to record mconfigptr of hart {}: {:?}", hart_id, err),
+        }
+    }
+}
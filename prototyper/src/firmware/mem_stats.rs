@@ -0,0 +1,97 @@
+//! Memory usage of the firmware.
+//!
+//! The image has no heap: everything it uses is placed by the linker, and the
+//! hart stacks are the only part whose use varies at run time. Stacks are
+//! painted at entry, so the deepest use of each one can be read back later.
+//! The summary is printed before the jump and stays readable through the
+//! debug extension, for sizing deployments that run from SRAM alone.
+
+use core::arch::asm;
+use core::ops::Range;
+
+use crate::firmware;
+use crate::riscv_spec::current_hartid;
+use crate::sbi::trap_stack::{self, LEN_STACK_PER_HART, NUM_HART_MAX};
+
+/// Parts of the firmware image readable through `footprint`.
+#[derive(Clone, Copy, Debug)]
+pub enum Section {
+    /// Code.
+    Text,
+    /// Read-only data and relocations.
+    Rodata,
+    /// Initialized data.
+    Data,
+    /// Zeroed data, without the stacks.
+    Bss,
+    /// Stacks and hart contexts of all harts.
+    Stacks,
+}
+
+const SECTIONS: [Section; 5] = [
+    Section::Text,
+    Section::Rodata,
+    Section::Data,
+    Section::Bss,
+    Section::Stacks,
+];
+
+impl Section {
+    fn name(self) -> &'static str {
+        match self {
+            Section::Text => "Firmware Text",
+            Section::Rodata => "Firmware Rodata",
+            Section::Data => "Firmware Data",
+            Section::Bss => "Firmware Bss",
+            Section::Stacks => "Firmware Stacks",
+        }
+    }
+}
+
+macro_rules! symbols {
+    ($start:literal, $end:literal) => {{
+        let (start, end): (usize, usize);
+        unsafe {
+            asm!(concat!("la {}, ", $start), out(reg) start, options(nomem));
+            asm!(concat!("la {}, ", $end), out(reg) end, options(nomem));
+        }
+        start..end
+    }};
+}
+
+fn range(section: Section) -> Range<usize> {
+    match section {
+        Section::Text => symbols!("sbi_start", "sbi_rodata_start"),
+        Section::Rodata => symbols!("sbi_rodata_start", "sbi_rodata_end"),
+        Section::Data => symbols!("sbi_data_start", "sbi_data_end"),
+        Section::Bss => symbols!("sbi_bss_start", "sbi_bss_end"),
+        Section::Stacks => {
+            let start = core::ptr::addr_of!(trap_stack::ROOT_STACK) as usize;
+            start..start + LEN_STACK_PER_HART * NUM_HART_MAX
+        }
+    }
+}
+
+/// Bytes taken by `section` of the image.
+pub fn footprint(section: Section) -> usize {
+    range(section).len()
+}
+
+/// Print the size of each section and the stack use of the current hart.
+pub fn print_summary() {
+    for section in SECTIONS {
+        info!("{:<30}: {} bytes", section.name(), footprint(section));
+    }
+    info!(
+        "{:<30}: {} bytes",
+        "Firmware Image Total",
+        firmware::firmware_range().len()
+    );
+    let hart_id = current_hartid();
+    if let Some(used) = trap_stack::stack_high_water(hart_id) {
+        info!(
+            "{:<30}: {} of {} bytes on hart {}",
+            "Stack High-water Mark", used, LEN_STACK_PER_HART, hart_id
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Quick memory test of RAM at cold boot.
+//!
+//! Enabled by the `memtest` feature, or at run time by a `rustsbi,memtest`
+//! property in `/chosen`. Every word of RAM outside the firmware, the device
+//! tree, the crash dump region, the next stage and the memory the tree
+//! reserves, with the initrd, is written with its own address, checked,
+//! written with the inverted address and checked again, then scrubbed to
+//! zero. That catches stuck, shorted and aliased address lines, the usual
+//! symptoms of a marginal DRAM controller setup, in three passes over memory.
+
+use core::mem::size_of;
+use core::ops::Range;
+
+use prototyper_common::fdt_reader::FdtReader;
+
+use crate::firmware;
+use crate::firmware::fdt_fixup;
+use crate::platform::PLATFORM;
+
+/// Room left after the device tree for the fixups to grow it.
+const FDT_GROWTH: usize = 64 * 1024;
+/// Progress is reported every this many bytes.
+const PROGRESS_STEP: usize = 256 * 1024 * 1024;
+/// Most excluded ranges, the firmware's own and those the tree reserves.
+const MAX_EXCLUDED: usize = 32;
+
+/// Result of testing one contiguous range.
+struct RegionReport {
+    errors: usize,
+    /// First failing address, with the value expected and the one read.
+    first: Option<(usize, usize, usize)>,
+}
+
+/// Returns true if the build or the device tree asks for a memory test.
+#[inline]
+pub fn enabled(chosen_flag: bool) -> bool {
+    cfg!(feature = "memtest") || chosen_flag
+}
+
+/// Memory that must survive the test, sorted by start address.
+///
+/// Returns `None` if there are more ranges than can be kept apart.
+#[allow(unused_variables)]
+fn excluded(
+    fdt_address: usize,
+    next_address: usize,
+) -> Option<([Range<usize>; MAX_EXCLUDED], usize)> {
+    let mut ranges = [const { 0..0 }; MAX_EXCLUDED];
+    let mut count = 0;
+    let mut overflow = false;
+    let mut push = |range: Range<usize>| {
+        if range.is_empty() {
+            return;
+        }
+        match ranges.get_mut(count) {
+            Some(slot) => *slot = range,
+            None => overflow = true,
+        }
+        count += 1;
+    };
+    push(firmware::firmware_range());
+    #[cfg(feature = "payload")]
+    push(firmware::payload::payload_range());
+    let fdt_size = fdt_fixup::total_size(fdt_address).unwrap_or(0);
+    push(fdt_address..fdt_address + fdt_size + FDT_GROWTH);
+    if let Some(crashdump) = unsafe { PLATFORM.info.crashdump.clone() } {
+        push(crashdump);
+    }
+    #[cfg(not(feature = "payload"))]
+    push(next_address..next_address.saturating_add(firmware::NEXT_STAGE_RESERVE));
+    // Memory the tree reserves and the initrd, an address past the
+    // address space cannot be in memory.
+    let reader = unsafe { FdtReader::from_address(fdt_address) };
+    if let Err(err) = reader.and_then(|fdt| {
+        fdt.reserved_ranges(|range| {
+            if let Ok(start) = usize::try_from(range.start) {
+                push(start..usize::try_from(range.end).unwrap_or(usize::MAX));
+            }
+        })
+    }) {
+        warn!("Memory test: reserved memory unknown, {:?}", err);
+        return None;
+    }
+    if overflow {
+        warn!("Memory test: more than {} ranges to keep", MAX_EXCLUDED);
+        return None;
+    }
+    ranges[..count].sort_unstable_by_key(|range| range.start);
+    Some((ranges, count))
+}
+
+/// Run all passes over `region`, whose bounds are word aligned.
+fn test_region(region: &Range<usize>, tested: &mut usize, total: usize) -> RegionReport {
+    let words = || (region.start..region.end).step_by(size_of::<usize>());
+    let mut report = RegionReport {
+        errors: 0,
+        first: None,
+    };
+    let mut check = |address: usize, expected: usize, next: usize| {
+        let word = address as *mut usize;
+        let actual = unsafe { word.read_volatile() };
+        if actual != expected {
+            report.errors += 1;
+            report.first.get_or_insert((address, expected, actual));
+        }
+        unsafe { word.write_volatile(next) };
+    };
+    for address in words() {
+        unsafe { (address as *mut usize).write_volatile(address) };
+    }
+    for address in words() {
+        check(address, address, !address);
+    }
+    for address in words() {
+        check(address, !address, 0);
+        if (address - region.start) % PROGRESS_STEP == 0 && address != region.start {
+            info!(
+                "Memory test: {} of {} MiB",
+                (*tested + address - region.start) >> 20,
+                total >> 20
+            );
+        }
+    }
+    *tested += region.end - region.start;
+    report
+}
+
+/// Test and scrub `memory`, keeping the firmware, the device tree at
+/// `fdt_address`, the memory it reserves and the next stage at
+/// `next_address` intact.
+///
+/// Returns false if any word failed. Nothing is tested if the reserved
+/// memory cannot be told.
+pub fn run(memory: &Range<usize>, fdt_address: usize, next_address: usize) -> bool {
+    let align = |address: usize| address & !(size_of::<usize>() - 1);
+    let Some((excluded, count)) = excluded(fdt_address, next_address) else {
+        warn!("Memory test skipped");
+        return true;
+    };
+    let mut regions = [const { 0..0 }; MAX_EXCLUDED + 1];
+    let mut start = memory.start;
+    for (region, skip) in regions.iter_mut().zip(excluded[..count].iter()) {
+        *region =
+            align(start + size_of::<usize>() - 1)..align(skip.start.min(memory.end).max(start));
+        start = start.max(skip.end);
+    }
+    regions[count] = align(start.min(memory.end) + size_of::<usize>() - 1)..align(memory.end);
+    let total = regions.iter().map(|region| region.len()).sum();
+
+    info!(
+        "Memory test: 0x{:x} - 0x{:x}, {} MiB to test",
+        memory.start,
+        memory.end,
+        total >> 20
+    );
+    let mut tested = 0;
+    let mut passed = true;
+    for region in regions.iter().filter(|region| !region.is_empty()) {
+        let report = test_region(region, &mut tested, total);
+        match report.first {
+            None => info!(
+                "Memory test: 0x{:x} - 0x{:x} passed",
+                region.start, region.end
+            ),
+            Some((address, expected, actual)) => {
+                passed = false;
+                error!(
+                    "Memory test: 0x{:x} - 0x{:x} failed, {} bad words",
+                    region.start, region.end, report.errors
+                );
+                error!(
+                    "Memory test: first at 0x{:x}, expected 0x{:x}, read 0x{:x}",
+                    address, expected, actual
+                );
+            }
+        }
+    }
+    passed
+}
@@ -1,12 +1,46 @@
+pub mod amp;
+#[cfg(feature = "boot-menu")]
+pub mod boot_menu;
+pub mod boot_profile;
+pub mod boot_protocol;
+pub mod cache;
+pub mod counter;
+pub mod deferred;
 #[cfg(not(feature = "payload"))]
 pub mod dynamic;
+pub mod fdt_domain;
+pub mod fdt_dump;
+pub mod hart_remap;
+pub mod image_header;
+pub mod mconfig;
+pub mod mem_stats;
+pub mod memtest;
 #[cfg(feature = "payload")]
 pub mod payload;
+pub mod secondary;
+pub mod seed;
+#[cfg(feature = "boot-menu")]
+pub mod shell;
+pub mod timebase;
+#[cfg(debug_assertions)]
+pub mod watchpoint;
+
+pub use prototyper_common::fdt_fixup;
+#[cfg(feature = "payload-gzip")]
+pub use prototyper_common::gzip;
 
 use core::arch::asm;
 use core::ops::Range;
 use riscv::register::mstatus;
 
+use crate::error::{FwError, FwResult};
+use crate::platform;
+use crate::riscv_spec::{current_hartid, mseccfg, pmp};
+use crate::sbi::extensions::{hart_extension_probe, Extension};
+use crate::sbi::update;
+use fdt_fixup::FixupError;
+use prototyper_common::fdt_reader::FdtReader;
+
 pub struct BootInfo {
     pub next_address: usize,
     pub mpp: mstatus::MPP,
@@ -59,24 +93,109 @@ pub fn get_boot_hart(opaque: usize, nonstandard_a2: usize) -> BootHart {
     }
 }
 
+/// Apply build time device tree fixups before the next stage sees the tree.
+///
+/// A device tree embedded with the `fdt` feature is left alone, since growing
+/// it would overwrite the firmware image.
+#[allow(unused_variables)]
+pub fn fixup_device_tree(fdt_address: usize) {
+    #[cfg(not(feature = "fdt"))]
+    if let Some(bootargs) = option_env!("PROTOTYPER_BOOTARGS") {
+        match fdt_fixup::append_bootargs(fdt_address, bootargs) {
+            Ok(()) => info!("{:<30}: {}", "Appended Boot Arguments", bootargs),
+            Err(err) => warn!("Failed to append boot arguments: {:?}", err),
+        }
+    }
+    #[cfg(not(feature = "fdt"))]
+    match fdt_fixup::fixup_iommu(fdt_address) {
+        Ok(0) => {}
+        Ok(added) => info!(
+            "{:<30}: {} properties added",
+            "IOMMU Device Tree Fixup", added
+        ),
+        Err(err) => warn!("Failed to fix up IOMMU nodes: {:?}", err),
+    }
+    #[cfg(not(feature = "fdt"))]
+    {
+        let (nodes, count) = platform::numa().nodes();
+        match fdt_fixup::fixup_numa(fdt_address, &nodes[..count]) {
+            Ok(0) => {}
+            Ok(written) => info!(
+                "{:<30}: {} distance entries written",
+                "NUMA Device Tree Fixup", written
+            ),
+            Err(err) => warn!("Failed to fix up the NUMA distance map: {:?}", err),
+        }
+    }
+    #[cfg(not(feature = "fdt"))]
+    if let Some(freq) = timebase::corrected() {
+        match fdt_fixup::fixup_timebase(fdt_address, freq) {
+            Ok(()) => info!("{:<30}: {} Hz", "Corrected Timebase Frequency", freq),
+            Err(err) => warn!("Failed to correct timebase frequency: {:?}", err),
+        }
+    }
+}
+
+/// Memory assumed taken by a next stage a previous stage loaded, from its entry.
+pub const NEXT_STAGE_RESERVE: usize = 64 * 1024 * 1024;
+
+/// Memory occupied by the firmware image, which lower privileges may never run from.
+pub fn firmware_range() -> Range<usize> {
+    let (start, end): (usize, usize);
+    unsafe {
+        asm!("la {}, sbi_start", out(reg) start, options(nomem));
+        asm!("la {}, sbi_end", out(reg) end, options(nomem));
+    }
+    start..end
+}
+
+/// Memory lower privileges may never access: the image and the update
+/// window after it.
+pub fn private_range() -> Range<usize> {
+    let image = firmware_range();
+    image.start..update::window().end.max(image.end)
+}
+
+/// End of the free memory from `start`: the end of memory, or the first of
+/// the device tree at `fdt_address`, memory it reserves and the initrd that
+/// lies above.
+///
+/// Nothing is free if `start` is itself reserved.
+pub fn free_end(start: usize, fdt_address: usize) -> Result<usize, FixupError> {
+    let mut end = platform::memory_range().map_or(start, |memory| memory.end);
+    if fdt_address >= start {
+        end = end.min(fdt_address);
+    }
+    let fdt = unsafe { FdtReader::from_address(fdt_address) }?;
+    fdt.reserved_ranges(|taken| {
+        let Ok(taken_start) = usize::try_from(taken.start) else {
+            return;
+        };
+        let taken_end = usize::try_from(taken.end).unwrap_or(usize::MAX);
+        if taken_end > start {
+            end = end.min(taken_start.max(start));
+        }
+    })?;
+    Ok(end.max(start))
+}
+
 static mut SBI_START_ADDRESS: usize = 0;
-static mut SBI_END_ADDRESS: usize = 0;
 static mut RODATA_START_ADDRESS: usize = 0;
 static mut RODATA_END_ADDRESS: usize = 0;
 
-pub fn set_pmp(memory_range: &Range<usize>) {
+/// Program the PMP of the current hart, see `check_pmp_layout` for the self-check.
+pub fn set_pmp(memory_range: &Range<usize>) -> FwResult {
     unsafe {
         // [0..memory_range.start] RW
         // [memory_range.start..sbi_start] RWX
         // [sbi_start..sbi_rodata_start] NONE
-        // [sbi_rodata_start..sbi_rodata_end] NONE
-        // [sbi_rodata_end..sbi_end] NONE
-        // [sbi_end..memory_range.end] RWX
+        // [sbi_rodata_start..sbi_rodata_end] R, for a device tree embedded there
+        // [sbi_rodata_end..update window end] NONE
+        // [update window end..memory_range.end] RWX
         // [memory_range.end..INF] RW
         use riscv::register::*;
 
         asm!("la {}, sbi_start", out(reg) SBI_START_ADDRESS, options(nomem));
-        asm!("la {}, sbi_end", out(reg) SBI_END_ADDRESS, options(nomem));
         asm!("la {}, sbi_rodata_start", out(reg) RODATA_START_ADDRESS, options(nomem));
         asm!("la {}, sbi_rodata_end", out(reg) RODATA_END_ADDRESS, options(nomem));
 
@@ -88,15 +207,160 @@ pub fn set_pmp(memory_range: &Range<usize>) {
         pmpaddr2::write(SBI_START_ADDRESS >> 2);
         pmpcfg0::set_pmp(3, Range::TOR, Permission::NONE, false);
         pmpaddr3::write(RODATA_START_ADDRESS >> 2);
-        pmpcfg0::set_pmp(4, Range::TOR, Permission::RW, false);
+        pmpcfg0::set_pmp(4, Range::TOR, Permission::R, false);
         pmpaddr4::write(RODATA_END_ADDRESS >> 2);
         pmpcfg0::set_pmp(5, Range::TOR, Permission::NONE, false);
-        pmpaddr5::write(SBI_END_ADDRESS >> 2);
+        pmpaddr5::write(private_range().end >> 2);
         pmpcfg0::set_pmp(6, Range::TOR, Permission::RWX, false);
         pmpaddr6::write(memory_range.end >> 2);
         pmpcfg0::set_pmp(7, Range::TOR, Permission::RW, false);
         pmpaddr7::write(usize::MAX >> 2);
     }
+    check_pmp_layout(memory_range)
+}
+
+/// Check that the image is split into text, read-only data and writable data
+/// at page boundaries, and that the PMP entries read back as `set_pmp` wrote
+/// them. Fails otherwise, so no hart runs with lower privileges able to
+/// write firmware memory.
+fn check_pmp_layout(memory_range: &Range<usize>) -> FwResult {
+    const PAGE: usize = 0x1000;
+    let (text, rodata, data) = sections();
+    let ordered = text.start <= text.end
+        && text.end == rodata.start
+        && rodata.start <= rodata.end
+        && rodata.end <= data.start
+        && data.start <= data.end;
+    let aligned = [text.start, rodata.start, rodata.end, data.end]
+        .iter()
+        .all(|boundary| boundary % PAGE == 0);
+    if !ordered || !aligned {
+        return Err(FwError::SectionLayout { text, rodata, data });
+    }
+    if pmp::read_cfg(1) == Some(0) {
+        warn!("No PMP entries implemented, firmware memory is unprotected");
+        return Ok(());
+    }
+    check_pmp_entries(pmp_layout(memory_range, false))
+}
+
+/// Configuration and address of each PMP entry as `set_pmp` programs it, or
+/// as `lock_down` leaves it with `locked`.
+fn pmp_layout(memory_range: &Range<usize>, locked: bool) -> [(u8, usize); 8] {
+    const L: u8 = 1 << 7;
+    const TOR: u8 = 1 << 3;
+    const R: u8 = 1 << 0;
+    const W: u8 = 1 << 1;
+    const X: u8 = 1 << 2;
+    let (text, rodata, _) = sections();
+    // Under MML `W | X` without `R` is read and write for every privilege,
+    // and locked `R | W | X` read-only for every privilege.
+    let (shared, text_cfg, rodata_cfg, data_cfg) = if locked {
+        (
+            TOR | W | X,
+            L | TOR | R | X,
+            L | TOR | R | W | X,
+            L | TOR | R | W,
+        )
+    } else {
+        (TOR | R | W, TOR, TOR | R, TOR)
+    };
+    [
+        (0, 0),
+        (shared, memory_range.start >> 2),
+        (TOR | R | W | X, text.start >> 2),
+        (text_cfg, rodata.start >> 2),
+        (rodata_cfg, rodata.end >> 2),
+        (data_cfg, private_range().end >> 2),
+        (TOR | R | W | X, memory_range.end >> 2),
+        (shared, usize::MAX >> 2),
+    ]
+}
+
+/// Check that the PMP entries of the current hart read back as `expected`.
+fn check_pmp_entries(expected: [(u8, usize); 8]) -> FwResult {
+    for (index, (cfg, addr)) in expected.into_iter().enumerate() {
+        let found = (pmp::read_cfg(index), pmp::read_addr(index));
+        // Only the top address of the last entry may be truncated by the hardware.
+        let addr_matches = found.1 == Some(addr) || (index == 7 && found.1.is_some());
+        if found.0 != Some(cfg) || !addr_matches {
+            return Err(FwError::PmpReadback {
+                hart_id: current_hartid(),
+                index,
+                found,
+                cfg,
+                addr,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the current hart runs under the Smepmp machine mode lockdown.
+pub fn locked_down() -> bool {
+    hart_extension_probe(current_hartid(), Extension::Smepmp) && mseccfg::read() & mseccfg::MML != 0
+}
+
+/// Enable M-mode W^X on the current hart if it implements Smepmp: lock the
+/// firmware PMP entries and set `mseccfg.MML` and `MMWP`. Not with the
+/// `no-lockdown` feature, which keeps in place updates possible instead.
+///
+/// M-mode can then no longer write its text or execute its data, and has
+/// no access to memory lower privileges may execute. Devices and memory
+/// outside the main memory stay shared. Call it last before the hart first
+/// leaves M-mode, supervisor memory is reached through `guest_mem` from then
+/// on.
+pub fn lock_down(memory_range: &Range<usize>) -> FwResult {
+    let hart_id = current_hartid();
+    if cfg!(feature = "no-lockdown")
+        || !hart_extension_probe(hart_id, Extension::Smepmp)
+        || pmp::read_cfg(1) == Some(0)
+    {
+        return Ok(());
+    }
+    unsafe {
+        use riscv::register::*;
+        // Locked entries bind M-mode before MML, which takes them off lower
+        // privileges. Their addresses are locked with them.
+        pmpcfg0::set_pmp(3, Range::TOR, Permission::RX, true);
+        pmpcfg0::set_pmp(4, Range::TOR, Permission::RWX, true);
+        pmpcfg0::set_pmp(5, Range::TOR, Permission::RW, true);
+        mseccfg::set_bits(mseccfg::MML | mseccfg::MMWP);
+        // Only meaningful under MML, reserved before.
+        pmpcfg0::set_pmp(1, Range::TOR, Permission::WX, false);
+        pmpcfg0::set_pmp(7, Range::TOR, Permission::WX, false);
+    }
+    let bits = mseccfg::read();
+    if bits & (mseccfg::MML | mseccfg::MMWP) != mseccfg::MML | mseccfg::MMWP {
+        return Err(FwError::Lockdown {
+            hart_id,
+            mseccfg: bits,
+        });
+    }
+    check_pmp_entries(pmp_layout(memory_range, true))
+}
+
+/// Text, read-only data and writable data of the image, from the linker script.
+fn sections() -> (Range<usize>, Range<usize>, Range<usize>) {
+    let (start, rodata_start, rodata_end, end): (usize, usize, usize, usize);
+    unsafe {
+        asm!("la {}, sbi_start", out(reg) start, options(nomem));
+        asm!("la {}, sbi_rodata_start", out(reg) rodata_start, options(nomem));
+        asm!("la {}, sbi_rodata_end", out(reg) rodata_end, options(nomem));
+        asm!("la {}, sbi_end", out(reg) end, options(nomem));
+    }
+    (
+        start..rodata_start,
+        rodata_start..rodata_end,
+        rodata_end..end,
+    )
+}
+
+/// Log the PMP layout of the main memory, as a deferred job.
+pub fn log_pmp_layout() {
+    if let Some(memory_range) = platform::memory_range() {
+        log_pmp_cfg(memory_range);
+    }
 }
 
 pub fn log_pmp_cfg(memory_range: &Range<usize>) {
@@ -115,7 +379,12 @@ pub fn log_pmp_cfg(memory_range: &Range<usize>) {
         );
         info!(
             "{:<10} {:<10} {:<15} 0x{:08x} - 0x{:08x} - 0x{:08x}",
-            "PMP 3-5:", "TOR", "NONE/RW", RODATA_START_ADDRESS, RODATA_END_ADDRESS, SBI_END_ADDRESS
+            "PMP 3-5:",
+            "TOR",
+            "NONE/R",
+            RODATA_START_ADDRESS,
+            RODATA_END_ADDRESS,
+            private_range().end
         );
         info!(
             "{:<10} {:<10} {:<15} 0x{:08x}",
@@ -1,8 +1,13 @@
 use core::arch::asm;
+use core::ops::Range;
+#[cfg(feature = "payload-gzip")]
+use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::{AtomicBool, Ordering};
 use riscv::register::mstatus;
 
 use super::BootInfo;
+#[cfg(feature = "payload-gzip")]
+use crate::error::FwError;
 
 /// Determine whether the current hart is boot hart.
 ///
@@ -21,6 +26,7 @@ pub fn get_boot_info(_nonstandard_a2: usize) -> BootInfo {
 
 #[naked]
 #[link_section = ".payload"]
+#[cfg(not(feature = "payload-gzip"))]
 pub unsafe extern "C" fn payload_image() {
     asm!(
         concat!(".incbin \"", env!("PROTOTYPER_PAYLOAD_PATH"), "\""),
@@ -28,7 +34,91 @@ pub unsafe extern "C" fn payload_image() {
     );
 }
 
+// The compressed payload stays in the firmware image, the payload address
+// left empty for it to be inflated to.
+#[cfg(feature = "payload-gzip")]
+core::arch::global_asm!(
+    ".pushsection .payload.packed, \"a\"",
+    concat!(".incbin \"", env!("PROTOTYPER_PAYLOAD_PATH"), "\""),
+    ".popsection",
+);
+
 #[inline]
+#[cfg(not(feature = "payload-gzip"))]
 fn get_image_address() -> usize {
     payload_image as usize
 }
+
+/// Size of the inflated payload, set once by the boot hart.
+#[cfg(feature = "payload-gzip")]
+static INFLATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Inflate the payload to its address on first call and return the address.
+#[cfg(feature = "payload-gzip")]
+fn get_image_address() -> usize {
+    let start = payload_start();
+    if INFLATED.load(Ordering::Acquire) != 0 {
+        return start;
+    }
+    let (packed_start, packed_end): (usize, usize);
+    unsafe {
+        asm!("la {}, sbi_payload_packed_start", out(reg) packed_start, options(nomem));
+        asm!("la {}, sbi_payload_packed_end", out(reg) packed_end, options(nomem));
+    }
+    let packed = unsafe {
+        core::slice::from_raw_parts(packed_start as *const u8, packed_end - packed_start)
+    };
+    let end = inflate_end(start);
+    let output = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, end - start) };
+    match super::gzip::decompress(packed, output) {
+        Ok(size) => {
+            info!(
+                "{:<30}: {} bytes from {} bytes at 0x{:x}",
+                "Inflated Payload",
+                size,
+                packed.len(),
+                start
+            );
+            // Cleaned and fetched anew by `cache::prepare_next_stage`.
+            INFLATED.store(size, Ordering::Release);
+        }
+        Err(err) => crate::fail::boot(FwError::Payload(err)),
+    }
+    start
+}
+
+/// End of the memory the payload may be inflated to from `start`, see
+/// `free_end`.
+///
+/// Nothing may be inflated if the reserved memory cannot be read.
+#[cfg(feature = "payload-gzip")]
+fn inflate_end(start: usize) -> usize {
+    match super::free_end(start, crate::sbi::update::boot_fdt_address()) {
+        Ok(end) => end,
+        Err(err) => {
+            warn!("Inflated Payload: reserved memory unknown, {:?}", err);
+            start
+        }
+    }
+}
+
+fn payload_start() -> usize {
+    let start: usize;
+    unsafe { asm!("la {}, sbi_payload_start", out(reg) start, options(nomem)) };
+    start
+}
+
+/// Memory occupied by the bundled payload.
+#[cfg(not(feature = "payload-gzip"))]
+pub fn payload_range() -> Range<usize> {
+    let end: usize;
+    unsafe { asm!("la {}, sbi_payload_end", out(reg) end, options(nomem)) };
+    payload_start()..end
+}
+
+/// Memory occupied by the inflated payload, empty before it is inflated.
+#[cfg(feature = "payload-gzip")]
+pub fn payload_range() -> Range<usize> {
+    let start = payload_start();
+    start..start + INFLATED.load(Ordering::Acquire)
+}
@@ -0,0 +1,70 @@
+//! Secondary hart arrival.
+//!
+//! A hart that hangs or faults before it handles SBI events would leave the
+//! supervisor waiting for a CPU that never comes online. Before handing over,
+//! the boot hart gives every enabled hart a bounded time to come up, and
+//! marks the missing ones failed in the device tree, which the next stage
+//! skips. The mark only lasts for this boot; nothing is written back.
+//!
+//! Harts from `NUM_HART_MAX` on have no firmware stack and are parked at
+//! entry, so they are marked failed without waiting.
+
+use crate::config;
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::current_hartid;
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+use crate::sync::Backoff;
+use crate::time;
+
+/// Time all enabled harts have to come up, counted from the first check,
+/// `harts.arrival-timeout-ms` of the platform manifest.
+const ARRIVAL_TIMEOUT_US: u64 = config::ARRIVAL_TIMEOUT_MS * 1000;
+
+/// Wait for the enabled harts and report those that never came up.
+pub fn check_arrival(fdt_address: usize) {
+    // A built-in device tree is read only, missing harts are only reported.
+    #[cfg(feature = "fdt")]
+    let _ = fdt_address;
+    #[cfg(not(feature = "fdt"))]
+    match super::fdt_fixup::mark_cpus_failed_from(fdt_address, NUM_HART_MAX) {
+        Ok(0) => {}
+        Ok(parked) => warn!(
+            "{} hart(s) from hart {} on are parked, marking them failed",
+            parked, NUM_HART_MAX
+        ),
+        Err(err) => warn!("Failed to mark parked harts failed: {:?}", err),
+    }
+    let Some(cpu_enabled) = (unsafe { PLATFORM.info.cpu_enabled }) else {
+        return;
+    };
+    // Without a timer the wait could not end.
+    if platform::ipi().is_none() {
+        return;
+    }
+    let current_hart = current_hartid();
+    let deadline = time::Deadline::after_us(ARRIVAL_TIMEOUT_US);
+    let mut backoff = Backoff::new();
+    for hart_id in
+        (0..NUM_HART_MAX).filter(|&hart_id| hart_id != current_hart && cpu_enabled[hart_id])
+    {
+        while !hart_init::reached(hart_id, InitState::SbiReady) && !deadline.expired() {
+            backoff.spin();
+        }
+        if hart_init::reached(hart_id, InitState::SbiReady) {
+            continue;
+        }
+        hart_init::mark_failed(hart_id);
+        warn!(
+            "Hart {} did not come up (state {}), marking it failed",
+            hart_id,
+            hart_init::state(hart_id)
+        );
+        #[cfg(not(feature = "fdt"))]
+        match super::fdt_fixup::mark_cpu_failed(fdt_address, hart_id) {
+            Ok(true) => {}
+            Ok(false) => warn!("No cpu node for hart {} in the device tree", hart_id),
+            Err(err) => warn!("Failed to mark hart {} failed: {:?}", hart_id, err),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Seed CSR access policy for S-mode and U-mode (Zkr).
+//!
+//! The policy is chosen at build time with `PROTOTYPER_SEED_POLICY`:
+//!
+//! - `machine` (default): only the firmware reads `seed`. S-mode gets entropy
+//!   through the firmware entropy extension instead.
+//! - `supervisor`: S-mode may read `seed` directly.
+//! - `user`: both S-mode and U-mode may read `seed` directly.
+
+use crate::riscv_spec::mseccfg;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedPolicy {
+    Machine,
+    Supervisor,
+    User,
+}
+
+impl SeedPolicy {
+    /// Returns the policy selected at build time.
+    pub fn current() -> Self {
+        match option_env!("PROTOTYPER_SEED_POLICY") {
+            Some("supervisor") => SeedPolicy::Supervisor,
+            Some("user") => SeedPolicy::User,
+            _ => SeedPolicy::Machine,
+        }
+    }
+
+    /// `mseccfg` seed access bits for this policy.
+    #[inline]
+    pub const fn mseccfg(self) -> usize {
+        match self {
+            SeedPolicy::Machine => 0,
+            SeedPolicy::Supervisor => mseccfg::SSEED,
+            SeedPolicy::User => mseccfg::SSEED | mseccfg::USEED,
+        }
+    }
+}
+
+/// Program the seed access bits of `mseccfg` on the current hart.
+///
+/// Only call this on harts implementing Zkr, `mseccfg` may not exist otherwise.
+pub fn init() {
+    let bits = SeedPolicy::current().mseccfg();
+    mseccfg::clear_bits(mseccfg::SSEED | mseccfg::USEED);
+    mseccfg::set_bits(bits);
+}
@@ -0,0 +1,204 @@
+//! Minimal firmware shell for board bring-up.
+//!
+//! Reached from the boot menu, or by pressing Ctrl-C during the boot delay.
+//! Memory commands access physical addresses directly, so touching unmapped
+//! addresses traps the firmware.
+
+use core::arch::asm;
+use rustsbi::{spec::hsm::hart_state, Ipi};
+
+use crate::firmware::boot_menu::read_key;
+use crate::firmware::fdt_dump;
+use crate::platform::{self, PLATFORM};
+use crate::sbi::hart_mask;
+use crate::sbi::hsm::remote_hsm;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+const LINE_MAX: usize = 128;
+const HELP: [&str; 8] = [
+    "help                 show this help",
+    "mr <addr> [count]    read 32-bit words",
+    "mw <addr> <value>    write a 32-bit word",
+    "csr <name>           read a machine CSR",
+    "dt                   print the device tree",
+    "harts                show hart states",
+    "ipi <hart>           send a supervisor software interrupt",
+    "boot                 leave the shell and continue booting",
+];
+
+/// Read a line into `buf`, echoing it, and return its length.
+fn read_line(buf: &mut [u8; LINE_MAX]) -> usize {
+    let mut len = 0;
+    loop {
+        match read_key() {
+            b'\r' | b'\n' => {
+                println!("");
+                return len;
+            }
+            // Backspace and delete.
+            0x08 | 0x7f => {
+                if len > 0 {
+                    len -= 1;
+                    print!("\x08 \x08");
+                }
+            }
+            c @ 0x20..=0x7e if len < LINE_MAX => {
+                buf[len] = c;
+                len += 1;
+                print!("{}", c as char);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse a hexadecimal number with `0x` prefix, or a decimal one.
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+macro_rules! read_csr {
+    ($name:expr, $($csr:literal),* $(,)?) => {
+        match $name {
+            $($csr => {
+                let value: usize;
+                unsafe { asm!(concat!("csrr {}, ", $csr), out(reg) value) };
+                Some(value)
+            })*
+            _ => None,
+        }
+    };
+}
+
+/// Read a CSR every privileged version implements, by name.
+fn read_csr(name: &str) -> Option<usize> {
+    read_csr!(
+        name,
+        "mstatus",
+        "misa",
+        "medeleg",
+        "mideleg",
+        "mie",
+        "mip",
+        "mtvec",
+        "mscratch",
+        "mepc",
+        "mcause",
+        "mtval",
+        "mcounteren",
+        "mhartid",
+        "mvendorid",
+        "marchid",
+        "mimpid",
+        "mcycle",
+        "minstret",
+        "satp",
+    )
+}
+
+fn memory_read(addr: usize, count: usize) {
+    if addr % 4 != 0 {
+        println!("address must be 4 byte aligned");
+        return;
+    }
+    for i in 0..count {
+        let at = addr + i * 4;
+        let value = unsafe { (at as *const u32).read_volatile() };
+        println!("{:#018x}: {:#010x}", at, value);
+    }
+}
+
+fn memory_write(addr: usize, value: usize) {
+    if addr % 4 != 0 {
+        println!("address must be 4 byte aligned");
+        return;
+    }
+    unsafe { (addr as *mut u32).write_volatile(value as u32) };
+}
+
+fn hart_states() {
+    let cpu_enabled = unsafe { PLATFORM.info.cpu_enabled };
+    for hart_id in 0..NUM_HART_MAX {
+        if !cpu_enabled.is_some_and(|enabled| enabled[hart_id]) {
+            continue;
+        }
+        let state = match remote_hsm(hart_id).map(|hsm| hsm.sbi_get_status()) {
+            Some(hart_state::STARTED) => "started",
+            Some(hart_state::STOPPED) => "stopped",
+            Some(hart_state::START_PENDING) => "start pending",
+            Some(hart_state::STOP_PENDING) => "stop pending",
+            Some(hart_state::SUSPENDED) => "suspended",
+            Some(hart_state::SUSPEND_PENDING) => "suspend pending",
+            Some(hart_state::RESUME_PENDING) => "resume pending",
+            _ => "unknown",
+        };
+        println!("hart {}: {}", hart_id, state);
+    }
+}
+
+fn send_ipi(hart_id: usize) {
+    match platform::ipi() {
+        Some(ipi) => {
+            let ret = ipi.send_ipi(hart_mask::from_hart_ids(core::iter::once(hart_id)));
+            if ret.is_err() {
+                println!("failed: error {}", ret.error as isize);
+            }
+        }
+        None => println!("no IPI device"),
+    }
+}
+
+/// Run one command line, returns false when the shell should exit.
+fn execute(line: &str, fdt_address: usize) -> bool {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return true;
+    };
+    let mut arg = || words.next().and_then(parse_number);
+    match command {
+        "help" => HELP.iter().for_each(|line| println!("{}", line)),
+        "mr" => match (arg(), arg()) {
+            (Some(addr), count) => memory_read(addr, count.unwrap_or(1)),
+            _ => println!("usage: mr <addr> [count]"),
+        },
+        "mw" => match (arg(), arg()) {
+            (Some(addr), Some(value)) => memory_write(addr, value),
+            _ => println!("usage: mw <addr> <value>"),
+        },
+        "csr" => match line.split_whitespace().nth(1).map(read_csr) {
+            Some(Some(value)) => println!("{:#018x}", value),
+            Some(None) => println!("unknown CSR"),
+            None => println!("usage: csr <name>"),
+        },
+        "dt" => {
+            if let Err(err) = fdt_dump::dump(fdt_address) {
+                println!("bad device tree: {:?}", err);
+            }
+        }
+        "harts" => hart_states(),
+        "ipi" => match arg() {
+            Some(hart_id) => send_ipi(hart_id),
+            None => println!("usage: ipi <hart>"),
+        },
+        "boot" | "exit" => return false,
+        _ => println!("unknown command, try help"),
+    }
+    true
+}
+
+/// Run the shell until the user leaves it with `boot`.
+pub fn run(fdt_address: usize) {
+    let mut buf = [0u8; LINE_MAX];
+    loop {
+        print!("prototyper> ");
+        let len = read_line(&mut buf);
+        // Only printable ASCII is stored.
+        let line = core::str::from_utf8(&buf[..len]).unwrap_or("");
+        if !execute(line, fdt_address) {
+            return;
+        }
+    }
+}
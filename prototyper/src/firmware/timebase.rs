@@ -0,0 +1,121 @@
+//! Timebase frequency calibration.
+//!
+//! Some vendor platforms describe `timebase-frequency` from the core clock,
+//! which stops being right once CPPC or DVFS scales the cores while mtime
+//! keeps its fixed rate. The boot hart measures mtime against a trusted
+//! reference clock and, if the device tree is off by more than the
+//! tolerance, uses the measured rate and corrects the tree for the next
+//! stage.
+//!
+//! The reference is the board's [`BoardHooks::reference_clock`], else a
+//! Goldfish RTC outside QEMU. QEMU runs the Goldfish RTC from the host clock
+//! and mtime from the virtual clock, which part ways whenever the guest is
+//! descheduled, so it is no reference there. Without a trusted reference
+//! nothing is changed.
+//!
+//! None of the boards in `boards/` has either: calibration is dormant on all
+//! of them until a port supplies a reference clock.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::platform::board::{Board, BoardHooks};
+use crate::platform::rtc::GoldfishRtc;
+use crate::platform::suspend::QEMU_VIRT_MODEL;
+use crate::platform::{self, PLATFORM};
+use crate::time;
+
+/// Length of one measurement, in real time.
+const CALIBRATION_NS: u64 = 10_000_000;
+/// Deviation from the device tree value tolerated, in parts per thousand.
+const TOLERANCE_PERMILLE: u64 = 20;
+/// Spread between two measurements tolerated, in parts per thousand.
+const AGREEMENT_PERMILLE: u64 = 2;
+/// Measured rates are rounded to this many Hz.
+const ROUNDING_HZ: u64 = 1_000;
+
+/// Corrected frequency for the device tree, 0 if the tree is right.
+static CORRECTED: AtomicU32 = AtomicU32::new(0);
+
+/// Measure the mtime rate in Hz over `CALIBRATION_NS` of `read_ns` time.
+///
+/// Returns `None` if the clock does not advance while mtime counts a full
+/// second at the device tree rate.
+fn measure(read_ns: impl Fn() -> u64, expected: u64) -> Option<u64> {
+    let start_ns = read_ns();
+    let start = time::current_ticks();
+    loop {
+        let elapsed_ns = read_ns().wrapping_sub(start_ns);
+        let ticks = time::current_ticks().wrapping_sub(start);
+        if elapsed_ns >= CALIBRATION_NS {
+            let freq = (ticks as u128 * 1_000_000_000 / elapsed_ns as u128) as u64;
+            return Some((freq + ROUNDING_HZ / 2) / ROUNDING_HZ * ROUNDING_HZ);
+        }
+        if ticks > expected {
+            return None;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Measure twice, so a reference that jumps or stalls is not taken at its word.
+fn measure_steady(read_ns: impl Fn() -> u64, expected: u64) -> Option<u64> {
+    let first = measure(&read_ns, expected)?;
+    let second = measure(&read_ns, expected)?;
+    if first.abs_diff(second) * 1000 > first * AGREEMENT_PERMILLE {
+        warn!(
+            "Reference clock is unsteady, measured {} Hz then {} Hz",
+            first, second
+        );
+        return None;
+    }
+    Some(second)
+}
+
+/// The Goldfish RTC, if there is one and the firmware is not on QEMU.
+fn trusted_rtc() -> Option<GoldfishRtc> {
+    let base = unsafe { PLATFORM.info.rtc }?;
+    if unsafe { PLATFORM.info.model.as_str() }.starts_with(QEMU_VIRT_MODEL) {
+        return None;
+    }
+    Some(GoldfishRtc::new(base))
+}
+
+/// Check the timebase frequency on the boot hart, once devices are up.
+pub fn calibrate() {
+    if platform::ipi().is_none() {
+        return;
+    }
+    let described = time::timebase_frequency();
+    let measured = if let Some(read_ns) = Board::reference_clock() {
+        measure_steady(read_ns, described)
+    } else if let Some(rtc) = trusted_rtc() {
+        measure_steady(|| rtc.read_ns(), described)
+    } else {
+        return;
+    };
+    let Some(measured) = measured else {
+        warn!("Reference clock unusable, timebase frequency left unchecked");
+        return;
+    };
+    if measured.abs_diff(described) * 1000 <= described * TOLERANCE_PERMILLE {
+        return;
+    }
+    let Ok(corrected) = u32::try_from(measured) else {
+        warn!("Measured timebase frequency {} Hz out of range", measured);
+        return;
+    };
+    warn!(
+        "Timebase frequency is {} Hz, not {} Hz as described, correcting",
+        measured, described
+    );
+    unsafe { PLATFORM.info.timebase_frequency = Some(measured) };
+    CORRECTED.store(corrected, Ordering::Relaxed);
+}
+
+/// Frequency the device tree should be corrected to, if calibration found it wrong.
+pub fn corrected() -> Option<u32> {
+    match CORRECTED.load(Ordering::Relaxed) {
+        0 => None,
+        freq => Some(freq),
+    }
+}
@@ -0,0 +1,149 @@
+//! Watchpoints on firmware data, for bring-up in debug builds.
+//!
+//! Spare Sdtrig triggers are set to fire on S-mode and U-mode stores into
+//! state the supervisor has no business touching: the platform devices,
+//! including the IPI device, and the hart stacks, which hold the HSM state.
+//! PMP already denies these writes, so a hit points at a PMP hole or a
+//! misconfigured region while bringing up a board. Stores by DMA masters
+//! never match the triggers of a hart and are not caught.
+//!
+//! Triggers are claimed from the highest index down, so debuggers and future
+//! S-mode trigger users keep the low ones.
+
+use core::mem::{size_of, size_of_val};
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::platform::{Platform, PLATFORM};
+use crate::riscv_spec::trigger::{self, CSR_TSELECT};
+use crate::sbi::trap_stack::ROOT_STACK;
+
+/// Most triggers looked at.
+const MAX_TRIGGERS: usize = usize::BITS as usize;
+
+const TDATA1_ENABLES: usize = trigger::TDATA1_M
+    | trigger::TDATA1_S
+    | trigger::TDATA1_U
+    | trigger::TDATA1_EXECUTE
+    | trigger::TDATA1_STORE
+    | trigger::TDATA1_LOAD;
+
+percpu! {
+    /// Triggers armed on each hart, one bit per trigger index.
+    static ARMED: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Firmware data that S-mode must never write.
+fn watched_regions() -> [Range<usize>; 2] {
+    let platform = core::ptr::addr_of!(PLATFORM) as usize;
+    let stacks = core::ptr::addr_of!(ROOT_STACK);
+    [
+        platform..platform + size_of::<Platform>(),
+        stacks as usize..stacks as usize + size_of_val(unsafe { &*stacks }),
+    ]
+}
+
+/// Fields that must read back as written for a watchpoint to work.
+const TDATA1_CHECKED: usize = TDATA1_ENABLES | trigger::TDATA1_CHAIN | (0xf << 7);
+
+/// Type of the selected trigger if it is an unused address match trigger.
+fn spare_trigger_type() -> Option<usize> {
+    let tdata1 = trigger::read_tdata1();
+    let kind = tdata1 >> trigger::TDATA1_TYPE_SHIFT;
+    (matches!(kind, trigger::TYPE_MCONTROL | trigger::TYPE_MCONTROL6)
+        && tdata1 & TDATA1_ENABLES == 0)
+        .then_some(kind)
+}
+
+/// Program triggers `index` and `index + 1` to fire on stores into `region`.
+fn arm_pair(index: usize, kind: usize, region: &Range<usize>) -> bool {
+    let base = (kind << trigger::TDATA1_TYPE_SHIFT)
+        | trigger::TDATA1_S
+        | trigger::TDATA1_U
+        | trigger::TDATA1_STORE;
+    let first = base | trigger::TDATA1_MATCH_GE | trigger::TDATA1_CHAIN;
+    let second = base | trigger::TDATA1_MATCH_LT;
+    for (index, tdata1, tdata2) in [
+        (index, first, region.start),
+        (index + 1, second, region.end),
+    ] {
+        trigger::select(index);
+        trigger::write_tdata1(0);
+        trigger::write_tdata2(tdata2);
+        trigger::write_tdata1(tdata1);
+    }
+    // `tdata1` is WARL, the hart may not support range chains.
+    let accepted = [(index, first), (index + 1, second)]
+        .into_iter()
+        .all(|(index, tdata1)| {
+            trigger::select(index);
+            trigger::read_tdata1() & TDATA1_CHECKED == tdata1 & TDATA1_CHECKED
+        });
+    if !accepted {
+        for index in [index, index + 1] {
+            trigger::select(index);
+            trigger::write_tdata1(0);
+        }
+    }
+    accepted
+}
+
+/// Arm watchpoints on the current hart with its spare triggers.
+///
+/// Breakpoint exceptions stop being delegated once a watchpoint is armed,
+/// `check_hit` tells watchpoint hits from breakpoints meant for S-mode.
+pub fn init() {
+    if !has_csr!(CSR_TSELECT) {
+        return;
+    }
+    let mut spare = [None; MAX_TRIGGERS];
+    let mut count = 0;
+    while count < MAX_TRIGGERS && trigger::select(count) == count {
+        spare[count] = spare_trigger_type();
+        count += 1;
+    }
+
+    let mut armed = 0usize;
+    let mut next = count;
+    for region in watched_regions().iter() {
+        let pair = (1..next)
+            .rev()
+            .find(|&index| spare[index].is_some() && spare[index - 1] == spare[index]);
+        let Some(second) = pair else {
+            break;
+        };
+        let first = second - 1;
+        if arm_pair(first, spare[first].unwrap(), region) {
+            armed |= 0b11 << first;
+        }
+        next = first;
+    }
+    ARMED.local().store(armed, Ordering::Relaxed);
+    if armed != 0 {
+        unsafe { riscv::register::medeleg::clear_breakpoint() };
+    }
+    debug!(
+        "Watchpoints: {} of {} triggers armed",
+        armed.count_ones(),
+        count
+    );
+}
+
+/// Returns true if a watchpoint of the current hart fired, clearing its hit bit.
+pub fn check_hit() -> bool {
+    let armed = ARMED.local().load(Ordering::Relaxed);
+    let mut hit = false;
+    for index in (0..MAX_TRIGGERS).filter(|index| armed & (1 << index) != 0) {
+        trigger::select(index);
+        let tdata1 = trigger::read_tdata1();
+        let hit_bit = match tdata1 >> trigger::TDATA1_TYPE_SHIFT {
+            trigger::TYPE_MCONTROL6 => trigger::MCONTROL6_HIT0,
+            _ => trigger::MCONTROL_HIT,
+        };
+        if tdata1 & hit_bit != 0 {
+            trigger::write_tdata1(tdata1 & !hit_bit);
+            hit = true;
+        }
+    }
+    hit
+}
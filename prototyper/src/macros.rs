@@ -1,26 +1,16 @@
 #[allow(unused)]
 macro_rules! print {
     ($($arg:tt)*) => {
-        use core::fmt::Write;
-        if unsafe {$crate::platform::PLATFORM.have_console()} {
-            let console = unsafe { $crate::platform::PLATFORM.sbi.console.as_mut().unwrap() };
-            console.write_fmt(core::format_args!($($arg)*)).unwrap();
-            drop(console);
-        }
+        $crate::sbi::console::print(core::format_args!($($arg)*))
     }
 }
 
 #[allow(unused)]
 macro_rules! println {
     () => ($crate::print!("\n\r"));
-    ($($arg:tt)*) => {{
-        use core::fmt::Write;
-        if unsafe {$crate::platform::PLATFORM.have_console()} {
-            let console = unsafe { $crate::platform::PLATFORM.sbi.console.as_mut().unwrap() };
-            console.write_fmt(core::format_args!($($arg)*)).unwrap();
-            console.write_str("\n\r").unwrap();
-        }
-    }}
+    ($($arg:tt)*) => {
+        $crate::sbi::console::print(core::format_args!("{}\n\r", core::format_args!($($arg)*)))
+    }
 }
 
 #[allow(unused)]
@@ -47,3 +37,97 @@ macro_rules! has_csr {
             res == 0
     }};
 }
+
+/// Declare a static with one cache line aligned instance per hart.
+///
+/// ```ignore
+/// percpu! {
+///     static IPI_TYPE: AtomicU8 = AtomicU8::new(0);
+/// }
+/// IPI_TYPE.local().load(Ordering::Acquire);
+/// ```
+#[allow(unused)]
+macro_rules! percpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::sync::PerCpu<$ty> = $crate::sync::PerCpu::new(
+            [const { $crate::sync::CacheAligned::new($init) }; $crate::sbi::trap_stack::NUM_HART_MAX],
+        );
+    };
+}
+
+/// Size in bytes of a general purpose register, for assembly offsets.
+#[cfg(target_arch = "riscv64")]
+macro_rules! xlenb {
+    () => {
+        "8"
+    };
+}
+#[cfg(target_arch = "riscv32")]
+macro_rules! xlenb {
+    () => {
+        "4"
+    };
+}
+
+/// Full width register store and load mnemonics.
+#[cfg(target_arch = "riscv64")]
+macro_rules! reg_s {
+    () => {
+        "sd"
+    };
+}
+#[cfg(target_arch = "riscv64")]
+macro_rules! reg_l {
+    () => {
+        "ld"
+    };
+}
+#[cfg(target_arch = "riscv32")]
+macro_rules! reg_s {
+    () => {
+        "sw"
+    };
+}
+#[cfg(target_arch = "riscv32")]
+macro_rules! reg_l {
+    () => {
+        "lw"
+    };
+}
+
+/// Assembly storing `$reg` at the frame offset given by the asm operand
+/// named after `$field`, the register itself by default.
+macro_rules! frame_store {
+    ($reg: ident) => {
+        frame_store!($reg, $reg)
+    };
+    ($reg: ident, $field: ident) => {
+        concat!(
+            reg_s!(),
+            "     ",
+            stringify!($reg),
+            ", {",
+            stringify!($field),
+            "}(sp)"
+        )
+    };
+}
+
+/// Assembly loading `$reg` from the frame offset given by the asm operand
+/// named after `$field`, the register itself by default.
+macro_rules! frame_load {
+    ($reg: ident) => {
+        frame_load!($reg, $reg)
+    };
+    ($reg: ident, $field: ident) => {
+        concat!(
+            reg_l!(),
+            "     ",
+            stringify!($reg),
+            ", {",
+            stringify!($field),
+            "}(sp)"
+        )
+    };
+}
@@ -9,15 +9,24 @@ extern crate log;
 #[macro_use]
 mod macros;
 
+mod config;
 mod dt;
+mod error;
 mod fail;
 mod firmware;
+#[cfg(feature = "fast-mem")]
+mod mem;
 mod platform;
 mod riscv_spec;
 mod sbi;
+mod sync;
+mod time;
 
 use core::arch::asm;
 
+use crate::error::{FwError, FwResult};
+use crate::firmware::boot_profile::Phase;
+use crate::platform::board::{Board, BoardHooks};
 use crate::platform::PLATFORM;
 use crate::riscv_spec::{current_hartid, menvcfg};
 use crate::sbi::extensions::{
@@ -25,35 +34,62 @@ use crate::sbi::extensions::{
     PrivilegedVersion,
 };
 use crate::sbi::hart_context::NextStage;
-use crate::sbi::hsm::local_remote_hsm;
+use crate::sbi::hart_init::{self, InitState};
 use crate::sbi::ipi;
 use crate::sbi::trap::{self, trap_vec};
 use crate::sbi::trap_stack;
 
-pub const START_ADDRESS: usize = 0x80000000;
+pub const START_ADDRESS: usize = config::MEMORY_BASE;
 pub const R_RISCV_RELATIVE: usize = 3;
 
 #[no_mangle]
 extern "C" fn rust_main(_hart_id: usize, opaque: usize, nonstandard_a2: usize) {
+    if let Err(err) = init_hart(opaque, nonstandard_a2) {
+        fail::boot(err);
+    }
+}
+
+/// Bring up this hart, and the platform and the next stage first on the boot hart.
+fn init_hart(opaque: usize, nonstandard_a2: usize) -> FwResult {
+    trap_stack::paint_stack();
     // Track whether SBI is initialized and ready.
 
     let boot_hart_info = firmware::get_boot_hart(opaque, nonstandard_a2);
     // boot hart task entry.
     if boot_hart_info.is_boot_hart {
+        firmware::boot_profile::mark(Phase::Entry);
         // parse the device tree
         let fdt_address = boot_hart_info.fdt_address;
 
-        unsafe {
-            PLATFORM.init(fdt_address);
-            PLATFORM.print_board_info();
-        }
+        unsafe { PLATFORM.init(fdt_address) }?;
+        firmware::boot_profile::mark(Phase::Platform);
+        hart_init::advance(InitState::DevicesReady);
+        firmware::timebase::calibrate();
+        // The boot report is read-only from now on, a secondary hart prints it
+        // meanwhile.
+        firmware::deferred::post(|| unsafe { PLATFORM.print_board_info() });
 
-        firmware::set_pmp(unsafe { PLATFORM.info.memory_range.as_ref().unwrap() });
-        firmware::log_pmp_cfg(unsafe { PLATFORM.info.memory_range.as_ref().unwrap() });
+        let memory = platform::memory_range().ok_or(FwError::NoMemory)?;
+        Board::memory_init(memory);
+
+        firmware::fixup_device_tree(fdt_address);
+        sbi::update::record_boot_args(fdt_address, nonstandard_a2);
 
         // Get boot information and prepare for kernel entry.
         let boot_info = firmware::get_boot_info(nonstandard_a2);
         let (mpp, next_addr) = (boot_info.mpp, boot_info.next_address);
+        // The PMP of every hart keeps the window private, the others wait for it.
+        sbi::update::claim_window(fdt_address, next_addr);
+
+        firmware::set_pmp(memory)?;
+        firmware::deferred::post(firmware::log_pmp_layout);
+        firmware::boot_profile::mark(Phase::Pmp);
+
+        if firmware::memtest::enabled(unsafe { PLATFORM.info.memtest })
+            && !firmware::memtest::run(memory, fdt_address, next_addr)
+        {
+            error!("Memory test failed, continuing boot");
+        }
 
         // Log boot hart ID and PMP information
         let hart_id = current_hartid();
@@ -61,11 +97,72 @@ extern "C" fn rust_main(_hart_id: usize, opaque: usize, nonstandard_a2: usize) {
 
         // Detection Priv Ver
         privileged_version_detection();
+        // Before `mirror` below, which records what every hart probed.
+        firmware::mconfig::probe();
         let priv_version = hart_privileged_version(hart_id);
         info!("{:<30}: {:?}", "Boot HART Privileged Version", priv_version);
+        info!(
+            "{:<30}: {:?}",
+            "Counter Access Policy",
+            firmware::counter::CounterPolicy::current()
+        );
+        if hart_extension_probe(hart_id, Extension::Zkr) {
+            info!(
+                "{:<30}: {:?}",
+                "Seed Access Policy",
+                firmware::seed::SeedPolicy::current()
+            );
+        }
+        if hart_extension_probe(hart_id, Extension::Smepmp) && !cfg!(feature = "no-lockdown") {
+            info!("{:<30}: {}", "Machine Mode W^X", "Smepmp lockdown");
+        }
+        if hart_extension_probe(hart_id, Extension::Smrnmi) {
+            info!("{:<30}: 0x{:x}", "RNMI Handler", sbi::rnmi::entry_address());
+        }
+        match firmware::image_header::image_header() {
+            Some(header) => info!(
+                "{:<30}: version {:#x}, features {:#x}",
+                "Firmware Image Header", header.firmware_version, header.features
+            ),
+            None => warn!("{:<30}: Not Found", "Firmware Image Header"),
+        }
+        info!(
+            "{:<30}: {:?}",
+            "Next Stage Boot Protocol",
+            firmware::boot_protocol::BootProtocol::current()
+        );
+
+        #[cfg(feature = "boot-menu")]
+        firmware::boot_menu::run(fdt_address);
+
+        // Do not let the next stage wait for harts that never came up.
+        firmware::secondary::check_arrival(fdt_address);
+        firmware::boot_profile::mark(Phase::HartRelease);
+        #[cfg(not(feature = "fdt"))]
+        firmware::mconfig::mirror(fdt_address);
+
+        // Stop DMA a previous stage may have left running.
+        unsafe { PLATFORM.pci_prepare_handoff() };
+
+        // Hide from each supervisor what it does not own.
+        #[cfg(not(feature = "fdt"))]
+        firmware::fdt_domain::prepare(fdt_address, next_addr);
+
+        // Make the device tree and payload visible to the next stage.
+        firmware::cache::prepare_next_stage(fdt_address);
+
+        // Whatever no secondary hart took is done now, and nothing prints
+        // over the next stage.
+        firmware::deferred::finish();
+        firmware::boot_profile::mark(Phase::Jump);
+        firmware::boot_profile::print_summary();
+        firmware::mem_stats::print_summary();
 
-        // Start kernel.
-        local_remote_hsm().start(NextStage {
+        // Domains with a next stage of their own start along with the kernel.
+        firmware::amp::start_domains(fdt_address);
+
+        // Start kernel, on another hart if the supervisor needs it.
+        let boot_hart = firmware::hart_remap::start_next_stage(NextStage {
             start_addr: next_addr,
             next_mode: mpp,
             opaque: fdt_address,
@@ -73,48 +170,73 @@ extern "C" fn rust_main(_hart_id: usize, opaque: usize, nonstandard_a2: usize) {
 
         info!(
             "Redirecting hart {} to 0x{:0>16x} in {:?} mode.",
-            current_hartid(),
-            next_addr,
-            mpp
+            boot_hart, next_addr, mpp
         );
     } else {
         // Other harts task entry.
         trap_stack::prepare_for_trap();
 
         // Wait for boot hart to complete SBI initialization.
+        let mut backoff = sync::Backoff::new();
         while !unsafe { PLATFORM.ready() } {
-            core::hint::spin_loop()
+            backoff.spin();
         }
+        hart_init::advance(InitState::DevicesReady);
 
-        firmware::set_pmp(unsafe { PLATFORM.info.memory_range.as_ref().unwrap() });
+        firmware::set_pmp(platform::memory_range().ok_or(FwError::NoMemory)?)?;
+        privileged_version_detection();
+        // Before this hart reports SbiReady, which `mirror` waits for.
+        firmware::mconfig::probe();
     }
+    // Guard the hart context against stack overflows, now that entropy sources are up.
+    trap_stack::arm_canary();
     // Clear all pending IPIs.
     ipi::clear_all();
 
     // Configure CSRs and trap handling.
+    firmware::counter::init();
+    if hart_extension_probe(current_hartid(), Extension::Zkr) {
+        firmware::seed::init();
+    }
+    sbi::rnmi::init();
     unsafe {
         // Delegate all interrupts and exceptions to supervisor mode.
         asm!("csrw mideleg,    {}", in(reg) !0);
         asm!("csrw medeleg,    {}", in(reg) !0);
-        asm!("csrw mcounteren, {}", in(reg) !0);
-        asm!("csrw scounteren, {}", in(reg) !0);
         use riscv::register::{medeleg, mtvec};
         // Keep supervisor environment calls and illegal instructions in M-mode.
         medeleg::clear_supervisor_env_call();
         medeleg::clear_illegal_instruction();
         if hart_privileged_version(current_hartid()) >= PrivilegedVersion::Version1_12 {
             // Configure environment features based on available extensions.
+            let mut menvcfg_bits = menvcfg::CBIE_INVALIDATE | menvcfg::CBCFE | menvcfg::CBZE;
             if hart_extension_probe(current_hartid(), Extension::Sstc) {
-                menvcfg::set_bits(
-                    menvcfg::STCE | menvcfg::CBIE_INVALIDATE | menvcfg::CBCFE | menvcfg::CBZE,
-                );
-            } else {
-                menvcfg::set_bits(menvcfg::CBIE_INVALIDATE | menvcfg::CBCFE | menvcfg::CBZE);
+                menvcfg_bits |= menvcfg::STCE;
+            }
+            // Let S-mode program hpmcounters directly instead of trapping into firmware.
+            // The firmware has no SBI PMU extension to advertise this through,
+            // S-mode finds smcdeleg and ssccfg in the device tree ISA string.
+            if hart_extension_probe(current_hartid(), Extension::Smcdeleg)
+                && hart_extension_probe(current_hartid(), Extension::Ssccfg)
+            {
+                menvcfg_bits |= menvcfg::CDE;
             }
+            menvcfg::set_bits(menvcfg_bits);
         }
+        sbi::inject::init();
         // Set up vectored trap handling.
         mtvec::write(trap_vec as _, mtvec::TrapMode::Vectored);
     }
+    // Catch supervisor stores into firmware data while bringing up a board.
+    #[cfg(debug_assertions)]
+    firmware::watchpoint::init();
+    sbi::base_cache::fill();
+    hart_init::advance(InitState::SbiReady);
+    if !boot_hart_info.is_boot_hart {
+        firmware::deferred::run_pending();
+    }
+    // Last, M-mode keeps no direct access to supervisor memory afterwards.
+    firmware::lock_down(platform::memory_range().ok_or(FwError::NoMemory)?)
 }
 
 #[naked]
@@ -125,34 +247,42 @@ unsafe extern "C" fn start() -> ! {
         // 1. Turn off interrupt.
         "   csrw    mie, zero",
         // 2. Initialize programming language runtime.
-        // only clear bss if hartid matches preferred boot hart id.
+        // Only the preferred boot hart relocates and clears bss, every other
+        // hart must not touch any static until it sees the ready signal.
         "   csrr    t0, mhartid",
         "   bne     t0, zero, 4f",
         "   call    {relocation_update}",
-        "1:",
-        // 3. Hart 0 clear bss segment.
+        // 3. Hart 0 clears bss, which the linker script aligns to a register.
         "   lla     t0, sbi_bss_start
             lla     t1, sbi_bss_end
-         2: bgeu    t0, t1, 3f
-            sd      zero, 0(t0)
-            addi    t0, t0, 8
-            j       2b",
-        "3: ", // Hart 0 set bss ready signal.
-        "   lla     t0, 6f
+            bltu    t1, t0, 9f
+            or      t2, t0, t1",
+        concat!("   andi    t2, t2, ", xlenb!(), " - 1"),
+        "   bnez    t2, 9f
+         2: bgeu    t0, t1, 3f",
+        concat!("   ", reg_s!(), "      zero, 0(t0)"),
+        concat!("   addi    t0, t0, ", xlenb!()),
+        "   j       2b",
+        "3: ", // Hart 0 publishes relocated data and cleared bss, then sets the ready signal.
+        "   fence   rw, w
+            lla     t0, 6f
             li      t1, 1
-            amoadd.w t0, t1, 0(t0)
+            sw      t1, 0(t0)
             j       5f",
-        "4:", // Other harts are waiting for bss ready signal.
+        "4:", // Other harts are waiting for the ready signal.
         "   li      t1, 1
             lla     t0, 6f
             lw      t0, 0(t0)
-            bne     t0, t1, 4b",
+            bne     t0, t1, 4b
+            fence   r, rw",
         "5:",
          // 4. Prepare stack for each hart.
         "   call    {locate_stack}",
         "   call    {main}",
         "   csrw    mscratch, sp",
         "   j       {hart_boot}",
+        "9: wfi", // Bad bss bounds, stop here.
+        "   j       9b",
         "  .balign  4",
         "6:",  // bss ready signal.
         "  .word    0",
@@ -165,6 +295,10 @@ unsafe extern "C" fn start() -> ! {
 }
 
 // Handle relocations for position-independent code
+//
+// Runs before bss is cleared, so a malformed image stops the hart instead of
+// reporting anything: `.rela.dyn` must hold whole entries, and every target
+// must be an aligned word inside the loaded firmware.
 #[naked]
 unsafe extern "C" fn relocation_update() {
     asm!(
@@ -172,25 +306,43 @@ unsafe extern "C" fn relocation_update() {
         "   li t0, {START_ADDRESS}",
         "   lla t1, sbi_start",
         "   sub t2, t1, t0",
+        // Boot arguments are still in a0 to a2.
+        "   lla a3, sbi_start",
+        "   lla a4, sbi_end",
 
         // Foreach rela.dyn and update relocation.
         "   lla t0, __rel_dyn_start",
         "   lla t1, __rel_dyn_end",
+        "   bltu t1, t0, 9f",
+        "   sub t4, t1, t0",
+        concat!("   li t5, 3*", xlenb!()),
+        "   remu t4, t4, t5",
+        "   bnez t4, 9f",
         "   li  t3, {R_RISCV_RELATIVE}",
-        "1:",
-        "   ld  t4, 8(t0)",
+        // Rela entries are r_offset, r_info and r_addend, one register each.
+        "1: bgeu t0, t1, 3f",
+        concat!("   ", reg_l!(), " t4, 1*", xlenb!(), "(t0)"),
         "   bne t4, t3, 2f",
-        "   ld t4, 0(t0)", // Get offset
-        "   ld t5, 16(t0)", // Get append
+        concat!("   ", reg_l!(), " t4, 0(t0)"), // Get offset
+        concat!("   ", reg_l!(), " t5, 2*", xlenb!(), "(t0)"), // Get append
         "   add t4, t4, t2", // Add load offset to offset add append
         "   add t5, t5, t2",
-        "   sd t5, 0(t4)", // Update address
-        "   addi t0, t0, 24", // Get next rela item
+        // The target must be an aligned word of the firmware.
+        "   bltu t4, a3, 9f",
+        "   bgeu t4, a4, 9f",
+        concat!("   addi t6, t4, ", xlenb!()),
+        "   bltu a4, t6, 9f",
+        concat!("   andi t6, t4, ", xlenb!(), " - 1"),
+        "   bnez t6, 9f",
+        concat!("   ", reg_s!(), " t5, 0(t4)"), // Update address
         "2:",
-        "   blt t0, t1, 1b",
+        concat!("   addi t0, t0, 3*", xlenb!()), // Get next rela item
+        "   j 1b",
 
         // Return
-        "   ret",
+        "3: ret",
+        "9: wfi", // Malformed relocations, stop here.
+        "   j 9b",
         R_RISCV_RELATIVE = const R_RISCV_RELATIVE,
         START_ADDRESS = const START_ADDRESS,
         options(noreturn)
@@ -200,12 +352,22 @@ unsafe extern "C" fn relocation_update() {
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     use riscv::register::*;
+    sbi::quarantine::release_locks();
+    sbi::crashdump::write(None);
+    // A dying firmware needs the console more than the supervisor does.
+    sbi::logger::set_supervisor_owns_console(false);
     error!("Hart {} {info}", riscv::register::mhartid::read());
     error!("-----------------------------");
     error!("mcause:  {:?}", mcause::read().cause());
     error!("mepc:    {:#018x}", mepc::read());
     error!("mtval:   {:#018x}", mtval::read());
     error!("-----------------------------");
+    // Neither path below returns, let the report out of the DMA ring first.
+    sbi::console_dma::drain(sbi::console_dma::DRAIN_TIMEOUT_US);
+    if sbi::quarantine::available() {
+        sbi::quarantine::enter();
+    }
     error!("System shutdown scheduled due to RustSBI panic");
+    sbi::console_dma::drain(sbi::console_dma::DRAIN_TIMEOUT_US);
     loop {}
 }
@@ -0,0 +1,287 @@
+//! Word sized `memcpy`, `memmove` and `memset`.
+//!
+//! With the `fast-mem` feature these replace the weak, generic routines of
+//! `compiler_builtins` used by device tree fixups, tree copies and firmware
+//! updates. Mutually aligned buffers are copied eight registers at a time,
+//! buffers aligned to four bytes a word at a time, anything else bytewise.
+//! When every hart has Zicboz with the same block size, `memset` to zero
+//! clears whole blocks with `cbo.zero`.
+//!
+//! The routines are written in assembly: in Rust, the compiler would turn
+//! their loops back into calls to themselves.
+
+use core::arch::global_asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_device_tree::buildin::NodeSeq;
+
+use crate::dt::Cpu;
+use crate::sbi::extensions::{hart_extension_probe, Extension};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Size in bytes of a register.
+const WORD: usize = size_of::<usize>();
+/// Bytes moved by one iteration of the unrolled loops.
+const BLOCK: usize = 8 * WORD;
+
+/// Zicboz block size of every hart in bytes, 0 to never use `cbo.zero`.
+static CBO_ZERO_BLOCK: AtomicUsize = AtomicUsize::new(0);
+
+/// Let `memset` use `cbo.zero` if every hart in `cpus` has Zicboz blocks of
+/// one size; any hart may call it.
+pub fn init(cpus: &NodeSeq) {
+    let mut common = None;
+    for cpu in cpus.iter() {
+        let cpu = cpu.deserialize::<Cpu>();
+        let Some(hart_id) = cpu.reg.iter().next().map(|reg| reg.0.start) else {
+            return;
+        };
+        // Harts without a stack never run the firmware.
+        if hart_id >= NUM_HART_MAX {
+            continue;
+        }
+        let block = cpu.cboz_block_size.unwrap_or(0) as usize;
+        if !hart_extension_probe(hart_id, Extension::Zicboz)
+            || !block.is_power_of_two()
+            || block % WORD != 0
+            || common.is_some_and(|common| common != block)
+        {
+            return;
+        }
+        common = Some(block);
+    }
+    if let Some(block) = common {
+        CBO_ZERO_BLOCK.store(block, Ordering::Relaxed);
+        info!("{:<30}: {} bytes", "Zicboz Zeroing Block", block);
+    }
+}
+
+// Registers are moved through t0 to t5, a3 and a4; t6 is the destination
+// cursor, so a0 is returned untouched.
+global_asm!(
+    ".pushsection .text.fast_mem, \"ax\"",
+    ".global memcpy",
+    ".type memcpy, @function",
+    "memcpy:",
+    "   mv      t6, a0",
+    "   li      t0, {small}",
+    "   bltu    a2, t0, 8f",
+    "   xor     t0, t6, a1",
+    "   andi    t1, t0, {word} - 1",
+    "   bnez    t1, 6f",
+    // Align the destination, and with it the source.
+    "1: andi    t0, t6, {word} - 1",
+    "   beqz    t0, 2f",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(t6)",
+    "   addi    t6, t6, 1",
+    "   addi    a1, a1, 1",
+    "   addi    a2, a2, -1",
+    "   j       1b",
+    "2: li      a5, {block}",
+    "   bltu    a2, a5, 4f",
+    concat!("3: ", reg_l!(), "      t0, 0*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t1, 1*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t2, 2*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t3, 3*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t4, 4*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t5, 5*{word}(a1)"),
+    concat!("   ", reg_l!(), "      a3, 6*{word}(a1)"),
+    concat!("   ", reg_l!(), "      a4, 7*{word}(a1)"),
+    concat!("   ", reg_s!(), "      t0, 0*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t1, 1*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t2, 2*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t3, 3*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t4, 4*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t5, 5*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a3, 6*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a4, 7*{word}(t6)"),
+    "   add     t6, t6, a5",
+    "   add     a1, a1, a5",
+    "   sub     a2, a2, a5",
+    "   bgeu    a2, a5, 3b",
+    "4: li      a5, {word}",
+    "5: bltu    a2, a5, 8f",
+    concat!("   ", reg_l!(), "      t0, 0(a1)"),
+    concat!("   ", reg_s!(), "      t0, 0(t6)"),
+    "   add     t6, t6, a5",
+    "   add     a1, a1, a5",
+    "   sub     a2, a2, a5",
+    "   j       5b",
+    // Only aligned to each other by four bytes.
+    "6: andi    t1, t0, 3",
+    "   bnez    t1, 8f",
+    "7: andi    t0, t6, 3",
+    "   beqz    t0, 10f",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(t6)",
+    "   addi    t6, t6, 1",
+    "   addi    a1, a1, 1",
+    "   addi    a2, a2, -1",
+    "   j       7b",
+    "10: li     a5, 4",
+    "11: bltu   a2, a5, 8f",
+    "   lw      t0, 0(a1)",
+    "   sw      t0, 0(t6)",
+    "   addi    t6, t6, 4",
+    "   addi    a1, a1, 4",
+    "   addi    a2, a2, -4",
+    "   j       11b",
+    "8: beqz    a2, 9f",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(t6)",
+    "   addi    t6, t6, 1",
+    "   addi    a1, a1, 1",
+    "   addi    a2, a2, -1",
+    "   j       8b",
+    "9: ret",
+    ".size memcpy, . - memcpy",
+
+    ".global memmove",
+    ".type memmove, @function",
+    "memmove:",
+    // Copying forward is safe unless the destination starts inside the source.
+    "   sub     t0, a0, a1",
+    "   bgeu    t0, a2, memcpy",
+    // Copy backward from the ends.
+    "   add     t6, a0, a2",
+    "   add     a1, a1, a2",
+    "   li      t0, {small}",
+    "   bltu    a2, t0, 8f",
+    "   xor     t0, t6, a1",
+    "   andi    t1, t0, {word} - 1",
+    "   bnez    t1, 6f",
+    "1: andi    t0, t6, {word} - 1",
+    "   beqz    t0, 2f",
+    "   addi    t6, t6, -1",
+    "   addi    a1, a1, -1",
+    "   addi    a2, a2, -1",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(t6)",
+    "   j       1b",
+    "2: li      a5, {block}",
+    "   bltu    a2, a5, 4f",
+    "3: sub     t6, t6, a5",
+    "   sub     a1, a1, a5",
+    "   sub     a2, a2, a5",
+    concat!("   ", reg_l!(), "      t0, 0*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t1, 1*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t2, 2*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t3, 3*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t4, 4*{word}(a1)"),
+    concat!("   ", reg_l!(), "      t5, 5*{word}(a1)"),
+    concat!("   ", reg_l!(), "      a3, 6*{word}(a1)"),
+    concat!("   ", reg_l!(), "      a4, 7*{word}(a1)"),
+    concat!("   ", reg_s!(), "      t0, 0*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t1, 1*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t2, 2*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t3, 3*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t4, 4*{word}(t6)"),
+    concat!("   ", reg_s!(), "      t5, 5*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a3, 6*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a4, 7*{word}(t6)"),
+    "   bgeu    a2, a5, 3b",
+    "4: li      a5, {word}",
+    "5: bltu    a2, a5, 8f",
+    "   sub     t6, t6, a5",
+    "   sub     a1, a1, a5",
+    "   sub     a2, a2, a5",
+    concat!("   ", reg_l!(), "      t0, 0(a1)"),
+    concat!("   ", reg_s!(), "      t0, 0(t6)"),
+    "   j       5b",
+    "6: andi    t1, t0, 3",
+    "   bnez    t1, 8f",
+    "7: andi    t0, t6, 3",
+    "   beqz    t0, 10f",
+    "   addi    t6, t6, -1",
+    "   addi    a1, a1, -1",
+    "   addi    a2, a2, -1",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(t6)",
+    "   j       7b",
+    "10: li     a5, 4",
+    "11: bltu   a2, a5, 8f",
+    "   addi    t6, t6, -4",
+    "   addi    a1, a1, -4",
+    "   addi    a2, a2, -4",
+    "   lw      t0, 0(a1)",
+    "   sw      t0, 0(t6)",
+    "   j       11b",
+    "8: beqz    a2, 9f",
+    "   addi    t6, t6, -1",
+    "   addi    a1, a1, -1",
+    "   addi    a2, a2, -1",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(t6)",
+    "   j       8b",
+    "9: ret",
+    ".size memmove, . - memmove",
+
+    ".global memset",
+    ".type memset, @function",
+    "memset:",
+    "   mv      t6, a0",
+    "   andi    a1, a1, 0xff",
+    "   li      t0, {small}",
+    "   bltu    a2, t0, 8f",
+    // Repeat the byte over the whole register.
+    "   li      t0, {splat}",
+    "   mul     a1, a1, t0",
+    "1: andi    t0, t6, {word} - 1",
+    "   beqz    t0, 2f",
+    "   sb      a1, 0(t6)",
+    "   addi    t6, t6, 1",
+    "   addi    a2, a2, -1",
+    "   j       1b",
+    "2: bnez    a1, 4f",
+    "   lla     t0, {cbo_zero_block}",
+    concat!("   ", reg_l!(), "      t0, 0(t0)"),
+    "   beqz    t0, 4f",
+    "   addi    t1, t0, -1",
+    // Zero words up to a block boundary, then whole blocks.
+    "10: bltu   a2, t0, 4f",
+    "   and     t2, t6, t1",
+    "   beqz    t2, 11f",
+    concat!("   ", reg_s!(), "      zero, 0(t6)"),
+    "   addi    t6, t6, {word}",
+    "   addi    a2, a2, -{word}",
+    "   j       10b",
+    // cbo.zero (t6), CBO function 4 in the immediate
+    "11: .insn i 0x0f, 2, x0, t6, 4",
+    "   add     t6, t6, t0",
+    "   sub     a2, a2, t0",
+    "   bgeu    a2, t0, 11b",
+    "4: li      a5, {block}",
+    "   bltu    a2, a5, 6f",
+    concat!("3: ", reg_s!(), "      a1, 0*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 1*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 2*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 3*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 4*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 5*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 6*{word}(t6)"),
+    concat!("   ", reg_s!(), "      a1, 7*{word}(t6)"),
+    "   add     t6, t6, a5",
+    "   sub     a2, a2, a5",
+    "   bgeu    a2, a5, 3b",
+    "6: li      a5, {word}",
+    "7: bltu    a2, a5, 8f",
+    concat!("   ", reg_s!(), "      a1, 0(t6)"),
+    "   add     t6, t6, a5",
+    "   sub     a2, a2, a5",
+    "   j       7b",
+    "8: beqz    a2, 9f",
+    "   sb      a1, 0(t6)",
+    "   addi    t6, t6, 1",
+    "   addi    a2, a2, -1",
+    "   j       8b",
+    "9: ret",
+    ".size memset, . - memset",
+    ".popsection",
+    word = const WORD,
+    block = const BLOCK,
+    small = const 2 * WORD,
+    splat = const usize::MAX / 0xff,
+    cbo_zero_block = sym CBO_ZERO_BLOCK,
+);
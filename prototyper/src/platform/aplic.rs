@@ -0,0 +1,196 @@
+use crate::sbi::irq::IrqController;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+pub(crate) const APLIC_COMPATIBLE: [&str; 1] = ["riscv,aplic"];
+
+const DOMAINCFG: usize = 0x0;
+/// Interrupt enable of the domain.
+const DOMAINCFG_IE: u32 = 1 << 8;
+const SOURCECFG_STRIDE: usize = 0x4;
+/// Source mode: active, asserted at high level.
+const SOURCECFG_LEVEL_HIGH: u32 = 6;
+/// Source delegated to the child domain in the low bits.
+const SOURCECFG_D: u32 = 1 << 10;
+/// Highest source number an APLIC may implement.
+const SOURCES_MAX: u32 = 1023;
+const SETIENUM: usize = 0x1edc;
+const CLRIENUM: usize = 0x1fdc;
+const TARGET_STRIDE: usize = 0x4;
+const TARGET_BASE: usize = 0x3000;
+const TARGET_HART_SHIFT: u32 = 18;
+const IDC_OFFSET: usize = 0x4000;
+const IDC_STRIDE: usize = 0x20;
+const IDC_IDELIVERY: usize = 0x00;
+const IDC_ITHRESHOLD: usize = 0x08;
+const IDC_CLAIMI: usize = 0x1c;
+/// Interrupt identity field of `claimi`.
+const CLAIMI_ID_SHIFT: u32 = 16;
+
+/// Marks harts without an interrupt delivery control.
+const NO_IDC: u16 = u16::MAX;
+/// Number of `riscv,delegation` entries kept.
+const MAX_DELEGATIONS: usize = 4;
+
+/// A range of sources handed to a child domain.
+#[derive(Clone, Copy, Debug)]
+struct Delegation {
+    child: u32,
+    first: u32,
+    last: u32,
+}
+
+/// The machine level domain of an APLIC, the interrupt delivery control of
+/// each hart and the sources delegated to child domains.
+#[derive(Clone, Copy, Debug)]
+pub struct AplicInfo {
+    pub base: usize,
+    /// The domain forwards interrupts as MSIs instead of delivering them.
+    pub msi: bool,
+    idcs: [u16; NUM_HART_MAX],
+    delegations: [Option<Delegation>; MAX_DELEGATIONS],
+}
+
+impl AplicInfo {
+    /// An APLIC domain with no interrupt delivery control yet.
+    pub fn new(base: usize) -> Self {
+        Self {
+            base,
+            msi: false,
+            idcs: [NO_IDC; NUM_HART_MAX],
+            delegations: [None; MAX_DELEGATIONS],
+        }
+    }
+
+    /// Hand sources `first` to `last` to child domain `child`. Returns false
+    /// if the range is not one of sources, or no more ranges are kept.
+    pub fn delegate(&mut self, child: u32, first: u32, last: u32) -> bool {
+        if first == 0 || first > last || last > SOURCES_MAX {
+            return false;
+        }
+        match self.delegations.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Delegation { child, first, last });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record interrupt delivery control `idc` as the one of `hart_id`.
+    pub fn set_idc(&mut self, hart_id: usize, idc: usize) {
+        if let (Some(slot), Ok(idc)) = (self.idcs.get_mut(hart_id), u16::try_from(idc)) {
+            *slot = idc;
+        }
+    }
+
+    /// Number of harts with an interrupt delivery control.
+    pub fn idc_count(&self) -> usize {
+        self.idcs.iter().filter(|&&idc| idc != NO_IDC).count()
+    }
+}
+
+/// Advanced Platform-Level Interrupt Controller, machine level domain in
+/// direct delivery mode.
+///
+/// Sources the firmware takes are configured active high level and kept
+/// in this domain. A source has one priority, the lowest, and is delivered
+/// to a single hart. Reading `claimi` clears the pending bit of the source
+/// claimed, so completion has nothing left to do.
+#[derive(Clone, Copy)]
+pub struct Aplic {
+    info: AplicInfo,
+}
+
+impl Aplic {
+    #[inline]
+    pub const fn new(info: AplicInfo) -> Self {
+        Self { info }
+    }
+
+    /// Delegate the sources listed by the device tree to their child domains.
+    ///
+    /// Sources the firmware later enables are taken back into this domain.
+    pub fn delegate_sources(&self) {
+        for delegation in self.info.delegations.iter().flatten() {
+            for irq in delegation.first..=delegation.last {
+                unsafe {
+                    self.reg(irq as usize * SOURCECFG_STRIDE)
+                        .write_volatile(SOURCECFG_D | delegation.child);
+                }
+            }
+        }
+    }
+
+    /// Interrupt delivery control of a hart, if it has one.
+    #[inline]
+    fn idc(&self, hart_id: usize) -> Option<usize> {
+        match self.info.idcs.get(hart_id) {
+            Some(&idc) if idc != NO_IDC => Some(idc as usize),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.info.base + offset) as *mut u32
+    }
+
+    #[inline]
+    fn idc_reg(&self, hart_id: usize, offset: usize) -> Option<*mut u32> {
+        let idc = self.idc(hart_id)?;
+        Some(self.reg(IDC_OFFSET + idc * IDC_STRIDE + offset))
+    }
+}
+
+impl IrqController for Aplic {
+    // The priority goes into the target register, written on enable.
+    #[inline]
+    fn set_priority(&self, _irq: usize, _priority: u32) {}
+
+    // Harts without an interrupt delivery control take no interrupts from the APLIC.
+    #[inline]
+    fn enable(&self, hart_id: usize, irq: usize) {
+        let Some(idc) = self.idc(hart_id) else {
+            return;
+        };
+        unsafe {
+            self.reg(irq * SOURCECFG_STRIDE)
+                .write_volatile(SOURCECFG_LEVEL_HIGH);
+            self.reg(TARGET_BASE + irq * TARGET_STRIDE)
+                .write_volatile(((idc as u32) << TARGET_HART_SHIFT) | 1);
+            self.reg(SETIENUM).write_volatile(irq as u32);
+            let domaincfg = self.reg(DOMAINCFG);
+            domaincfg.write_volatile(domaincfg.read_volatile() | DOMAINCFG_IE);
+        }
+    }
+
+    #[inline]
+    fn disable(&self, _hart_id: usize, irq: usize) {
+        unsafe { self.reg(CLRIENUM).write_volatile(irq as u32) }
+    }
+
+    #[inline]
+    fn set_threshold(&self, hart_id: usize, threshold: u32) {
+        if let (Some(ithreshold), Some(idelivery)) = (
+            self.idc_reg(hart_id, IDC_ITHRESHOLD),
+            self.idc_reg(hart_id, IDC_IDELIVERY),
+        ) {
+            unsafe {
+                ithreshold.write_volatile(threshold);
+                idelivery.write_volatile(1);
+            }
+        }
+    }
+
+    #[inline]
+    fn claim(&self, hart_id: usize) -> Option<usize> {
+        let reg = self.idc_reg(hart_id, IDC_CLAIMI)?;
+        match unsafe { reg.read_volatile() } >> CLAIMI_ID_SHIFT {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+
+    #[inline]
+    fn complete(&self, _hart_id: usize, _irq: usize) {}
+}
@@ -0,0 +1,42 @@
+//! Hooks for board ports.
+//!
+//! Some boards need code of their own during cold boot, such as finishing
+//! DRAM controller setup or zeroing memory so ECC check bits become valid
+//! before anything reads it. A port implements [`BoardHooks`] and points
+//! [`Board`] at its implementation; the default methods do nothing.
+//!
+//! The firmware itself and the device tree live in memory usable at entry,
+//! so these hooks only cover RAM beyond them.
+
+use core::ops::Range;
+
+/// Board specific steps of cold boot, all run on the boot hart.
+pub trait BoardHooks {
+    /// Bring up `memory`, the RAM described by the device tree.
+    ///
+    /// Called once the console and the device tree are available and any
+    /// crash dump has been read, while the other harts are still waiting.
+    /// Device tree fixups, PMP setup and the memory test all follow, so
+    /// this is the last point before the firmware writes RAM outside its
+    /// own image. Regions still needed afterwards, the device tree, crash
+    /// dump region and the next stage, must be left intact.
+    fn memory_init(_memory: &Range<usize>) {}
+
+    /// A clock in nanoseconds that runs independently of mtime and the core
+    /// clock, such as a crystal driven RTC.
+    ///
+    /// Timebase calibration measures mtime against it and corrects the
+    /// device tree if it is off. Boards without a clock they can vouch for
+    /// keep the default, and calibration leaves the tree alone.
+    fn reference_clock() -> Option<fn() -> u64> {
+        None
+    }
+}
+
+/// Boards without special requirements.
+pub struct Generic;
+
+impl BoardHooks for Generic {}
+
+/// Hooks of the board this firmware is built for.
+pub type Board = Generic;
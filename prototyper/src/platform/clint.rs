@@ -1,10 +1,60 @@
 use aclint::SifiveClint;
 use core::arch::asm;
+use core::ops::Range;
 use xuantie_riscv::peripheral::clint::THeadClint;
 
 use crate::sbi::ipi::IpiDevice;
 pub(crate) const CLINT_COMPATIBLE: [&str; 1] = ["riscv,clint0"];
 
+/// Most CLINT instances a platform may describe.
+pub(crate) const MAX_CLINTS: usize = 4;
+
+/// Offsets of the timer registers in a SiFive CLINT.
+#[cfg(target_arch = "riscv32")]
+const SIFIVE_MTIMECMP_OFFSET: usize = 0x4000;
+#[cfg(target_arch = "riscv32")]
+const SIFIVE_MTIME_OFFSET: usize = 0xbff8;
+
+/// Read a 64-bit counter with two 32-bit accesses, retrying if the high half
+/// changed while the low half was read.
+#[cfg(target_arch = "riscv32")]
+#[inline(always)]
+unsafe fn read_u64_split(addr: usize) -> u64 {
+    let lo = addr as *const u32;
+    let hi = (addr + 4) as *const u32;
+    loop {
+        let high = hi.read_volatile();
+        let low = lo.read_volatile();
+        if hi.read_volatile() == high {
+            return ((high as u64) << 32) | low as u64;
+        }
+    }
+}
+
+/// Write a 64-bit compare value with 32-bit accesses. Raising the high half
+/// first keeps the register from matching a mix of the old and new halves.
+#[cfg(target_arch = "riscv32")]
+#[inline(always)]
+unsafe fn write_mtimecmp_split(addr: usize, val: u64) {
+    let lo = addr as *mut u32;
+    let hi = (addr + 4) as *mut u32;
+    hi.write_volatile(u32::MAX);
+    lo.write_volatile(val as u32);
+    hi.write_volatile((val >> 32) as u32);
+}
+
+/// Write a 64-bit counter with 32-bit accesses. Clearing the low half first
+/// keeps it from carrying into the high half between the writes.
+#[cfg(target_arch = "riscv32")]
+#[inline(always)]
+unsafe fn write_mtime_split(addr: usize, val: u64) {
+    let lo = addr as *mut u32;
+    let hi = (addr + 4) as *mut u32;
+    lo.write_volatile(0);
+    hi.write_volatile((val >> 32) as u32);
+    lo.write_volatile(val as u32);
+}
+
 #[doc(hidden)]
 #[allow(unused)]
 #[derive(Clone, Copy, Debug)]
@@ -13,6 +63,17 @@ pub enum MachineClintType {
     TheadClint,
 }
 
+/// A CLINT found in the device tree and the harts it serves.
+#[derive(Clone, Copy, Debug)]
+pub struct ClintInfo {
+    pub base: usize,
+    pub kind: MachineClintType,
+    /// First hart served, it uses index 0 of the instance.
+    pub first_hart: usize,
+    /// Number of harts served.
+    pub hart_count: usize,
+}
+
 #[doc(hidden)]
 #[allow(unused)]
 pub enum MachineClint {
@@ -25,7 +86,13 @@ impl IpiDevice for MachineClint {
     #[inline(always)]
     fn read_mtime(&self) -> u64 {
         match self {
+            #[cfg(target_arch = "riscv64")]
             Self::SiFive(sifive_clint) => unsafe { (**sifive_clint).read_mtime() },
+            #[cfg(target_arch = "riscv32")]
+            Self::SiFive(sifive_clint) => unsafe {
+                read_u64_split(*sifive_clint as usize + SIFIVE_MTIME_OFFSET)
+            },
+            #[cfg(target_arch = "riscv64")]
             Self::THead(_) => unsafe {
                 let mut mtime: u64 = 0;
                 asm!(
@@ -34,13 +101,35 @@ impl IpiDevice for MachineClint {
                 );
                 mtime
             },
+            #[cfg(target_arch = "riscv32")]
+            Self::THead(_) => loop {
+                let (high, low, again): (u32, u32, u32);
+                unsafe {
+                    asm!(
+                        "rdtimeh {0}",
+                        "rdtime  {1}",
+                        "rdtimeh {2}",
+                        out(reg) high,
+                        out(reg) low,
+                        out(reg) again,
+                    )
+                };
+                if high == again {
+                    break ((high as u64) << 32) | low as u64;
+                }
+            },
         }
     }
 
     #[inline(always)]
     fn write_mtime(&self, val: u64) {
         match self {
+            #[cfg(target_arch = "riscv64")]
             Self::SiFive(sifive_clint) => unsafe { (**sifive_clint).write_mtime(val) },
+            #[cfg(target_arch = "riscv32")]
+            Self::SiFive(sifive_clint) => unsafe {
+                write_mtime_split(*sifive_clint as usize + SIFIVE_MTIME_OFFSET, val)
+            },
             Self::THead(_) => {
                 unimplemented!()
             }
@@ -50,7 +139,12 @@ impl IpiDevice for MachineClint {
     #[inline(always)]
     fn read_mtimecmp(&self, hart_idx: usize) -> u64 {
         match self {
+            #[cfg(target_arch = "riscv64")]
             Self::SiFive(sifive_clint) => unsafe { (**sifive_clint).read_mtimecmp(hart_idx) },
+            #[cfg(target_arch = "riscv32")]
+            Self::SiFive(sifive_clint) => unsafe {
+                read_u64_split(*sifive_clint as usize + SIFIVE_MTIMECMP_OFFSET + 8 * hart_idx)
+            },
             Self::THead(thead_clint) => unsafe { (**thead_clint).read_mtimecmp(hart_idx) },
         }
     }
@@ -58,7 +152,15 @@ impl IpiDevice for MachineClint {
     #[inline(always)]
     fn write_mtimecmp(&self, hart_idx: usize, val: u64) {
         match self {
+            #[cfg(target_arch = "riscv64")]
             Self::SiFive(sifive_clint) => unsafe { (**sifive_clint).write_mtimecmp(hart_idx, val) },
+            #[cfg(target_arch = "riscv32")]
+            Self::SiFive(sifive_clint) => unsafe {
+                write_mtimecmp_split(
+                    *sifive_clint as usize + SIFIVE_MTIMECMP_OFFSET + 8 * hart_idx,
+                    val,
+                )
+            },
             Self::THead(thead_clint) => unsafe { (**thead_clint).write_mtimecmp(hart_idx, val) },
         }
     }
@@ -87,3 +189,94 @@ impl IpiDevice for MachineClint {
         }
     }
 }
+
+/// All CLINT instances of the platform, each serving a range of harts.
+///
+/// Chiplet and multi-socket parts carry one CLINT per die. Per-hart registers
+/// are routed to the instance serving the hart, indexed relative to its first
+/// hart. The instances' `mtime` counters are expected to run in lockstep, so
+/// reads use the first one and writes go to all of them.
+pub struct MachineClintSet {
+    clints: [Option<(Range<usize>, MachineClint)>; MAX_CLINTS],
+}
+
+impl MachineClintSet {
+    pub fn new(infos: &[Option<ClintInfo>; MAX_CLINTS]) -> Self {
+        let mut clints = [const { None }; MAX_CLINTS];
+        for (slot, info) in clints.iter_mut().zip(infos) {
+            *slot = info.map(|info| {
+                let clint = match info.kind {
+                    MachineClintType::SiFiveClint => MachineClint::SiFive(info.base as _),
+                    MachineClintType::TheadClint => MachineClint::THead(info.base as _),
+                };
+                (info.first_hart..info.first_hart + info.hart_count, clint)
+            });
+        }
+        Self { clints }
+    }
+
+    /// Instance serving `hart_id` and the hart's index within it.
+    #[inline]
+    fn route(&self, hart_id: usize) -> Option<(&MachineClint, usize)> {
+        self.clints.iter().flatten().find_map(|(harts, clint)| {
+            harts
+                .contains(&hart_id)
+                .then(|| (clint, hart_id - harts.start))
+        })
+    }
+
+    #[inline]
+    fn first(&self) -> &MachineClint {
+        let Some(Some((_, clint))) = self.clints.first() else {
+            unreachable!("CLINT set created without instances")
+        };
+        clint
+    }
+}
+
+impl IpiDevice for MachineClintSet {
+    #[inline(always)]
+    fn read_mtime(&self) -> u64 {
+        self.first().read_mtime()
+    }
+
+    #[inline(always)]
+    fn write_mtime(&self, val: u64) {
+        for (_, clint) in self.clints.iter().flatten() {
+            clint.write_mtime(val);
+        }
+    }
+
+    #[inline(always)]
+    fn read_mtimecmp(&self, hart_idx: usize) -> u64 {
+        self.route(hart_idx)
+            .map_or(u64::MAX, |(clint, idx)| clint.read_mtimecmp(idx))
+    }
+
+    #[inline(always)]
+    fn write_mtimecmp(&self, hart_idx: usize, val: u64) {
+        if let Some((clint, idx)) = self.route(hart_idx) {
+            clint.write_mtimecmp(idx, val);
+        }
+    }
+
+    #[inline(always)]
+    fn read_msip(&self, hart_idx: usize) -> bool {
+        self.route(hart_idx)
+            .is_some_and(|(clint, idx)| clint.read_msip(idx))
+    }
+
+    #[inline(always)]
+    fn set_msip(&self, hart_idx: usize) {
+        if let Some((clint, idx)) = self.route(hart_idx) {
+            clint.set_msip(idx);
+        }
+    }
+
+    #[inline(always)]
+    fn clear_msip(&self, hart_idx: usize) {
+        if let Some((clint, idx)) = self.route(hart_idx) {
+            clint.clear_msip(idx);
+        }
+    }
+}
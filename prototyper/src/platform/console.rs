@@ -1,11 +1,30 @@
 use uart16550::Uart16550;
 use uart_xilinx::MmioUartAxiLite;
 
+use crate::config;
+use crate::platform::htif::Htif;
 use crate::sbi::console::ConsoleDevice;
 pub(crate) const UART16650U8_COMPATIBLE: [&str; 1] = ["ns16550a"];
 pub(crate) const UART16650U32_COMPATIBLE: [&str; 1] = ["snps,dw-apb-uart"];
 pub(crate) const UARTAXILITE_COMPATIBLE: [&str; 1] = ["xlnx,xps-uartlite-1.00.a"];
 
+/// Driver for a UART with the given `compatible` string, including the
+/// strings added by the platform manifest.
+pub(crate) fn console_type(compatible: &str) -> Option<MachineConsoleType> {
+    let matches = |builtin: &[&str], extra: &[&str]| {
+        builtin.contains(&compatible) || extra.contains(&compatible)
+    };
+    if matches(&UART16650U8_COMPATIBLE, config::UART16550U8_EXTRA) {
+        Some(MachineConsoleType::Uart16550U8)
+    } else if matches(&UART16650U32_COMPATIBLE, config::UART16550U32_EXTRA) {
+        Some(MachineConsoleType::Uart16550U32)
+    } else if matches(&UARTAXILITE_COMPATIBLE, config::UARTAXILITE_EXTRA) {
+        Some(MachineConsoleType::UartAxiLite)
+    } else {
+        None
+    }
+}
+
 #[doc(hidden)]
 #[allow(unused)]
 #[derive(Clone, Copy, Debug)]
@@ -13,6 +32,7 @@ pub enum MachineConsoleType {
     Uart16550U8,
     Uart16550U32,
     UartAxiLite,
+    Htif,
 }
 #[doc(hidden)]
 #[allow(unused)]
@@ -20,6 +40,7 @@ pub enum MachineConsole {
     Uart16550U8(*const Uart16550<u8>),
     Uart16550U32(*const Uart16550<u32>),
     UartAxiLite(MmioUartAxiLite),
+    Htif(Htif),
 }
 
 unsafe impl Send for MachineConsole {}
@@ -31,6 +52,17 @@ impl ConsoleDevice for MachineConsole {
             Self::Uart16550U8(uart16550) => unsafe { (**uart16550).read(buf) },
             Self::Uart16550U32(uart16550) => unsafe { (**uart16550).read(buf) },
             Self::UartAxiLite(axilite) => axilite.read(buf),
+            Self::Htif(htif) => {
+                let mut count = 0;
+                for byte in buf.iter_mut() {
+                    match htif.getchar() {
+                        Some(c) => *byte = c,
+                        None => break,
+                    }
+                    count += 1;
+                }
+                count
+            }
         }
     }
 
@@ -39,6 +71,10 @@ impl ConsoleDevice for MachineConsole {
             MachineConsole::Uart16550U8(uart16550) => unsafe { (**uart16550).write(buf) },
             MachineConsole::Uart16550U32(uart16550) => unsafe { (**uart16550).write(buf) },
             Self::UartAxiLite(axilite) => axilite.write(buf),
+            Self::Htif(htif) => {
+                buf.iter().for_each(|c| htif.putchar(*c));
+                buf.len()
+            }
         }
     }
 }
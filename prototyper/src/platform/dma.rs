@@ -0,0 +1,166 @@
+use crate::sbi::console_dma::DmaTxChannel;
+
+pub(crate) const DW_AXI_DMA_COMPATIBLE: [&str; 2] =
+    ["snps,axi-dma-1.01a", "starfive,jh7110-axi-dma"];
+
+/// Channels a controller may have for the register layout used here.
+pub(crate) const DW_AXI_DMA_MAX_CHANNELS: u32 = 8;
+
+const DMAC_CFGREG: usize = 0x10;
+const DMAC_CHENREG: usize = 0x18;
+const DMAC_EN: u32 = 1 << 0;
+const DMAC_INT_EN: u32 = 1 << 1;
+/// Write enable of channel `n` enable bit `n` in `DMAC_CHENREG`.
+const DMAC_CHEN_WE_SHIFT: usize = 8;
+
+const CH_BASE: usize = 0x100;
+const CH_STRIDE: usize = 0x100;
+const CH_SAR: usize = 0x00;
+const CH_DAR: usize = 0x08;
+const CH_BLOCK_TS: usize = 0x10;
+const CH_CTL: usize = 0x18;
+const CH_CFG: usize = 0x20;
+const CH_INTSTATUS_ENABLE: usize = 0x80;
+const CH_INTSIGNAL_ENABLE: usize = 0x90;
+const CH_INTCLEAR: usize = 0x98;
+
+/// Fixed destination address, for a peripheral register.
+const CH_CTL_DST_FIXED: u64 = 1 << 6;
+/// 32-bit source and destination transfers.
+const CH_CTL_WIDTH_32: u64 = (2 << 8) | (2 << 11);
+/// Last block of the transfer.
+const CH_CTL_LAST: u64 = 1 << 62;
+/// Memory to peripheral, with the controller controlling the flow.
+const CH_CFG_MEM_TO_PER: u64 = 1 << 32;
+const CH_CFG_DST_PER_SHIFT: usize = 44;
+/// Transfer done interrupt.
+const CH_IRQ_DMA_TRF: u32 = 1 << 1;
+
+#[doc(hidden)]
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum MachineDmaType {
+    DwAxi,
+}
+
+/// DMA channel feeding the console, as found in the device tree.
+#[derive(Clone, Copy, Debug)]
+pub struct ConsoleDmaInfo {
+    pub kind: MachineDmaType,
+    pub base: usize,
+    /// Completion interrupt of the controller.
+    pub irq: usize,
+    pub channel: usize,
+    /// Handshake interface of the UART transmitter.
+    pub request: u32,
+    /// Most words one transfer can move.
+    pub max_block: usize,
+}
+
+#[doc(hidden)]
+#[allow(unused)]
+pub enum MachineDma {
+    DwAxi(DwAxiDmaChannel),
+}
+
+impl DmaTxChannel for MachineDma {
+    #[inline]
+    fn start(&self, src: usize, count: usize) {
+        match self {
+            Self::DwAxi(channel) => channel.start(src, count),
+        }
+    }
+
+    #[inline]
+    fn busy(&self) -> bool {
+        match self {
+            Self::DwAxi(channel) => channel.busy(),
+        }
+    }
+
+    #[inline]
+    fn ack(&self) {
+        match self {
+            Self::DwAxi(channel) => channel.ack(),
+        }
+    }
+
+    #[inline]
+    fn max_block(&self) -> usize {
+        match self {
+            Self::DwAxi(channel) => channel.max_block,
+        }
+    }
+}
+
+/// One channel of a Synopsys DesignWare AXI DMA controller, sending to a
+/// peripheral register through a hardware handshake interface.
+///
+/// Clocks and resets of the controller are left to an earlier boot stage.
+pub struct DwAxiDmaChannel {
+    base: usize,
+    channel: usize,
+    handshake: u32,
+    dst: usize,
+    max_block: usize,
+}
+
+impl DwAxiDmaChannel {
+    /// Channel `channel` of the controller at `base`, writing to `dst`
+    /// through handshake interface `handshake`, and enable the controller.
+    pub fn new(base: usize, channel: usize, handshake: u32, dst: usize, max_block: usize) -> Self {
+        let dma = Self {
+            base,
+            channel,
+            handshake,
+            dst,
+            max_block,
+        };
+        dma.write(DMAC_CFGREG, DMAC_EN | DMAC_INT_EN);
+        dma
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    /// Write a 64-bit channel register, low half first.
+    #[inline]
+    fn write_channel(&self, offset: usize, value: u64) {
+        let offset = CH_BASE + self.channel * CH_STRIDE + offset;
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+
+    fn start(&self, src: usize, count: usize) {
+        self.write_channel(CH_INTCLEAR, u32::MAX as u64);
+        self.write_channel(CH_SAR, src as u64);
+        self.write_channel(CH_DAR, self.dst as u64);
+        self.write_channel(CH_BLOCK_TS, count as u64 - 1);
+        self.write_channel(CH_CTL, CH_CTL_DST_FIXED | CH_CTL_WIDTH_32 | CH_CTL_LAST);
+        self.write_channel(
+            CH_CFG,
+            CH_CFG_MEM_TO_PER | (self.handshake as u64) << CH_CFG_DST_PER_SHIFT,
+        );
+        self.write_channel(CH_INTSTATUS_ENABLE, CH_IRQ_DMA_TRF as u64);
+        self.write_channel(CH_INTSIGNAL_ENABLE, CH_IRQ_DMA_TRF as u64);
+        let bit = 1 << self.channel;
+        self.write(DMAC_CHENREG, bit | bit << DMAC_CHEN_WE_SHIFT);
+    }
+
+    #[inline]
+    fn busy(&self) -> bool {
+        self.read(DMAC_CHENREG) & (1 << self.channel) != 0
+    }
+
+    #[inline]
+    fn ack(&self) {
+        self.write_channel(CH_INTCLEAR, u32::MAX as u64);
+    }
+}
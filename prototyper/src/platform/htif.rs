@@ -0,0 +1,93 @@
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicIsize, Ordering};
+
+pub(crate) const HTIF_COMPATIBLE: [&str; 1] = ["ucb,htif0"];
+
+/// HTIF device identifiers.
+const HTIF_DEV_SYSTEM: u64 = 0;
+const HTIF_DEV_CONSOLE: u64 = 1;
+/// HTIF console commands.
+const HTIF_CONSOLE_CMD_GETC: u64 = 0;
+const HTIF_CONSOLE_CMD_PUTC: u64 = 1;
+
+// Spike and other HTIF hosts locate these words through the ELF symbol table,
+// so their names must not be mangled.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+#[link_section = ".htif"]
+static mut tohost: u64 = 0;
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+#[link_section = ".htif"]
+static mut fromhost: u64 = 0;
+
+/// Last character received from the host console plus one, 0 when a new
+/// request should be issued, or -1 while a request is outstanding.
+static CONSOLE_BUF: AtomicIsize = AtomicIsize::new(0);
+
+/// Host-Target Interface used by Spike and compatible simulators.
+///
+/// Callers are expected to serialize access, the console layer and reset
+/// layer already hold a lock around every call.
+#[derive(Clone, Copy)]
+pub struct Htif;
+
+impl Htif {
+    /// Returns the address of the `tohost` word, used for display purposes.
+    #[inline]
+    pub fn base_address() -> usize {
+        addr_of!(tohost) as usize
+    }
+
+    /// Consume a pending response from the host, if any.
+    fn check_fromhost(&self) {
+        let fh = unsafe { addr_of!(fromhost).read_volatile() };
+        if fh == 0 {
+            return;
+        }
+        unsafe { addr_of_mut!(fromhost).write_volatile(0) };
+        let dev = fh >> 56;
+        let cmd = (fh >> 48) & 0xff;
+        if dev == HTIF_DEV_CONSOLE && cmd == HTIF_CONSOLE_CMD_GETC {
+            CONSOLE_BUF.store(1 + (fh & 0xff) as isize, Ordering::Relaxed);
+        }
+    }
+
+    /// Wait until the host drained the previous command, then post a new one.
+    fn set_tohost(&self, dev: u64, cmd: u64, data: u64) {
+        while unsafe { addr_of!(tohost).read_volatile() } != 0 {
+            self.check_fromhost();
+        }
+        unsafe { addr_of_mut!(tohost).write_volatile((dev << 56) | (cmd << 48) | data) };
+    }
+
+    pub fn putchar(&self, c: u8) {
+        self.set_tohost(HTIF_DEV_CONSOLE, HTIF_CONSOLE_CMD_PUTC, c as u64);
+    }
+
+    /// Returns a received character, or `None` if the host has nothing yet.
+    pub fn getchar(&self) -> Option<u8> {
+        self.check_fromhost();
+        let ch = CONSOLE_BUF.load(Ordering::Relaxed);
+        if ch >= 0 {
+            CONSOLE_BUF.store(-1, Ordering::Relaxed);
+            self.set_tohost(HTIF_DEV_CONSOLE, HTIF_CONSOLE_CMD_GETC, 0);
+        }
+        if ch > 0 {
+            Some((ch - 1) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Request the host to exit with the given code; 0 means success.
+    pub fn exit(&self, code: u16) -> ! {
+        let payload = ((code as u64) << 1) | 1;
+        loop {
+            unsafe {
+                addr_of_mut!(fromhost).write_volatile(0);
+                addr_of_mut!(tohost).write_volatile((HTIF_DEV_SYSTEM << 56) | payload);
+            }
+        }
+    }
+}
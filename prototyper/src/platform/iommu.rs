@@ -0,0 +1,64 @@
+//! RISC-V IOMMU default mode.
+//!
+//! The firmware does not translate DMA itself, it only makes sure the IOMMU
+//! starts in a known mode when the build asks for one with
+//! `PROTOTYPER_IOMMU_MODE`:
+//!
+//! - `bypass`: DMA passes untranslated until the supervisor takes over.
+//! - `blocking`: all DMA is refused until the supervisor takes over.
+//!
+//! Without the variable the IOMMU is left as reset or the previous stage left it.
+
+pub(crate) const IOMMU_COMPATIBLE: [&str; 1] = ["riscv,iommu"];
+
+/// Most IOMMU instances a platform may describe.
+pub(crate) const MAX_IOMMUS: usize = 4;
+
+/// Device directory table pointer register.
+const DDTP_OFFSET: usize = 0x10;
+const DDTP_MODE_MASK: u32 = 0xf;
+const DDTP_BUSY: u32 = 0x1 << 4;
+/// Polls of `ddtp` before a busy IOMMU is given up on.
+const BUSY_POLLS: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IommuMode {
+    /// `ddtp.iommu_mode` Off, all DMA is blocked.
+    Blocking = 0,
+    /// `ddtp.iommu_mode` Bare, DMA is not translated.
+    Bypass = 1,
+}
+
+impl IommuMode {
+    /// Returns the mode selected at build time, if any.
+    pub fn current() -> Option<Self> {
+        match option_env!("PROTOTYPER_IOMMU_MODE") {
+            Some("bypass") => Some(IommuMode::Bypass),
+            Some("blocking") => Some(IommuMode::Blocking),
+            _ => None,
+        }
+    }
+}
+
+/// Low word of `ddtp`. Mode and busy bits sit there, and the high word only
+/// holds the table address, which neither Off nor Bare uses.
+#[inline]
+fn ddtp(base: usize) -> *mut u32 {
+    (base + DDTP_OFFSET) as *mut u32
+}
+
+fn wait_idle(base: usize) -> bool {
+    (0..BUSY_POLLS).any(|_| unsafe { ddtp(base).read_volatile() } & DDTP_BUSY == 0)
+}
+
+/// Switch the IOMMU at `base` to `mode`, returns false if it did not take it.
+pub fn set_mode(base: usize, mode: IommuMode) -> bool {
+    if !wait_idle(base) {
+        return false;
+    }
+    unsafe {
+        ((base + DDTP_OFFSET + 4) as *mut u32).write_volatile(0);
+        ddtp(base).write_volatile(mode as u32);
+    }
+    wait_idle(base) && unsafe { ddtp(base).read_volatile() } & DDTP_MODE_MASK == mode as u32
+}
@@ -0,0 +1,60 @@
+use crate::platform::aplic::Aplic;
+use crate::platform::plic::Plic;
+use crate::sbi::irq::IrqController;
+
+/// Interrupt controller taking machine external interrupts.
+#[derive(Clone, Copy)]
+pub enum MachineIrqChip {
+    Plic(Plic),
+    Aplic(Aplic),
+}
+
+impl IrqController for MachineIrqChip {
+    #[inline]
+    fn set_priority(&self, irq: usize, priority: u32) {
+        match self {
+            Self::Plic(plic) => plic.set_priority(irq, priority),
+            Self::Aplic(aplic) => aplic.set_priority(irq, priority),
+        }
+    }
+
+    #[inline]
+    fn enable(&self, hart_id: usize, irq: usize) {
+        match self {
+            Self::Plic(plic) => plic.enable(hart_id, irq),
+            Self::Aplic(aplic) => aplic.enable(hart_id, irq),
+        }
+    }
+
+    #[inline]
+    fn disable(&self, hart_id: usize, irq: usize) {
+        match self {
+            Self::Plic(plic) => plic.disable(hart_id, irq),
+            Self::Aplic(aplic) => aplic.disable(hart_id, irq),
+        }
+    }
+
+    #[inline]
+    fn set_threshold(&self, hart_id: usize, threshold: u32) {
+        match self {
+            Self::Plic(plic) => plic.set_threshold(hart_id, threshold),
+            Self::Aplic(aplic) => aplic.set_threshold(hart_id, threshold),
+        }
+    }
+
+    #[inline]
+    fn claim(&self, hart_id: usize) -> Option<usize> {
+        match self {
+            Self::Plic(plic) => plic.claim(hart_id),
+            Self::Aplic(aplic) => aplic.claim(hart_id),
+        }
+    }
+
+    #[inline]
+    fn complete(&self, hart_id: usize, irq: usize) {
+        match self {
+            Self::Plic(plic) => plic.complete(hart_id, irq),
+            Self::Aplic(aplic) => aplic.complete(hart_id, irq),
+        }
+    }
+}
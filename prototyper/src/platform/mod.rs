@@ -1,74 +1,286 @@
-use crate::fail;
-use crate::platform::clint::{MachineClint, MachineClintType, CLINT_COMPATIBLE};
-use crate::platform::console::{
-    MachineConsole, MachineConsoleType, UART16650U32_COMPATIBLE, UART16650U8_COMPATIBLE,
-    UARTAXILITE_COMPATIBLE,
+use crate::error::{FwError, FwResult};
+use crate::firmware::fdt_fixup;
+use crate::platform::aplic::{Aplic, AplicInfo, APLIC_COMPATIBLE};
+use crate::platform::clint::{
+    ClintInfo, MachineClintSet, MachineClintType, CLINT_COMPATIBLE, MAX_CLINTS,
 };
-use crate::platform::reset::SIFIVETEST_COMPATIBLE;
+use crate::platform::console::{MachineConsole, MachineConsoleType};
+use crate::platform::dma::{
+    ConsoleDmaInfo, DwAxiDmaChannel, MachineDma, MachineDmaType, DW_AXI_DMA_COMPATIBLE,
+    DW_AXI_DMA_MAX_CHANNELS,
+};
+use crate::platform::htif::{Htif, HTIF_COMPATIBLE};
+use crate::platform::iommu::{IommuMode, IOMMU_COMPATIBLE, MAX_IOMMUS};
+use crate::platform::irq_chip::MachineIrqChip;
+use crate::platform::numa::NumaInfo;
+use crate::platform::pci::{MAX_PCI_HOSTS, PCI_HOST_COMPATIBLE};
+use crate::platform::plic::{Plic, PlicInfo, IRQ_M_EXT, PLIC_COMPATIBLE};
+use crate::platform::reset::{MachineReset, MachineResetType, SIFIVETEST_COMPATIBLE};
+use crate::platform::rtc::GOLDFISH_RTC_COMPATIBLE;
+use crate::platform::suspend::{MachineSuspend, MachineSuspendType, QemuSuspend, QEMU_VIRT_MODEL};
+use crate::platform::trng::{MachineTrng, MachineTrngType, StarFiveTrng, STARFIVE_TRNG_COMPATIBLE};
+use crate::riscv_spec::current_hartid;
 use crate::sbi::console::SbiConsole;
+use crate::sbi::console_dma::{self, SbiConsoleDma};
+use crate::sbi::crashdump::{self, CRASHDUMP_COMPATIBLE};
+use crate::sbi::domain::{self, DomainInfo, DOMAIN_COMPATIBLE, MAX_DOMAINS};
+use crate::sbi::extension_mask;
 use crate::sbi::extensions;
 use crate::sbi::hsm::SbiHsm;
+use crate::sbi::idle_states::{self, IdleState, IDLE_STATE_COMPATIBLE, MAX_IDLE_STATES};
 use crate::sbi::ipi::SbiIpi;
+use crate::sbi::irq::SbiIrq;
 use crate::sbi::logger;
 use crate::sbi::reset::SbiReset;
 use crate::sbi::trap_stack;
-use crate::sbi::trap_stack::NUM_HART_MAX;
 use crate::sbi::SBI;
+use crate::sync::Mutex;
 use crate::{dt, sbi::rfence::SbiRFence};
 use core::{
     fmt::{Display, Formatter, Result},
     ops::Range,
-    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
-use sifive_test_device::SifiveTestDevice;
-use spin::Mutex;
 use uart_xilinx::MmioUartAxiLite;
 
+mod aplic;
+pub mod board;
 mod clint;
 mod console;
+mod dma;
+mod htif;
+mod iommu;
+mod irq_chip;
+pub mod numa;
+pub mod pci;
+mod plic;
 mod reset;
+pub mod rtc;
+pub mod suspend;
+mod trng;
 
 type BaseAddress = usize;
 /// Store finite-length string on the stack.
 pub(crate) struct StringInline<const N: usize>(usize, [u8; N]);
 
+impl<const N: usize> StringInline<N> {
+    pub fn as_str(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.1[..self.0]) }
+    }
+}
+
 impl<const N: usize> Display for StringInline<N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "{}", unsafe {
-            core::str::from_utf8_unchecked(&self.1[..self.0])
-        })
+        write!(f, "{}", self.as_str())
     }
 }
 
 type CpuEnableList = [bool; trap_stack::NUM_HART_MAX];
+/// A CLINT and the harts its device tree node names, if any.
+type FoundClint = (BaseAddress, MachineClintType, Option<Range<usize>>);
+
+/// CLINT instances in device tree order, before their hart ranges are known.
+#[derive(Default)]
+struct ClintCollector {
+    found: [Option<FoundClint>; MAX_CLINTS],
+    count: usize,
+}
+
+impl ClintCollector {
+    fn push(&mut self, base: BaseAddress, kind: MachineClintType, harts: Option<Range<usize>>) {
+        if self.count == MAX_CLINTS {
+            warn!(
+                "Ignoring CLINT at 0x{:x}, at most {} supported",
+                base, MAX_CLINTS
+            );
+            return;
+        }
+        self.found[self.count] = Some((base, kind, harts));
+        self.count += 1;
+    }
+
+    /// Give each instance the harts its `interrupts-extended` names. One
+    /// without the property serves all harts after those of the instances
+    /// before it, and is dropped if there are none left.
+    fn finish(self) -> [Option<ClintInfo>; MAX_CLINTS] {
+        let mut clints = [None; MAX_CLINTS];
+        let mut slots = clints.iter_mut();
+        let mut next_hart = 0;
+        for (base, kind, harts) in self.found.into_iter().flatten() {
+            let harts = harts.unwrap_or(next_hart..trap_stack::NUM_HART_MAX.max(next_hart));
+            if harts.is_empty() {
+                warn!("Ignoring CLINT at 0x{:x}, no harts left for it", base);
+                continue;
+            }
+            next_hart = next_hart.max(harts.end);
+            *slots.next().unwrap() = Some(ClintInfo {
+                base,
+                kind,
+                first_hart: harts.start,
+                hart_count: harts.len(),
+            });
+        }
+        clints
+    }
+}
+
+/// Harts a CLINT serves, from its `interrupts-extended` list of a software
+/// and a timer interrupt per hart, its hart index being the list order.
+///
+/// Returns `None` if the list is empty, names a controller that is not a
+/// hart's, or names harts that are not consecutive, which the hart ranges
+/// of CLINT instances cannot describe.
+fn clint_harts(
+    node: &serde_device_tree::buildin::Node,
+    cpus: &serde_device_tree::buildin::NodeSeq,
+) -> Option<Range<usize>> {
+    let mut harts: Option<Range<usize>> = None;
+    let mut valid = true;
+    let mut entries = 0;
+    dt::for_each_hart_interrupt(node, cpus, |hart, _| {
+        let index = entries / 2;
+        entries += 1;
+        match (hart, &mut harts) {
+            (Some(hart), None) if index == 0 => harts = Some(hart..hart + 1),
+            (Some(hart), Some(range)) if hart == range.start + index => {
+                range.end = range.end.max(hart + 1)
+            }
+            _ => valid = false,
+        }
+    });
+    harts.filter(|_| valid && entries % 2 == 0)
+}
 
 pub struct BoardInfo {
     pub memory_range: Option<Range<usize>>,
+    pub numa: NumaInfo,
     pub console: Option<(BaseAddress, MachineConsoleType)>,
-    pub reset: Option<BaseAddress>,
-    pub ipi: Option<(BaseAddress, MachineClintType)>,
+    pub console_dma: Option<ConsoleDmaInfo>,
+    pub reset: Option<(BaseAddress, MachineResetType)>,
+    pub ipi: [Option<ClintInfo>; MAX_CLINTS],
+    pub plic: Option<PlicInfo>,
+    pub aplic: Option<AplicInfo>,
+    pub iommu: [Option<BaseAddress>; MAX_IOMMUS],
+    pub pci_ecam: [Option<Range<usize>>; MAX_PCI_HOSTS],
+    pub trng: Option<(BaseAddress, MachineTrngType)>,
+    pub rtc: Option<BaseAddress>,
+    pub suspend: Option<MachineSuspendType>,
+    pub idle_states: [Option<IdleState>; MAX_IDLE_STATES],
+    pub domains: [Option<DomainInfo>; MAX_DOMAINS],
+    pub crashdump: Option<Range<usize>>,
     pub cpu_num: Option<usize>,
     pub cpu_enabled: Option<CpuEnableList>,
+    pub timebase_frequency: Option<u64>,
+    pub memtest: bool,
+    pub supervisor_boot_hart: Option<usize>,
     pub model: StringInline<128>,
 }
 
 impl BoardInfo {
+    /// Record the idle state described by `node`.
+    fn add_idle_state(&mut self, node: &serde_device_tree::buildin::Node) {
+        let Some(suspend_type) = dt::get_u32(node, "riscv,sbi-suspend-param") else {
+            warn!("Ignoring idle state without riscv,sbi-suspend-param");
+            return;
+        };
+        if !idle_states::valid(suspend_type) {
+            warn!(
+                "Ignoring idle state of reserved suspend type 0x{:x}",
+                suspend_type
+            );
+            return;
+        }
+        let state = IdleState {
+            suspend_type,
+            entry_latency_us: dt::get_u32(node, "entry-latency-us").unwrap_or(0),
+            exit_latency_us: dt::get_u32(node, "exit-latency-us").unwrap_or(0),
+            min_residency_us: dt::get_u32(node, "min-residency-us").unwrap_or(0),
+            local_timer_stop: node.get_prop("local-timer-stop").is_some(),
+        };
+        match self.idle_states.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(state),
+            None => warn!("Ignoring idle state of suspend type 0x{:x}", suspend_type),
+        }
+    }
+
     pub const fn new() -> Self {
         BoardInfo {
             memory_range: None,
+            numa: NumaInfo::new(),
             console: None,
+            console_dma: None,
             reset: None,
-            ipi: None,
+            ipi: [None; MAX_CLINTS],
+            plic: None,
+            aplic: None,
+            iommu: [None; MAX_IOMMUS],
+            pci_ecam: [const { None }; MAX_PCI_HOSTS],
+            trng: None,
+            rtc: None,
+            suspend: None,
+            idle_states: [None; MAX_IDLE_STATES],
+            domains: [None; MAX_DOMAINS],
+            crashdump: None,
             cpu_enabled: None,
             cpu_num: None,
+            timebase_frequency: None,
+            memtest: false,
+            supervisor_boot_hart: None,
             model: StringInline(0, [0u8; 128]),
         }
     }
 }
 
+/// The console DMA channel on the DesignWare AXI DMA controller `node` at
+/// `base`, serving handshake interface `request`.
+///
+/// The controller must be kept from the supervisor with
+/// `rustsbi,firmware-reserved`, whose driver would otherwise reset it. The
+/// firmware takes the last channel.
+fn console_dma_info(
+    node: &serde_device_tree::buildin::Node,
+    base: usize,
+    request: u32,
+) -> Option<ConsoleDmaInfo> {
+    if node.get_prop(fdt_fixup::FIRMWARE_RESERVED).is_none() {
+        info!("Console DMA controller not reserved for the firmware, not using it");
+        return None;
+    }
+    let channels = dt::get_u32(node, "dma-channels").unwrap_or(0);
+    if channels == 0 || channels > DW_AXI_DMA_MAX_CHANNELS {
+        warn!("Ignoring console DMA controller with {} channels", channels);
+        return None;
+    }
+    let Some(irq) = dt::get_cell(node, "interrupts", 0) else {
+        warn!("Ignoring console DMA controller without an interrupt");
+        return None;
+    };
+    let channel = channels as usize - 1;
+    Some(ConsoleDmaInfo {
+        kind: MachineDmaType::DwAxi,
+        base,
+        irq: irq as usize,
+        channel,
+        request,
+        max_block: dt::get_cell(node, "snps,block-size", channel).map_or(64, |size| size as usize),
+    })
+}
+
+/// Board information and SBI devices of the running platform.
+///
+/// Devices are published once: the boot hart fills `info` and `sbi` in
+/// `init`, then stores `ready` with Release ordering. Every other hart must
+/// observe `ready()` (an Acquire load) before touching `sbi`, which makes all
+/// device writes of the boot hart visible to it. After publication the
+/// devices are never replaced, and each of them serializes access through
+/// its own lock.
 pub struct Platform {
     pub info: BoardInfo,
-    pub sbi: SBI<MachineConsole, MachineClint, SifiveTestDevice>,
+    pub sbi: SBI<MachineConsole, MachineClintSet, MachineReset>,
+    pub irq: Option<SbiIrq<MachineIrqChip>>,
+    pub console_dma: Option<SbiConsoleDma<MachineDma>>,
+    pub trng: Option<Mutex<MachineTrng>>,
+    pub suspend: Option<Mutex<MachineSuspend>>,
     pub ready: AtomicBool,
 }
 
@@ -77,44 +289,81 @@ impl Platform {
         Platform {
             info: BoardInfo::new(),
             sbi: SBI::new(),
+            irq: None,
+            console_dma: None,
+            trng: None,
+            suspend: None,
             ready: AtomicBool::new(false),
         }
     }
 
-    pub fn init(&mut self, fdt_address: usize) {
-        self.info_init(fdt_address);
-        self.sbi_init();
-        logger::Logger::init().unwrap();
+    pub fn init(&mut self, fdt_address: usize) -> FwResult {
+        INIT_HART.store(current_hartid(), Ordering::Relaxed);
+        // Bring up the console first, so device tree parsing failures are reported.
+        self.early_console_init();
+        logger::Logger::init().map_err(|_| FwError::Logger)?;
+        self.info_init(fdt_address)?;
+        crashdump::init();
+        self.sbi_init()?;
         trap_stack::prepare_for_trap();
-        self.ready.swap(true, Ordering::Release);
+        // Publish devices to other harts, see `Platform` for the protocol.
+        self.ready.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Set up the console named by `PROTOTYPER_EARLY_UART` at build time, if any.
+    ///
+    /// The value has the form `<compatible>@<address>`, for example
+    /// `ns16550a@0x10000000`. The device tree console replaces it once parsed.
+    fn early_console_init(&mut self) {
+        let Some(hint) = option_env!("PROTOTYPER_EARLY_UART") else {
+            return;
+        };
+        let Some((compatible, address)) = hint.split_once('@') else {
+            return;
+        };
+        let address = address.trim();
+        let address = match address.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => address.parse(),
+        };
+        let Ok(base) = address else {
+            return;
+        };
+        let compatible = compatible.trim();
+        let console_type = if HTIF_COMPATIBLE.contains(&compatible) {
+            MachineConsoleType::Htif
+        } else if let Some(console_type) = console::console_type(compatible) {
+            console_type
+        } else {
+            return;
+        };
+        self.info.console = Some((base, console_type));
+        self.sbi_console_init();
     }
 
-    fn info_init(&mut self, fdt_address: usize) {
-        let dtb = dt::parse_device_tree(fdt_address).unwrap_or_else(fail::device_tree_format);
+    fn info_init(&mut self, fdt_address: usize) -> FwResult {
+        let dtb = dt::parse_device_tree(fdt_address).map_err(FwError::DeviceTreeFormat)?;
         let dtb = dtb.share();
 
-        let root: serde_device_tree::buildin::Node = serde_device_tree::from_raw_mut(&dtb)
-            .unwrap_or_else(fail::device_tree_deserialize_root);
+        let root: serde_device_tree::buildin::Node =
+            serde_device_tree::from_raw_mut(&dtb).map_err(FwError::DeviceTreeRoot)?;
         let tree: dt::Tree = root.deserialize();
 
         //  Get console device info
-        for console_path in tree.chosen.stdout_path.iter() {
+        let mut console_tx_dma = None;
+        for console_path in tree.chosen.stdout_path.iter().flat_map(|path| path.iter()) {
             if let Some(node) = root.find(console_path) {
                 let info = dt::get_compatible_and_range(&node);
                 let result = info.is_some_and(|info| {
                     let (compatible, regs) = info;
                     for device_id in compatible.iter() {
-                        if UART16650U8_COMPATIBLE.contains(&device_id) {
-                            self.info.console = Some((regs.start, MachineConsoleType::Uart16550U8));
-                            return true;
-                        }
-                        if UART16650U32_COMPATIBLE.contains(&device_id) {
-                            self.info.console =
-                                Some((regs.start, MachineConsoleType::Uart16550U32));
-                            return true;
-                        }
-                        if UARTAXILITE_COMPATIBLE.contains(&device_id) {
-                            self.info.console = Some((regs.start, MachineConsoleType::UartAxiLite));
+                        if let Some(console_type) = console::console_type(device_id) {
+                            self.info.console = Some((regs.start, console_type));
+                            // DMA moves whole words, as only the 32-bit UARTs take them.
+                            if matches!(console_type, MachineConsoleType::Uart16550U32) {
+                                console_tx_dma = dt::get_dma_request(&node, "tx");
+                            }
                             return true;
                         }
                     }
@@ -126,8 +375,48 @@ impl Platform {
             }
         }
 
+        // Get SBI extensions disabled at build time or by the device tree
+        extension_mask::init();
+        if let Some(names) = &tree.chosen.disable_extensions {
+            extension_mask::disable(names.iter());
+        }
+        if let Some(version) = tree
+            .chosen
+            .sbi_spec_version
+            .as_ref()
+            .and_then(|v| v.iter().next())
+        {
+            extension_mask::set_spec_version(version);
+        }
+        self.info.memtest = tree.chosen.memtest.is_some();
+        self.info.supervisor_boot_hart = tree.chosen.supervisor_boot_hart.map(|hart| hart as usize);
+
         // Get ipi and reset device info
+        let mut has_htif = false;
+        let mut clints = ClintCollector::default();
         let mut find_device = |node: &serde_device_tree::buildin::Node| {
+            // HTIF nodes carry no `reg`, the host locates `tohost` and `fromhost` by symbol.
+            if dt::get_compatible(node)
+                .is_some_and(|compatible| compatible.iter().any(|id| HTIF_COMPATIBLE.contains(&id)))
+            {
+                has_htif = true;
+            }
+            // Idle states carry no `reg` either.
+            if dt::get_compatible(node).is_some_and(|compatible| {
+                compatible
+                    .iter()
+                    .any(|id| IDLE_STATE_COMPATIBLE.contains(&id))
+            }) {
+                self.info.add_idle_state(node);
+            }
+            if dt::get_compatible(node).is_some_and(|compatible| {
+                compatible.iter().any(|id| DOMAIN_COMPATIBLE.contains(&id))
+            }) {
+                match self.info.domains.iter_mut().find(|slot| slot.is_none()) {
+                    Some(slot) => *slot = Some(DomainInfo::from_node(node)),
+                    None => warn!("Ignoring domain, at most {} supported", MAX_DOMAINS),
+                }
+            }
             let info = dt::get_compatible_and_range(node);
             if let Some(info) = info {
                 let (compatible, regs) = info;
@@ -135,39 +424,174 @@ impl Platform {
                 for device_id in compatible.iter() {
                     // Initialize clint device.
                     if CLINT_COMPATIBLE.contains(&device_id) {
-                        if node.get_prop("clint,has-no-64bit-mmio").is_some() {
-                            self.info.ipi = Some((base_address, MachineClintType::TheadClint));
+                        let kind = if node.get_prop("clint,has-no-64bit-mmio").is_some() {
+                            MachineClintType::TheadClint
+                        } else {
+                            MachineClintType::SiFiveClint
+                        };
+                        if node.get_prop("interrupts-extended").is_none() {
+                            clints.push(base_address, kind, None);
+                        } else if let Some(harts) = clint_harts(node, &tree.cpus.cpu) {
+                            clints.push(base_address, kind, Some(harts));
                         } else {
-                            self.info.ipi = Some((base_address, MachineClintType::SiFiveClint));
+                            warn!(
+                                "Ignoring CLINT at 0x{:x}, its interrupts-extended names no consecutive harts",
+                                base_address
+                            );
                         }
                     }
                     // Initialize reset device.
                     if SIFIVETEST_COMPATIBLE.contains(&device_id) {
-                        self.info.reset = Some(base_address);
+                        self.info.reset = Some((base_address, MachineResetType::SifiveTest));
+                    }
+                    // Initialize interrupt controller.
+                    if PLIC_COMPATIBLE.contains(&device_id) {
+                        // Context `index` belongs to the hart and interrupt
+                        // of entry `index` of `interrupts-extended`.
+                        let mut plic = PlicInfo::new(base_address);
+                        let mut index = 0;
+                        let described =
+                            dt::for_each_hart_interrupt(node, &tree.cpus.cpu, |hart, irq| {
+                                if let Some(hart) = hart.filter(|_| irq == IRQ_M_EXT) {
+                                    plic.set_m_context(hart, index);
+                                }
+                                index += 1;
+                            });
+                        if !described {
+                            warn!(
+                                "PLIC at 0x{:x} has no interrupts-extended, assuming QEMU virt contexts",
+                                base_address
+                            );
+                            plic = PlicInfo::qemu_virt(base_address);
+                        }
+                        self.info.plic = Some(plic);
+                    }
+                    // The machine level APLIC domain is the one with child
+                    // domains, or delivering machine external interrupts.
+                    if APLIC_COMPATIBLE.contains(&device_id) {
+                        let mut aplic = AplicInfo::new(base_address);
+                        let mut index = 0;
+                        dt::for_each_hart_interrupt(node, &tree.cpus.cpu, |hart, irq| {
+                            if let Some(hart) = hart.filter(|_| irq == IRQ_M_EXT) {
+                                aplic.set_idc(hart, index);
+                            }
+                            index += 1;
+                        });
+                        let has_children = node.get_prop("riscv,children").is_some();
+                        if (has_children || aplic.idc_count() > 0) && self.info.aplic.is_none() {
+                            aplic.msi = node.get_prop("msi-parent").is_some();
+                            // Entries of `riscv,delegation` are a child
+                            // phandle and the first and last source.
+                            let mut entry = 0;
+                            while let (Some(child), Some(first), Some(last)) = (
+                                dt::get_cell(node, "riscv,delegation", entry * 3),
+                                dt::get_cell(node, "riscv,delegation", entry * 3 + 1),
+                                dt::get_cell(node, "riscv,delegation", entry * 3 + 2),
+                            ) {
+                                let child = (0..)
+                                    .map_while(|index| dt::get_cell(node, "riscv,children", index))
+                                    .position(|phandle| phandle == child);
+                                if !child
+                                    .is_some_and(|child| aplic.delegate(child as u32, first, last))
+                                {
+                                    warn!(
+                                        "Ignoring APLIC delegation of sources {} to {}",
+                                        first, last
+                                    );
+                                }
+                                entry += 1;
+                            }
+                            self.info.aplic = Some(aplic);
+                        }
+                    }
+                    // IOMMUs, only their default mode is managed.
+                    if IOMMU_COMPATIBLE.contains(&device_id) {
+                        match self.info.iommu.iter_mut().find(|slot| slot.is_none()) {
+                            Some(slot) => *slot = Some(base_address),
+                            None => warn!("Ignoring IOMMU at 0x{:x}", base_address),
+                        }
+                    }
+                    // PCI configuration space.
+                    if PCI_HOST_COMPATIBLE.contains(&device_id) {
+                        match self.info.pci_ecam.iter_mut().find(|slot| slot.is_none()) {
+                            Some(slot) => *slot = Some(regs.clone()),
+                            None => warn!("Ignoring PCI host bridge at 0x{:x}", base_address),
+                        }
+                    }
+                    // Initialize random number generator.
+                    if STARFIVE_TRNG_COMPATIBLE.contains(&device_id) {
+                        self.info.trng = Some((base_address, MachineTrngType::StarFiveJh7110));
+                    }
+                    // Real time clock, a timebase calibration reference outside QEMU.
+                    if GOLDFISH_RTC_COMPATIBLE.contains(&device_id) {
+                        self.info.rtc = Some(base_address);
+                    }
+                    // Crash dump region.
+                    if CRASHDUMP_COMPATIBLE.contains(&device_id) {
+                        self.info.crashdump = Some(regs.clone());
+                    }
+                    // DMA controller of the console transmitter.
+                    if DW_AXI_DMA_COMPATIBLE.contains(&device_id) {
+                        if let Some((_, request)) = console_tx_dma
+                            .filter(|&(phandle, _)| dt::get_u32(node, "phandle") == Some(phandle))
+                        {
+                            self.info.console_dma = console_dma_info(node, base_address, request);
+                        }
                     }
                 }
             }
         };
         root.search(&mut find_device);
+        self.info.ipi = clints.finish();
+        domain::init(&self.info.domains);
 
-        // Get memory info
-        // TODO: More than one memory node or range?
-        let memory_reg = tree
-            .memory
-            .iter()
-            .next()
-            .unwrap()
-            .deserialize::<dt::Memory>()
-            .reg;
-        let memory_range = memory_reg.iter().next().unwrap().0;
+        // Fall back to HTIF for devices the tree does not otherwise describe.
+        if has_htif {
+            if self.info.console.is_none() {
+                self.info.console = Some((Htif::base_address(), MachineConsoleType::Htif));
+            }
+            if self.info.reset.is_none() {
+                self.info.reset = Some((Htif::base_address(), MachineResetType::Htif));
+            }
+        }
+
+        // Get memory info, all ranges with their NUMA node
+        for memory in tree.memory.iter() {
+            let memory = memory.deserialize::<dt::Memory>();
+            for range in memory.reg.iter() {
+                self.info.numa.add_memory(range.0, memory.numa_node_id);
+            }
+        }
+        // TODO: Firmware and guests are still limited to the first range.
+        let memory_range = self.info.numa.memory[0].clone().ok_or(FwError::NoMemory)?.0;
+        // Memory is mapped executable for lower privileges, configuration space must not be.
+        for slot in self.info.pci_ecam.iter_mut() {
+            if slot
+                .as_ref()
+                .is_some_and(|ecam| ecam.start < memory_range.end && memory_range.start < ecam.end)
+            {
+                let ecam = slot.take().unwrap();
+                warn!(
+                    "PCI ECAM 0x{:x} - 0x{:x} overlaps memory, ignoring the host bridge",
+                    ecam.start, ecam.end
+                );
+            }
+        }
         self.info.memory_range = Some(memory_range);
 
         // Get cpu number info
         self.info.cpu_num = Some(tree.cpus.cpu.len());
 
+        // Get timer frequency info
+        self.info.timebase_frequency = tree.cpus.timebase_frequency.map(u64::from);
+
         // Get model info
         if let Some(model) = tree.model {
             let model = model.iter().next().unwrap_or("<unspecified>");
+            // QEMU has no sleep states, a stub stands in for testing.
+            if model.starts_with(QEMU_VIRT_MODEL) {
+                self.info.suspend = Some(MachineSuspendType::QemuVirt);
+            }
             self.info.model.0 = model.as_bytes().len();
             self.info.model.1[..self.info.model.0].copy_from_slice(model.as_bytes());
         } else {
@@ -177,27 +601,49 @@ impl Platform {
         }
 
         // TODO: Need a better extension initialization method
-        extensions::init(&tree.cpus.cpu);
+        extensions::init(&tree.cpus.cpu)?;
+        #[cfg(feature = "fast-mem")]
+        crate::mem::init(&tree.cpus.cpu);
 
         // Find which hart is enabled by fdt
         let mut cpu_list: CpuEnableList = [false; trap_stack::NUM_HART_MAX];
         for cpu_iter in tree.cpus.cpu.iter() {
             use dt::Cpu;
             let cpu = cpu_iter.deserialize::<Cpu>();
-            let hart_id = cpu.reg.iter().next().unwrap().0.start;
+            let hart_id = cpu.reg.iter().next().ok_or(FwError::CpuWithoutReg)?.0.start;
             if let Some(x) = cpu_list.get_mut(hart_id) {
                 *x = true;
+                self.info.numa.hart_node[hart_id] = cpu.numa_node_id;
             }
         }
+        self.info.numa.check(&cpu_list);
         self.info.cpu_enabled = Some(cpu_list);
+        Ok(())
     }
 
-    fn sbi_init(&mut self) {
+    fn sbi_init(&mut self) -> FwResult {
         self.sbi_console_init();
-        self.sbi_ipi_init();
+        self.sbi_ipi_init()?;
         self.sbi_hsm_init();
         self.sbi_reset_init();
         self.sbi_rfence_init();
+        self.irq_init();
+        self.console_dma_init();
+        self.trng_init();
+        self.suspend_init();
+        self.iommu_init();
+        Ok(())
+    }
+
+    fn iommu_init(&mut self) {
+        let Some(mode) = IommuMode::current() else {
+            return;
+        };
+        for &base in self.info.iommu.iter().flatten() {
+            if !iommu::set_mode(base, mode) {
+                warn!("IOMMU at 0x{:x} did not enter {:?} mode", base, mode);
+            }
+        }
     }
 
     fn sbi_console_init(&mut self) {
@@ -208,39 +654,48 @@ impl Platform {
                 MachineConsoleType::UartAxiLite => {
                     MachineConsole::UartAxiLite(MmioUartAxiLite::new(base))
                 }
+                MachineConsoleType::Htif => MachineConsole::Htif(Htif),
             };
-            self.sbi.console = Some(SbiConsole::new(Mutex::new(new_console)));
+            self.sbi.console = Some(SbiConsole::new(Mutex::named("console", new_console)));
         } else {
             self.sbi.console = None;
         }
     }
 
     fn sbi_reset_init(&mut self) {
-        if let Some(base) = self.info.reset {
-            self.sbi.reset = Some(SbiReset::new(AtomicPtr::new(base as _)));
+        if let Some((base, reset_type)) = self.info.reset {
+            let new_reset = match reset_type {
+                MachineResetType::SifiveTest => MachineReset::SifiveTest(base as _),
+                MachineResetType::Htif => MachineReset::Htif(Htif),
+            };
+            self.sbi.reset = Some(SbiReset::new(Mutex::named("reset", new_reset)));
         } else {
             self.sbi.reset = None;
         }
     }
 
-    fn sbi_ipi_init(&mut self) {
-        if let Some((base, clint_type)) = self.info.ipi {
-            let new_clint = match clint_type {
-                MachineClintType::SiFiveClint => MachineClint::SiFive(base as _),
-                MachineClintType::TheadClint => MachineClint::THead(base as _),
-            };
-            self.sbi.ipi = Some(SbiIpi::new(
-                Mutex::new(new_clint),
-                self.info.cpu_num.unwrap_or(NUM_HART_MAX),
-            ));
-        } else {
-            self.sbi.ipi = None;
+    fn sbi_ipi_init(&mut self) -> FwResult {
+        if self.info.ipi[0].is_some() {
+            let new_clint = MachineClintSet::new(&self.info.ipi);
+            self.sbi.ipi = Some(SbiIpi::new(Mutex::named("ipi", new_clint)));
+            return Ok(());
+        }
+        self.sbi.ipi = None;
+        // Without IPIs HSM cannot start the other harts.
+        let harts = self
+            .info
+            .cpu_enabled
+            .as_ref()
+            .map_or(0, |list| list.iter().filter(|&&enabled| enabled).count());
+        if harts > 1 {
+            return Err(FwError::NoIpi { harts });
         }
+        Ok(())
     }
 
     fn sbi_hsm_init(&mut self) {
         // TODO: Can HSM work properly when there is no ipi device?
-        if self.info.ipi.is_some() {
+        if self.info.ipi[0].is_some() {
             self.sbi.hsm = Some(SbiHsm);
         } else {
             self.sbi.hsm = None;
@@ -249,13 +704,64 @@ impl Platform {
 
     fn sbi_rfence_init(&mut self) {
         // TODO: Can rfence work properly when there is no ipi device?
-        if self.info.ipi.is_some() {
+        if self.info.ipi[0].is_some() {
             self.sbi.rfence = Some(SbiRFence);
         } else {
             self.sbi.rfence = None;
         }
     }
 
+    fn irq_init(&mut self) {
+        if let Some(info) = self.info.plic {
+            self.irq = Some(SbiIrq::new(MachineIrqChip::Plic(Plic::new(info))));
+        } else if let Some(info) = self.info.aplic {
+            let aplic = Aplic::new(info);
+            aplic.delegate_sources();
+            // Without an IMSIC driver no MSI reaches the firmware.
+            if !info.msi {
+                self.irq = Some(SbiIrq::new(MachineIrqChip::Aplic(aplic)));
+            }
+        }
+    }
+
+    fn console_dma_init(&mut self) {
+        let (Some(info), Some((console_base, _))) = (self.info.console_dma, self.info.console)
+        else {
+            return;
+        };
+        let channel = match info.kind {
+            MachineDmaType::DwAxi => MachineDma::DwAxi(DwAxiDmaChannel::new(
+                info.base,
+                info.channel,
+                info.request,
+                console_base,
+                info.max_block,
+            )),
+        };
+        self.console_dma = Some(SbiConsoleDma::new(channel));
+        console_dma::init(info.irq);
+    }
+
+    fn trng_init(&mut self) {
+        self.trng = self.info.trng.map(|(base, trng_type)| {
+            let trng = match trng_type {
+                MachineTrngType::StarFiveJh7110 => {
+                    MachineTrng::StarFiveJh7110(StarFiveTrng::new(base))
+                }
+            };
+            Mutex::named("trng", trng)
+        });
+    }
+
+    fn suspend_init(&mut self) {
+        self.suspend = self.info.suspend.map(|suspend_type| {
+            let suspend = match suspend_type {
+                MachineSuspendType::QemuVirt => MachineSuspend::QemuVirt(QemuSuspend),
+            };
+            Mutex::named("suspend", suspend)
+        });
+    }
+
     pub fn print_board_info(&self) {
         info!("RustSBI version {}", rustsbi::VERSION);
         rustsbi::LOGO.lines().for_each(|line| info!("{}", line));
@@ -265,6 +771,7 @@ impl Platform {
         self.print_cpu_info();
         self.print_device_info();
         self.print_memory_info();
+        self.print_extension_mask_info();
         self.print_additional_info();
     }
 
@@ -290,30 +797,60 @@ impl Platform {
                 }
             }
             info!("{:<30}: {:?}", "Enabled HARTs", &enabled_harts[..count]);
+            let beyond = self.info.cpu_num.unwrap_or(0).saturating_sub(count);
+            if beyond > 0 {
+                warn!(
+                    "{} HART(s) beyond harts.max = {} are left parked, raise it in the platform manifest",
+                    beyond,
+                    trap_stack::NUM_HART_MAX
+                );
+            }
         } else {
             warn!("{:<30}: Not Available", "Enabled HARTs");
         }
+
+        match self.info.timebase_frequency {
+            Some(freq) => info!("{:<30}: {} Hz", "Timebase Frequency", freq),
+            None => warn!(
+                "{:<30}: Not Available, assuming {} Hz",
+                "Timebase Frequency",
+                crate::time::timebase_frequency()
+            ),
+        }
     }
 
     #[inline]
     fn print_device_info(&self) {
         self.print_clint_info();
         self.print_console_info();
+        self.print_console_dma_info();
         self.print_reset_info();
+        self.print_irq_info();
+        self.print_trng_info();
+        self.print_rtc_info();
+        self.print_suspend_info();
+        self.print_idle_state_info();
+        self.print_domain_info();
+        self.print_iommu_info();
+        self.print_pci_info();
         self.print_hsm_info();
         self.print_rfence_info();
     }
 
     #[inline]
     fn print_clint_info(&self) {
-        match self.info.ipi {
-            Some((base, device)) => {
-                info!(
-                    "{:<30}: {:?} (Base Address: 0x{:x})",
-                    "Platform IPI Device", device, base
-                );
-            }
-            None => warn!("{:<30}: Not Available", "Platform IPI Device"),
+        if self.info.ipi[0].is_none() {
+            warn!("{:<30}: Not Available", "Platform IPI Device");
+        }
+        for clint in self.info.ipi.iter().flatten() {
+            info!(
+                "{:<30}: {:?} (Base Address: 0x{:x}, Harts: {}-{})",
+                "Platform IPI Device",
+                clint.kind,
+                clint.base,
+                clint.first_hart,
+                clint.first_hart + clint.hart_count - 1
+            );
         }
     }
 
@@ -332,16 +869,148 @@ impl Platform {
 
     #[inline]
     fn print_reset_info(&self) {
-        if let Some(base) = self.info.reset {
+        if let Some((base, device)) = self.info.reset {
             info!(
-                "{:<30}: Available (Base Address: 0x{:x})",
-                "Platform Reset Device", base
+                "{:<30}: {:?} (Base Address: 0x{:x})",
+                "Platform Reset Device", device, base
             );
         } else {
             warn!("{:<30}: Not Available", "Platform Reset Device");
         }
     }
 
+    #[inline]
+    fn print_irq_info(&self) {
+        if let Some(plic) = self.info.plic {
+            info!(
+                "{:<30}: PLIC (Base Address: 0x{:x}, M-mode Contexts: {})",
+                "Platform Interrupt Controller",
+                plic.base,
+                plic.m_context_count()
+            );
+        } else if let Some(aplic) = self.info.aplic.filter(|aplic| !aplic.msi) {
+            info!(
+                "{:<30}: APLIC (Base Address: 0x{:x}, M-mode IDCs: {})",
+                "Platform Interrupt Controller",
+                aplic.base,
+                aplic.idc_count()
+            );
+        } else if let Some(aplic) = self.info.aplic {
+            warn!(
+                "{:<30}: APLIC in MSI mode (Base Address: 0x{:x}), no firmware owned interrupts",
+                "Platform Interrupt Controller", aplic.base
+            );
+        } else {
+            warn!("{:<30}: Not Available", "Platform Interrupt Controller");
+        }
+    }
+
+    #[inline]
+    fn print_console_dma_info(&self) {
+        if let Some(info) = self.info.console_dma {
+            info!(
+                "{:<30}: {:?} (Base Address: 0x{:x}, Channel: {}, Request: {}, IRQ: {})",
+                "Platform Console DMA", info.kind, info.base, info.channel, info.request, info.irq
+            );
+        }
+    }
+
+    #[inline]
+    fn print_trng_info(&self) {
+        if let Some((base, device)) = self.info.trng {
+            info!(
+                "{:<30}: {:?} (Base Address: 0x{:x})",
+                "Platform TRNG Device", device, base
+            );
+        }
+    }
+
+    #[inline]
+    fn print_suspend_info(&self) {
+        if let Some(device) = self.info.suspend {
+            info!("{:<30}: {:?}", "Platform Suspend Device", device);
+        }
+    }
+
+    #[inline]
+    fn print_domain_info(&self) {
+        for (index, domain) in self.info.domains.iter().enumerate() {
+            if let Some(domain) = domain {
+                info!(
+                    "{:<30}: {} (Harts: {:#x}, Denied Extensions: {:#x})",
+                    "Platform Domain", index, domain.harts, domain.denied
+                );
+                if let Some(boot) = domain.boot {
+                    info!(
+                        "{:<30}: {} (Hart {}, 0x{:x} in {:?} mode)",
+                        "Platform Domain Next Stage",
+                        index,
+                        boot.hart_id,
+                        boot.next_addr,
+                        boot.next_mode
+                    );
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn print_idle_state_info(&self) {
+        for state in self.info.idle_states.iter().flatten() {
+            info!(
+                "{:<30}: 0x{:08x} ({}, entry {} us, exit {} us, residency {} us{})",
+                "Platform Idle State",
+                state.suspend_type,
+                if state.non_retentive() {
+                    "non-retentive"
+                } else {
+                    "retentive"
+                },
+                state.entry_latency_us,
+                state.exit_latency_us,
+                state.min_residency_us,
+                if state.local_timer_stop {
+                    ", timer stops"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+
+    #[inline]
+    fn print_rtc_info(&self) {
+        if let Some(base) = self.info.rtc {
+            info!(
+                "{:<30}: Goldfish (Base Address: 0x{:x})",
+                "Platform RTC Device", base
+            );
+        }
+    }
+
+    #[inline]
+    fn print_iommu_info(&self) {
+        for base in self.info.iommu.iter().flatten() {
+            match IommuMode::current() {
+                Some(mode) => info!(
+                    "{:<30}: {:?} mode (Base Address: 0x{:x})",
+                    "Platform IOMMU", mode, base
+                ),
+                None => info!("{:<30}: Base Address: 0x{:x}", "Platform IOMMU", base),
+            }
+        }
+    }
+
+    #[inline]
+    fn print_pci_info(&self) {
+        for ecam in self.info.pci_ecam.iter().flatten() {
+            info!(
+                "{:<30}: ECAM 0x{:x} - 0x{:x}",
+                "Platform PCI Host Bridge", ecam.start, ecam.end
+            );
+        }
+    }
+
     #[inline]
     fn print_memory_info(&self) {
         if let Some(memory_range) = &self.info.memory_range {
@@ -352,6 +1021,24 @@ impl Platform {
         } else {
             warn!("{:<30}: Not Available", "Memory range");
         }
+        let (_, nodes) = self.info.numa.nodes();
+        if nodes > 0 {
+            info!("{:<30}: {}", "NUMA Nodes", nodes);
+            for (range, node) in self.info.numa.memory.iter().flatten() {
+                if let Some(node) = node {
+                    info!(
+                        "{:<30}: node {}, 0x{:x} - 0x{:x}",
+                        "NUMA Memory", node, range.start, range.end
+                    );
+                }
+            }
+        }
+        if let Some(crashdump) = &self.info.crashdump {
+            info!(
+                "{:<30}: 0x{:x} - 0x{:x}",
+                "Crash dump region", crashdump.start, crashdump.end
+            );
+        }
     }
 
     #[inline]
@@ -380,6 +1067,18 @@ impl Platform {
         );
     }
 
+    #[inline]
+    fn print_extension_mask_info(&self) {
+        info!(
+            "{:<30}: {}",
+            "SBI Specification Version",
+            extension_mask::spec_version()
+        );
+        for ext in extension_mask::disabled() {
+            info!("{:<30}: {}", "Disabled SBI Extension", ext.as_str());
+        }
+    }
+
     #[inline]
     fn print_additional_info(&self) {
         if !self.ready.load(Ordering::Acquire) {
@@ -406,6 +1105,20 @@ impl Platform {
         self.sbi.reset.is_some()
     }
 
+    /// Quiesce PCI devices before the next stage takes over, see `pci`.
+    pub fn pci_prepare_handoff(&self) {
+        if !pci::bus_master_off() {
+            return;
+        }
+        for ecam in self.info.pci_ecam.iter().flatten() {
+            let disabled = pci::disable_bus_master(ecam);
+            info!(
+                "PCI host bridge 0x{:x}: bus mastering turned off on {} functions",
+                ecam.start, disabled
+            );
+        }
+    }
+
     pub fn have_ipi(&self) -> bool {
         self.sbi.ipi.is_some()
     }
@@ -424,3 +1137,76 @@ impl Platform {
 }
 
 pub(crate) static mut PLATFORM: Platform = Platform::new();
+
+/// Hart running `Platform::init`, `usize::MAX` until it starts.
+static INIT_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Set once `check_published` reported a hart reading devices too early.
+static EARLY_USE_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Panic in debug builds if the current hart reads `what` while another
+/// hart is still filling in the platform, see `Platform` for the protocol.
+///
+/// Reports once only, so the panic can still reach the console and timer.
+#[inline]
+fn check_published(what: &str) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let init_hart = INIT_HART.load(Ordering::Relaxed);
+    if init_hart == usize::MAX || init_hart == current_hartid() || unsafe { PLATFORM.ready() } {
+        return;
+    }
+    if !EARLY_USE_REPORTED.swap(true, Ordering::Relaxed) {
+        panic!(
+            "Hart {} used the {} before the platform was published",
+            current_hartid(),
+            what
+        );
+    }
+}
+
+// Accessors of the published devices. Each holds the only `unsafe` read of
+// `PLATFORM` its callers need: devices are written by `init` alone and never
+// replaced, so shared references to them stay valid for good.
+
+/// The IPI and timer device, if any.
+#[inline]
+pub fn ipi() -> Option<&'static SbiIpi<MachineClintSet>> {
+    check_published("IPI device");
+    unsafe { PLATFORM.sbi.ipi.as_ref() }
+}
+
+/// The console, if any.
+#[inline]
+pub fn console() -> Option<&'static SbiConsole<MachineConsole>> {
+    check_published("console");
+    unsafe { PLATFORM.sbi.console.as_ref() }
+}
+
+/// The reset device, if any.
+#[inline]
+pub fn reset() -> Option<&'static SbiReset<MachineReset>> {
+    check_published("reset device");
+    unsafe { PLATFORM.sbi.reset.as_ref() }
+}
+
+/// The HSM extension, if any.
+#[inline]
+pub fn hsm() -> Option<&'static SbiHsm> {
+    check_published("HSM extension");
+    unsafe { PLATFORM.sbi.hsm.as_ref() }
+}
+
+/// Main memory of the board, if the device tree describes it.
+#[inline]
+pub fn memory_range() -> Option<&'static Range<usize>> {
+    check_published("memory range");
+    unsafe { PLATFORM.info.memory_range.as_ref() }
+}
+
+/// NUMA nodes of memory and harts, as the device tree gives them.
+#[inline]
+pub fn numa() -> &'static NumaInfo {
+    check_published("NUMA map");
+    unsafe { &PLATFORM.info.numa }
+}
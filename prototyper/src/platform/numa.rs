@@ -0,0 +1,100 @@
+//! NUMA associations of memory and harts.
+//!
+//! Memory and cpu nodes name their NUMA node in `numa-node-id`, and
+//! `/distance-map` gives the distances between nodes. The firmware keeps the
+//! node of every memory range and hart, and before handoff makes sure the
+//! tree has a distance map Linux accepts, see `fdt_fixup::fixup_numa`.
+
+use core::ops::Range;
+
+pub(crate) use crate::firmware::fdt_fixup::MAX_NUMA_NODES;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Most memory ranges kept from the device tree.
+pub(crate) const MAX_MEMORY_RANGES: usize = 8;
+
+/// Memory ranges and harts with their NUMA node, `None` where the tree names none.
+pub struct NumaInfo {
+    /// Memory ranges in tree order.
+    pub memory: [Option<(Range<usize>, Option<u32>)>; MAX_MEMORY_RANGES],
+    /// Node of each hart.
+    pub hart_node: [Option<u32>; NUM_HART_MAX],
+}
+
+impl NumaInfo {
+    pub const fn new() -> Self {
+        Self {
+            memory: [const { None }; MAX_MEMORY_RANGES],
+            hart_node: [None; NUM_HART_MAX],
+        }
+    }
+
+    /// Record memory range `range` of node `node`.
+    pub fn add_memory(&mut self, range: Range<usize>, node: Option<u32>) {
+        match self.memory.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some((range, node)),
+            None => warn!(
+                "Ignoring memory 0x{:x} - 0x{:x} beyond the first {} ranges",
+                range.start, range.end, MAX_MEMORY_RANGES
+            ),
+        }
+    }
+
+    /// Node of `hart_id`, if the tree names one.
+    #[inline]
+    pub fn node_of_hart(&self, hart_id: usize) -> Option<u32> {
+        self.hart_node.get(hart_id).copied().flatten()
+    }
+
+    /// Distinct nodes of memory and harts in ascending order, and their number.
+    pub fn nodes(&self) -> ([u32; MAX_NUMA_NODES], usize) {
+        let mut nodes = [0; MAX_NUMA_NODES];
+        let mut count = 0;
+        let named = self
+            .memory
+            .iter()
+            .flatten()
+            .filter_map(|&(_, node)| node)
+            .chain(self.hart_node.iter().copied().flatten());
+        for node in named {
+            if nodes[..count].contains(&node) {
+                continue;
+            }
+            if count == MAX_NUMA_NODES {
+                warn!(
+                    "Ignoring NUMA node {} beyond the first {}",
+                    node, MAX_NUMA_NODES
+                );
+                continue;
+            }
+            nodes[count] = node;
+            count += 1;
+        }
+        nodes[..count].sort_unstable();
+        (nodes, count)
+    }
+
+    /// Warn about trees Linux turns NUMA off for: all memory must name its
+    /// node as soon as anything does, or the unnamed ranges belong to none.
+    pub fn check(&self, enabled: &[bool; NUM_HART_MAX]) {
+        let memory = || self.memory.iter().flatten();
+        let harts = || (0..NUM_HART_MAX).filter(|&hart_id| enabled[hart_id]);
+        let named = memory().any(|(_, node)| node.is_some())
+            || harts().any(|hart_id| self.node_of_hart(hart_id).is_some());
+        if !named {
+            return;
+        }
+        for (range, _) in memory().filter(|(_, node)| node.is_none()) {
+            warn!(
+                "Memory 0x{:x} - 0x{:x} has no numa-node-id, NUMA kernels may drop it",
+                range.start, range.end
+            );
+        }
+        for hart_id in harts().filter(|&hart_id| self.node_of_hart(hart_id).is_none()) {
+            warn!(
+                "Hart {} has no numa-node-id, NUMA kernels assume node 0",
+                hart_id
+            );
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! PCI host bridges with an ECAM configuration space.
+//!
+//! Devices left bus mastering by a previous boot stage, a network card still
+//! receiving into buffers of a bootloader for example, keep writing to memory
+//! the next stage considers free. With `PROTOTYPER_PCI_BUS_MASTER_OFF` set at
+//! build time, bus mastering is turned off on every function before handoff
+//! and drivers turn it back on as they claim their devices.
+
+use core::ops::Range;
+
+pub(crate) const PCI_HOST_COMPATIBLE: [&str; 1] = ["pci-host-ecam-generic"];
+
+/// Most host bridges a platform may describe.
+pub(crate) const MAX_PCI_HOSTS: usize = 4;
+
+const ECAM_BUS_SHIFT: usize = 20;
+const ECAM_DEVICE_SHIFT: usize = 15;
+const ECAM_FUNCTION_SHIFT: usize = 12;
+
+const CONFIG_VENDOR_ID: usize = 0x00;
+const CONFIG_COMMAND: usize = 0x04;
+const CONFIG_HEADER_TYPE: usize = 0x0e;
+const COMMAND_BUS_MASTER: u16 = 0x1 << 2;
+const HEADER_TYPE_MULTI_FUNCTION: u8 = 0x1 << 7;
+/// Vendor ID read from a function that does not exist.
+const VENDOR_ID_NONE: u16 = 0xffff;
+
+/// Returns true if the build asks for bus mastering to be turned off.
+#[inline]
+pub fn bus_master_off() -> bool {
+    option_env!("PROTOTYPER_PCI_BUS_MASTER_OFF").is_some_and(|value| value != "0")
+}
+
+#[inline]
+fn function_config(ecam: &Range<usize>, bus: usize, device: usize, function: usize) -> usize {
+    ecam.start
+        + (bus << ECAM_BUS_SHIFT)
+        + (device << ECAM_DEVICE_SHIFT)
+        + (function << ECAM_FUNCTION_SHIFT)
+}
+
+/// Turn off bus mastering on every function behind the host bridge whose
+/// configuration space is `ecam`, returns how many functions had it on.
+pub fn disable_bus_master(ecam: &Range<usize>) -> usize {
+    let buses = (ecam.end - ecam.start) >> ECAM_BUS_SHIFT;
+    let mut disabled = 0;
+    for bus in 0..buses {
+        for device in 0..32 {
+            for function in 0..8 {
+                let config = function_config(ecam, bus, device, function);
+                let vendor = unsafe { ((config + CONFIG_VENDOR_ID) as *const u16).read_volatile() };
+                if vendor == VENDOR_ID_NONE {
+                    if function == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                let command = (config + CONFIG_COMMAND) as *mut u16;
+                let value = unsafe { command.read_volatile() };
+                if value & COMMAND_BUS_MASTER != 0 {
+                    unsafe { command.write_volatile(value & !COMMAND_BUS_MASTER) };
+                    disabled += 1;
+                }
+                let header =
+                    unsafe { ((config + CONFIG_HEADER_TYPE) as *const u8).read_volatile() };
+                if function == 0 && header & HEADER_TYPE_MULTI_FUNCTION == 0 {
+                    break;
+                }
+            }
+        }
+    }
+    disabled
+}
@@ -0,0 +1,146 @@
+use crate::sbi::irq::IrqController;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+pub(crate) const PLIC_COMPATIBLE: [&str; 2] = ["riscv,plic0", "sifive,plic-1.0.0"];
+
+const PRIORITY_OFFSET: usize = 0x0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0x0;
+const CONTEXT_CLAIM: usize = 0x4;
+
+/// Machine external interrupt, as a PLIC context names it in `interrupts-extended`.
+pub(crate) const IRQ_M_EXT: u32 = 11;
+/// Marks harts without a machine mode context.
+const NO_CONTEXT: u16 = u16::MAX;
+
+/// A PLIC found in the device tree and the machine mode context of each hart.
+#[derive(Clone, Copy, Debug)]
+pub struct PlicInfo {
+    pub base: usize,
+    m_contexts: [u16; NUM_HART_MAX],
+}
+
+impl PlicInfo {
+    /// A PLIC with no machine mode context yet.
+    pub fn new(base: usize) -> Self {
+        Self {
+            base,
+            m_contexts: [NO_CONTEXT; NUM_HART_MAX],
+        }
+    }
+
+    /// A PLIC whose contexts are not described, laid out as on QEMU virt with
+    /// one machine and one supervisor context per hart.
+    pub fn qemu_virt(base: usize) -> Self {
+        let mut info = Self::new(base);
+        for hart_id in 0..NUM_HART_MAX {
+            info.set_m_context(hart_id, hart_id * 2);
+        }
+        info
+    }
+
+    /// Record context `context` as the machine mode one of `hart_id`.
+    pub fn set_m_context(&mut self, hart_id: usize, context: usize) {
+        if let (Some(slot), Ok(context)) =
+            (self.m_contexts.get_mut(hart_id), u16::try_from(context))
+        {
+            *slot = context;
+        }
+    }
+
+    /// Number of harts with a machine mode context.
+    pub fn m_context_count(&self) -> usize {
+        self.m_contexts
+            .iter()
+            .filter(|&&context| context != NO_CONTEXT)
+            .count()
+    }
+}
+
+/// Platform-Level Interrupt Controller.
+#[derive(Clone, Copy)]
+pub struct Plic {
+    info: PlicInfo,
+}
+
+impl Plic {
+    #[inline]
+    pub const fn new(info: PlicInfo) -> Self {
+        Self { info }
+    }
+
+    /// Machine mode context of a hart, if it has one.
+    #[inline]
+    fn m_context(&self, hart_id: usize) -> Option<usize> {
+        match self.info.m_contexts.get(hart_id) {
+            Some(&context) if context != NO_CONTEXT => Some(context as usize),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.info.base + offset) as *mut u32
+    }
+
+    #[inline]
+    fn enable_reg(&self, hart_id: usize, irq: usize) -> Option<*mut u32> {
+        let context = self.m_context(hart_id)?;
+        Some(self.reg(ENABLE_OFFSET + context * ENABLE_STRIDE + (irq / 32) * 4))
+    }
+
+    #[inline]
+    fn context_reg(&self, hart_id: usize, offset: usize) -> Option<*mut u32> {
+        let context = self.m_context(hart_id)?;
+        Some(self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + offset))
+    }
+}
+
+impl IrqController for Plic {
+    #[inline]
+    fn set_priority(&self, irq: usize, priority: u32) {
+        unsafe { self.reg(PRIORITY_OFFSET + irq * 4).write_volatile(priority) }
+    }
+
+    // Harts without a machine mode context take no interrupts from the PLIC.
+
+    #[inline]
+    fn enable(&self, hart_id: usize, irq: usize) {
+        if let Some(reg) = self.enable_reg(hart_id, irq) {
+            unsafe { reg.write_volatile(reg.read_volatile() | (1 << (irq % 32))) }
+        }
+    }
+
+    #[inline]
+    fn disable(&self, hart_id: usize, irq: usize) {
+        if let Some(reg) = self.enable_reg(hart_id, irq) {
+            unsafe { reg.write_volatile(reg.read_volatile() & !(1 << (irq % 32))) }
+        }
+    }
+
+    #[inline]
+    fn set_threshold(&self, hart_id: usize, threshold: u32) {
+        if let Some(reg) = self.context_reg(hart_id, CONTEXT_THRESHOLD) {
+            unsafe { reg.write_volatile(threshold) }
+        }
+    }
+
+    #[inline]
+    fn claim(&self, hart_id: usize) -> Option<usize> {
+        let reg = self.context_reg(hart_id, CONTEXT_CLAIM)?;
+        match unsafe { reg.read_volatile() } {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+
+    #[inline]
+    fn complete(&self, hart_id: usize, irq: usize) {
+        if let Some(reg) = self.context_reg(hart_id, CONTEXT_CLAIM) {
+            unsafe { reg.write_volatile(irq as u32) }
+        }
+    }
+}
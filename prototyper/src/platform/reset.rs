@@ -1,22 +1,58 @@
 use sifive_test_device::SifiveTestDevice;
 
+use crate::platform::htif::Htif;
 use crate::sbi::reset::ResetDevice;
 pub(crate) const SIFIVETEST_COMPATIBLE: [&str; 1] = ["sifive,test0"];
 
-/// Reset Device: SifiveTestDevice
-impl ResetDevice for SifiveTestDevice {
+#[doc(hidden)]
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum MachineResetType {
+    SifiveTest,
+    Htif,
+}
+
+#[doc(hidden)]
+#[allow(unused)]
+pub enum MachineReset {
+    SifiveTest(*const SifiveTestDevice),
+    Htif(Htif),
+}
+
+unsafe impl Send for MachineReset {}
+unsafe impl Sync for MachineReset {}
+
+/// Reset Device: SifiveTestDevice or HTIF
+impl ResetDevice for MachineReset {
     #[inline]
     fn fail(&self, code: u16) -> ! {
-        self.fail(code)
+        match self {
+            Self::SifiveTest(test) => unsafe { (**test).fail(code) },
+            // HTIF treats exit code 0 as success, so never report 0 on failure.
+            Self::Htif(htif) => htif.exit(code.max(1)),
+        }
     }
 
     #[inline]
     fn pass(&self) -> ! {
-        self.pass()
+        match self {
+            Self::SifiveTest(test) => unsafe { (**test).pass() },
+            Self::Htif(htif) => htif.exit(0),
+        }
     }
 
     #[inline]
     fn reset(&self) -> ! {
-        self.reset()
+        match self {
+            Self::SifiveTest(test) => unsafe { (**test).reset() },
+            // HTIF has no reboot command, the best we can do is leave the simulator.
+            Self::Htif(htif) => htif.exit(0),
+        }
+    }
+
+    #[cfg(feature = "exit-dump")]
+    #[inline]
+    fn is_test_finisher(&self) -> bool {
+        matches!(self, Self::SifiveTest(_))
     }
 }
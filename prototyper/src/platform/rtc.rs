@@ -0,0 +1,26 @@
+pub(crate) const GOLDFISH_RTC_COMPATIBLE: [&str; 1] = ["google,goldfish-rtc"];
+
+const GOLDFISH_TIME_LOW: usize = 0x00;
+const GOLDFISH_TIME_HIGH: usize = 0x04;
+
+/// Goldfish real time clock, counting nanoseconds independently of mtime.
+#[derive(Clone, Copy)]
+pub struct GoldfishRtc {
+    base: usize,
+}
+
+impl GoldfishRtc {
+    #[inline]
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// Current time in nanoseconds.
+    #[inline]
+    pub fn read_ns(&self) -> u64 {
+        // Reading the low word latches the high word.
+        let low = unsafe { ((self.base + GOLDFISH_TIME_LOW) as *const u32).read_volatile() };
+        let high = unsafe { ((self.base + GOLDFISH_TIME_HIGH) as *const u32).read_volatile() };
+        ((high as u64) << 32) | low as u64
+    }
+}
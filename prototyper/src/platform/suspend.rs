@@ -0,0 +1,72 @@
+use rustsbi::SbiRet;
+
+use crate::sbi::susp::{SuspendDevice, SUSPEND_TO_RAM};
+use crate::time;
+
+/// Model prefix of QEMU `virt` machines.
+pub(crate) const QEMU_VIRT_MODEL: &str = "riscv-virtio";
+
+/// Time the QEMU stub stays suspended.
+const QEMU_SUSPEND_US: u64 = 5_000_000;
+
+#[doc(hidden)]
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum MachineSuspendType {
+    QemuVirt,
+}
+
+#[doc(hidden)]
+#[allow(unused)]
+pub enum MachineSuspend {
+    QemuVirt(QemuSuspend),
+}
+
+/// Suspend Device: QEMU virt stub
+impl SuspendDevice for MachineSuspend {
+    #[inline]
+    fn supports(&self, sleep_type: u32) -> bool {
+        match self {
+            Self::QemuVirt(stub) => stub.supports(sleep_type),
+        }
+    }
+
+    #[inline]
+    fn enter(&self, sleep_type: u32) -> Result<(), SbiRet> {
+        match self {
+            Self::QemuVirt(stub) => stub.enter(sleep_type),
+        }
+    }
+
+    #[inline]
+    fn exit(&self, sleep_type: u32) {
+        match self {
+            Self::QemuVirt(stub) => stub.exit(sleep_type),
+        }
+    }
+}
+
+/// Suspend to RAM on QEMU virt, which cannot power anything down.
+///
+/// Waits a fixed time in place of the sleep and wakes up by itself, so the
+/// supervisor's suspend and resume paths can be tested without a wakeup source.
+pub struct QemuSuspend;
+
+impl QemuSuspend {
+    fn supports(&self, sleep_type: u32) -> bool {
+        sleep_type == SUSPEND_TO_RAM
+    }
+
+    fn enter(&self, sleep_type: u32) -> Result<(), SbiRet> {
+        info!(
+            "System suspend {}, waking up in {} us",
+            sleep_type, QEMU_SUSPEND_US
+        );
+        time::udelay(QEMU_SUSPEND_US);
+        Ok(())
+    }
+
+    fn exit(&self, sleep_type: u32) {
+        info!("System resume from {}", sleep_type);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::sbi::entropy::TrngDevice;
+use crate::time;
+
+pub(crate) const STARFIVE_TRNG_COMPATIBLE: [&str; 1] = ["starfive,jh7110-trng"];
+
+const STARFIVE_CTRL: usize = 0x00;
+const STARFIVE_STAT: usize = 0x04;
+const STARFIVE_ISTAT: usize = 0x14;
+const STARFIVE_RAND0: usize = 0x20;
+
+const STARFIVE_CTRL_GENE_RANDNUM: u32 = 0x1;
+const STARFIVE_CTRL_EXEC_RANDRESEED: u32 = 0x2;
+const STARFIVE_STAT_SEEDED: u32 = 1 << 9;
+const STARFIVE_ISTAT_RAND_RDY: u32 = 1 << 0;
+const STARFIVE_ISTAT_SEED_DONE: u32 = 1 << 1;
+const STARFIVE_ISTAT_LFSR_LOCKUP: u32 = 1 << 4;
+/// Random words produced by one generate command in the default 128-bit mode.
+const STARFIVE_RAND_WORDS: usize = 4;
+
+/// Time a single reseed or generate command may take.
+const TRNG_TIMEOUT_US: u64 = 10_000;
+
+#[doc(hidden)]
+#[allow(unused)]
+#[derive(Clone, Copy, Debug)]
+pub enum MachineTrngType {
+    StarFiveJh7110,
+}
+
+#[doc(hidden)]
+#[allow(unused)]
+pub enum MachineTrng {
+    StarFiveJh7110(StarFiveTrng),
+}
+
+/// TRNG Device: StarFive JH7110
+impl TrngDevice for MachineTrng {
+    #[inline]
+    fn fill(&self, buf: &mut [u32]) -> bool {
+        match self {
+            Self::StarFiveJh7110(trng) => trng.fill(buf),
+        }
+    }
+}
+
+/// StarFive JH7110 true random number generator.
+///
+/// Clocks and resets of the block are left to an earlier boot stage.
+pub struct StarFiveTrng {
+    base: usize,
+}
+
+impl StarFiveTrng {
+    #[inline]
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    #[inline]
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Issue `command` and wait for `done` in the interrupt status register.
+    fn command(&self, command: u32, done: u32) -> bool {
+        self.write(STARFIVE_CTRL, command);
+        let deadline = time::Deadline::after_us(TRNG_TIMEOUT_US);
+        loop {
+            let status = self.read(STARFIVE_ISTAT);
+            if status & STARFIVE_ISTAT_LFSR_LOCKUP != 0 {
+                self.write(STARFIVE_ISTAT, STARFIVE_ISTAT_LFSR_LOCKUP);
+                return false;
+            }
+            if status & done != 0 {
+                self.write(STARFIVE_ISTAT, done);
+                return true;
+            }
+            if deadline.expired() {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn fill(&self, buf: &mut [u32]) -> bool {
+        if self.read(STARFIVE_STAT) & STARFIVE_STAT_SEEDED == 0
+            && !self.command(STARFIVE_CTRL_EXEC_RANDRESEED, STARFIVE_ISTAT_SEED_DONE)
+        {
+            return false;
+        }
+        for chunk in buf.chunks_mut(STARFIVE_RAND_WORDS) {
+            if !self.command(STARFIVE_CTRL_GENE_RANDNUM, STARFIVE_ISTAT_RAND_RDY) {
+                return false;
+            }
+            for (i, word) in chunk.iter_mut().enumerate() {
+                *word = self.read(STARFIVE_RAND0 + i * 4);
+            }
+        }
+        true
+    }
+}
@@ -4,29 +4,66 @@
 ///
 /// Time value (lower 32 bits).
 pub const CSR_TIME: u32 = 0xc01;
-/// Time value (upper 32 bits).
+/// Time value (upper 32 bits), RV32 only.
+#[cfg(target_arch = "riscv32")]
 pub const CSR_TIMEH: u32 = 0xc81;
 /// Supervisor timer compare value.
 pub const CSR_STIMECMP: u32 = 0x14D;
 
+/// Machine interrupt enable in `mstatus`.
+pub const MSTATUS_MIE: usize = 0x1 << 3;
+/// Previous privilege of M-mode traps in `mstatus`.
+pub const MSTATUS_MPP: usize = 0x3 << 11;
+/// Loads and stores use the privilege in `MPP`, in `mstatus`.
+pub const MSTATUS_MPRV: usize = 0x1 << 17;
+/// Loads may read executable pages, in `mstatus`.
+pub const MSTATUS_MXR: usize = 0x1 << 19;
+/// Previous virtualization mode of M-mode traps in `mstatus` (H), RV64 only.
+#[cfg(target_arch = "riscv64")]
+pub const MSTATUS_MPV: usize = 0x1 << 39;
+
+/// Previous landing pad state of S-mode in `mstatus` (Zicfilp).
+pub const MSTATUS_SPELP: usize = 0x1 << 23;
+/// Previous landing pad state of M-mode traps in `mstatus` (Zicfilp).
+#[cfg(target_arch = "riscv64")]
+pub const MSTATUS_MPELP: usize = 0x1 << 41;
+
 /// Machine environment configuration register (menvcfg) bit fields.
+///
+/// The register is 64 bits wide on both XLENs, RV32 keeps the upper half in
+/// `menvcfgh`, so bits are given as `u64` and accesses cover both halves.
 pub mod menvcfg {
     use core::arch::asm;
 
     /// Fence of I/O implies memory.
-    pub const FIOM: usize = 0x1 << 0;
+    pub const FIOM: u64 = 0x1 << 0;
+    /// Landing pad enable for S-mode (Zicfilp).
+    pub const LPE: u64 = 0x1 << 2;
+    /// Shadow stack enable for S-mode (Zicfiss).
+    pub const SSE: u64 = 0x1 << 3;
     /// Cache block invalidate - flush.
-    pub const CBIE_FLUSH: usize = 0x01 << 4;
+    pub const CBIE_FLUSH: u64 = 0x01 << 4;
     /// Cache block invalidate - invalidate.
-    pub const CBIE_INVALIDATE: usize = 0x11 << 4;
+    pub const CBIE_INVALIDATE: u64 = 0x11 << 4;
     /// Cache block clean for enclave.
-    pub const CBCFE: usize = 0x1 << 6;
+    pub const CBCFE: u64 = 0x1 << 6;
     /// Cache block zero for enclave.
-    pub const CBZE: usize = 0x1 << 7;
+    pub const CBZE: u64 = 0x1 << 7;
+    /// Pointer masking mode of S-mode (Smnpm), RV64 only.
+    #[cfg(target_arch = "riscv64")]
+    pub const PMM: u64 = 0x3 << 32;
+    /// `PMM` value masking the upper 7 bits of addresses.
+    #[cfg(target_arch = "riscv64")]
+    pub const PMM_PMLEN_7: u64 = 0x2 << 32;
+    /// `PMM` value masking the upper 16 bits of addresses.
+    #[cfg(target_arch = "riscv64")]
+    pub const PMM_PMLEN_16: u64 = 0x3 << 32;
+    /// Counter delegation enable (Smcdeleg).
+    pub const CDE: u64 = 0x1 << 60;
     /// Page-based memory types enable.
-    pub const PBMTE: usize = 0x1 << 62;
+    pub const PBMTE: u64 = 0x1 << 62;
     /// Supervisor timer counter enable.
-    pub const STCE: usize = 0x1 << 63;
+    pub const STCE: u64 = 0x1 << 63;
 
     /// Sets the STCE bit to enable supervisor timer counter.
     #[inline(always)]
@@ -34,19 +71,307 @@ pub mod menvcfg {
         set_bits(STCE);
     }
 
+    /// Reads the menvcfg register.
+    #[inline]
+    pub fn read() -> u64 {
+        let low: usize;
+        unsafe { asm!("csrr {}, menvcfg", out(reg) low, options(nomem)) };
+        #[cfg(target_pointer_width = "32")]
+        {
+            let high: usize;
+            unsafe { asm!("csrr {}, menvcfgh", out(reg) high, options(nomem)) };
+            (high as u64) << 32 | low as u64
+        }
+        #[cfg(target_pointer_width = "64")]
+        {
+            low as u64
+        }
+    }
+
+    /// Clears specified bits in menvcfg register.
+    pub fn clear_bits(option: u64) {
+        unsafe { asm!("csrc menvcfg, {}", in(reg) option as usize, options(nomem)) };
+        #[cfg(target_pointer_width = "32")]
+        unsafe {
+            asm!("csrc menvcfgh, {}", in(reg) (option >> 32) as usize, options(nomem))
+        };
+    }
+
     /// Sets specified bits in menvcfg register.
-    pub fn set_bits(option: usize) {
-        let mut bits: usize;
+    pub fn set_bits(option: u64) {
+        unsafe { asm!("csrs menvcfg, {}", in(reg) option as usize, options(nomem)) };
+        #[cfg(target_pointer_width = "32")]
         unsafe {
-            // Read current `menvcfg` value.
-            asm!("csrr {}, menvcfg", out(reg) bits, options(nomem));
-        }
-        // Set requested bits
-        bits |= option;
+            asm!("csrs menvcfgh, {}", in(reg) (option >> 32) as usize, options(nomem))
+        };
+    }
+}
+
+/// Machine security configuration register (mseccfg) bit fields.
+pub mod mseccfg {
+    use core::arch::asm;
+
+    /// Machine mode lockdown (Smepmp): locked PMP entries are M-mode only,
+    /// the others lower privilege only.
+    pub const MML: usize = 0x1 << 0;
+    /// Machine mode whitelist policy (Smepmp): M-mode accesses no PMP entry
+    /// matches are denied.
+    pub const MMWP: usize = 0x1 << 1;
+    /// U-mode may access the `seed` CSR (Zkr).
+    pub const USEED: usize = 0x1 << 8;
+    /// S-mode may access the `seed` CSR (Zkr).
+    pub const SSEED: usize = 0x1 << 9;
+
+    /// Reads the mseccfg register.
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x747", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    /// Sets specified bits in mseccfg register.
+    pub fn set_bits(option: usize) {
+        unsafe { asm!("csrs 0x747, {}", in(reg) option, options(nomem)) };
+    }
+
+    /// Clears specified bits in mseccfg register.
+    pub fn clear_bits(option: usize) {
+        unsafe { asm!("csrc 0x747, {}", in(reg) option, options(nomem)) };
+    }
+}
+
+/// Machine configuration pointer register (mconfigptr), from privileged spec 1.12.
+pub mod mconfigptr {
+    use core::arch::asm;
+
+    /// Reads `mconfigptr`, 0 when the hart has no configuration structure.
+    #[inline]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0xf15", out(reg) bits, options(nomem)) };
+        bits
+    }
+}
+
+/// Resumable NMI registers, from Smrnmi.
+pub mod rnmi {
+    use core::arch::asm;
+
+    /// `mnstatus`: RNMIs are enabled.
+    pub const MNSTATUS_NMIE: usize = 0x1 << 3;
+    /// `mncause`: the RNMI was an interrupt rather than an exception.
+    #[allow(unused)]
+    pub const MNCAUSE_INTERRUPT: usize = 0x1 << (usize::BITS - 1);
+
+    /// Sets `mnscratch`.
+    #[inline]
+    pub fn write_mnscratch(value: usize) {
+        unsafe { asm!("csrw 0x740, {}", in(reg) value, options(nomem)) };
+    }
+
+    /// Reads `mnepc`.
+    #[inline]
+    pub fn read_mnepc() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x741", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    /// Reads `mncause`.
+    #[inline]
+    pub fn read_mncause() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x742", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    /// Reads `mnstatus`.
+    #[allow(unused)]
+    #[inline]
+    pub fn read_mnstatus() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x744", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    /// Sets specified bits in `mnstatus`.
+    #[inline]
+    pub fn set_mnstatus_bits(option: usize) {
+        unsafe { asm!("csrs 0x744, {}", in(reg) option, options(nomem)) };
+    }
+}
+
+/// Virtual supervisor interrupt registers, from Smaia.
+pub mod aia {
+    use core::arch::asm;
+
+    /// Supervisor software interrupt bit of `mvien` and `mvip`.
+    pub const SSIP: usize = 0x1 << 1;
+
+    /// Reads `mvien`.
+    #[inline]
+    pub fn read_mvien() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x308", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    /// Sets specified bits in `mvien`.
+    #[inline]
+    pub fn set_mvien_bits(option: usize) {
+        unsafe { asm!("csrs 0x308, {}", in(reg) option, options(nomem)) };
+    }
+
+    /// Clears specified bits in `mvien`.
+    #[inline]
+    pub fn clear_mvien_bits(option: usize) {
+        unsafe { asm!("csrc 0x308, {}", in(reg) option, options(nomem)) };
+    }
+
+    /// Sets specified bits in `mvip`.
+    #[inline]
+    pub fn set_mvip_bits(option: usize) {
+        unsafe { asm!("csrs 0x309, {}", in(reg) option, options(nomem)) };
+    }
+
+    /// Clears specified bits in `mvip`.
+    #[inline]
+    pub fn clear_mvip_bits(option: usize) {
+        unsafe { asm!("csrc 0x309, {}", in(reg) option, options(nomem)) };
+    }
+}
+
+/// Physical memory protection registers.
+pub mod pmp {
+    use core::arch::asm;
+
+    /// PMP entries the firmware may read back.
+    pub const ENTRIES: usize = 16;
+
+    macro_rules! read_indexed_csr {
+        ($index:expr, $($n:literal => $name:literal),* $(,)?) => {
+            match $index {
+                $($n => {
+                    let bits: usize;
+                    unsafe { asm!(concat!("csrr {}, ", $name), out(reg) bits, options(nomem)) };
+                    Some(bits)
+                })*
+                _ => None,
+            }
+        };
+    }
+
+    /// Reads `pmpaddr<index>`.
+    pub fn read_addr(index: usize) -> Option<usize> {
+        read_indexed_csr!(index,
+            0 => "pmpaddr0", 1 => "pmpaddr1", 2 => "pmpaddr2", 3 => "pmpaddr3",
+            4 => "pmpaddr4", 5 => "pmpaddr5", 6 => "pmpaddr6", 7 => "pmpaddr7",
+            8 => "pmpaddr8", 9 => "pmpaddr9", 10 => "pmpaddr10", 11 => "pmpaddr11",
+            12 => "pmpaddr12", 13 => "pmpaddr13", 14 => "pmpaddr14", 15 => "pmpaddr15",
+        )
+    }
+
+    /// Reads the configuration byte of PMP entry `index`.
+    pub fn read_cfg(index: usize) -> Option<u8> {
+        const PER_REG: usize = usize::BITS as usize / 8;
+        // RV64 only has the even numbered `pmpcfg` registers.
+        let reg = index / PER_REG * (PER_REG / 4);
+        let bits = read_indexed_csr!(reg,
+            0 => "pmpcfg0", 1 => "pmpcfg1", 2 => "pmpcfg2", 3 => "pmpcfg3",
+        );
+        bits.filter(|_| index < ENTRIES)
+            .map(|bits| (bits >> (index % PER_REG * 8)) as u8)
+    }
+}
+
+/// Trigger module registers, from Sdtrig.
+pub mod trigger {
+    use core::arch::asm;
+
+    pub const CSR_TSELECT: u32 = 0x7a0;
+
+    /// `tdata1` type field, in the top four bits.
+    pub const TDATA1_TYPE_SHIFT: u32 = usize::BITS - 4;
+    /// Address and data match trigger.
+    pub const TYPE_MCONTROL: usize = 2;
+    /// Address and data match trigger, Sdtrig 1.0 layout.
+    pub const TYPE_MCONTROL6: usize = 6;
+
+    // Fields shared by `mcontrol` and `mcontrol6`.
+    pub const TDATA1_CHAIN: usize = 0x1 << 11;
+    /// Fire when the address is greater than or equal to `tdata2`.
+    pub const TDATA1_MATCH_GE: usize = 2 << 7;
+    /// Fire when the address is less than `tdata2`.
+    pub const TDATA1_MATCH_LT: usize = 3 << 7;
+    pub const TDATA1_M: usize = 0x1 << 6;
+    pub const TDATA1_S: usize = 0x1 << 4;
+    pub const TDATA1_U: usize = 0x1 << 3;
+    pub const TDATA1_EXECUTE: usize = 0x1 << 2;
+    pub const TDATA1_STORE: usize = 0x1 << 1;
+    pub const TDATA1_LOAD: usize = 0x1 << 0;
+    /// Hit bit of `mcontrol`.
+    pub const MCONTROL_HIT: usize = 0x1 << 20;
+    /// Hit bit of `mcontrol6`.
+    pub const MCONTROL6_HIT0: usize = 0x1 << 22;
+
+    /// Selects trigger `index`, returns the index actually selected.
+    #[inline]
+    pub fn select(index: usize) -> usize {
+        let selected: usize;
         unsafe {
-            // Write back updated value
-            asm!("csrw menvcfg, {}", in(reg) bits, options(nomem));
-        }
+            asm!(
+                "csrw 0x7a0, {}",
+                "csrr {}, 0x7a0",
+                in(reg) index,
+                lateout(reg) selected,
+                options(nomem),
+            )
+        };
+        selected
+    }
+
+    /// Reads `tdata1` of the selected trigger.
+    #[inline]
+    pub fn read_tdata1() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x7a1", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    /// Writes `tdata1` of the selected trigger.
+    #[inline]
+    pub fn write_tdata1(value: usize) {
+        unsafe { asm!("csrw 0x7a1, {}", in(reg) value, options(nomem)) };
+    }
+
+    /// Writes `tdata2` of the selected trigger.
+    #[inline]
+    pub fn write_tdata2(value: usize) {
+        unsafe { asm!("csrw 0x7a2, {}", in(reg) value, options(nomem)) };
+    }
+}
+
+/// Entropy source register (seed) operations, from Zkr.
+pub mod seed {
+    use core::arch::asm;
+
+    /// Operational status field.
+    pub const OPST_SHIFT: u32 = 30;
+    /// Built-in self test running, no entropy yet.
+    pub const OPST_BIST: u32 = 0b00;
+    /// No entropy available right now, poll again.
+    pub const OPST_WAIT: u32 = 0b01;
+    /// 16 bits of entropy in the low bits.
+    pub const OPST_ES16: u32 = 0b10;
+    /// Unrecoverable failure of the entropy source.
+    pub const OPST_DEAD: u32 = 0b11;
+
+    /// Read `seed`. The CSR must be accessed with a write, reads alone trap.
+    #[inline]
+    pub fn read() -> u32 {
+        let value: usize;
+        unsafe { asm!("csrrw {}, 0x015, zero", out(reg) value, options(nomem)) };
+        value as u32
     }
 }
 
@@ -55,11 +380,29 @@ pub mod stimecmp {
     use core::arch::asm;
 
     /// Sets the supervisor timer compare value.
+    #[cfg(target_arch = "riscv64")]
     pub fn set(value: u64) {
         unsafe {
             asm!("csrrw zero, stimecmp, {}", in(reg) value, options(nomem));
         }
     }
+
+    /// Sets the supervisor timer compare value, high half first raised so
+    /// no intermediate value fires early.
+    #[cfg(target_arch = "riscv32")]
+    pub fn set(value: u64) {
+        unsafe {
+            asm!(
+                "csrw 0x15d, {max}",
+                "csrw stimecmp, {lo}",
+                "csrw 0x15d, {hi}",
+                max = in(reg) usize::MAX,
+                lo = in(reg) value as usize,
+                hi = in(reg) (value >> 32) as usize,
+                options(nomem),
+            );
+        }
+    }
 }
 
 /// Returns the current hart (hardware thread) ID.
@@ -67,3 +410,11 @@ pub mod stimecmp {
 pub fn current_hartid() -> usize {
     riscv::register::mhartid::read()
 }
+
+/// Reads `mstatus` as raw bits, which `riscv::register::mstatus` keeps private.
+#[inline]
+pub fn read_mstatus() -> usize {
+    let bits: usize;
+    unsafe { core::arch::asm!("csrr {}, mstatus", out(reg) bits, options(nomem)) };
+    bits
+}
@@ -0,0 +1,76 @@
+//! Cached answers of base extension queries.
+//!
+//! The specification version, implementation and machine ID queries of the
+//! base extension answer the same on a hart from the moment it is ready.
+//! Some guests issue them in hot paths, so `ecall_fast` serves them from a
+//! table per hart, filled once from the full dispatcher, without dispatching
+//! again. Like every other call, a cached one is only served once the
+//! extension mask and the domain of the caller allow it. Probing is not
+//! cached, its answer depends on the domain.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rustsbi::RustSBI;
+use sbi_spec::base;
+
+use crate::platform::PLATFORM;
+use crate::sbi::extension_mask;
+
+/// Base functions up to and including `get_mimpid` have constant answers.
+const CACHED: usize = base::GET_MIMPID + 1;
+
+/// Answers of one hart, written and read by that hart only.
+struct BaseCache {
+    ready: AtomicBool,
+    values: [AtomicUsize; CACHED],
+}
+
+percpu! {
+    /// Base extension answers of each hart, by function ID.
+    static CACHE: BaseCache = BaseCache {
+        ready: AtomicBool::new(false),
+        values: [const { AtomicUsize::new(0) }; CACHED],
+    };
+}
+
+/// Fill the table of the current hart, once the SBI implementation is set up.
+///
+/// Answers are taken from the dispatcher with the fix-ups of `fast_handler`,
+/// so cached and dispatched calls cannot disagree.
+pub fn fill() {
+    let cache = CACHE.local();
+    for (fid, slot) in cache.values.iter().enumerate() {
+        let value = match fid {
+            base::PROBE_EXTENSION => continue,
+            base::GET_SBI_SPEC_VERSION => extension_mask::spec_version().encoded(),
+            _ => {
+                let ret = unsafe { PLATFORM.sbi.handle_ecall(base::EID_BASE, fid, [0; 6]) };
+                if ret.is_err() {
+                    warn!("Base function {} failed, not caching base answers", fid);
+                    return;
+                }
+                ret.value
+            }
+        };
+        slot.store(value, Ordering::Relaxed);
+    }
+    cache.ready.store(true, Ordering::Relaxed);
+}
+
+/// Cached answer of function `fid` of extension `eid` on the current hart.
+///
+/// The caller checks the extension mask and the domain first.
+#[inline]
+pub fn lookup(eid: usize, fid: usize) -> Option<usize> {
+    if eid != base::EID_BASE || fid == base::PROBE_EXTENSION {
+        return None;
+    }
+    let cache = CACHE.local();
+    if !cache.ready.load(Ordering::Relaxed) {
+        return None;
+    }
+    cache
+        .values
+        .get(fid)
+        .map(|value| value.load(Ordering::Relaxed))
+}
@@ -0,0 +1,186 @@
+//! Compact binary log frames.
+//!
+//! With the `binary-log` feature, log records reach the console as binary
+//! frames instead of text. Format string pieces and static string arguments
+//! sit in the firmware's read-only data, so a frame refers to them by offset
+//! and only formatted values are sent as bytes; `cargo xtask decode-log` turns
+//! the stream back into text with the firmware ELF. Everything else printed on
+//! the console stays text and passes the decoder unchanged, and so does the
+//! RAM log.
+//!
+//! A frame is a zero byte, the start mark 0xff, the COBS encoded payload and
+//! a zero byte. Text never contains zero bytes, and 0xff is no UTF-8, so a
+//! decoder that lost a zero byte finds the next frame at its start mark
+//! rather than taking the following text for a frame. The payload is the level, 1 (error) to 5
+//! (trace), in one byte, the hart ID and the mtime value as LEB128, and then
+//! pieces up to its end:
+//!
+//! - `0`, offset from `sbi_start` and length, as LEB128: static text;
+//! - `1`, length as LEB128 and the bytes: formatted text;
+//! - `2`: the record was cut short to fit the frame.
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+
+use crate::sbi::console;
+
+/// Largest payload of a frame without the truncation mark; longer records are cut.
+const PAYLOAD_MAX: usize = 256;
+/// COBS adds a byte per 254 and the frame a zero byte at either end and the start mark.
+const FRAME_MAX: usize = PAYLOAD_MAX + 1 + (PAYLOAD_MAX + 1) / 254 + 4;
+/// Byte after the leading zero byte of a frame.
+const FRAME_START: u8 = 0xff;
+/// Static text shorter than this is cheaper sent as bytes.
+const STATIC_MIN: usize = 5;
+/// Longest formatted text piece that new bytes are merged into, so its
+/// length stays a single LEB128 byte. Padding arrives a byte at a time.
+const MERGE_MAX: usize = 0x7f;
+
+const PIECE_STATIC: u8 = 0;
+const PIECE_BYTES: u8 = 1;
+const PIECE_TRUNCATED: u8 = 2;
+
+struct Payload {
+    bytes: [u8; PAYLOAD_MAX + 1],
+    len: usize,
+    truncated: bool,
+    /// Length byte of the formatted text piece at the end, if it can grow.
+    open_bytes: Option<usize>,
+    /// Firmware start and read-only data, to tell static text apart.
+    base: usize,
+    rodata: (usize, usize),
+}
+
+impl Payload {
+    fn new() -> Self {
+        let (base, rodata_start, rodata_end): (usize, usize, usize);
+        unsafe {
+            asm!("la {}, sbi_start", out(reg) base, options(nomem));
+            asm!("la {}, sbi_rodata_start", out(reg) rodata_start, options(nomem));
+            asm!("la {}, sbi_rodata_end", out(reg) rodata_end, options(nomem));
+        }
+        Self {
+            bytes: [0; PAYLOAD_MAX + 1],
+            len: 0,
+            truncated: false,
+            open_bytes: None,
+            base,
+            rodata: (rodata_start, rodata_end),
+        }
+    }
+
+    /// Append `bytes` if they all fit.
+    fn push(&mut self, bytes: &[u8]) -> bool {
+        if self.len + bytes.len() > PAYLOAD_MAX {
+            return false;
+        }
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        true
+    }
+
+    fn leb128(&mut self, mut value: u64) -> bool {
+        let mut encoded = [0; 10];
+        let mut len = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                encoded[len] = byte;
+                len += 1;
+                break;
+            }
+            encoded[len] = byte | 0x80;
+            len += 1;
+        }
+        self.push(&encoded[..len])
+    }
+
+    /// Append one piece, or nothing if it does not fit.
+    fn piece(&mut self, s: &str) -> bool {
+        let (start, end) = (s.as_ptr() as usize, s.as_ptr() as usize + s.len());
+        if s.len() >= STATIC_MIN && self.rodata.0 <= start && end <= self.rodata.1 {
+            self.open_bytes = None;
+            let before = self.len;
+            let fits = self.push(&[PIECE_STATIC])
+                && self.leb128((start - self.base) as u64)
+                && self.leb128(s.len() as u64);
+            if !fits {
+                self.len = before;
+            }
+            return fits;
+        }
+        let room = PAYLOAD_MAX - self.len;
+        if let Some(at) = self.open_bytes {
+            let merged = self.bytes[at] as usize + s.len();
+            if merged <= MERGE_MAX {
+                // Keep what fits of a piece cut short.
+                let len = s.len().min(room);
+                self.push(&s.as_bytes()[..len]);
+                self.bytes[at] += len as u8;
+                return len == s.len();
+            }
+        }
+        // A tag and a length below 2^14 take at most three bytes.
+        if room <= 3 {
+            return false;
+        }
+        let len = s.len().min(room - 3);
+        let at = self.len + 1;
+        self.push(&[PIECE_BYTES]);
+        self.leb128(len as u64);
+        self.push(&s.as_bytes()[..len]);
+        self.open_bytes = (len <= MERGE_MAX).then_some(at);
+        len == s.len()
+    }
+}
+
+impl Write for Payload {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated || !self.piece(s) {
+            self.truncated = true;
+        }
+        Ok(())
+    }
+}
+
+/// Write `payload` to the console as one COBS framed frame.
+fn send(payload: &[u8]) {
+    let mut frame = [0; FRAME_MAX];
+    frame[1] = FRAME_START;
+    let mut len = 2;
+    for block in payload.split(|&byte| byte == 0) {
+        // A block may not span more than 254 bytes before its code byte.
+        let mut chunks = block.chunks(254).peekable();
+        if chunks.peek().is_none() {
+            frame[len] = 1;
+            len += 1;
+        }
+        while let Some(chunk) = chunks.next() {
+            frame[len] = chunk.len() as u8 + 1;
+            frame[len + 1..len + 1 + chunk.len()].copy_from_slice(chunk);
+            len += 1 + chunk.len();
+            // A full chunk carries no implicit zero, an empty one follows it.
+            if chunk.len() == 254 && chunks.peek().is_none() {
+                frame[len] = 1;
+                len += 1;
+            }
+        }
+    }
+    console::write_bytes_whole(&frame[..len + 1]);
+}
+
+/// Send `record`, logged by `hart_id` at `ticks`, as a binary frame.
+pub fn log(record: &log::Record, hart_id: usize, ticks: u64) {
+    let mut payload = Payload::new();
+    payload.push(&[record.level() as u8]);
+    payload.leb128(hart_id as u64);
+    payload.leb128(ticks);
+    let _ = payload.write_fmt(*record.args());
+    if payload.truncated {
+        // Pieces stop at `PAYLOAD_MAX`, the mark always has room.
+        payload.bytes[payload.len] = PIECE_TRUNCATED;
+        payload.len += 1;
+    }
+    send(&payload.bytes[..payload.len]);
+}
@@ -0,0 +1,141 @@
+//! Optional log of incoming SBI calls.
+//!
+//! Enabled by the `sbi-trace` feature and switched on at run time through the
+//! debug extension. Every call is logged with its arguments and result; to
+//! keep a chatty supervisor from drowning the console, each extension may log
+//! at most `CALLS_PER_WINDOW` calls per second and the rest are counted.
+//! Up to `BUCKETS` extensions are limited at a time, calls of further ones
+//! are only counted until a bucket frees up.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use rustsbi::SbiRet;
+
+use crate::riscv_spec::current_hartid;
+use crate::sync::Mutex;
+use crate::time;
+
+/// Calls logged per extension in each rate limiting window.
+const CALLS_PER_WINDOW: u32 = 16;
+/// Length of a rate limiting window.
+const WINDOW_US: u64 = 1_000_000;
+/// Number of extensions rate limited at the same time.
+const BUCKETS: usize = 16;
+/// Extension ID of a bucket in use by no extension.
+const FREE: usize = usize::MAX;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    eid: usize,
+    window_start: u64,
+    logged: u32,
+    suppressed: u32,
+}
+
+/// Buckets of the extensions called last, each holding one extension ID,
+/// and the calls not logged for want of a bucket.
+struct Buckets {
+    buckets: [Bucket; BUCKETS],
+    unbucketed: u32,
+}
+
+static BUCKETS_STATE: Mutex<Buckets> = Mutex::named(
+    "sbi trace",
+    Buckets {
+        buckets: [Bucket {
+            eid: FREE,
+            window_start: 0,
+            logged: 0,
+            suppressed: 0,
+        }; BUCKETS],
+        unbucketed: 0,
+    },
+);
+
+/// Calls not logged, to report before the next logged one.
+#[derive(Default)]
+struct Suppressed {
+    /// Extension and count of a bucket whose window ended.
+    bucket: Option<(usize, u32)>,
+    /// Calls of extensions that found every bucket busy.
+    unbucketed: u32,
+}
+
+/// Turn call tracing on or off.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Decide whether a call to `eid` is logged, returning the calls suppressed
+/// in windows that ended.
+///
+/// An extension keeps its bucket while its window runs. A new one takes a
+/// free bucket or one whose window ended; with every bucket busy its call
+/// is not logged and only counted.
+fn admit(eid: usize) -> Option<Suppressed> {
+    let now = time::current_ticks();
+    let window = time::us_to_ticks(WINDOW_US);
+    let mut state = BUCKETS_STATE.lock();
+    let expired = |bucket: &Bucket| now.wrapping_sub(bucket.window_start) >= window;
+    let index = match state.buckets.iter().position(|bucket| bucket.eid == eid) {
+        Some(index) => index,
+        None => match state
+            .buckets
+            .iter()
+            .position(|bucket| bucket.eid == FREE || expired(bucket))
+        {
+            Some(index) => index,
+            None => {
+                state.unbucketed += 1;
+                return None;
+            }
+        },
+    };
+    let bucket = &mut state.buckets[index];
+    if bucket.eid != eid || expired(bucket) {
+        let ended = Some((bucket.eid, bucket.suppressed)).filter(|&(_, count)| count != 0);
+        *bucket = Bucket {
+            eid,
+            window_start: now,
+            logged: 1,
+            suppressed: 0,
+        };
+        return Some(Suppressed {
+            bucket: ended,
+            unbucketed: core::mem::take(&mut state.unbucketed),
+        });
+    }
+    if bucket.logged < CALLS_PER_WINDOW {
+        bucket.logged += 1;
+        Some(Suppressed::default())
+    } else {
+        bucket.suppressed += 1;
+        None
+    }
+}
+
+/// Log a finished SBI call if tracing is on.
+#[inline]
+pub fn record(eid: usize, fid: usize, param: [usize; 6], ret: SbiRet) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(suppressed) = admit(eid) else {
+        return;
+    };
+    let hart_id = current_hartid();
+    if let Some((eid, count)) = suppressed.bucket {
+        info!("sbi: eid {:#x}: {} calls not logged", eid, count);
+    }
+    if suppressed.unbucketed != 0 {
+        info!(
+            "sbi: {} calls of other extensions not logged",
+            suppressed.unbucketed
+        );
+    }
+    info!(
+        "sbi: hart {} eid {:#x} fid {:#x} args {:#x?} -> error {} value {:#x}",
+        hart_id, eid, fid, param, ret.error as isize, ret.value
+    );
+}
@@ -1,7 +1,33 @@
-use crate::platform::PLATFORM;
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::current_hartid;
+use crate::sbi::console_dma;
+use crate::sbi::guest_mem::{self, Mode};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+use crate::sync::{Mutex, MutexGuard};
+use crate::time;
 use core::fmt::{self, Write};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use rustsbi::{Console, Physical, SbiRet};
-use spin::Mutex;
+
+/// The device of an `SbiConsole`, as its lock holder sees it.
+enum Device<'a, T> {
+    Locked(MutexGuard<'a, T>),
+    /// The current hart holds the lock in [`hold`].
+    Held(&'a T),
+}
+
+impl<T> Deref for Device<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        match self {
+            Device::Locked(guard) => guard,
+            Device::Held(device) => device,
+        }
+    }
+}
 
 /// A trait that must be implemented by console devices to provide basic I/O functionality.
 pub trait ConsoleDevice {
@@ -18,6 +44,59 @@ pub trait ConsoleDevice {
     fn write(&self, buf: &[u8]) -> usize;
 }
 
+/// Time a console write may stall on a full transmitter before bytes are dropped.
+const CONSOLE_TX_TIMEOUT_US: u64 = 10_000;
+
+/// Most bytes written while holding the console lock.
+///
+/// Harts take turns between lines, and between chunks of longer lines, so one
+/// hart printing a long trace does not stall the others in their trap handlers.
+const CHUNK_MAX: usize = 128;
+
+/// Number of bytes dropped because the console transmitter stopped draining.
+static DROPPED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of console bytes dropped so far.
+#[inline]
+pub fn dropped_bytes() -> usize {
+    DROPPED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Write what `console` takes of `bytes` now, queueing it for the console
+/// DMA channel where there is one.
+#[inline]
+fn device_write<T: ConsoleDevice>(console: &T, bytes: &[u8]) -> usize {
+    match console_dma::write(bytes) {
+        Some(count) => count,
+        None => console.write(bytes),
+    }
+}
+
+/// Write all of `bytes` to `console`, giving up on the remainder if the
+/// device accepts nothing for `CONSOLE_TX_TIMEOUT_US`.
+///
+/// A wedged UART (clock gated, flow control asserted) would otherwise hang every
+/// hart that tries to print.
+fn write_all<T: ConsoleDevice>(console: &T, mut bytes: &[u8]) {
+    let mut deadline = None;
+    while !bytes.is_empty() {
+        let count = device_write(console, bytes);
+        if count != 0 {
+            bytes = &bytes[count..];
+            deadline = None;
+            continue;
+        }
+        // Only read the timer once the device stalls, keeping the fast path cheap.
+        let deadline =
+            deadline.get_or_insert_with(|| time::Deadline::after_us(CONSOLE_TX_TIMEOUT_US));
+        if deadline.expired() {
+            DROPPED_BYTES.fetch_add(bytes.len(), Ordering::Relaxed);
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
 /// An implementation of the SBI console interface that wraps a console device.
 ///
 /// This provides a safe interface for interacting with console hardware through the
@@ -36,6 +115,17 @@ impl<T: ConsoleDevice> SbiConsole<T> {
         Self { inner }
     }
 
+    /// The console device, locked unless the current hart already holds it
+    /// through [`hold`].
+    #[inline]
+    fn device(&self) -> Device<'_, T> {
+        if self.inner.held_here() {
+            Device::Held(unsafe { &*self.inner.data_ptr() })
+        } else {
+            Device::Locked(self.inner.lock())
+        }
+    }
+
     /// Writes a single character to the console.
     ///
     /// # Arguments
@@ -49,21 +139,48 @@ impl<T: ConsoleDevice> SbiConsole<T> {
         0
     }
 
-    /// Reads a single character from the console.
+    /// Writes raw bytes to the console, bypassing formatting.
     ///
-    /// This method will block until a character is available to be read.
+    /// The lock is taken once per line or `CHUNK_MAX` bytes.
+    #[inline]
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+            for chunk in line.chunks(CHUNK_MAX) {
+                write_all(&*self.device(), chunk);
+            }
+        }
+    }
+
+    /// Writes raw bytes to the console in one lock hold, for output that
+    /// must not be split, such as binary log frames.
+    #[inline]
+    pub fn write_bytes_whole(&self, bytes: &[u8]) {
+        write_all(&*self.device(), bytes);
+    }
+
+    /// Give back the device lock if the current hart left it held.
+    ///
+    /// # Safety
+    ///
+    /// See [`Mutex::force_unlock`].
+    #[inline]
+    pub unsafe fn force_unlock(&self) -> bool {
+        unsafe { self.inner.force_unlock() }
+    }
+
+    /// Reads a single character from the console.
     ///
     /// # Returns
-    /// The read character as a usize
+    /// The read character as a usize, or -1 if no character is available,
+    /// as required by the legacy console extension.
     #[inline]
     pub fn getchar(&self) -> usize {
         let mut c = 0u8;
-        let console = self.inner.lock();
-        // Block until we successfully read 1 byte
-        while console.read(core::slice::from_mut(&mut c)) != 1 {
-            core::hint::spin_loop();
+        if self.device().read(core::slice::from_mut(&mut c)) == 1 {
+            c as usize
+        } else {
+            usize::MAX
         }
-        c as usize
     }
 }
 
@@ -71,27 +188,55 @@ impl<T: ConsoleDevice> Console for SbiConsole<T> {
     /// Write a physical memory buffer to the console.
     #[inline]
     fn write(&self, bytes: Physical<&[u8]>) -> SbiRet {
-        // TODO: verify valid memory range for a `Physical` slice.
-        let start = bytes.phys_addr_lo();
-        let buf = unsafe { core::slice::from_raw_parts(start as *const u8, bytes.num_bytes()) };
-        let bytes_written = self.inner.lock().write(buf);
-        SbiRet::success(bytes_written)
+        let (start, len) = (bytes.phys_addr_lo(), bytes.num_bytes());
+        if bytes.phys_addr_hi() != 0 || !guest_mem::phys_accessible(start, len) {
+            return SbiRet::invalid_param();
+        }
+        let inner = self.device();
+        let mut chunk = [0; CHUNK_MAX];
+        let mut written = 0;
+        while written < len {
+            let size = (len - written).min(CHUNK_MAX);
+            if guest_mem::read(Mode::Physical, start + written, &mut chunk[..size]).is_err() {
+                return SbiRet::failed();
+            }
+            let count = device_write(&*inner, &chunk[..size]);
+            written += count;
+            if count < size {
+                break;
+            }
+        }
+        SbiRet::success(written)
     }
 
     /// Read from console into a physical memory buffer.
     #[inline]
     fn read(&self, bytes: Physical<&mut [u8]>) -> SbiRet {
-        // TODO: verify valid memory range for a `Physical` slice.
-        let start = bytes.phys_addr_lo();
-        let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, bytes.num_bytes()) };
-        let bytes_read = self.inner.lock().read(buf);
-        SbiRet::success(bytes_read)
+        let (start, len) = (bytes.phys_addr_lo(), bytes.num_bytes());
+        if bytes.phys_addr_hi() != 0 || !guest_mem::phys_accessible(start, len) {
+            return SbiRet::invalid_param();
+        }
+        let inner = self.device();
+        let mut chunk = [0; CHUNK_MAX];
+        let mut read = 0;
+        while read < len {
+            let size = (len - read).min(CHUNK_MAX);
+            let count = inner.read(&mut chunk[..size]);
+            if guest_mem::write(Mode::Physical, start + read, &chunk[..count]).is_err() {
+                return SbiRet::failed();
+            }
+            read += count;
+            if count < size {
+                break;
+            }
+        }
+        SbiRet::success(read)
     }
 
     /// Write a single byte to the console.
     #[inline]
     fn write_byte(&self, byte: u8) -> SbiRet {
-        self.inner.lock().write(&[byte]);
+        write_all(&*self.device(), &[byte]);
         SbiRet::success(0)
     }
 }
@@ -100,25 +245,124 @@ impl<T: ConsoleDevice> fmt::Write for SbiConsole<T> {
     /// Implement Write trait for string formatting.
     #[inline]
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let mut bytes = s.as_bytes();
-        let console = self.inner.lock();
-        // Write all bytes in chunks
-        while !bytes.is_empty() {
-            let count = console.write(bytes);
-            bytes = &bytes[count..];
+        write_all(&*self.device(), s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Formatted output of one hart, collected before it is written.
+struct PrintBuffer {
+    bytes: [u8; CHUNK_MAX],
+    len: usize,
+}
+
+impl PrintBuffer {
+    fn flush(&mut self) {
+        write_bytes(&self.bytes[..self.len]);
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for PrintBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == CHUNK_MAX {
+                self.flush();
+            }
+            self.bytes[self.len] = byte;
+            self.len += 1;
         }
         Ok(())
     }
 }
 
+/// Per-hart print buffers, only ever touched by their own hart.
+///
+/// A nested print on the same hart, from a panic while formatting, appends to
+/// the same buffer instead of waiting for a lock it holds.
+static mut PRINT_BUFFERS: [PrintBuffer; NUM_HART_MAX] = [const {
+    PrintBuffer {
+        bytes: [0; CHUNK_MAX],
+        len: 0,
+    }
+}; NUM_HART_MAX];
+
+/// Print formatted output, as `print!` and `println!` do.
+///
+/// The output is formatted without holding the console lock and written in
+/// `CHUNK_MAX` pieces, so a typical line goes out in one piece and lines of
+/// different harts do not mix. Harts past `NUM_HART_MAX` have no buffer and
+/// write each formatted piece under the lock directly.
+pub fn print(args: fmt::Arguments) {
+    if !unsafe { PLATFORM.have_console() } {
+        return;
+    }
+    let Some(buffer) = (unsafe { PRINT_BUFFERS.get_mut(current_hartid()) }) else {
+        if let Some(console) = unsafe { PLATFORM.sbi.console.as_mut() } {
+            let _ = console.write_fmt(args);
+        }
+        return;
+    };
+    let _ = buffer.write_fmt(args);
+    buffer.flush();
+}
+
 /// Global function to write a character to the console.
+#[allow(unused)]
 #[inline]
 pub fn putchar(c: usize) -> usize {
-    unsafe { PLATFORM.sbi.console.as_mut().unwrap().putchar(c) }
+    match unsafe { PLATFORM.sbi.console.as_mut() } {
+        Some(console) => console.putchar(c),
+        None => usize::MAX,
+    }
+}
+
+/// Global function to write raw bytes to the console.
+#[inline]
+pub fn write_bytes(bytes: &[u8]) {
+    if let Some(console) = platform::console() {
+        console.write_bytes(bytes);
+    }
+}
+
+/// Global function to write raw bytes to the console in one lock hold.
+#[allow(unused)]
+#[inline]
+pub fn write_bytes_whole(bytes: &[u8]) {
+    if let Some(console) = platform::console() {
+        console.write_bytes_whole(bytes);
+    }
+}
+
+/// Run `f` holding the console lock, so other harts cannot print in the
+/// middle of its output. Output of the current hart goes through meanwhile.
+pub fn hold(f: impl FnOnce()) {
+    let Some(console) = platform::console() else {
+        return f();
+    };
+    if console.inner.held_here() {
+        return f();
+    }
+    let _guard = console.inner.lock();
+    f();
+}
+
+/// Give back the console lock if the current hart left it held.
+///
+/// # Safety
+///
+/// See [`Mutex::force_unlock`].
+#[inline]
+pub unsafe fn force_unlock() -> bool {
+    platform::console().is_some_and(|console| unsafe { console.force_unlock() })
 }
 
 /// Global function to read a character from the console.
+#[allow(unused)]
 #[inline]
 pub fn getchar() -> usize {
-    unsafe { PLATFORM.sbi.console.as_mut().unwrap().getchar() }
+    match platform::console() {
+        Some(console) => console.getchar(),
+        None => usize::MAX,
+    }
 }
@@ -0,0 +1,179 @@
+//! DMA backed console output.
+//!
+//! When the console UART names a `tx` DMA channel the firmware owns, bytes
+//! written to the console are queued in a ring and moved to the UART by the
+//! DMA engine, one word per byte as the UART register is wide. Writers only
+//! wait when the ring is full; the completion interrupt, handled by the boot
+//! hart, starts the next transfer. Verbose trace output then costs a copy
+//! instead of a wait on the transmit FIFO.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::firmware::cache;
+use crate::platform::PLATFORM;
+use crate::sbi::irq;
+use crate::sync::Mutex;
+use crate::time;
+
+/// Bytes the ring holds.
+const RING_LEN: usize = 2048;
+
+/// Time the ring gets to drain before the system resets or a hart stops.
+pub const DRAIN_TIMEOUT_US: u64 = 100_000;
+
+/// A DMA channel moving words from memory to the console UART.
+pub trait DmaTxChannel {
+    /// Start moving `count` words from `src` to the UART.
+    fn start(&self, src: usize, count: usize);
+    /// Whether a transfer is running.
+    fn busy(&self) -> bool;
+    /// Clear the completion interrupt of the last transfer.
+    fn ack(&self);
+    /// Most words one transfer can move.
+    fn max_block(&self) -> usize;
+}
+
+/// Bytes queued for the DMA channel, in a ring of one word per byte.
+struct TxRing<T> {
+    channel: T,
+    words: [u32; RING_LEN],
+    /// Bytes queued so far, the next one going to `head % RING_LEN`.
+    head: usize,
+    /// Bytes sent so far.
+    tail: usize,
+    /// Bytes of the running transfer, which starts at `tail`.
+    in_flight: usize,
+}
+
+impl<T: DmaTxChannel> TxRing<T> {
+    /// Retire a finished transfer and start the next one.
+    fn kick(&mut self) {
+        if self.in_flight != 0 {
+            if self.channel.busy() {
+                return;
+            }
+            self.tail += self.in_flight;
+            self.in_flight = 0;
+        }
+        let start = self.tail % RING_LEN;
+        let count = (self.head - self.tail)
+            .min(RING_LEN - start)
+            .min(self.channel.max_block());
+        if count == 0 {
+            return;
+        }
+        let words = &self.words[start..start + count];
+        let src = words.as_ptr() as usize;
+        // The engine reads memory, not this hart's data cache.
+        cache::clean_dcache_range(src..src + count * 4);
+        self.channel.start(src, count);
+        self.in_flight = count;
+    }
+
+    /// Queue what fits of `bytes`, returning how many were taken.
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        self.kick();
+        let count = bytes.len().min(RING_LEN - (self.head - self.tail));
+        for &byte in &bytes[..count] {
+            self.words[self.head % RING_LEN] = byte as u32;
+            self.head += 1;
+        }
+        self.kick();
+        count
+    }
+}
+
+/// Console DMA channel of the platform and its ring.
+pub struct SbiConsoleDma<T: DmaTxChannel> {
+    ring: Mutex<TxRing<T>>,
+}
+
+impl<T: DmaTxChannel> SbiConsoleDma<T> {
+    #[inline]
+    pub fn new(channel: T) -> Self {
+        Self {
+            ring: Mutex::named(
+                "console dma",
+                TxRing {
+                    channel,
+                    words: [0; RING_LEN],
+                    head: 0,
+                    tail: 0,
+                    in_flight: 0,
+                },
+            ),
+        }
+    }
+
+    fn complete(&self) {
+        let mut ring = self.ring.lock();
+        ring.channel.ack();
+        ring.kick();
+    }
+}
+
+/// Whether the completion interrupt is registered, so `write` may use the channel.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Route the completion interrupt `irq` of the console DMA channel to the current hart.
+pub fn init(irq: usize) {
+    if unsafe { PLATFORM.console_dma.is_none() } {
+        return;
+    }
+    match irq::register(irq, complete_handler) {
+        Ok(()) => ENABLED.store(true, Ordering::Release),
+        Err(err) => warn!(
+            "Console DMA interrupt {} unavailable, writing the UART directly: {:?}",
+            irq, err
+        ),
+    }
+}
+
+fn complete_handler(_irq: usize) {
+    if let Some(dma) = unsafe { PLATFORM.console_dma.as_ref() } {
+        dma.complete();
+    }
+}
+
+/// Give back the ring lock if the current hart left it held.
+///
+/// # Safety
+///
+/// See [`Mutex::force_unlock`].
+pub unsafe fn force_unlock() -> bool {
+    unsafe { PLATFORM.console_dma.as_ref() }.is_some_and(|dma| unsafe { dma.ring.force_unlock() })
+}
+
+/// Wait up to `timeout_us` for every queued byte to reach the UART,
+/// returning whether the ring drained.
+///
+/// The ring lock is only tried, a panicking hart may hold it.
+pub fn drain(timeout_us: u64) -> bool {
+    let Some(dma) = (unsafe { PLATFORM.console_dma.as_ref() }) else {
+        return true;
+    };
+    let deadline = time::Deadline::after_us(timeout_us);
+    loop {
+        if let Some(mut ring) = dma.ring.try_lock() {
+            ring.kick();
+            if ring.head == ring.tail && ring.in_flight == 0 {
+                return true;
+            }
+        }
+        if deadline.expired() {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Queue what fits of `bytes` for the console DMA channel, returning how
+/// many were taken, or `None` if the console is written directly.
+#[inline]
+pub fn write(bytes: &[u8]) -> Option<usize> {
+    if !ENABLED.load(Ordering::Acquire) {
+        return None;
+    }
+    let dma = unsafe { PLATFORM.console_dma.as_ref() }?;
+    Some(dma.ring.lock().push(bytes))
+}
@@ -0,0 +1,132 @@
+//! Crash dumps of fatal machine mode traps.
+//!
+//! The device tree may reserve a region for dumps with a node compatible with
+//! `rustsbi,crashdump`, usually under `/reserved-memory`. On a fatal trap or
+//! panic the firmware writes a `CrashDump` at the start of the region and sets
+//! `valid`. The next boot reports it, and supervisor tools may read it and
+//! clear `valid` once extracted.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use fast_trap::FlowContext;
+use riscv::register::{mcause, mepc, mie, mip, mtval};
+
+use crate::firmware;
+use crate::platform::PLATFORM;
+use crate::riscv_spec::{current_hartid, read_mstatus};
+use crate::sbi::guest_mem::{self, Mode};
+use crate::sbi::hsm::remote_hsm;
+use crate::sbi::trap_frame;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+pub(crate) const CRASHDUMP_COMPATIBLE: [&str; 1] = ["rustsbi,crashdump"];
+
+/// `RSBICRSH` in little endian.
+pub const CRASHDUMP_MAGIC: u64 = u64::from_le_bytes(*b"RSBICRSH");
+/// Layout version of `CrashDump`.
+pub const CRASHDUMP_VERSION: u32 = 1;
+
+/// Contents of the crash dump region.
+#[allow(unused)]
+#[repr(C)]
+pub struct CrashDump {
+    pub magic: u64,
+    pub version: u32,
+    /// Non-zero while the dump has not been extracted.
+    pub valid: u32,
+    pub hart_id: usize,
+    pub mcause: usize,
+    pub mepc: usize,
+    pub mtval: usize,
+    pub mstatus: usize,
+    pub mie: usize,
+    pub mip: usize,
+    /// Non-zero if `regs` holds the trapped context.
+    pub has_regs: usize,
+    /// General purpose registers `x0` to `x31` at the time of the trap.
+    pub regs: [usize; 32],
+    /// SBI HSM state of every hart, `usize::MAX` for harts that do not exist.
+    pub hart_state: [usize; NUM_HART_MAX],
+}
+
+/// Set once this boot wrote a dump, so the first fault is the one kept.
+static DUMPED: AtomicBool = AtomicBool::new(false);
+
+fn region() -> Option<*mut CrashDump> {
+    let range = unsafe { PLATFORM.info.crashdump.as_ref() }?;
+    if range.len() < core::mem::size_of::<CrashDump>() {
+        return None;
+    }
+    Some(range.start as *mut CrashDump)
+}
+
+/// Report a dump left by the previous boot.
+pub fn init() {
+    let Some(dump) = region() else {
+        return;
+    };
+    let dump = unsafe { &*dump };
+    if dump.magic == CRASHDUMP_MAGIC && dump.version == CRASHDUMP_VERSION && dump.valid != 0 {
+        warn!(
+            "Crash dump from previous boot: hart {}, mcause {:#x}, mepc {:#018x}, mtval {:#018x}",
+            dump.hart_id, dump.mcause, dump.mepc, dump.mtval
+        );
+    }
+}
+
+/// Write a dump of the current hart, with `frame` as the trapped context if known.
+pub fn write(frame: Option<&FlowContext>) {
+    if DUMPED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let Some(dump) = region() else {
+        return;
+    };
+    let mut regs = [0; 32];
+    if let Some(frame) = frame {
+        for (index, reg) in regs.iter_mut().enumerate() {
+            *reg = trap_frame::gpr(frame, index as u32);
+        }
+    }
+    let mut hart_state = [usize::MAX; NUM_HART_MAX];
+    for (hart_id, state) in hart_state.iter_mut().enumerate() {
+        if let Some(remote) = remote_hsm(hart_id) {
+            *state = remote.sbi_get_status();
+        }
+    }
+    let record = CrashDump {
+        magic: CRASHDUMP_MAGIC,
+        version: CRASHDUMP_VERSION,
+        valid: 0,
+        hart_id: current_hartid(),
+        mcause: mcause::read().bits(),
+        mepc: mepc::read(),
+        mtval: mtval::read(),
+        mstatus: read_mstatus(),
+        mie: mie::read().bits(),
+        mip: mip::read().bits(),
+        has_regs: frame.is_some() as usize,
+        regs,
+        hart_state,
+    };
+    // Under the Smepmp lockdown only `guest_mem` reaches supervisor memory.
+    if firmware::locked_down() {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                core::ptr::addr_of!(record).cast::<u8>(),
+                core::mem::size_of::<CrashDump>(),
+            )
+        };
+        let valid = unsafe { core::ptr::addr_of_mut!((*dump).valid) } as usize;
+        if guest_mem::write(Mode::Physical, dump as usize, bytes).is_ok() {
+            core::sync::atomic::fence(Ordering::Release);
+            let _ = guest_mem::write(Mode::Physical, valid, &1u32.to_ne_bytes());
+        }
+        return;
+    }
+    unsafe {
+        dump.write_volatile(record);
+        // Flag the dump only once its contents are in memory.
+        core::sync::atomic::fence(Ordering::Release);
+        core::ptr::addr_of_mut!((*dump).valid).write_volatile(1);
+    }
+}
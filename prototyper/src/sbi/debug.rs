@@ -0,0 +1,349 @@
+//! RustSBI Prototyper debug extension.
+//!
+//! A firmware specific extension letting S-mode inspect firmware internal state.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use prototyper_common::fdt_reader;
+use rustsbi::{Hsm, SbiRet};
+
+use crate::firmware::{self, boot_profile, fdt_dump, fdt_fixup, image_header, mem_stats};
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::pmp;
+use crate::sbi::console;
+use crate::sbi::guest_mem;
+use crate::sbi::hart_init;
+use crate::sbi::logger;
+use crate::sbi::rnmi;
+use crate::sbi::trap_stack::{self, NUM_HART_MAX};
+use crate::sbi::update;
+
+#[cfg(feature = "sbi-trace")]
+use crate::sbi::call_trace;
+#[cfg(feature = "timer-trace")]
+use crate::sbi::timer_trace;
+
+/// Extension ID of the debug extension, in the firmware specific range.
+pub const EID_DEBUG: usize = 0x0A52_5342;
+
+/// Print the `set_timer` trace of hart `a0` to the firmware console.
+#[allow(unused)]
+pub const DUMP_TIMER_TRACE: usize = 0;
+
+/// Read the firmware statistic counter selected by `a0`.
+pub const GET_STATISTIC: usize = 1;
+
+/// Print the device tree the firmware booted with to the firmware console.
+///
+/// Fails with `SBI_ERR_INVALID_ADDRESS` once the tree no longer lies in
+/// memory S-mode may read, and with `SBI_ERR_FAILED` if it is malformed.
+pub const DUMP_DEVICE_TREE: usize = 2;
+
+/// Turn SBI call tracing off with `a0` = 0, on otherwise.
+#[allow(unused)]
+pub const SET_CALL_TRACE: usize = 3;
+
+/// Read field `a1` of the RNMI record of hart `a0`: 0 for the number of RNMIs
+/// taken, 1 for `mncause` and 2 for `mnepc` of the last one.
+pub const GET_RNMI_RECORD: usize = 4;
+
+/// Read field `a0` of the running firmware image, see `firmware_info`.
+pub const GET_FIRMWARE_INFO: usize = 5;
+
+/// Read field `a1` of hart `a0`: 0 for its HSM state, 1 for how far its
+/// firmware initialization got.
+pub const GET_HART_STATE: usize = 6;
+
+/// Read field `a1` of memory region `a0`, see `memory_region`: 0 for the
+/// start address and 1 for the size, both 0 if the region does not exist.
+pub const GET_MEMORY_REGION: usize = 7;
+
+/// Read PMP entry `a0` of the calling hart: `a1` = 0 for its configuration
+/// byte, 1 for its address register.
+pub const GET_PMP_ENTRY: usize = 8;
+
+/// Print the RAM log to the firmware console and empty it, returning the
+/// number of bytes printed.
+pub const FLUSH_LOG: usize = 9;
+
+/// Read the most verbose log level printed to the firmware console, from 0
+/// for none through 1 for errors up to 5 for trace messages.
+pub const GET_LOG_LEVEL: usize = 10;
+
+/// Print messages up to log level `a0` to the firmware console from now on.
+pub const SET_LOG_LEVEL: usize = 11;
+
+/// Read field `a1` of boot phase `a0`, see `boot_profile::Phase`: 0 for the
+/// microseconds since reset, 1 for `mcycle`. Fails for phases not reached,
+/// and for the microseconds of phases before the timer was found.
+pub const GET_BOOT_PHASE: usize = 12;
+
+/// Read memory statistic `a0`, see `memory_stat`, of hart `a1` where it is
+/// kept per hart.
+pub const GET_MEMORY_STAT: usize = 13;
+
+/// Tell who owns the firmware console: `a0` = 1 when the supervisor takes it
+/// over, leaving firmware messages to the RAM log, 0 to hand it back. Returns
+/// 1 if the supervisor owned it before. `FLUSH_LOG` still prints the RAM log.
+pub const SET_CONSOLE_OWNER: usize = 14;
+
+/// Statistic counters readable through `GET_STATISTIC`.
+pub mod statistic {
+    /// Bytes dropped because the console transmitter stopped draining.
+    pub const CONSOLE_DROPPED_BYTES: usize = 0;
+    /// Resumable NMIs taken on all harts.
+    pub const RNMI_COUNT: usize = 1;
+    /// Supervisor software interrupts sent through `sbi_send_ipi`.
+    pub const IPI_SENT: usize = 2;
+    /// Machine software interrupts handled on all harts.
+    pub const IPI_RECEIVED: usize = 3;
+    /// Traps taken into the firmware from lower privilege levels.
+    pub const TRAPS: usize = 4;
+}
+
+/// Fields readable through `GET_FIRMWARE_INFO`.
+pub mod firmware_info {
+    /// Version as `major << 16 | minor << 8 | patch`.
+    pub const VERSION: usize = 0;
+    /// Feature bits of the image header.
+    pub const FEATURES: usize = 1;
+    /// Address the image was linked to run at.
+    pub const LOAD_ADDRESS: usize = 2;
+    /// Size of the image in memory.
+    pub const IMAGE_SIZE: usize = 3;
+}
+
+/// Regions readable through `GET_MEMORY_REGION`.
+pub mod memory_region {
+    /// Main memory from the device tree.
+    pub const RAM: usize = 0;
+    /// The firmware image, inaccessible to lower privilege levels.
+    pub const FIRMWARE: usize = 1;
+    /// The device tree the firmware booted with.
+    pub const DEVICE_TREE: usize = 2;
+    /// Memory reserved for crash dumps.
+    pub const CRASHDUMP: usize = 3;
+}
+
+/// Statistics readable through `GET_MEMORY_STAT`, all in bytes.
+pub mod memory_stat {
+    /// Code of the firmware image.
+    pub const TEXT: usize = 0;
+    /// Read-only data of the firmware image.
+    pub const RODATA: usize = 1;
+    /// Initialized data of the firmware image.
+    pub const DATA: usize = 2;
+    /// Zeroed data of the firmware image, without the stacks.
+    pub const BSS: usize = 3;
+    /// Stacks of all harts.
+    pub const STACKS: usize = 4;
+    /// Deepest stack use of hart `a1` so far.
+    pub const STACK_HIGH_WATER: usize = 5;
+}
+
+/// Events counted per hart for `GET_STATISTIC`.
+#[derive(Clone, Copy)]
+pub enum Event {
+    IpiSent = 0,
+    IpiReceived = 1,
+    Trap = 2,
+}
+
+const EVENTS: usize = 3;
+
+percpu! {
+    /// Event counters of each hart.
+    static EVENT_COUNT: [AtomicUsize; EVENTS] = [const { AtomicUsize::new(0) }; EVENTS];
+}
+
+/// Count one `event` on the current hart.
+#[inline]
+pub fn count(event: Event) {
+    EVENT_COUNT.local()[event as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count of `event` on `hart_id` so far.
+#[cfg(feature = "exit-dump")]
+pub fn hart_count(hart_id: usize, event: Event) -> usize {
+    EVENT_COUNT
+        .get(hart_id)
+        .map_or(0, |counts| counts[event as usize].load(Ordering::Relaxed))
+}
+
+/// Count of `event` summed over all harts.
+pub fn total(event: Event) -> usize {
+    (0..NUM_HART_MAX)
+        .filter_map(|hart_id| EVENT_COUNT.get(hart_id))
+        .map(|counts| counts[event as usize].load(Ordering::Relaxed))
+        .sum()
+}
+
+fn get_statistic(id: usize) -> SbiRet {
+    match id {
+        statistic::CONSOLE_DROPPED_BYTES => SbiRet::success(console::dropped_bytes()),
+        statistic::RNMI_COUNT => SbiRet::success(rnmi::total_count()),
+        statistic::IPI_SENT => SbiRet::success(total(Event::IpiSent)),
+        statistic::IPI_RECEIVED => SbiRet::success(total(Event::IpiReceived)),
+        statistic::TRAPS => SbiRet::success(total(Event::Trap)),
+        _ => SbiRet::invalid_param(),
+    }
+}
+
+fn get_firmware_info(field: usize) -> SbiRet {
+    let Some(header) = image_header::image_header() else {
+        return SbiRet::failed();
+    };
+    match field {
+        firmware_info::VERSION => SbiRet::success(header.firmware_version as usize),
+        firmware_info::FEATURES => SbiRet::success(header.features as usize),
+        firmware_info::LOAD_ADDRESS => SbiRet::success(header.load_address as usize),
+        firmware_info::IMAGE_SIZE => SbiRet::success(firmware::firmware_range().len()),
+        _ => SbiRet::invalid_param(),
+    }
+}
+
+fn get_hart_state(hart_id: usize, field: usize) -> SbiRet {
+    if hart_id >= NUM_HART_MAX {
+        return SbiRet::invalid_param();
+    }
+    match field {
+        0 => match platform::hsm() {
+            Some(hsm) => hsm.hart_get_status(hart_id),
+            None => SbiRet::not_supported(),
+        },
+        1 => SbiRet::success(hart_init::state(hart_id) as usize),
+        _ => SbiRet::invalid_param(),
+    }
+}
+
+fn get_memory_region(region: usize, field: usize) -> SbiRet {
+    let range = match region {
+        memory_region::RAM => platform::memory_range().cloned(),
+        memory_region::FIRMWARE => Some(firmware::firmware_range()),
+        memory_region::DEVICE_TREE => {
+            let start = update::boot_fdt_address();
+            fdt_fixup::total_size(start).map(|size| start..start + size)
+        }
+        memory_region::CRASHDUMP => unsafe { PLATFORM.info.crashdump.clone() },
+        _ => return SbiRet::invalid_param(),
+    };
+    let range = range.unwrap_or(0..0);
+    match field {
+        0 => SbiRet::success(range.start),
+        1 => SbiRet::success(range.len()),
+        _ => SbiRet::invalid_param(),
+    }
+}
+
+fn get_pmp_entry(index: usize, field: usize) -> SbiRet {
+    let value = match field {
+        0 => pmp::read_cfg(index).map(usize::from),
+        1 => pmp::read_addr(index),
+        _ => return SbiRet::invalid_param(),
+    };
+    match value {
+        Some(value) => SbiRet::success(value),
+        None => SbiRet::invalid_param(),
+    }
+}
+
+fn get_boot_phase(phase: usize, field: usize) -> SbiRet {
+    if phase >= boot_profile::PHASES {
+        return SbiRet::invalid_param();
+    }
+    let value = match field {
+        0 => boot_profile::elapsed_us(phase),
+        1 => boot_profile::cycles(phase),
+        _ => return SbiRet::invalid_param(),
+    };
+    match value {
+        Some(value) => SbiRet::success(value as usize),
+        None => SbiRet::failed(),
+    }
+}
+
+fn get_memory_stat(stat: usize, hart_id: usize) -> SbiRet {
+    use mem_stats::Section;
+    let section = match stat {
+        memory_stat::TEXT => Section::Text,
+        memory_stat::RODATA => Section::Rodata,
+        memory_stat::DATA => Section::Data,
+        memory_stat::BSS => Section::Bss,
+        memory_stat::STACKS => Section::Stacks,
+        memory_stat::STACK_HIGH_WATER => {
+            return match trap_stack::stack_high_water(hart_id) {
+                Some(used) => SbiRet::success(used),
+                None => SbiRet::invalid_param(),
+            };
+        }
+        _ => return SbiRet::invalid_param(),
+    };
+    SbiRet::success(mem_stats::footprint(section))
+}
+
+fn get_rnmi_record(hart_id: usize, field: usize) -> SbiRet {
+    let Some(record) = rnmi::RECORD.get(hart_id) else {
+        return SbiRet::invalid_param();
+    };
+    let value = match field {
+        0 => &record.count,
+        1 => &record.cause,
+        2 => &record.epc,
+        _ => return SbiRet::invalid_param(),
+    };
+    SbiRet::success(value.load(Ordering::Relaxed))
+}
+
+fn dump_device_tree() -> SbiRet {
+    // The supervisor owns the tree by now, read no more than it could itself.
+    let fdt_address = update::boot_fdt_address();
+    // The dump reads the tree directly, which the Smepmp lockdown forbids.
+    if firmware::locked_down() {
+        return SbiRet::not_supported();
+    }
+    if !guest_mem::phys_accessible(fdt_address, fdt_reader::HEADER_SIZE) {
+        return SbiRet::invalid_address();
+    }
+    let header =
+        unsafe { core::slice::from_raw_parts(fdt_address as *const u8, fdt_reader::HEADER_SIZE) };
+    match fdt_reader::total_size(header) {
+        Some(size) if guest_mem::phys_accessible(fdt_address, size) => {}
+        Some(_) => return SbiRet::invalid_address(),
+        None => return SbiRet::failed(),
+    }
+    match fdt_dump::dump(fdt_address) {
+        Ok(()) => SbiRet::success(0),
+        Err(_) => SbiRet::failed(),
+    }
+}
+
+/// Dispatch a call to the debug extension.
+#[allow(unused_variables)]
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        #[cfg(feature = "timer-trace")]
+        DUMP_TIMER_TRACE => timer_trace::dump(param[0]),
+        GET_STATISTIC => get_statistic(param[0]),
+        DUMP_DEVICE_TREE => dump_device_tree(),
+        GET_RNMI_RECORD => get_rnmi_record(param[0], param[1]),
+        GET_FIRMWARE_INFO => get_firmware_info(param[0]),
+        GET_HART_STATE => get_hart_state(param[0], param[1]),
+        GET_MEMORY_REGION => get_memory_region(param[0], param[1]),
+        GET_PMP_ENTRY => get_pmp_entry(param[0], param[1]),
+        FLUSH_LOG => SbiRet::success(logger::flush_ram_log()),
+        GET_LOG_LEVEL => SbiRet::success(logger::console_level() as usize),
+        SET_LOG_LEVEL if logger::set_console_level(param[0]) => SbiRet::success(0),
+        SET_LOG_LEVEL => SbiRet::invalid_param(),
+        GET_BOOT_PHASE => get_boot_phase(param[0], param[1]),
+        GET_MEMORY_STAT => get_memory_stat(param[0], param[1]),
+        SET_CONSOLE_OWNER => match param[0] {
+            0 | 1 => SbiRet::success(logger::set_supervisor_owns_console(param[0] == 1) as usize),
+            _ => SbiRet::invalid_param(),
+        },
+        #[cfg(feature = "sbi-trace")]
+        SET_CALL_TRACE => {
+            call_trace::set_enabled(param[0] != 0);
+            SbiRet::success(0)
+        }
+        _ => SbiRet::not_supported(),
+    }
+}
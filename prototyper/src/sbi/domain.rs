@@ -0,0 +1,257 @@
+//! Domains: groups of harts running one supervisor each.
+//!
+//! A domain is a `rustsbi,domain` node of the device tree, usually under
+//! `/chosen`, listing its hart IDs in `rustsbi,harts`. Harts outside every
+//! domain form an unrestricted default. Each domain may refuse SBI
+//! extensions named in `rustsbi,deny-extensions`, using the names of the
+//! extension disable list: the dispatcher reports them absent to the domain's
+//! harts and answers their calls with `SBI_ERR_NOT_SUPPORTED`, so for example
+//! an RTOS beside Linux cannot reset the system.
+//!
+//! A domain without the boot hart may also name its own next stage in
+//! `rustsbi,next-addr`, with `rustsbi,next-mode` (0 for U, 1 for S, the
+//! default) and `rustsbi,next-arg1`, its device tree by default. Its
+//! boot hart, `rustsbi,boot-hart` or else its lowest hart, is started there
+//! along with the boot hart; the rest of its harts are left to its supervisor.
+//! A supervisor can only start harts of its own domain.
+//!
+//! Devices listed by phandle in `rustsbi,devices` belong to the domain and
+//! are left out of the device trees of all other supervisors.
+
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+use riscv::register::mstatus::MPP;
+use serde_device_tree::buildin::{Node, StrSeq};
+
+use crate::dt;
+use crate::riscv_spec::current_hartid;
+use crate::sbi::extension_mask::SbiExtension;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Compatible string of a domain node.
+pub(crate) const DOMAIN_COMPATIBLE: [&str; 1] = ["rustsbi,domain"];
+
+/// Number of domains kept from the device tree.
+pub const MAX_DOMAINS: usize = 4;
+
+/// Number of devices a domain may own.
+pub const MAX_DOMAIN_DEVICES: usize = 8;
+
+/// Domain index of harts outside every domain.
+const NO_DOMAIN: u8 = u8::MAX;
+
+// Domains keep their harts in a `u64` and harts their domain in a `u8`.
+const _: () = assert!(NUM_HART_MAX <= u64::BITS as usize);
+const _: () = assert!(MAX_DOMAINS < NO_DOMAIN as usize);
+
+/// A domain as described by the device tree.
+#[derive(Clone, Copy, Debug)]
+pub struct DomainInfo {
+    /// Bit `n` set for hart ID `n`.
+    pub harts: u64,
+    /// Extensions refused to the domain, as bits of `SbiExtension`.
+    pub denied: u32,
+    /// Next stage of the domain, if it boots one of its own.
+    pub boot: Option<DomainBoot>,
+    /// Phandles of the devices the domain owns, 0 for unused slots.
+    pub devices: [u32; MAX_DOMAIN_DEVICES],
+}
+
+/// Next stage a domain boots on its boot hart.
+#[derive(Clone, Copy, Debug)]
+pub struct DomainBoot {
+    /// Hart started at the next stage.
+    pub hart_id: usize,
+    /// Entry address of the next stage.
+    pub next_addr: usize,
+    /// Privilege mode of the next stage.
+    pub next_mode: MPP,
+    /// Value in `a1` on entry, `None` for the domain's device tree.
+    pub arg1: Option<usize>,
+}
+
+impl DomainInfo {
+    /// Read the domain described by `node`.
+    pub fn from_node(node: &Node) -> Self {
+        let mut harts = 0;
+        if let Some(cells) = node
+            .get_prop("rustsbi,harts")
+            .map(|prop| prop.deserialize::<&[u8]>())
+        {
+            for cell in cells.chunks_exact(4) {
+                let hart_id = u32::from_be_bytes(cell.try_into().unwrap()) as usize;
+                if hart_id < NUM_HART_MAX {
+                    harts |= 1 << hart_id;
+                } else {
+                    warn!(
+                        "Ignoring hart {} in domain, beyond the supported harts",
+                        hart_id
+                    );
+                }
+            }
+        }
+        let mut denied = 0;
+        if let Some(names) = node
+            .get_prop("rustsbi,deny-extensions")
+            .map(|prop| prop.deserialize::<StrSeq>())
+        {
+            for name in names.iter().map(str::trim) {
+                match SbiExtension::from_name(name) {
+                    Some(ext) => denied |= ext.bit(),
+                    None => warn!("Unknown SBI extension `{}` in domain deny list", name),
+                }
+            }
+        }
+        let mut devices = [0; MAX_DOMAIN_DEVICES];
+        if let Some(cells) = node
+            .get_prop("rustsbi,devices")
+            .map(|prop| prop.deserialize::<&[u8]>())
+        {
+            if cells.len() / 4 > MAX_DOMAIN_DEVICES {
+                warn!(
+                    "Ignoring devices of domain beyond the first {}",
+                    MAX_DOMAIN_DEVICES
+                );
+            }
+            for (slot, cell) in devices.iter_mut().zip(cells.chunks_exact(4)) {
+                *slot = u32::from_be_bytes(cell.try_into().unwrap());
+            }
+        }
+        let boot = dt::get_address(node, "rustsbi,next-addr")
+            .and_then(|next_addr| DomainBoot::from_node(node, harts, next_addr));
+        Self {
+            harts,
+            denied,
+            boot,
+            devices,
+        }
+    }
+
+    /// Phandles of the devices the domain owns.
+    #[inline]
+    pub fn devices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.devices.iter().copied().filter(|&phandle| phandle != 0)
+    }
+
+    /// Whether `hart_id` belongs to the domain.
+    #[inline]
+    pub fn contains(&self, hart_id: usize) -> bool {
+        hart_id < NUM_HART_MAX && self.harts & (1 << hart_id) != 0
+    }
+}
+
+impl DomainBoot {
+    /// Read the rest of the next stage of a domain of `harts` entering at `next_addr`.
+    fn from_node(node: &Node, harts: u64, next_addr: usize) -> Option<Self> {
+        let next_mode = match dt::get_u32(node, "rustsbi,next-mode") {
+            None | Some(1) => MPP::Supervisor,
+            Some(0) => MPP::User,
+            Some(mode) => {
+                warn!("Ignoring domain next stage, invalid mode {}", mode);
+                return None;
+            }
+        };
+        let hart_id = match dt::get_u32(node, "rustsbi,boot-hart") {
+            Some(hart_id) => hart_id as usize,
+            None if harts != 0 => harts.trailing_zeros() as usize,
+            None => {
+                warn!("Ignoring domain next stage, the domain has no harts");
+                return None;
+            }
+        };
+        if hart_id >= NUM_HART_MAX || harts & (1 << hart_id) == 0 {
+            warn!(
+                "Ignoring domain next stage, boot hart {} not in the domain",
+                hart_id
+            );
+            return None;
+        }
+        Some(Self {
+            hart_id,
+            next_addr,
+            next_mode,
+            arg1: dt::get_address(node, "rustsbi,next-arg1"),
+        })
+    }
+}
+
+percpu! {
+    /// Domain index of each hart.
+    static DOMAIN: AtomicU8 = AtomicU8::new(NO_DOMAIN);
+}
+
+percpu! {
+    /// Extensions each hart's domain refuses.
+    static DENIED: AtomicU32 = AtomicU32::new(0);
+}
+
+percpu! {
+    /// Calls each hart had refused by its domain.
+    static VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Assign every hart to its domain, the first one listing it.
+pub fn init(domains: &[Option<DomainInfo>]) {
+    for (index, domain) in domains.iter().enumerate() {
+        let Some(domain) = domain else {
+            continue;
+        };
+        for hart_id in (0..NUM_HART_MAX).filter(|&hart_id| domain.contains(hart_id)) {
+            let slot = DOMAIN.get(hart_id).unwrap();
+            if slot.load(Ordering::Relaxed) != NO_DOMAIN {
+                warn!("Hart {} listed in more than one domain", hart_id);
+                continue;
+            }
+            slot.store(index as u8, Ordering::Relaxed);
+            DENIED
+                .get(hart_id)
+                .unwrap()
+                .store(domain.denied, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Domain of `hart_id`, `None` for harts outside every domain.
+pub fn of_hart(hart_id: usize) -> Option<usize> {
+    match DOMAIN.get(hart_id)?.load(Ordering::Relaxed) {
+        NO_DOMAIN => None,
+        index => Some(index as usize),
+    }
+}
+
+/// Whether the current hart may start or stop `hart_id`, which must be in
+/// the same domain.
+#[inline]
+pub fn may_control(hart_id: usize) -> bool {
+    of_hart(hart_id) == of_hart(current_hartid())
+}
+
+/// Whether the domain of the current hart may use the extension of `eid`.
+#[inline]
+pub fn allows(eid: usize) -> bool {
+    match SbiExtension::from_eid(eid) {
+        Some(ext) => DENIED.local().load(Ordering::Relaxed) & ext.bit() == 0,
+        None => true,
+    }
+}
+
+/// Check a call into `eid` against the domain of the current hart, logging
+/// refused calls at each power of two.
+#[inline]
+pub fn check_call(eid: usize) -> bool {
+    if allows(eid) {
+        return true;
+    }
+    let count = VIOLATIONS.local().fetch_add(1, Ordering::Relaxed) + 1;
+    if count.is_power_of_two() {
+        let hart_id = current_hartid();
+        warn!(
+            "Hart {} in domain {} refused extension {:#x}, {} call(s) so far",
+            hart_id,
+            of_hart(hart_id).unwrap_or(0),
+            eid,
+            count
+        );
+    }
+    false
+}
@@ -0,0 +1,100 @@
+//! RustSBI Prototyper entropy extension.
+//!
+//! Hands out raw entropy from the `seed` CSR or a platform TRNG, so a
+//! supervisor can seed its random number generator before its own drivers are
+//! up, and without having direct `seed` access granted by the seed policy.
+//! Firmware code needing random values uses `random_usize` alike.
+
+use rustsbi::SbiRet;
+
+use crate::platform::PLATFORM;
+use crate::riscv_spec::{current_hartid, seed};
+use crate::sbi::extensions::{hart_extension_probe, Extension};
+use crate::time;
+
+/// Extension ID of the entropy extension, in the firmware specific range.
+pub const EID_ENTROPY: usize = 0x0A52_5345;
+
+/// Return `usize::BITS` bits of raw entropy in the value.
+pub const GET_ENTROPY: usize = 0;
+
+/// Time to wait for the entropy source before giving up.
+const ENTROPY_TIMEOUT_US: u64 = 10_000;
+
+/// A true random number generator device.
+pub trait TrngDevice {
+    /// Fills `buf` with random words, returning false if the device failed.
+    fn fill(&self, buf: &mut [u32]) -> bool;
+}
+
+#[inline]
+fn has_seed() -> bool {
+    hart_extension_probe(current_hartid(), Extension::Zkr)
+}
+
+/// Whether entropy can be provided on the current hart.
+#[inline]
+pub fn available() -> bool {
+    has_seed() || unsafe { PLATFORM.trng.is_some() }
+}
+
+/// Collect 16 bits of entropy from `seed`.
+fn read_es16(deadline: &time::Deadline) -> Result<u16, SbiRet> {
+    loop {
+        let value = seed::read();
+        match value >> seed::OPST_SHIFT {
+            seed::OPST_ES16 => return Ok(value as u16),
+            seed::OPST_DEAD => return Err(SbiRet::failed()),
+            // BIST or WAIT, the source will produce entropy later.
+            _ if deadline.expired() => return Err(SbiRet::failed()),
+            _ => core::hint::spin_loop(),
+        }
+    }
+}
+
+fn read_seed() -> Result<usize, SbiRet> {
+    let deadline = time::Deadline::after_us(ENTROPY_TIMEOUT_US);
+    let mut entropy = 0usize;
+    for _ in 0..usize::BITS / 16 {
+        entropy = (entropy << 16) | read_es16(&deadline)? as usize;
+    }
+    Ok(entropy)
+}
+
+fn read_trng() -> Result<usize, SbiRet> {
+    let Some(trng) = (unsafe { PLATFORM.trng.as_ref() }) else {
+        return Err(SbiRet::not_supported());
+    };
+    let mut words = [0u32; (usize::BITS / u32::BITS) as usize];
+    if !trng.lock().fill(&mut words) {
+        return Err(SbiRet::failed());
+    }
+    let entropy = words
+        .iter()
+        .fold(0u64, |acc, &word| (acc << 32) | word as u64);
+    Ok(entropy as usize)
+}
+
+/// Collect `usize::BITS` bits of entropy, preferring the hart's `seed` CSR.
+pub fn random_usize() -> Result<usize, SbiRet> {
+    if has_seed() {
+        read_seed()
+    } else {
+        read_trng()
+    }
+}
+
+fn get_entropy() -> SbiRet {
+    match random_usize() {
+        Ok(entropy) => SbiRet::success(entropy),
+        Err(err) => err,
+    }
+}
+
+/// Dispatch a call to the entropy extension.
+pub fn handle_ecall(function: usize, _param: [usize; 6]) -> SbiRet {
+    match function {
+        GET_ENTROPY => get_entropy(),
+        _ => SbiRet::not_supported(),
+    }
+}
@@ -0,0 +1,97 @@
+//! Machine readable state dump at the end of a QEMU run.
+//!
+//! With the `exit-dump` feature, shutting down through the `sifive,test0`
+//! finisher first prints the final state of the firmware as JSON lines, one
+//! object per line, each tagged with a `rustsbi_exit` kind:
+//!
+//! - `result`: outcome (`pass` or `fail`), exit code and the hart shutting down.
+//! - `counter`: one firmware wide counter, by `name` and `value`.
+//! - `hart`: HSM state, init state and event counts of one hart.
+//! - `end`: the dump is complete, with the number of lines before it.
+//!
+//! A CI harness picks these lines out of the console log to build its
+//! failure report; everything else on the console is left as it is.
+
+use rustsbi::spec::hsm::hart_state;
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::console;
+use crate::sbi::debug::{self, Event};
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::hsm::remote_hsm;
+use crate::sbi::rnmi;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Counters of every hart, by name.
+const EVENTS: [(&str, Event); 3] = [
+    ("ipi_sent", Event::IpiSent),
+    ("ipi_received", Event::IpiReceived),
+    ("traps", Event::Trap),
+];
+
+fn hsm_state_name(state: usize) -> &'static str {
+    match state {
+        hart_state::STARTED => "started",
+        hart_state::STOPPED => "stopped",
+        hart_state::START_PENDING => "start_pending",
+        hart_state::STOP_PENDING => "stop_pending",
+        hart_state::SUSPENDED => "suspended",
+        hart_state::SUSPEND_PENDING => "suspend_pending",
+        hart_state::RESUME_PENDING => "resume_pending",
+        _ => "unknown",
+    }
+}
+
+fn init_state_name(state: u8) -> &'static str {
+    match state {
+        s if s == InitState::Uninit as u8 => "uninit",
+        s if s == InitState::StacksReady as u8 => "stacks_ready",
+        s if s == InitState::DevicesReady as u8 => "devices_ready",
+        s if s == InitState::SbiReady as u8 => "sbi_ready",
+        _ => "unknown",
+    }
+}
+
+/// Print the dump for a run ending with exit code `code`, 0 for a pass.
+pub fn write(code: u16) {
+    let mut lines = 0;
+    println!(
+        r#"{{"rustsbi_exit":"result","outcome":"{}","code":{},"hart":{}}}"#,
+        if code == 0 { "pass" } else { "fail" },
+        code,
+        current_hartid()
+    );
+    lines += 1;
+    let counters = [
+        ("console_dropped_bytes", console::dropped_bytes()),
+        ("rnmi", rnmi::total_count()),
+    ]
+    .into_iter()
+    .chain(EVENTS.map(|(name, event)| (name, debug::total(event))));
+    for (name, value) in counters {
+        println!(
+            r#"{{"rustsbi_exit":"counter","name":"{}","value":{}}}"#,
+            name, value
+        );
+        lines += 1;
+    }
+    for hart_id in 0..NUM_HART_MAX {
+        let init = hart_init::state(hart_id);
+        if init == InitState::Uninit as u8 {
+            continue;
+        }
+        let hsm = remote_hsm(hart_id).map_or("unknown", |hsm| hsm_state_name(hsm.sbi_get_status()));
+        println!(
+            r#"{{"rustsbi_exit":"hart","id":{},"hsm":"{}","init":"{}","failed":{},"ipi_sent":{},"ipi_received":{},"traps":{}}}"#,
+            hart_id,
+            hsm,
+            init_state_name(init),
+            hart_init::failed(hart_id),
+            debug::hart_count(hart_id, Event::IpiSent),
+            debug::hart_count(hart_id, Event::IpiReceived),
+            debug::hart_count(hart_id, Event::Trap)
+        );
+        lines += 1;
+    }
+    println!(r#"{{"rustsbi_exit":"end","lines":{}}}"#, lines);
+}
@@ -0,0 +1,269 @@
+//! Build-time and device tree controlled SBI extension mask.
+//!
+//! Extensions can be turned off with a comma separated list in the
+//! `PROTOTYPER_DISABLE_EXTENSIONS` build environment variable, or with the
+//! `rustsbi,disable-extensions` string list property in `/chosen`. Disabled
+//! extensions are reported absent by `probe_extension` and every call into
+//! them returns `SBI_ERR_NOT_SUPPORTED`.
+//!
+//! Calls to function IDs an enabled extension does not implement, such as
+//! ones added by newer specifications, are refused the same way before they
+//! reach the extension.
+//!
+//! The advertised SBI specification version is chosen with
+//! `PROTOTYPER_SBI_SPEC` at build time or the `rustsbi,sbi-spec-version`
+//! string in `/chosen`, either `2.0` or `3.0`. Extensions introduced after
+//! the chosen version are masked as if disabled, and under 2.0 the error
+//! codes 3.0 added are reported as `SBI_ERR_FAILED`. Every function the
+//! firmware implements of the remaining extensions dates from 2.0, so no
+//! function IDs need masking.
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use prototyper_common::sbi_functions;
+use rustsbi::SbiRet;
+use sbi_spec::{base, dbcn, hsm, legacy, rfnc, spi, srst, time};
+
+use crate::sbi::debug;
+use crate::sbi::entropy;
+use crate::sbi::fwft;
+use crate::sbi::susp;
+use crate::sbi::update;
+
+/// SBI specification versions the firmware can advertise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpecVersion {
+    V2_0 = 0,
+    V3_0 = 1,
+}
+
+impl SpecVersion {
+    fn from_str(version: &str) -> Option<Self> {
+        match version.trim() {
+            "2.0" => Some(SpecVersion::V2_0),
+            "3.0" => Some(SpecVersion::V3_0),
+            _ => None,
+        }
+    }
+
+    /// Encoding returned by `sbi_get_spec_version`.
+    pub fn encoded(&self) -> usize {
+        match self {
+            SpecVersion::V2_0 => 2 << 24,
+            SpecVersion::V3_0 => 3 << 24,
+        }
+    }
+}
+
+impl core::fmt::Display for SpecVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SpecVersion::V2_0 => write!(f, "2.0"),
+            SpecVersion::V3_0 => write!(f, "3.0"),
+        }
+    }
+}
+
+/// SBI extensions that can be disabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbiExtension {
+    Console = 0,
+    Ipi = 1,
+    Timer = 2,
+    Hsm = 3,
+    Reset = 4,
+    RFence = 5,
+    Legacy = 6,
+    Debug = 7,
+    Update = 8,
+    Entropy = 9,
+    Fwft = 10,
+    Susp = 11,
+}
+
+impl SbiExtension {
+    const ITER: [Self; 12] = [
+        SbiExtension::Console,
+        SbiExtension::Ipi,
+        SbiExtension::Timer,
+        SbiExtension::Hsm,
+        SbiExtension::Reset,
+        SbiExtension::RFence,
+        SbiExtension::Legacy,
+        SbiExtension::Debug,
+        SbiExtension::Update,
+        SbiExtension::Entropy,
+        SbiExtension::Fwft,
+        SbiExtension::Susp,
+    ];
+
+    /// Name used in the disable lists, following the SBI specification.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SbiExtension::Console => "dbcn",
+            SbiExtension::Ipi => "spi",
+            SbiExtension::Timer => "time",
+            SbiExtension::Hsm => "hsm",
+            SbiExtension::Reset => "srst",
+            SbiExtension::RFence => "rfnc",
+            SbiExtension::Legacy => "legacy",
+            SbiExtension::Debug => "debug",
+            SbiExtension::Update => "update",
+            SbiExtension::Entropy => "entropy",
+            SbiExtension::Fwft => "fwft",
+            SbiExtension::Susp => "susp",
+        }
+    }
+
+    /// The extension called `name` in the disable lists.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ITER.into_iter().find(|ext| ext.as_str() == name)
+    }
+
+    /// Map an extension ID to the extension it belongs to.
+    pub fn from_eid(eid: usize) -> Option<Self> {
+        match eid {
+            dbcn::EID_DBCN => Some(SbiExtension::Console),
+            spi::EID_SPI => Some(SbiExtension::Ipi),
+            time::EID_TIME => Some(SbiExtension::Timer),
+            hsm::EID_HSM => Some(SbiExtension::Hsm),
+            srst::EID_SRST => Some(SbiExtension::Reset),
+            rfnc::EID_RFNC => Some(SbiExtension::RFence),
+            legacy::LEGACY_SET_TIMER..=legacy::LEGACY_SHUTDOWN => Some(SbiExtension::Legacy),
+            debug::EID_DEBUG => Some(SbiExtension::Debug),
+            update::EID_UPDATE => Some(SbiExtension::Update),
+            entropy::EID_ENTROPY => Some(SbiExtension::Entropy),
+            fwft::EID_FWFT => Some(SbiExtension::Fwft),
+            susp::EID_SUSP => Some(SbiExtension::Susp),
+            _ => None,
+        }
+    }
+
+    /// Specification version that introduced the extension.
+    pub fn since(&self) -> SpecVersion {
+        match self {
+            SbiExtension::Fwft => SpecVersion::V3_0,
+            _ => SpecVersion::V2_0,
+        }
+    }
+
+    /// Bit of the extension in extension bitmaps.
+    #[inline]
+    pub fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// Bitmap of disabled extensions.
+static DISABLED: AtomicU32 = AtomicU32::new(0);
+/// Advertised specification version, as a `SpecVersion`.
+static SPEC_VERSION: AtomicU8 = AtomicU8::new(SpecVersion::V3_0 as u8);
+
+/// Advertise `version`, one of `2.0` and `3.0`.
+pub fn set_spec_version(version: &str) {
+    match SpecVersion::from_str(version) {
+        Some(version) => SPEC_VERSION.store(version as u8, Ordering::Relaxed),
+        None => warn!("Unknown SBI specification version `{}`", version),
+    }
+}
+
+/// Specification version advertised to the supervisor.
+#[inline]
+pub fn spec_version() -> SpecVersion {
+    match SPEC_VERSION.load(Ordering::Relaxed) {
+        0 => SpecVersion::V2_0,
+        _ => SpecVersion::V3_0,
+    }
+}
+
+/// `SBI_ERR_NO_SHMEM`, the last error code of SBI 2.0.
+const LAST_V2_0_ERROR: isize = -9;
+
+/// Report an error code newer than the advertised specification as
+/// `SBI_ERR_FAILED`, which every version defines.
+///
+/// Not for legacy calls, whose error field is their return value.
+#[inline]
+pub fn mask_error(ret: &mut SbiRet) {
+    if spec_version() < SpecVersion::V3_0 && (ret.error as isize) < LAST_V2_0_ERROR {
+        ret.error = SbiRet::failed().error;
+    }
+}
+
+/// Disable every extension named in `names`, warning about unknown names.
+pub fn disable<'a>(names: impl Iterator<Item = &'a str>) {
+    for name in names.map(str::trim).filter(|name| !name.is_empty()) {
+        match SbiExtension::from_name(name) {
+            Some(ext) => {
+                DISABLED.fetch_or(ext.bit(), Ordering::Relaxed);
+            }
+            None => warn!("Unknown SBI extension `{}` in disable list", name),
+        }
+    }
+}
+
+/// Apply the build-time disable list and specification version.
+pub fn init() {
+    if let Some(list) = option_env!("PROTOTYPER_DISABLE_EXTENSIONS") {
+        disable(list.split(','));
+    }
+    if let Some(version) = option_env!("PROTOTYPER_SBI_SPEC") {
+        set_spec_version(version);
+    }
+}
+
+/// Returns false if the extension `eid` belongs to has been disabled, or is
+/// newer than the advertised specification.
+#[inline]
+pub fn is_enabled(eid: usize) -> bool {
+    match SbiExtension::from_eid(eid) {
+        Some(ext) => {
+            DISABLED.load(Ordering::Relaxed) & ext.bit() == 0 && ext.since() <= spec_version()
+        }
+        None => true,
+    }
+}
+
+// The shared table must end each extension at its last function.
+const _: () = {
+    let last = [
+        (dbcn::EID_DBCN, dbcn::CONSOLE_WRITE_BYTE),
+        (spi::EID_SPI, spi::SEND_IPI),
+        (time::EID_TIME, time::SET_TIMER),
+        (hsm::EID_HSM, hsm::HART_SUSPEND),
+        (srst::EID_SRST, srst::SYSTEM_RESET),
+        (rfnc::EID_RFNC, rfnc::REMOTE_HFENCE_VVMA),
+        (debug::EID_DEBUG, debug::SET_CONSOLE_OWNER),
+        (update::EID_UPDATE, update::CONFIRM),
+        (entropy::EID_ENTROPY, entropy::GET_ENTROPY),
+        (fwft::EID_FWFT, fwft::GET),
+        (susp::EID_SUSP, susp::SYSTEM_SUSPEND),
+        (base::EID_BASE, base::GET_MIMPID),
+    ];
+    let mut index = 0;
+    while index < last.len() {
+        let (eid, fid) = last[index];
+        assert!(matches!(sbi_functions::count(eid), Some(count) if count == fid + 1));
+        index += 1;
+    }
+};
+
+/// Returns false if `fid` is not a function of the known extension `eid`.
+///
+/// Unknown extensions and the base extension are left to the dispatcher.
+#[inline]
+pub fn is_implemented(eid: usize, fid: usize) -> bool {
+    match SbiExtension::from_eid(eid) {
+        // The extension ID is the function, `a6` is not looked at.
+        None | Some(SbiExtension::Legacy) => true,
+        Some(_) => sbi_functions::count(eid).map_or(true, |count| fid < count),
+    }
+}
+
+/// Iterate over disabled extensions, including those masked by the specification version.
+pub fn disabled() -> impl Iterator<Item = SbiExtension> {
+    let bits = DISABLED.load(Ordering::Relaxed);
+    let version = spec_version();
+    SbiExtension::ITER
+        .into_iter()
+        .filter(move |ext| bits & ext.bit() != 0 || ext.since() > version)
+}
@@ -1,16 +1,15 @@
 use serde_device_tree::buildin::NodeSeq;
 
+pub use prototyper_common::isa::{Extension, ExtensionSet};
+
+use crate::error::FwResult;
 use crate::riscv_spec::current_hartid;
 use crate::sbi::trap_stack::ROOT_STACK;
 
 pub struct HartFeatures {
-    extension: [bool; Extension::COUNT],
+    extension: ExtensionSet,
     privileged_version: PrivilegedVersion,
-}
-
-#[derive(Copy, Clone)]
-pub enum Extension {
-    Sstc = 0,
+    cbom_block_size: usize,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -21,27 +20,21 @@ pub enum PrivilegedVersion {
     Version1_12 = 3,
 }
 
-impl Extension {
-    const COUNT: usize = 1;
-    const ITER: [Self; Extension::COUNT] = [Extension::Sstc];
-
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Extension::Sstc => "sstc",
-        }
-    }
-
-    #[inline]
-    pub fn index(&self) -> usize {
-        *self as usize
+pub fn hart_extension_probe(hart_id: usize, ext: Extension) -> bool {
+    unsafe {
+        ROOT_STACK
+            .get_mut(hart_id)
+            .map(|x| x.hart_context().features.extension.contains(ext))
+            .unwrap()
     }
 }
 
-pub fn hart_extension_probe(hart_id: usize, ext: Extension) -> bool {
+/// Zicbom cache block size of a hart in bytes, 0 if the device tree has none.
+pub fn hart_cbom_block_size(hart_id: usize) -> usize {
     unsafe {
         ROOT_STACK
             .get_mut(hart_id)
-            .map(|x| x.hart_context().features.extension[ext.index()])
+            .map(|x| x.hart_context().features.cbom_block_size)
             .unwrap()
     }
 }
@@ -56,32 +49,30 @@ pub fn hart_privileged_version(hart_id: usize) -> PrivilegedVersion {
 }
 
 #[cfg(not(feature = "nemu"))]
-pub fn init(cpus: &NodeSeq) {
+pub fn init(cpus: &NodeSeq) -> FwResult {
     use crate::dt::Cpu;
+    use crate::error::FwError;
     for cpu_iter in cpus.iter() {
         let cpu = cpu_iter.deserialize::<Cpu>();
-        let hart_id = cpu.reg.iter().next().unwrap().0.start;
-        let mut hart_exts = [false; Extension::COUNT];
-        if cpu.isa_extensions.is_some() {
-            let isa = cpu.isa_extensions.unwrap();
-            Extension::ITER.iter().for_each(|ext| {
-                hart_exts[ext.index()] = isa.iter().any(|e| e == ext.as_str());
-            });
-        } else if cpu.isa.is_some() {
-            let isa_iter = cpu.isa.unwrap();
-            let isa = isa_iter.iter().next().unwrap_or_default();
-            Extension::ITER.iter().for_each(|ext| {
-                hart_exts[ext.index()] = isa.contains(ext.as_str());
-            })
-        }
+        let hart_id = cpu.reg.iter().next().ok_or(FwError::CpuWithoutReg)?.0.start;
+        let hart_exts = if let Some(isa) = cpu.isa_extensions {
+            ExtensionSet::from_names(isa.iter())
+        } else if let Some(isa) = cpu.isa {
+            ExtensionSet::from_isa(isa.iter().next().unwrap_or_default())
+        } else {
+            ExtensionSet::empty()
+        };
+
+        let cbom_block_size = cpu.cbom_block_size.unwrap_or(0) as usize;
 
-        unsafe {
-            ROOT_STACK
-                .get_mut(hart_id)
-                .map(|stack| stack.hart_context().features.extension = hart_exts)
-                .unwrap()
+        // Harts past the hart limit never enter the firmware.
+        if let Some(stack) = unsafe { ROOT_STACK.get_mut(hart_id) } {
+            let features = &mut stack.hart_context().features;
+            features.extension = hart_exts;
+            features.cbom_block_size = cbom_block_size;
         }
     }
+    Ok(())
 }
 
 pub fn privileged_version_detection() {
@@ -110,20 +101,17 @@ pub fn privileged_version_detection() {
 }
 
 #[cfg(feature = "nemu")]
-pub fn init(cpus: &NodeSeq) {
+pub fn init(cpus: &NodeSeq) -> FwResult {
     for hart_id in 0..cpus.len() {
-        let mut hart_exts = [false; Extension::COUNT];
-        hart_exts[Extension::Sstc.index()] = true;
-        unsafe {
-            ROOT_STACK
-                .get_mut(hart_id)
-                .map(|stack| {
-                    stack.hart_context().features = HartFeatures {
-                        extension: hart_exts,
-                        privileged_version: PrivilegedVersion::Version1_12,
-                    }
-                })
-                .unwrap()
+        let mut hart_exts = ExtensionSet::empty();
+        hart_exts.insert(Extension::Sstc);
+        if let Some(stack) = unsafe { ROOT_STACK.get_mut(hart_id) } {
+            stack.hart_context().features = HartFeatures {
+                extension: hart_exts,
+                privileged_version: PrivilegedVersion::Version1_12,
+                cbom_block_size: 0,
+            };
         }
     }
+    Ok(())
 }
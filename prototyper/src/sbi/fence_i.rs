@@ -0,0 +1,132 @@
+//! Remote instruction fences with generation counters.
+//!
+//! An instruction fence carries no arguments, so one `fence.i` executed after
+//! a request was posted satisfies every request posted before it. Each hart
+//! has a `requested` generation, raised by every sender, and a `completed`
+//! generation, the requested one it had read before its last `fence.i`. A
+//! sender only interrupts a target that has nothing outstanding; back to back
+//! broadcasts to a busy hart are coalesced into the fence it still has to run.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use rustsbi::{HartMask, SbiRet};
+
+use crate::platform;
+use crate::riscv_spec::current_hartid;
+use crate::sbi::hart_mask;
+use crate::sbi::ipi::{self, IPI_TYPE_FENCE_I};
+use crate::sbi::trap;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+use crate::sync::Backoff;
+use crate::time;
+
+/// Time after which a hart waiting for remote instruction fences reports it.
+const FENCE_I_TIMEOUT_US: u64 = 1_000_000;
+
+/// Instruction fence generations of one hart.
+///
+/// Both only move forward and are compared with wrapping arithmetic. The
+/// sender's increment of `requested` followed by its read of `completed`, and
+/// the target's store of `completed` followed by its read of `requested`, are
+/// sequentially consistent: either the sender sees nothing outstanding and
+/// interrupts the target, or the target sees the new request before it stops.
+struct Generations {
+    requested: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+impl Generations {
+    const fn new() -> Self {
+        Self {
+            requested: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+        }
+    }
+}
+
+percpu! {
+    /// Instruction fence generations of each hart.
+    static GENERATIONS: Generations = Generations::new();
+}
+
+/// Whether generation `generation` has been completed by a hart at `completed`.
+#[inline]
+fn reached(completed: usize, generation: usize) -> bool {
+    completed.wrapping_sub(generation) as isize >= 0
+}
+
+/// Run the instruction fences requested from the current hart.
+///
+/// Called from the machine software interrupt and from wait loops.
+pub fn handle_local() {
+    let generations = GENERATIONS.local();
+    loop {
+        // Pairs with the sender's increment, its code writes are visible now.
+        let requested = generations.requested.load(SeqCst);
+        if requested == generations.completed.load(SeqCst) {
+            return;
+        }
+        unsafe { asm!("fence.i") };
+        generations.completed.store(requested, SeqCst);
+    }
+}
+
+/// Execute `fence.i` on every hart of `hart_mask` and wait until they did.
+pub fn remote_fence_i(hart_mask: HartMask) -> SbiRet {
+    let hart_mask = match ipi::prepare_hart_mask(hart_mask) {
+        Ok(hart_mask) => hart_mask,
+        Err(err) => return err,
+    };
+    let Some(ipi_dev) = platform::ipi() else {
+        return SbiRet::failed();
+    };
+    let current_hart = current_hartid();
+    let mut waiting = [None; NUM_HART_MAX];
+    let mut local = false;
+    for hart_id in hart_mask::hart_ids(hart_mask) {
+        if hart_id == current_hart {
+            local = true;
+            continue;
+        }
+        let Some(generations) = GENERATIONS.get(hart_id) else {
+            continue;
+        };
+        let generation = generations.requested.fetch_add(1, SeqCst).wrapping_add(1);
+        waiting[hart_id] = Some(generation);
+        // Otherwise the target still has to run a fence that covers ours.
+        if generations.completed.load(SeqCst) == generation.wrapping_sub(1)
+            && ipi::set_ipi_type(hart_id, IPI_TYPE_FENCE_I) == 0
+        {
+            ipi_dev.set_msip(hart_id);
+        }
+    }
+    if local {
+        unsafe { asm!("fence.i") };
+    }
+
+    // Keep serving fences other harts ask of us, they may be waiting on us.
+    let deadline = time::Deadline::after_us(FENCE_I_TIMEOUT_US);
+    let mut warned = false;
+    let mut backoff = Backoff::new();
+    for (hart_id, generation) in waiting.iter().enumerate() {
+        let (Some(generation), Some(generations)) = (*generation, GENERATIONS.get(hart_id)) else {
+            continue;
+        };
+        while !reached(generations.completed.load(SeqCst), generation) {
+            if ipi::peek_ipi_type() != 0 {
+                trap::msoft_ipi_handler();
+            }
+            trap::rfence_handler();
+            if !warned && deadline.expired() {
+                warn!(
+                    "Hart {} still waiting for remote fence.i on hart {}",
+                    current_hart, hart_id
+                );
+                warned = true;
+            }
+            backoff.spin();
+        }
+    }
+    SbiRet::success(0)
+}
@@ -67,4 +67,21 @@ impl<T: Copy + Clone> Fifo<T> {
 
         Ok(element)
     }
+
+    /// Keep only the elements `f` returns true for, in order.
+    ///
+    /// Returns the number of elements removed.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) -> usize {
+        let count = self.count;
+        let mut removed = 0;
+        for _ in 0..count {
+            let element = self.pop().unwrap();
+            if f(&element) {
+                self.push(element).unwrap();
+            } else {
+                removed += 1;
+            }
+        }
+        removed
+    }
 }
@@ -0,0 +1,209 @@
+//! Firmware Features extension (FWFT).
+//!
+//! Lets S-mode turn on hart features gated by machine mode CSRs. Feature
+//! values live in the CSRs they control, only the lock bits are kept here.
+//! Values and locks return to their defaults when a hart is started.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use rustsbi::SbiRet;
+
+use crate::riscv_spec::{current_hartid, menvcfg};
+use crate::sbi::extensions::{
+    hart_extension_probe, hart_privileged_version, Extension, PrivilegedVersion,
+};
+
+/// Extension ID of the firmware features extension.
+pub const EID_FWFT: usize = 0x4657_4654;
+
+/// Set a feature of the calling hart.
+pub const SET: usize = 0;
+/// Get a feature of the calling hart.
+pub const GET: usize = 1;
+
+/// Feature IDs defined by the SBI specification.
+#[allow(unused)]
+pub mod feature {
+    pub const MISALIGNED_EXC_DELEG: usize = 0;
+    pub const LANDING_PAD: usize = 1;
+    pub const SHADOW_STACK: usize = 2;
+    pub const DOUBLE_TRAP: usize = 3;
+    pub const PTE_AD_HW_UPDATING: usize = 4;
+    pub const POINTER_MASKING_PMLEN: usize = 5;
+}
+
+/// Lock the feature until the next hart start.
+const FLAG_LOCK: usize = 1 << 0;
+
+/// `SBI_ERR_DENIED_LOCKED`, not known to `sbi-spec` yet.
+const ERR_DENIED_LOCKED: isize = -14;
+
+percpu! {
+    /// Bitmap of features locked by each hart.
+    static LOCKED: AtomicU32 = AtomicU32::new(0);
+}
+
+#[inline]
+fn has_menvcfg() -> bool {
+    hart_privileged_version(current_hartid()) >= PrivilegedVersion::Version1_12
+}
+
+/// Whether `feature` is implemented on the calling hart.
+fn supported(feature: usize) -> bool {
+    let hart_id = current_hartid();
+    match feature {
+        feature::LANDING_PAD => has_menvcfg() && hart_extension_probe(hart_id, Extension::Zicfilp),
+        feature::SHADOW_STACK => has_menvcfg() && hart_extension_probe(hart_id, Extension::Zicfiss),
+        #[cfg(target_arch = "riscv64")]
+        feature::POINTER_MASKING_PMLEN => {
+            has_menvcfg() && hart_extension_probe(hart_id, Extension::Smnpm)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `feature` is defined by the specification, supported or not.
+///
+/// Every other ID is reserved or platform specific, and no platform
+/// specific feature is implemented.
+#[inline]
+fn defined(feature: usize) -> bool {
+    feature <= feature::POINTER_MASKING_PMLEN
+}
+
+/// Program a boolean feature backed by `bits` of `menvcfg`.
+fn set_menvcfg(bits: u64, value: usize) -> Result<(), SbiRet> {
+    match value {
+        0 => menvcfg::clear_bits(bits),
+        1 => menvcfg::set_bits(bits),
+        _ => return Err(SbiRet::invalid_param()),
+    }
+    Ok(())
+}
+
+#[inline]
+fn get_menvcfg(bits: u64) -> usize {
+    (menvcfg::read() & bits != 0) as usize
+}
+
+/// `menvcfg.PMM` encodings by increasing `PMLEN`.
+#[cfg(target_arch = "riscv64")]
+const PMLEN_MODES: [(usize, u64); 2] = [(7, menvcfg::PMM_PMLEN_7), (16, menvcfg::PMM_PMLEN_16)];
+
+/// Whether the hart accepts `mode` in the WARL `menvcfg.PMM` field.
+#[cfg(target_arch = "riscv64")]
+fn pmm_implemented(mode: u64) -> bool {
+    let saved = menvcfg::read() & menvcfg::PMM;
+    menvcfg::clear_bits(menvcfg::PMM);
+    menvcfg::set_bits(mode);
+    let implemented = menvcfg::read() & menvcfg::PMM == mode;
+    menvcfg::clear_bits(menvcfg::PMM);
+    menvcfg::set_bits(saved);
+    implemented
+}
+
+/// Mask at least the upper `pmlen` address bits of S-mode, 0 turns masking off.
+///
+/// The smallest implemented `PMLEN` not below the request is chosen.
+#[cfg(target_arch = "riscv64")]
+fn set_pmlen(pmlen: usize) -> Result<(), SbiRet> {
+    let mode = if pmlen == 0 {
+        0
+    } else {
+        match PMLEN_MODES
+            .iter()
+            .find(|&&(len, mode)| len >= pmlen && pmm_implemented(mode))
+        {
+            Some(&(_, mode)) => mode,
+            None => return Err(SbiRet::invalid_param()),
+        }
+    };
+    menvcfg::clear_bits(menvcfg::PMM);
+    menvcfg::set_bits(mode);
+    Ok(())
+}
+
+#[cfg(target_arch = "riscv64")]
+fn get_pmlen() -> usize {
+    let mode = menvcfg::read() & menvcfg::PMM;
+    PMLEN_MODES
+        .iter()
+        .find(|&&(_, pmm)| pmm == mode)
+        .map_or(0, |&(len, _)| len)
+}
+
+fn set(feature: usize, value: usize, flags: usize) -> SbiRet {
+    if !defined(feature) {
+        return SbiRet::denied();
+    }
+    if flags & !FLAG_LOCK != 0 {
+        return SbiRet::invalid_param();
+    }
+    if !supported(feature) {
+        return SbiRet::not_supported();
+    }
+    let locked = LOCKED.local();
+    if locked.load(Ordering::Relaxed) & (1 << feature) != 0 {
+        return SbiRet {
+            error: ERR_DENIED_LOCKED as usize,
+            value: 0,
+        };
+    }
+    let result = match feature {
+        feature::LANDING_PAD => set_menvcfg(menvcfg::LPE, value),
+        feature::SHADOW_STACK => set_menvcfg(menvcfg::SSE, value),
+        #[cfg(target_arch = "riscv64")]
+        feature::POINTER_MASKING_PMLEN => set_pmlen(value),
+        _ => unreachable!(),
+    };
+    if let Err(err) = result {
+        return err;
+    }
+    if flags & FLAG_LOCK != 0 {
+        locked.fetch_or(1 << feature, Ordering::Relaxed);
+    }
+    SbiRet::success(0)
+}
+
+fn get(feature: usize) -> SbiRet {
+    if !defined(feature) {
+        return SbiRet::denied();
+    }
+    if !supported(feature) {
+        return SbiRet::not_supported();
+    }
+    match feature {
+        feature::LANDING_PAD => SbiRet::success(get_menvcfg(menvcfg::LPE)),
+        feature::SHADOW_STACK => SbiRet::success(get_menvcfg(menvcfg::SSE)),
+        #[cfg(target_arch = "riscv64")]
+        feature::POINTER_MASKING_PMLEN => SbiRet::success(get_pmlen()),
+        _ => unreachable!(),
+    }
+}
+
+/// Dispatch a call to the firmware features extension.
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        SET => set(param[0], param[1], param[2]),
+        GET => get(param[0]),
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// Return every feature of the calling hart to its default, on hart start.
+pub fn reset_local() {
+    LOCKED.local().store(0, Ordering::Relaxed);
+    if has_menvcfg() {
+        let mut bits = 0;
+        if supported(feature::LANDING_PAD) {
+            bits |= menvcfg::LPE;
+        }
+        if supported(feature::SHADOW_STACK) {
+            bits |= menvcfg::SSE;
+        }
+        #[cfg(target_arch = "riscv64")]
+        if supported(feature::POINTER_MASKING_PMLEN) {
+            bits |= menvcfg::PMM;
+        }
+        menvcfg::clear_bits(bits);
+    }
+}
@@ -0,0 +1,303 @@
+//! Access to memory of lower privilege modes.
+//!
+//! Emulation reads instructions and operands as the trapped mode sees them,
+//! and SBI calls copy buffers from the physical addresses S-mode passes. Both
+//! go through here: virtual accesses set `mstatus.MPRV` with the mode in
+//! `MPP` (and `MPV` for guests of a hypervisor), so translation and PMP apply
+//! as they would for that mode, and physical accesses are limited to memory
+//! outside the firmware. A fault during an access is caught and returned to
+//! the caller instead of entering the trap handler; `mepc`, `mcause`, `mtval`
+//! and `mstatus` are as they were afterwards.
+
+use core::arch::asm;
+
+use crate::firmware;
+use crate::platform;
+#[cfg(target_arch = "riscv64")]
+use crate::riscv_spec::MSTATUS_MPV;
+use crate::riscv_spec::{MSTATUS_MIE, MSTATUS_MPP, MSTATUS_MPRV, MSTATUS_MXR};
+use crate::sbi::insn::{self, Insn};
+
+/// Privilege an access is made with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Physical addresses outside the firmware, accessed as M-mode or, under
+    /// the Smepmp lockdown, as U-mode with translation off.
+    Physical,
+    /// Virtual addresses of U-mode.
+    User,
+    /// Virtual addresses of S-mode or HS-mode.
+    Supervisor,
+    /// Virtual addresses of VU-mode, with the H extension.
+    #[cfg(target_arch = "riscv64")]
+    VirtualUser,
+    /// Virtual addresses of VS-mode, with the H extension.
+    #[cfg(target_arch = "riscv64")]
+    VirtualSupervisor,
+}
+
+/// Why an access did not complete.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(unused)]
+pub enum AccessError {
+    /// The range or mode is not one the firmware gives access to.
+    Inaccessible,
+    /// The access faulted, with the `mcause` and `mtval` the mode would have seen.
+    Fault { cause: usize, tval: usize },
+}
+
+impl Mode {
+    /// Mode the current trap came from, `None` for M-mode.
+    pub fn trapped() -> Option<Self> {
+        let mstatus = read_mstatus();
+        #[cfg(target_arch = "riscv64")]
+        if mstatus & MSTATUS_MPV != 0 {
+            return match (mstatus & MSTATUS_MPP) >> 11 {
+                0 => Some(Mode::VirtualUser),
+                _ => Some(Mode::VirtualSupervisor),
+            };
+        }
+        match (mstatus & MSTATUS_MPP) >> 11 {
+            0 => Some(Mode::User),
+            1 => Some(Mode::Supervisor),
+            _ => None,
+        }
+    }
+
+    /// `MPP` and `MPV` bits of `mstatus` for accesses in this mode.
+    fn mstatus_bits(self) -> Option<usize> {
+        match self {
+            Mode::Physical | Mode::User => Some(0),
+            Mode::Supervisor => Some(1 << 11),
+            #[cfg(target_arch = "riscv64")]
+            Mode::VirtualUser | Mode::VirtualSupervisor => {
+                // Without the H extension `MPV` reads as zero and the access
+                // would be made as the host.
+                riscv::register::misa::read()
+                    .is_some_and(|misa| misa.has_extension('H'))
+                    .then_some(if self == Mode::VirtualUser {
+                        MSTATUS_MPV
+                    } else {
+                        MSTATUS_MPV | 1 << 11
+                    })
+            }
+        }
+    }
+}
+
+/// Whether `start..start + size` is memory S-mode may hand the firmware.
+pub fn phys_accessible(start: usize, size: usize) -> bool {
+    let Some(end) = start.checked_add(size) else {
+        return false;
+    };
+    let in_memory =
+        platform::memory_range().is_some_and(|memory| memory.start <= start && end <= memory.end);
+    let firmware = firmware::private_range();
+    in_memory && (end <= firmware.start || firmware.end <= start)
+}
+
+/// Read `buf.len()` bytes at `addr` of `mode`.
+pub fn read(mode: Mode, addr: usize, buf: &mut [u8]) -> Result<(), AccessError> {
+    access(mode, addr, buf.len(), 0, |mprv| unsafe {
+        copy_in(addr, buf.as_mut_ptr(), buf.len(), mprv)
+    })
+}
+
+/// Write `buf` at `addr` of `mode`.
+pub fn write(mode: Mode, addr: usize, buf: &[u8]) -> Result<(), AccessError> {
+    access(mode, addr, buf.len(), 0, |mprv| unsafe {
+        copy_out(buf.as_ptr(), addr, buf.len(), mprv)
+    })
+}
+
+/// Fetch the instruction at `pc` of `mode`, also from execute-only pages.
+pub fn fetch(mode: Mode, pc: usize) -> Result<Insn, AccessError> {
+    let mut parcel = [0; 2];
+    access(mode, pc, 2, MSTATUS_MXR, |mprv| unsafe {
+        copy_in(pc, parcel.as_mut_ptr(), 2, mprv)
+    })?;
+    let low = u16::from_le_bytes(parcel);
+    if insn::length(low) == 2 {
+        return Ok(Insn::new(low as u32));
+    }
+    access(mode, pc + 2, 2, MSTATUS_MXR, |mprv| unsafe {
+        copy_in(pc + 2, parcel.as_mut_ptr(), 2, mprv)
+    })?;
+    Ok(Insn::new(
+        (u16::from_le_bytes(parcel) as u32) << 16 | low as u32,
+    ))
+}
+
+/// Run `copy` with `mstatus` set up for `mode`, passing the bits it sets while
+/// touching memory of that mode, and restore the trap state afterwards.
+fn access(
+    mode: Mode,
+    addr: usize,
+    len: usize,
+    extra: usize,
+    copy: impl FnOnce(usize) -> (usize, usize),
+) -> Result<(), AccessError> {
+    let Some(bits) = mode.mstatus_bits() else {
+        return Err(AccessError::Inaccessible);
+    };
+    // Under the Smepmp lockdown M-mode has no access to supervisor memory,
+    // physical accesses are made as U-mode with translation off instead.
+    let bare = mode == Mode::Physical && firmware::locked_down();
+    let mprv = if mode == Mode::Physical {
+        if !phys_accessible(addr, len) {
+            return Err(AccessError::Inaccessible);
+        }
+        if bare {
+            MSTATUS_MPRV
+        } else {
+            0
+        }
+    } else {
+        MSTATUS_MPRV | extra
+    };
+    #[cfg(target_arch = "riscv64")]
+    let clear = MSTATUS_MIE | MSTATUS_MPP | MSTATUS_MPV | MSTATUS_MPRV | MSTATUS_MXR;
+    #[cfg(target_arch = "riscv32")]
+    let clear = MSTATUS_MIE | MSTATUS_MPP | MSTATUS_MPRV | MSTATUS_MXR;
+    let (mstatus, mepc, mcause, mtval): (usize, usize, usize, usize);
+    let (cause, tval) = unsafe {
+        asm!(
+            "csrr {}, mstatus",
+            "csrr {}, mepc",
+            "csrr {}, mcause",
+            "csrr {}, mtval",
+            out(reg) mstatus,
+            out(reg) mepc,
+            out(reg) mcause,
+            out(reg) mtval,
+        );
+        // No interrupt may be taken while the fault handler is installed.
+        asm!("csrw mstatus, {}", in(reg) mstatus & !clear | bits);
+        let satp = if bare {
+            let satp: usize;
+            asm!("csrrw {}, satp, zero", out(reg) satp);
+            satp
+        } else {
+            0
+        };
+        let fault = copy(mprv);
+        if bare {
+            asm!("csrw satp, {}", in(reg) satp);
+        }
+        asm!(
+            "csrw mstatus, {}",
+            "csrw mepc, {}",
+            "csrw mcause, {}",
+            "csrw mtval, {}",
+            in(reg) mstatus,
+            in(reg) mepc,
+            in(reg) mcause,
+            in(reg) mtval,
+        );
+        fault
+    };
+    match cause {
+        0 => Ok(()),
+        cause => Err(AccessError::Fault { cause, tval }),
+    }
+}
+
+fn read_mstatus() -> usize {
+    let bits: usize;
+    unsafe { asm!("csrr {}, mstatus", out(reg) bits, options(nomem)) };
+    bits
+}
+
+/// Trap handler installed during an access.
+///
+/// Returns the cause in `t0` and the value in `t1`, and resumes after the
+/// faulting instruction, which is never compressed.
+#[naked]
+#[repr(align(16))]
+unsafe extern "C" fn access_fault() {
+    asm!(
+        "csrr   t1, mepc",
+        "addi   t1, t1, 4",
+        "csrw   mepc, t1",
+        "csrr   t1, mtval",
+        "csrr   t0, mcause",
+        "mret",
+        options(noreturn)
+    )
+}
+
+/// Copy `len` bytes from `src`, read with `mprv` set in `mstatus`, to `dst`.
+///
+/// Returns the cause and value of the fault that stopped the copy, zero if none.
+unsafe fn copy_in(src: usize, dst: *mut u8, len: usize, mprv: usize) -> (usize, usize) {
+    let (cause, tval): (usize, usize);
+    asm!(
+        ".option push",
+        ".option norvc",
+        "csrrw  {mtvec}, mtvec, {mtvec}",
+        "li     t0, 0",
+        "li     t1, 0",
+        "1:",
+        "beqz   {len}, 2f",
+        "csrs   mstatus, {mprv}",
+        "lbu    {byte}, 0({src})",
+        "csrc   mstatus, {mprv}",
+        "bnez   t0, 2f",
+        "sb     {byte}, 0({dst})",
+        "addi   {src}, {src}, 1",
+        "addi   {dst}, {dst}, 1",
+        "addi   {len}, {len}, -1",
+        "j      1b",
+        "2:",
+        "csrw   mtvec, {mtvec}",
+        ".option pop",
+        mtvec = inout(reg) access_fault as usize => _,
+        src = inout(reg) src => _,
+        dst = inout(reg) dst => _,
+        len = inout(reg) len => _,
+        mprv = in(reg) mprv,
+        byte = out(reg) _,
+        out("t0") cause,
+        out("t1") tval,
+        options(nostack)
+    );
+    (cause, tval)
+}
+
+/// Copy `len` bytes from `src` to `dst`, written with `mprv` set in `mstatus`.
+///
+/// Returns the cause and value of the fault that stopped the copy, zero if none.
+unsafe fn copy_out(src: *const u8, dst: usize, len: usize, mprv: usize) -> (usize, usize) {
+    let (cause, tval): (usize, usize);
+    asm!(
+        ".option push",
+        ".option norvc",
+        "csrrw  {mtvec}, mtvec, {mtvec}",
+        "li     t0, 0",
+        "li     t1, 0",
+        "1:",
+        "beqz   {len}, 2f",
+        "lbu    {byte}, 0({src})",
+        "csrs   mstatus, {mprv}",
+        "sb     {byte}, 0({dst})",
+        "csrc   mstatus, {mprv}",
+        "bnez   t0, 2f",
+        "addi   {src}, {src}, 1",
+        "addi   {dst}, {dst}, 1",
+        "addi   {len}, {len}, -1",
+        "j      1b",
+        "2:",
+        "csrw   mtvec, {mtvec}",
+        ".option pop",
+        mtvec = inout(reg) access_fault as usize => _,
+        src = inout(reg) src => _,
+        dst = inout(reg) dst => _,
+        len = inout(reg) len => _,
+        mprv = in(reg) mprv,
+        byte = out(reg) _,
+        out("t0") cause,
+        out("t1") tval,
+        options(nostack)
+    );
+    (cause, tval)
+}
@@ -1,8 +1,9 @@
 use crate::sbi::extensions::HartFeatures;
 use crate::sbi::hsm::HsmCell;
 use crate::sbi::rfence::RFenceCell;
+#[cfg(feature = "timer-trace")]
+use crate::sbi::timer_trace::TimerTrace;
 use core::ptr::NonNull;
-use core::sync::atomic::AtomicU8;
 use fast_trap::FlowContext;
 use riscv::register::mstatus;
 
@@ -14,10 +15,11 @@ pub(crate) struct HartContext {
     pub hsm: HsmCell<NextStage>,
     /// Remote fence synchronization cell.
     pub rfence: RFenceCell,
-    /// Type of inter-processor interrupt pending.
-    pub ipi_type: AtomicU8,
     /// Supported hart features.
     pub features: HartFeatures,
+    /// Recent `set_timer` calls of this hart.
+    #[cfg(feature = "timer-trace")]
+    pub timer_trace: crate::sync::Mutex<TimerTrace>,
 }
 
 impl HartContext {
@@ -26,6 +28,10 @@ impl HartContext {
     pub fn init(&mut self) {
         self.hsm = HsmCell::new();
         self.rfence = RFenceCell::new();
+        #[cfg(feature = "timer-trace")]
+        {
+            self.timer_trace = crate::sync::Mutex::named("timer trace", TimerTrace::new());
+        }
     }
 
     /// Get a non-null pointer to the trap context.
@@ -36,11 +42,14 @@ impl HartContext {
 }
 
 /// Information needed to boot into the next execution stage.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct NextStage {
     /// Starting address to jump to.
     pub start_addr: usize,
-    /// Opaque value passed to next stage.
+    /// Opaque value passed to next stage, in `a1` with the hart ID in `a0`.
+    ///
+    /// Both are written last on entry, after whatever the hart sets up for
+    /// itself, so nothing run on the way can clobber them.
     pub opaque: usize,
     /// Privilege mode for next stage.
     pub next_mode: mstatus::MPP,
@@ -0,0 +1,74 @@
+//! Initialization state of each hart.
+//!
+//! Every hart moves through these states once per boot, in order. A hart
+//! acting on another one checks how far it got first: the hart context sits
+//! in uninitialized memory until its hart constructs it, and a hart clears
+//! its pending IPIs before it starts handling them. The states are kept in
+//! bss, so they start over at `Uninit` whenever the firmware is entered.
+//!
+//! A hart the boot hart gave up waiting for, or one quarantined after
+//! repeated faults, is marked failed. It is never started again, even if it
+//! shows up later.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Initialization steps of a hart.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InitState {
+    /// Nothing of the hart may be touched.
+    Uninit = 0,
+    /// The hart context and HSM cell are constructed.
+    StacksReady = 1,
+    /// The hart observed the devices published by the boot hart.
+    DevicesReady = 2,
+    /// Traps, delegation and pending IPIs are set up; the hart handles SBI
+    /// events from now on.
+    SbiReady = 3,
+}
+
+percpu! {
+    /// Initialization state of each hart.
+    static STATE: AtomicU8 = AtomicU8::new(InitState::Uninit as u8);
+}
+
+percpu! {
+    /// Whether each hart has been given up on.
+    static FAILED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Move the current hart to `state`, publishing everything it set up before.
+pub fn advance(state: InitState) {
+    let old = STATE.local().swap(state as u8, Ordering::Release);
+    debug_assert!(old < state as u8, "hart init state going backwards");
+}
+
+/// Raw state of `hart_id`, `Uninit` for harts that do not exist.
+pub fn state(hart_id: usize) -> u8 {
+    STATE.get(hart_id).map_or(InitState::Uninit as u8, |state| {
+        state.load(Ordering::Acquire)
+    })
+}
+
+/// Returns true if `hart_id` exists and got at least to `state`.
+#[inline]
+pub fn reached(hart_id: usize, state: InitState) -> bool {
+    STATE
+        .get(hart_id)
+        .is_some_and(|current| current.load(Ordering::Acquire) >= state as u8)
+}
+
+/// Give up on `hart_id`, it will not be started.
+pub fn mark_failed(hart_id: usize) {
+    if let Some(failed) = FAILED.get(hart_id) {
+        failed.store(true, Ordering::Release);
+    }
+}
+
+/// Returns true if `hart_id` has been given up on.
+#[inline]
+pub fn failed(hart_id: usize) -> bool {
+    FAILED
+        .get(hart_id)
+        .is_some_and(|failed| failed.load(Ordering::Acquire))
+}
@@ -0,0 +1,37 @@
+//! Helpers for walking SBI hart masks.
+//!
+//! The walk itself is in the common crate, where it is tested on the host.
+
+pub use prototyper_common::hart_mask::HartIds;
+use rustsbi::HartMask;
+
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+// `from_hart_ids` puts every hart in one mask word.
+const _: () = assert!(NUM_HART_MAX <= usize::BITS as usize);
+
+/// Walk the harts addressed by `hart_mask`.
+///
+/// With `hart_mask_base` of -1 every hart slot the firmware has is yielded;
+/// callers are expected to skip harts the platform does not enable.
+pub fn hart_ids(hart_mask: HartMask) -> HartIds {
+    let (mask, base) = hart_mask.into_inner();
+    HartIds::new(mask, base, NUM_HART_MAX)
+}
+
+/// Returns true if `hart_mask` uses the "every hart" encoding.
+#[inline]
+pub fn is_all_harts(hart_mask: HartMask) -> bool {
+    hart_mask.into_inner().1 == usize::MAX
+}
+
+/// Build a hart mask addressing the harts yielded by `ids`.
+///
+/// IDs must lie in `0..NUM_HART_MAX`, which fits in a single mask word.
+pub fn from_hart_ids(ids: impl Iterator<Item = usize>) -> HartMask {
+    let mut mask = 0;
+    for hart_id in ids.filter(|&id| id < NUM_HART_MAX) {
+        mask |= 1 << hart_id;
+    }
+    HartMask::from_mask_base(mask, 0)
+}
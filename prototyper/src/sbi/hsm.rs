@@ -1,21 +1,73 @@
+//! Hart state management.
+//!
+//! Every hart owns an `HsmCell` whose status only moves along the edges below,
+//! and every edge is taken with a single compare-exchange so concurrent callers
+//! on different harts can never observe or produce any other state:
+//!
+//! ```text
+//!             remote start                    publish data
+//!   STOPPED ---------------> START_PENDING_EXT ------------> START_PENDING
+//!      ^                      (internal only)                      |
+//!      | local stop                                    local start |
+//!      |                                                           v
+//!      +-------------------------------------------------------- STARTED
+//!                                                              |     ^
+//!                                                local suspend |     | local resume
+//!                                                              v     |
+//!                                                             SUSPENDED
+//! ```
+//!
+//! `START_PENDING_EXT` marks the window in which the starting hart's
+//! `NextStage` is being written; it is reported as `START_PENDING`.
+//!
+//! A hart taken out of service moves from any state to `QUARANTINED`, which it
+//! never leaves. It is internal only as well and reported as `STOPPED`. A hart
+//! still `START_PENDING` when its start deadline passed is given up on this
+//! way. Stopping needs no deadline, the stopping hart moves itself to
+//! `STOPPED` before `hart_stop` returns.
+
 use core::{
     cell::UnsafeCell,
-    hint::spin_loop,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 use riscv::register::mstatus::MPP;
 use rustsbi::{spec::hsm::hart_state, SbiRet};
 
-use crate::platform::PLATFORM;
+use crate::platform::{self, PLATFORM};
 use crate::riscv_spec::current_hartid;
+use crate::sbi::domain;
 use crate::sbi::hart_context::NextStage;
-use crate::sbi::trap_stack::ROOT_STACK;
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::idle_states;
+use crate::sbi::ipi;
+use crate::sbi::quarantine;
+use crate::sbi::susp::SuspendDevice;
+use crate::sbi::trap_stack::{NUM_HART_MAX, ROOT_STACK};
+use crate::sync::{Backoff, Mutex};
+use crate::time::Deadline;
 
 /// Special state indicating a hart is in the process of starting.
 const HART_STATE_START_PENDING_EXT: usize = usize::MAX;
+/// Special state of a hart that stopped handling events for good.
+const HART_STATE_QUARANTINED: usize = usize::MAX - 1;
+
+/// Time a hart asked to start has to take its start parameters.
+const HART_START_TIMEOUT_US: u64 = 1_000_000;
 
 type HsmState = AtomicUsize;
 
+percpu! {
+    /// When each hart asked to start must have started by.
+    static START_DEADLINE: Mutex<Option<Deadline>> = Mutex::named("hsm start deadline", None);
+}
+
+/// Returns true if `state`, as returned by a failed transition, is the one of
+/// a quarantined hart.
+#[inline]
+pub(crate) fn is_quarantined(state: usize) -> bool {
+    state == HART_STATE_QUARANTINED
+}
+
 /// Cell for managing hart state and shared data between harts.
 pub(crate) struct HsmCell<T> {
     status: HsmState,
@@ -23,6 +75,16 @@ pub(crate) struct HsmCell<T> {
 }
 
 impl<T> HsmCell<T> {
+    /// Moves the cell from `from` to `to` if it is currently in `from`.
+    ///
+    /// Returns the observed state on failure.
+    #[inline]
+    fn transition(&self, from: usize, to: usize) -> Result<(), usize> {
+        self.status
+            .compare_exchange(from, to, Ordering::AcqRel, Ordering::Acquire)
+            .map(|_| ())
+    }
+
     /// Creates a new HsmCell with STOPPED state and no inner data.
     pub const fn new() -> Self {
         Self {
@@ -64,6 +126,7 @@ impl<T> LocalHsmCell<'_, T> {
     /// Returns inner data if successful, otherwise returns current state.
     #[inline]
     pub fn start(&self) -> Result<T, usize> {
+        let mut backoff = Backoff::new();
         loop {
             match self.0.status.compare_exchange(
                 hart_state::START_PENDING,
@@ -72,33 +135,49 @@ impl<T> LocalHsmCell<'_, T> {
                 Ordering::Relaxed,
             ) {
                 Ok(_) => break Ok(unsafe { (*self.0.inner.get()).take().unwrap() }),
-                Err(HART_STATE_START_PENDING_EXT) => spin_loop(),
+                // The starter is still writing the start parameters. States
+                // fit the low half of the word, which comes first in memory.
+                Err(HART_STATE_START_PENDING_EXT) => backoff.wait_while(
+                    self.0.status.as_ptr() as *const u32,
+                    HART_STATE_START_PENDING_EXT as u32,
+                ),
                 Err(s) => break Err(s),
             }
         }
     }
 
-    /// Transitions hart to STOPPED state.
-    #[allow(unused)]
+    /// Transitions hart from STARTED to STOPPED state.
+    ///
+    /// Returns the current state if the hart is not STARTED.
     #[inline]
-    pub fn stop(&self) {
-        self.0.status.store(hart_state::STOPPED, Ordering::Release)
+    pub fn stop(&self) -> Result<(), usize> {
+        self.0.transition(hart_state::STARTED, hart_state::STOPPED)
     }
 
-    /// Transitions hart to SUSPENDED state.
-    #[allow(unused)]
+    /// Transitions hart from STARTED to SUSPENDED state.
+    ///
+    /// Returns the current state if the hart is not STARTED.
     #[inline]
-    pub fn suspend(&self) {
+    pub fn suspend(&self) -> Result<(), usize> {
         self.0
-            .status
-            .store(hart_state::SUSPENDED, Ordering::Relaxed)
+            .transition(hart_state::STARTED, hart_state::SUSPENDED)
     }
 
-    /// Transitions hart to STARTED state.
-    #[allow(unused)]
+    /// Transitions hart from SUSPENDED back to STARTED state.
+    ///
+    /// Returns the current state if the hart is not SUSPENDED.
     #[inline]
-    pub fn resume(&self) {
-        self.0.status.store(hart_state::STARTED, Ordering::Relaxed)
+    pub fn resume(&self) -> Result<(), usize> {
+        self.0
+            .transition(hart_state::SUSPENDED, hart_state::STARTED)
+    }
+
+    /// Moves the hart to QUARANTINED, whatever state it was in.
+    #[inline]
+    pub fn quarantine(&self) {
+        self.0
+            .status
+            .store(HART_STATE_QUARANTINED, Ordering::Release);
     }
 }
 
@@ -110,13 +189,7 @@ impl<T: core::fmt::Debug> RemoteHsmCell<'_, T> {
     pub fn start(&self, t: T) -> bool {
         if self
             .0
-            .status
-            .compare_exchange(
-                hart_state::STOPPED,
-                HART_STATE_START_PENDING_EXT,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            )
+            .transition(hart_state::STOPPED, HART_STATE_START_PENDING_EXT)
             .is_ok()
         {
             unsafe { *self.0.inner.get() = Some(t) };
@@ -129,12 +202,31 @@ impl<T: core::fmt::Debug> RemoteHsmCell<'_, T> {
         }
     }
 
+    /// Moves the hart from START_PENDING to QUARANTINED.
+    ///
+    /// Returns false if the hart took its start parameters first.
+    #[inline]
+    pub fn give_up_start(&self) -> bool {
+        self.0
+            .transition(hart_state::START_PENDING, HART_STATE_QUARANTINED)
+            .is_ok()
+    }
+
+    /// Moves the hart to QUARANTINED, whatever state it was in.
+    #[inline]
+    pub fn quarantine(&self) {
+        self.0
+            .status
+            .store(HART_STATE_QUARANTINED, Ordering::Release);
+    }
+
     /// Gets the current state of the hart.
     #[allow(unused)]
     #[inline]
     pub fn sbi_get_status(&self) -> usize {
-        match self.0.status.load(Ordering::Relaxed) {
+        match self.0.status.load(Ordering::Acquire) {
             HART_STATE_START_PENDING_EXT => hart_state::START_PENDING,
+            HART_STATE_QUARANTINED => hart_state::STOPPED,
             normal => normal,
         }
     }
@@ -144,7 +236,7 @@ impl<T: core::fmt::Debug> RemoteHsmCell<'_, T> {
     #[inline]
     pub fn allow_ipi(&self) -> bool {
         matches!(
-            self.0.status.load(Ordering::Relaxed),
+            self.0.status.load(Ordering::Acquire),
             hart_state::STARTED | hart_state::SUSPENDED
         )
     }
@@ -173,8 +265,14 @@ pub(crate) fn local_remote_hsm() -> RemoteHsmCell<'static, NextStage> {
 }
 
 /// Gets a remote view of any hart's HSM cell.
+///
+/// Returns `None` for harts that don't exist or haven't constructed their
+/// hart context yet.
 #[allow(unused)]
 pub(crate) fn remote_hsm(hart_id: usize) -> Option<RemoteHsmCell<'static, NextStage>> {
+    if !hart_init::reached(hart_id, InitState::StacksReady) {
+        return None;
+    }
     unsafe {
         ROOT_STACK
             .get_mut(hart_id)
@@ -182,27 +280,92 @@ pub(crate) fn remote_hsm(hart_id: usize) -> Option<RemoteHsmCell<'static, NextSt
     }
 }
 
+/// Give up on `hart_id` if it was asked to start and its deadline passed.
+fn watch_start(hart_id: usize, remote: &RemoteHsmCell<'_, NextStage>) {
+    let Some(deadline) = START_DEADLINE.get(hart_id) else {
+        return;
+    };
+    let mut deadline = deadline.lock();
+    if !deadline.as_ref().is_some_and(Deadline::expired) {
+        return;
+    }
+    *deadline = None;
+    if remote.give_up_start() {
+        quarantine::enter_remote(hart_id, "it did not start in time");
+    }
+}
+
+/// Check that a supervisor entry address is executable memory outside the firmware.
+///
+/// Without a known memory range only the firmware is ruled out.
+pub(crate) fn check_entry_address(addr: usize) -> Result<(), SbiRet> {
+    static UNKNOWN_MEMORY_REPORTED: AtomicBool = AtomicBool::new(false);
+    let align = if riscv::register::misa::read().is_some_and(|misa| misa.has_extension('C')) {
+        2
+    } else {
+        4
+    };
+    if addr % align != 0 {
+        return Err(SbiRet::invalid_address());
+    }
+    let in_memory = match platform::memory_range() {
+        Some(memory_range) => memory_range.contains(&addr),
+        None => {
+            if !UNKNOWN_MEMORY_REPORTED.swap(true, Ordering::Relaxed) {
+                warn!("Memory range unknown, entry addresses are not checked against it");
+            }
+            true
+        }
+    };
+    if !in_memory || crate::firmware::private_range().contains(&addr) {
+        return Err(SbiRet::invalid_address());
+    }
+    Ok(())
+}
+
 /// Implementation of SBI HSM (Hart State Management) extension.
 pub(crate) struct SbiHsm;
 
 impl rustsbi::Hsm for SbiHsm {
     /// Starts execution on a stopped hart.
     fn hart_start(&self, hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+        // Harts of other domains run other supervisors.
+        if !domain::may_control(hartid) {
+            return SbiRet::invalid_param();
+        }
+        if let Err(err) = check_entry_address(start_addr) {
+            return err;
+        }
+        if hart_init::failed(hartid) {
+            return SbiRet::failed();
+        }
+        // Nothing could wake the hart up.
+        let Some(ipi) = platform::ipi() else {
+            return SbiRet::not_supported();
+        };
         match remote_hsm(hartid) {
             Some(remote) => {
+                watch_start(hartid, &remote);
+                // Held across the start, so the deadline of the previous
+                // start is never checked against this one.
+                let mut deadline = START_DEADLINE.get(hartid).unwrap().lock();
                 if remote.start(NextStage {
                     start_addr,
                     opaque,
                     next_mode: MPP::Supervisor,
                 }) {
-                    unsafe {
-                        PLATFORM.sbi.ipi.as_ref().unwrap().set_msip(hartid);
-                    }
+                    *deadline = Some(Deadline::after_us(HART_START_TIMEOUT_US));
+                    drop(deadline);
+                    ipi.set_msip(hartid);
                     SbiRet::success(0)
+                } else if hart_init::failed(hartid) {
+                    SbiRet::failed()
                 } else {
                     SbiRet::already_available()
                 }
             }
+            // The hart never reached the firmware, or is still setting up.
+            None if hartid < NUM_HART_MAX => SbiRet::failed(),
             None => SbiRet::invalid_param(),
         }
     }
@@ -210,11 +373,14 @@ impl rustsbi::Hsm for SbiHsm {
     /// Stops execution on the current hart.
     #[inline]
     fn hart_stop(&self) -> SbiRet {
-        local_hsm().stop();
+        if local_hsm().stop().is_err() {
+            return SbiRet::failed();
+        }
+        // The trap handler parks this hart until the next `hart_start`,
+        // which is signalled through a machine software interrupt.
         unsafe {
-            riscv::register::mie::clear_msoft();
+            riscv::register::mie::set_msoft();
         }
-        riscv::asm::wfi();
         SbiRet::success(0)
     }
 
@@ -222,33 +388,53 @@ impl rustsbi::Hsm for SbiHsm {
     #[inline]
     fn hart_get_status(&self, hartid: usize) -> SbiRet {
         match remote_hsm(hartid) {
-            Some(remote) => SbiRet::success(remote.sbi_get_status()),
+            Some(remote) => {
+                watch_start(hartid, &remote);
+                SbiRet::success(remote.sbi_get_status())
+            }
+            // Not running supervisor code, and not startable yet.
+            None if hartid < NUM_HART_MAX => SbiRet::success(hart_state::STOPPED),
             None => SbiRet::invalid_param(),
         }
     }
 
     /// Suspends execution on the current hart.
-    fn hart_suspend(&self, suspend_type: u32, _resume_addr: usize, _opaque: usize) -> SbiRet {
-        use rustsbi::spec::hsm::suspend_type::{NON_RETENTIVE, RETENTIVE};
-        if matches!(suspend_type, NON_RETENTIVE | RETENTIVE) {
-            unsafe {
-                PLATFORM
-                    .sbi
-                    .ipi
-                    .as_ref()
-                    .unwrap()
-                    .clear_msip(current_hartid());
-            }
-            unsafe {
-                riscv::register::mie::set_msoft();
+    ///
+    /// Besides the default types, platform specific types declared as idle
+    /// states in the device tree are accepted.
+    fn hart_suspend(&self, suspend_type: u32, resume_addr: usize, _opaque: usize) -> SbiRet {
+        let Ok(idle_state) = idle_states::lookup(suspend_type) else {
+            return SbiRet::invalid_param();
+        };
+        let non_retentive = idle_states::non_retentive(suspend_type);
+        if non_retentive {
+            if let Err(err) = check_entry_address(resume_addr) {
+                return err;
             }
-            local_hsm().suspend();
-            riscv::asm::wfi();
-            crate::trap::msoft_ipi_handler();
-            local_hsm().resume();
-            SbiRet::success(0)
-        } else {
-            SbiRet::not_supported()
         }
+        if local_hsm().suspend().is_err() {
+            return SbiRet::failed();
+        }
+        if let Some(ipi) = platform::ipi() {
+            ipi.clear_msip(current_hartid());
+        }
+        unsafe {
+            riscv::register::mie::set_msoft();
+        }
+        let suspend = idle_state.and_then(|state| {
+            let suspend = unsafe { PLATFORM.suspend.as_ref() }?;
+            suspend.lock().hart_idle_enter(&state);
+            Some((suspend, state))
+        });
+        crate::sbi::trap_stack::check_canary();
+        riscv::asm::wfi();
+        if let Some((suspend, state)) = suspend {
+            suspend.lock().hart_idle_exit(&state);
+        }
+        ipi::restore_local();
+        crate::trap::msoft_ipi_handler();
+        // Only this hart leaves SUSPENDED, so resuming cannot race with anyone.
+        let _ = local_hsm().resume();
+        SbiRet::success(0)
     }
 }
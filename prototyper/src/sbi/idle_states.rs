@@ -0,0 +1,87 @@
+//! Platform specific HSM suspend types.
+//!
+//! Idle states are taken from `riscv,idle-state` nodes of the device tree,
+//! usually under `/cpus/idle-states`, each naming its suspend type in
+//! `riscv,sbi-suspend-param`. The default retentive and non-retentive types
+//! are always accepted; a platform specific type only if a node declares it,
+//! and the platform suspend device is then told which state a hart enters.
+//! Bit 31 of the type tells non-retentive states from retentive ones.
+
+use crate::platform::PLATFORM;
+
+/// Compatible string of an idle state node.
+pub(crate) const IDLE_STATE_COMPATIBLE: [&str; 1] = ["riscv,idle-state"];
+
+/// Number of idle states kept from the device tree.
+pub const MAX_IDLE_STATES: usize = 8;
+
+/// Non-retentive suspend types have bit 31 set.
+const NON_RETENTIVE_BIT: u32 = 1 << 31;
+/// First platform specific type of either kind, with bit 31 clear.
+const PLATFORM_SPECIFIC_FIRST: u32 = 0x1000_0000;
+
+/// An idle state the platform declares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdleState {
+    /// HSM suspend type entering the state.
+    pub suspend_type: u32,
+    /// Worst case time to enter the state.
+    pub entry_latency_us: u32,
+    /// Worst case time to leave the state.
+    pub exit_latency_us: u32,
+    /// Time the state must last to save power.
+    pub min_residency_us: u32,
+    /// Whether the hart timer stops in the state.
+    pub local_timer_stop: bool,
+}
+
+impl IdleState {
+    /// Whether the state loses hart state, resuming at the resume address.
+    #[inline]
+    pub const fn non_retentive(&self) -> bool {
+        non_retentive(self.suspend_type)
+    }
+}
+
+/// Whether `suspend_type` is non-retentive.
+#[inline]
+pub const fn non_retentive(suspend_type: u32) -> bool {
+    suspend_type & NON_RETENTIVE_BIT != 0
+}
+
+/// Whether `suspend_type` is in a platform specific range.
+#[inline]
+const fn platform_specific(suspend_type: u32) -> bool {
+    suspend_type & !NON_RETENTIVE_BIT >= PLATFORM_SPECIFIC_FIRST
+}
+
+/// Why a suspend type is refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuspendTypeError {
+    /// Reserved by the specification.
+    Reserved,
+    /// Platform specific, but the platform declares no such state.
+    Undeclared,
+}
+
+/// Check `suspend_type` of a HSM suspend call.
+///
+/// Returns the declared idle state of the type, `None` for a default type
+/// the device tree does not describe.
+pub fn lookup(suspend_type: u32) -> Result<Option<IdleState>, SuspendTypeError> {
+    let declared = unsafe { PLATFORM.info.idle_states.iter() }
+        .flatten()
+        .find(|state| state.suspend_type == suspend_type);
+    match declared {
+        Some(state) => Ok(Some(*state)),
+        None if suspend_type & !NON_RETENTIVE_BIT == 0 => Ok(None),
+        None if platform_specific(suspend_type) => Err(SuspendTypeError::Undeclared),
+        None => Err(SuspendTypeError::Reserved),
+    }
+}
+
+/// Whether a device tree may declare `suspend_type`: a default or platform specific type.
+#[inline]
+pub const fn valid(suspend_type: u32) -> bool {
+    suspend_type & !NON_RETENTIVE_BIT == 0 || platform_specific(suspend_type)
+}
@@ -0,0 +1,95 @@
+//! Supervisor interrupt injection.
+//!
+//! M-mode raises supervisor software and timer interrupts on behalf of the
+//! IPI and timer extensions. Where the pending bit lives depends on the hart:
+//! with Smaia and `mvien.SSIP` set, the supervisor sees the software
+//! interrupt through `mvip` rather than `mip`; with Sstc enabled, STIP
+//! follows `stimecmp` and cannot be written at all. Firmware code should
+//! inject supervisor interrupts only through this module.
+//!
+//! Harts with Smaia get `mvien.SSIP` set and `mideleg.SSIP` cleared. Only
+//! with both does `sip.SSIP` alias `mvip.SSIP`; with the interrupt
+//! delegated, S-mode would keep reading `mip.SSIP` and never see what M-mode
+//! raises in `mvip`. S-mode then owns its software interrupt bit in `mvip`
+//! and takes the interrupt as a virtual one.
+
+use core::arch::asm;
+
+use riscv::register::mip;
+
+use crate::riscv_spec::{aia, current_hartid, stimecmp};
+use crate::sbi::extensions::{
+    hart_extension_probe, hart_privileged_version, Extension, PrivilegedVersion,
+};
+
+/// Whether the current hart runs the supervisor timer on `stimecmp`.
+///
+/// Matches the hart setup, which sets `menvcfg.STCE` on such harts.
+#[inline]
+pub fn sstc_enabled() -> bool {
+    let hart_id = current_hartid();
+    hart_extension_probe(hart_id, Extension::Sstc)
+        && hart_privileged_version(hart_id) >= PrivilegedVersion::Version1_12
+}
+
+/// Whether the supervisor software interrupt is taken from `mvip`.
+#[inline]
+fn ssip_virtualized() -> bool {
+    hart_extension_probe(current_hartid(), Extension::Smaia) && aia::read_mvien() & aia::SSIP != 0
+}
+
+/// Hand the supervisor software interrupt to S-mode through `mvip` on Smaia harts.
+///
+/// Must run after `mideleg` is written.
+pub fn init() {
+    if !hart_extension_probe(current_hartid(), Extension::Smaia) {
+        return;
+    }
+    aia::set_mvien_bits(aia::SSIP);
+    // Undelegate SSIP, or `sip.SSIP` keeps aliasing `mip.SSIP`.
+    unsafe { asm!("csrc mideleg, {}", in(reg) aia::SSIP) };
+    // Start without stale software interrupts from a previous stage.
+    aia::clear_mvip_bits(aia::SSIP);
+    unsafe { mip::clear_ssoft() };
+}
+
+/// Make a supervisor software interrupt pending on the current hart.
+#[inline]
+pub fn raise_software() {
+    if ssip_virtualized() {
+        aia::set_mvip_bits(aia::SSIP);
+    } else {
+        unsafe { mip::set_ssoft() };
+    }
+}
+
+/// Withdraw a pending supervisor software interrupt of the current hart.
+#[inline]
+pub fn clear_software() {
+    if ssip_virtualized() {
+        aia::clear_mvip_bits(aia::SSIP);
+    } else {
+        unsafe { mip::clear_ssoft() };
+    }
+}
+
+/// Make a supervisor timer interrupt pending on the current hart.
+///
+/// Only meaningful while the supervisor timer is emulated on mtimecmp.
+#[inline]
+pub fn raise_timer() {
+    debug_assert!(!sstc_enabled(), "STIP follows stimecmp with Sstc");
+    unsafe { mip::set_stimer() };
+}
+
+/// Withdraw a pending supervisor timer interrupt of the current hart.
+///
+/// With Sstc the interrupt is withdrawn by moving `stimecmp` out of reach.
+#[inline]
+pub fn clear_timer() {
+    if sstc_enabled() {
+        stimecmp::set(u64::MAX);
+    } else {
+        unsafe { mip::clear_stimer() };
+    }
+}
@@ -0,0 +1,21 @@
+//! Instruction fetch for the trap reports.
+//!
+//! The decoder lives in the common crate, where it is tested on the host.
+
+pub use prototyper_common::insn::*;
+
+use crate::firmware;
+
+/// The firmware instruction at `pc`, if `pc` lies in the firmware image.
+pub fn fetch_firmware(pc: usize) -> Option<Insn> {
+    let range = firmware::firmware_range();
+    if pc % 2 != 0 || !range.contains(&pc) {
+        return None;
+    }
+    let low = unsafe { (pc as *const u16).read_volatile() };
+    if length(low) == 2 || !range.contains(&(pc + 2)) {
+        return Some(Insn::new(low as u32));
+    }
+    let high = unsafe { ((pc + 2) as *const u16).read_volatile() };
+    Some(Insn::new((high as u32) << 16 | low as u32))
+}
@@ -1,18 +1,57 @@
-use crate::platform::PLATFORM;
+use crate::platform::{self, PLATFORM};
 use crate::riscv_spec::{current_hartid, stimecmp};
-use crate::sbi::extensions::{hart_extension_probe, Extension};
+use crate::sbi::debug;
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::hart_mask;
 use crate::sbi::hsm::remote_hsm;
+use crate::sbi::inject;
+use crate::sbi::quarantine;
 use crate::sbi::rfence;
+use crate::sbi::timer;
 use crate::sbi::trap;
-use crate::sbi::trap_stack::ROOT_STACK;
-use core::sync::atomic::Ordering::Relaxed;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+use crate::sync::Backoff;
+use crate::sync::Mutex;
+use crate::time;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering::{AcqRel, Acquire};
 use rustsbi::{HartMask, SbiRet};
-use spin::Mutex;
+
+/// Time after which a hart waiting for remote fences gives up on the targets
+/// that did not take them.
+const RFENCE_TIMEOUT_US: u64 = 1_000_000;
+
+percpu! {
+    /// Type of inter-processor interrupt pending on each hart.
+    static IPI_TYPE: AtomicU8 = AtomicU8::new(0);
+}
+
+/// Last mtimecmp value and msip state written for a hart.
+struct Shadow {
+    mtimecmp: u64,
+    msip: bool,
+}
+
+percpu! {
+    /// Interrupt controller state of each hart as the firmware programmed it,
+    /// written back after low-power states that may reset the CLINT.
+    static SHADOW: Mutex<Shadow> = Mutex::named(
+        "ipi shadow",
+        Shadow {
+            mtimecmp: u64::MAX,
+            msip: false,
+        },
+    );
+}
 
 /// IPI type for supervisor software interrupt.
 pub(crate) const IPI_TYPE_SSOFT: u8 = 1 << 0;
 /// IPI type for memory fence operations.
 pub(crate) const IPI_TYPE_FENCE: u8 = 1 << 1;
+/// IPI type for joining a firmware update.
+pub(crate) const IPI_TYPE_UPDATE: u8 = 1 << 2;
+/// IPI type for instruction fences, see `fence_i`.
+pub(crate) const IPI_TYPE_FENCE_I: u8 = 1 << 3;
 
 /// Trait defining interface for inter-processor interrupt device
 #[allow(unused)]
@@ -37,29 +76,23 @@ pub trait IpiDevice {
 pub struct SbiIpi<T: IpiDevice> {
     /// Reference to atomic pointer to IPI device.
     pub ipi_dev: Mutex<T>,
-    /// Maximum hart ID in the system
-    pub max_hart_id: usize,
 }
 
 impl<T: IpiDevice> rustsbi::Timer for SbiIpi<T> {
     /// Set timer value for current hart.
     #[inline]
     fn set_timer(&self, stime_value: u64) {
-        let hart_id = current_hartid();
-        let uses_sstc = hart_extension_probe(hart_id, Extension::Sstc);
+        let uses_sstc = inject::sstc_enabled();
+
+        #[cfg(feature = "timer-trace")]
+        crate::sbi::timer_trace::record(current_hartid(), stime_value, time::current_ticks());
 
         // Set timer value based on extension support.
+        // Without Sstc the deadline shares mtimecmp with firmware deadlines.
         if uses_sstc {
             stimecmp::set(stime_value);
         } else {
-            self.write_mtimecmp(hart_id, stime_value);
-            unsafe {
-                riscv::register::mip::clear_stimer();
-            }
-        }
-        // Enable machine timer interrupt.
-        unsafe {
-            riscv::register::mie::set_mtimer();
+            timer::set_supervisor(stime_value);
         }
     }
 }
@@ -68,40 +101,18 @@ impl<T: IpiDevice> rustsbi::Ipi for SbiIpi<T> {
     /// Send IPI to specified harts.
     #[inline]
     fn send_ipi(&self, hart_mask: rustsbi::HartMask) -> SbiRet {
-        let mut hart_mask = hart_mask;
-
-        for hart_id in 0..=self.max_hart_id {
-            if !hart_mask.has_bit(hart_id) {
-                continue;
-            }
-
-            // There are 2 situation to return invalid_param:
-            // 1. We can not get hsm, which usually means this hart_id is bigger than MAX_HART_ID.
-            // 2. BOARD hasn't init or this hart_id is not enabled by device tree.
-            // In the next loop, we'll assume that all of above situation will not happened and
-            // directly send ipi.
-            let Some(hsm) = remote_hsm(hart_id) else {
-                return SbiRet::invalid_param();
-            };
-
-            if unsafe {
-                PLATFORM
-                    .info
-                    .cpu_enabled
-                    .is_none_or(|list| list.get(hart_id).is_none_or(|res| !(*res)))
-            } {
-                return SbiRet::invalid_param();
-            }
-
-            if !hsm.allow_ipi() {
-                hart_mask = hart_mask_clear(hart_mask, hart_id);
-            }
-        }
-        for hart_id in 0..=self.max_hart_id {
-            if !hart_mask.has_bit(hart_id) {
+        let hart_mask = match prepare_hart_mask(hart_mask) {
+            Ok(hart_mask) => hart_mask,
+            Err(err) => return err,
+        };
+        let current_hart = current_hartid();
+        for hart_id in hart_mask::hart_ids(hart_mask) {
+            debug::count(debug::Event::IpiSent);
+            // No machine software interrupt is needed to reach ourselves.
+            if hart_id == current_hart {
+                inject::raise_software();
                 continue;
             }
-
             if set_ipi_type(hart_id, IPI_TYPE_SSOFT) == 0 {
                 self.set_msip(hart_id);
             }
@@ -114,11 +125,8 @@ impl<T: IpiDevice> rustsbi::Ipi for SbiIpi<T> {
 impl<T: IpiDevice> SbiIpi<T> {
     /// Create new SBI IPI instance.
     #[inline]
-    pub fn new(ipi_dev: Mutex<T>, max_hart_id: usize) -> Self {
-        Self {
-            ipi_dev,
-            max_hart_id,
-        }
+    pub fn new(ipi_dev: Mutex<T>) -> Self {
+        Self { ipi_dev }
     }
 
     /// Send IPI for remote fence operation.
@@ -128,42 +136,13 @@ impl<T: IpiDevice> SbiIpi<T> {
         ctx: rfence::RFenceContext,
     ) -> SbiRet {
         let current_hart = current_hartid();
-        let mut hart_mask = hart_mask;
-
-        for hart_id in 0..=self.max_hart_id {
-            if !hart_mask.has_bit(hart_id) {
-                continue;
-            }
-
-            // There are 2 situation to return invalid_param:
-            // 1. We can not get hsm, which usually means this hart_id is bigger than MAX_HART_ID.
-            // 2. BOARD hasn't init or this hart_id is not enabled by device tree.
-            // In the next loop, we'll assume that all of above situation will not happened and
-            // directly send ipi.
-            let Some(hsm) = remote_hsm(hart_id) else {
-                return SbiRet::invalid_param();
-            };
-
-            if unsafe {
-                PLATFORM
-                    .info
-                    .cpu_enabled
-                    .is_none_or(|list| list.get(hart_id).is_none_or(|res| !(*res)))
-            } {
-                return SbiRet::invalid_param();
-            }
-
-            if !hsm.allow_ipi() {
-                hart_mask = hart_mask_clear(hart_mask, hart_id);
-            }
-        }
+        let hart_mask = match prepare_hart_mask(hart_mask) {
+            Ok(hart_mask) => hart_mask,
+            Err(err) => return err,
+        };
 
         // Send fence operations to target harts
-        for hart_id in 0..=self.max_hart_id {
-            if !hart_mask.has_bit(hart_id) {
-                continue;
-            }
-
+        for hart_id in hart_mask::hart_ids(hart_mask) {
             if let Some(remote) = rfence::remote_rfence(hart_id) {
                 if let Some(local) = rfence::local_rfence() {
                     local.add();
@@ -178,42 +157,77 @@ impl<T: IpiDevice> SbiIpi<T> {
             }
         }
 
-        // Wait for all fence operations to complete
-        while !rfence::local_rfence().unwrap().is_sync() {
-            trap::rfence_single_handler();
+        // Wait for all fence operations to complete. Keep serving requests
+        // queued on this hart meanwhile, including our own when we are a
+        // target, so harts fencing each other at the same time both progress.
+        // Targets still holding requests of ours at the deadline are taken
+        // out of service, and their requests acknowledged on their behalf.
+        let deadline = time::Deadline::after_us(RFENCE_TIMEOUT_US);
+        let mut gave_up = false;
+        let mut backoff = Backoff::new();
+        loop {
+            trap::rfence_handler();
+            if rfence::local_rfence().unwrap().is_sync() {
+                break;
+            }
+            if !gave_up && deadline.expired() {
+                for hart_id in hart_mask::hart_ids(hart_mask).filter(|&id| id != current_hart) {
+                    if rfence::remote_rfence(hart_id).is_some_and(|remote| remote.withdraw()) {
+                        quarantine::enter_remote(
+                            hart_id,
+                            "it did not acknowledge remote fences in time",
+                        );
+                    }
+                }
+                gave_up = true;
+            }
+            // Acknowledge IPIs now rather than on the next trap, so a peer
+            // does not keep re-sending to a hart that already drained it.
+            if peek_ipi_type() != 0 {
+                trap::msoft_ipi_handler();
+            }
+            rfence::local_rfence().unwrap().wait(&mut backoff);
         }
 
         SbiRet::success(0)
     }
 
-    /// Get lower 32 bits of machine time.
+    /// Read machine time.
+    ///
+    /// Firmware code should use `crate::time` instead of calling this directly.
     #[inline]
-    pub fn get_time(&self) -> usize {
-        self.ipi_dev.lock().read_mtime() as usize
-    }
-
-    /// Get upper 32 bits of machine time.
-    #[inline]
-    pub fn get_timeh(&self) -> usize {
-        (self.ipi_dev.lock().read_mtime() >> 32) as usize
+    pub fn read_mtime(&self) -> u64 {
+        self.ipi_dev.lock().read_mtime()
     }
 
     /// Set machine software interrupt pending for hart.
     #[inline]
     pub fn set_msip(&self, hart_idx: usize) {
-        self.ipi_dev.lock().set_msip(hart_idx);
+        let ipi_dev = self.ipi_dev.lock();
+        if let Some(shadow) = SHADOW.get(hart_idx) {
+            shadow.lock().msip = true;
+        }
+        ipi_dev.set_msip(hart_idx);
     }
 
     /// Clear machine software interrupt pending for hart.
     #[inline]
     pub fn clear_msip(&self, hart_idx: usize) {
-        self.ipi_dev.lock().clear_msip(hart_idx);
+        let ipi_dev = self.ipi_dev.lock();
+        if let Some(shadow) = SHADOW.get(hart_idx) {
+            shadow.lock().msip = false;
+        }
+        ipi_dev.clear_msip(hart_idx);
     }
 
     /// Write machine timer compare value for hart.
     #[inline]
     pub fn write_mtimecmp(&self, hart_idx: usize, val: u64) {
-        self.ipi_dev.lock().write_mtimecmp(hart_idx, val);
+        let ipi_dev = self.ipi_dev.lock();
+        if let Some(shadow) = SHADOW.get(hart_idx) {
+            shadow.lock().mtimecmp = val;
+        }
+        ipi_dev.write_mtimecmp(hart_idx, val);
     }
 
     /// Clear all pending interrupts for current hart.
@@ -222,70 +236,118 @@ impl<T: IpiDevice> SbiIpi<T> {
         let hart_id = current_hartid();
         // Load ipi_dev once instead of twice
         let ipi_dev = self.ipi_dev.lock();
+        if let Some(shadow) = SHADOW.get(hart_id) {
+            *shadow.lock() = Shadow {
+                mtimecmp: u64::MAX,
+                msip: false,
+            };
+        }
         ipi_dev.clear_msip(hart_id);
         ipi_dev.write_mtimecmp(hart_id, u64::MAX);
     }
+
+    /// Write the shadowed mtimecmp and msip of `hart_idx` back to the device.
+    pub fn restore(&self, hart_idx: usize) {
+        let ipi_dev = self.ipi_dev.lock();
+        let Some(shadow) = SHADOW.get(hart_idx) else {
+            return;
+        };
+        let shadow = shadow.lock();
+        ipi_dev.write_mtimecmp(hart_idx, shadow.mtimecmp);
+        if shadow.msip {
+            ipi_dev.set_msip(hart_idx);
+        } else {
+            ipi_dev.clear_msip(hart_idx);
+        }
+    }
 }
 
 /// Set IPI type for specified hart.
+///
+/// The update must be visible before the receiver's msip is raised, and
+/// everything the sender wrote before (fence requests) must be visible once the
+/// receiver observes the type, hence AcqRel here and in `get_and_reset_ipi_type`.
+///
+/// Events for a hart that is not `SbiReady` yet are dropped: it clears its
+/// pending IPIs while setting up, and handles nothing before.
 pub fn set_ipi_type(hart_id: usize, event_id: u8) -> u8 {
-    unsafe {
-        ROOT_STACK
-            .get_unchecked_mut(hart_id)
-            .hart_context()
-            .ipi_type
-            .fetch_or(event_id, Relaxed)
+    match IPI_TYPE.get(hart_id) {
+        Some(ipi_type) if hart_init::reached(hart_id, InitState::SbiReady) => {
+            ipi_type.fetch_or(event_id, AcqRel)
+        }
+        _ => 0,
     }
 }
 
+/// Get IPI type pending for current hart without resetting it.
+#[inline]
+pub fn peek_ipi_type() -> u8 {
+    IPI_TYPE.local().load(Acquire)
+}
+
 /// Get and reset IPI type for current hart.
 pub fn get_and_reset_ipi_type() -> u8 {
-    unsafe {
-        ROOT_STACK
-            .get_unchecked_mut(current_hartid())
-            .hart_context()
-            .ipi_type
-            .swap(0, Relaxed)
-    }
+    IPI_TYPE.local().swap(0, AcqRel)
 }
 
 /// Clear machine software interrupt pending for current hart.
 #[inline]
 pub fn clear_msip() {
-    match unsafe { PLATFORM.sbi.ipi.as_ref() } {
+    match platform::ipi() {
         Some(ipi) => ipi.clear_msip(current_hartid()),
         None => error!("SBI or IPI device not initialized"),
     }
 }
 
-/// Clear machine timer interrupt for current hart.
+/// Restore mtimecmp and msip of the current hart after a low-power state.
+///
+/// Some SoCs reset the CLINT in deep idle states, which would lose the next
+/// timer deadline and any IPI sent while the hart was asleep.
 #[inline]
-pub fn clear_mtime() {
-    match unsafe { PLATFORM.sbi.ipi.as_ref() } {
-        Some(ipi) => ipi.write_mtimecmp(current_hartid(), u64::MAX),
-        None => error!("SBI or IPI device not initialized"),
+pub fn restore_local() {
+    if let Some(ipi) = platform::ipi() {
+        ipi.restore(current_hartid());
     }
 }
 
 /// Clear all pending interrupts for current hart.
 #[inline]
 pub fn clear_all() {
-    match unsafe { PLATFORM.sbi.ipi.as_ref() } {
+    match platform::ipi() {
         Some(ipi) => ipi.clear(),
         None => error!("SBI or IPI device not initialized"),
     }
 }
 
-pub fn hart_mask_clear(hart_mask: HartMask, hart_id: usize) -> HartMask {
-    let (mask, mask_base) = hart_mask.into_inner();
-    if mask_base == usize::MAX {
-        return HartMask::from_mask_base(mask & (!(1 << hart_id)), 0);
-    }
-    let Some(idx) = hart_id.checked_sub(mask_base) else {
-        return hart_mask;
-    };
-    if idx >= usize::BITS as usize {
-        return hart_mask;
+/// Returns true if `hart_id` exists and is enabled by the device tree.
+#[inline]
+fn hart_is_valid(hart_id: usize) -> bool {
+    hart_id < NUM_HART_MAX
+        && unsafe {
+            PLATFORM
+                .info
+                .cpu_enabled
+                .is_some_and(|list| list.get(hart_id).is_some_and(|res| *res))
+        }
+}
+
+/// Validate the harts addressed by `hart_mask` for an IPI or remote fence.
+///
+/// Returns `SBI_ERR_INVALID_PARAM` if any addressed hart does not exist or is
+/// not enabled by the device tree, as both the IPI and RFENCE extensions
+/// require. Valid harts that are not STARTED or SUSPENDED are dropped from the
+/// returned mask without error: they run no supervisor code that could observe
+/// the interrupt, and a hart that is started later enters the supervisor with
+/// clean TLB and instruction cache state. A `hart_mask_base` of -1 addresses
+/// every valid hart and never fails.
+pub fn prepare_hart_mask(hart_mask: HartMask) -> Result<HartMask, SbiRet> {
+    let all_harts = hart_mask::is_all_harts(hart_mask);
+    if !all_harts && !hart_mask::hart_ids(hart_mask).all(hart_is_valid) {
+        return Err(SbiRet::invalid_param());
     }
-    HartMask::from_mask_base(mask & (!(1 << hart_id)), mask_base)
+    Ok(hart_mask::from_hart_ids(
+        hart_mask::hart_ids(hart_mask)
+            .filter(|&id| hart_is_valid(id))
+            .filter(|&id| remote_hsm(id).is_some_and(|hsm| hsm.allow_ipi())),
+    ))
 }
@@ -0,0 +1,109 @@
+//! Machine external interrupts owned by the firmware.
+//!
+//! Firmware drivers register a handler for an interrupt source, which routes
+//! the source to the machine mode context of the registering hart. Machine
+//! external interrupts are then claimed, dispatched and completed here.
+
+use crate::sync::Mutex;
+use riscv::register::mie;
+
+use crate::platform::PLATFORM;
+use crate::riscv_spec::current_hartid;
+
+/// Handler of a firmware owned interrupt, called with the interrupt source.
+pub type IrqHandler = fn(irq: usize);
+
+/// Number of interrupt sources the firmware can own.
+pub const MAX_IRQ: usize = 128;
+
+/// Interrupt controller able to route sources to machine mode.
+pub trait IrqController {
+    fn set_priority(&self, irq: usize, priority: u32);
+    fn enable(&self, hart_id: usize, irq: usize);
+    fn disable(&self, hart_id: usize, irq: usize);
+    fn set_threshold(&self, hart_id: usize, threshold: u32);
+    /// Claim the highest priority pending source, if any.
+    fn claim(&self, hart_id: usize) -> Option<usize>;
+    fn complete(&self, hart_id: usize, irq: usize);
+}
+
+/// Errors returned when registering an interrupt handler.
+#[allow(unused)]
+#[derive(Debug)]
+pub enum IrqError {
+    /// The platform has no supported interrupt controller.
+    NoController,
+    /// The interrupt source is out of range.
+    InvalidIrq,
+    /// Another handler already owns the interrupt source.
+    Busy,
+}
+
+/// Firmware interrupt controller and its registered handlers.
+pub struct SbiIrq<T: IrqController> {
+    controller: T,
+    handlers: Mutex<[Option<IrqHandler>; MAX_IRQ]>,
+}
+
+impl<T: IrqController> SbiIrq<T> {
+    #[inline]
+    pub fn new(controller: T) -> Self {
+        Self {
+            controller,
+            handlers: Mutex::named("irq handlers", [None; MAX_IRQ]),
+        }
+    }
+
+    /// Register `handler` for `irq` and route it to the current hart.
+    pub fn register(&self, irq: usize, handler: IrqHandler) -> Result<(), IrqError> {
+        if irq == 0 || irq >= MAX_IRQ {
+            return Err(IrqError::InvalidIrq);
+        }
+        let hart_id = current_hartid();
+        let mut handlers = self.handlers.lock();
+        if handlers[irq].is_some() {
+            return Err(IrqError::Busy);
+        }
+        handlers[irq] = Some(handler);
+        self.controller.set_priority(irq, 1);
+        self.controller.set_threshold(hart_id, 0);
+        self.controller.enable(hart_id, irq);
+        unsafe { mie::set_mext() };
+        Ok(())
+    }
+
+    /// Claim, handle and complete every pending source of the current hart.
+    fn dispatch(&self) {
+        let hart_id = current_hartid();
+        while let Some(irq) = self.controller.claim(hart_id) {
+            let handler = self.handlers.lock().get(irq).copied().flatten();
+            match handler {
+                Some(handler) => handler(irq),
+                None => {
+                    // Nobody owns this source; mask it rather than take it again forever.
+                    warn!("Unhandled machine external interrupt {}, disabled", irq);
+                    self.controller.disable(hart_id, irq);
+                }
+            }
+            self.controller.complete(hart_id, irq);
+        }
+    }
+}
+
+/// Register a firmware handler for a machine external interrupt source.
+#[allow(unused)]
+pub fn register(irq: usize, handler: IrqHandler) -> Result<(), IrqError> {
+    match unsafe { PLATFORM.irq.as_ref() } {
+        Some(irq_chip) => irq_chip.register(irq, handler),
+        None => Err(IrqError::NoController),
+    }
+}
+
+/// Machine external interrupt entry, called from the trap vector.
+pub extern "C" fn mext_handler() {
+    match unsafe { PLATFORM.irq.as_ref() } {
+        Some(irq_chip) => irq_chip.dispatch(),
+        // Nothing can raise MEIP without a controller having enabled it.
+        None => unsafe { mie::clear_mext() },
+    }
+}
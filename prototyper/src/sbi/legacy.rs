@@ -0,0 +1,36 @@
+//! SBI v0.1 legacy extensions.
+//!
+//! rustsbi only dispatches v0.2+ extensions, so legacy calls are routed here
+//! when the `legacy-sbi` feature is enabled. Without it this module is left
+//! out, legacy calls return `SBI_ERR_NOT_SUPPORTED` in `a0` and probes report
+//! every legacy extension absent.
+
+use sbi_spec::legacy;
+
+use crate::platform::PLATFORM;
+use crate::sbi::console;
+
+/// Handle a legacy extension call.
+///
+/// Returns the value for `a0`, or `None` if `extension` is not a legacy
+/// extension this firmware implements.
+pub fn handle_ecall(extension: usize, param: [usize; 6]) -> Option<usize> {
+    if !probe(extension) {
+        return None;
+    }
+    match extension {
+        legacy::LEGACY_CONSOLE_PUTCHAR => Some(console::putchar(param[0])),
+        legacy::LEGACY_CONSOLE_GETCHAR => Some(console::getchar()),
+        _ => None,
+    }
+}
+
+/// Returns true if the legacy `extension` is available on this platform.
+pub fn probe(extension: usize) -> bool {
+    match extension {
+        legacy::LEGACY_CONSOLE_PUTCHAR | legacy::LEGACY_CONSOLE_GETCHAR => unsafe {
+            PLATFORM.have_console()
+        },
+        _ => false,
+    }
+}
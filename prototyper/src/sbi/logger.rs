@@ -1,7 +1,111 @@
+use core::fmt::{self, Write};
 use core::str::FromStr;
-use log::{Level, LevelFilter};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::LevelFilter;
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::console;
+use crate::sync::Mutex;
+use crate::time;
+
+/// Size of the RAM log, which keeps the most recent messages.
+const RAM_LOG_SIZE: usize = 4096;
+
+/// Every message logged so far, in a ring overwriting the oldest bytes.
+struct RamLog {
+    buf: [u8; RAM_LOG_SIZE],
+    /// Offset the next byte goes to.
+    head: usize,
+    /// Bytes stored, at most `RAM_LOG_SIZE`.
+    len: usize,
+}
+
+impl RamLog {
+    const fn new() -> Self {
+        Self {
+            buf: [0; RAM_LOG_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+}
+
+impl Write for RamLog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buf[self.head] = byte;
+            self.head = (self.head + 1) % RAM_LOG_SIZE;
+            self.len = (self.len + 1).min(RAM_LOG_SIZE);
+        }
+        Ok(())
+    }
+}
+
+/// What each log line starts with, chosen at build time with `PROTOTYPER_LOG_PREFIX`:
+///
+/// - `none`: nothing, as before multi-hart logs were prefixed.
+/// - `hart`: `[hartN]`, the hart that logged the record.
+/// - `time` (default): `[hartN][<ticks>]`, with the mtime value the record was
+///   logged at, so the boot logs of several harts can be put back in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogPrefix {
+    None,
+    Hart,
+    HartTime,
+}
+
+impl LogPrefix {
+    /// Returns the prefix selected at build time.
+    pub fn current() -> Self {
+        match option_env!("PROTOTYPER_LOG_PREFIX") {
+            Some("none") => LogPrefix::None,
+            Some("hart") => LogPrefix::Hart,
+            _ => LogPrefix::HartTime,
+        }
+    }
+}
+
+/// Prefix of a record logged by `hart_id` at `ticks`.
+struct Prefix {
+    kind: LogPrefix,
+    hart_id: usize,
+    ticks: u64,
+}
+
+impl Prefix {
+    fn now(kind: LogPrefix) -> Self {
+        Self {
+            kind,
+            hart_id: current_hartid(),
+            // Reading mtime is left out when the prefix does not show it.
+            ticks: match kind {
+                LogPrefix::HartTime => time::current_ticks(),
+                _ => 0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LogPrefix::None => Ok(()),
+            LogPrefix::Hart => write!(f, "[hart{}] ", self.hart_id),
+            LogPrefix::HartTime => write!(f, "[hart{}][{}] ", self.hart_id, self.ticks),
+        }
+    }
+}
+
+static RAM_LOG: Mutex<RamLog> = Mutex::named("ram log", RamLog::new());
+/// Most verbose level printed to the console, as a `LevelFilter`.
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+/// Whether the supervisor has taken over the console, leaving messages to the RAM log.
+static SUPERVISOR_OWNS_CONSOLE: AtomicBool = AtomicBool::new(false);
 
 /// Simple logger implementation for RustSBI that supports colored output.
+///
+/// Messages go to the RAM log and, up to the console level, to the console
+/// unless the supervisor has taken it over.
 pub struct Logger;
 
 impl Logger {
@@ -12,11 +116,122 @@ impl Logger {
             .and_then(|s| LevelFilter::from_str(s).ok())
             .unwrap_or(LevelFilter::Info);
 
+        CONSOLE_LEVEL.store(max_level as usize, Ordering::Relaxed);
         log::set_max_level(max_level);
         log::set_logger(&Logger)
     }
 }
 
+/// Most verbose level printed to the console.
+pub fn console_level() -> LevelFilter {
+    LEVELS[CONSOLE_LEVEL.load(Ordering::Relaxed)]
+}
+
+/// Log levels by their `LevelFilter` value.
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// Print messages up to `level` to the console from now on.
+///
+/// Returns false for an unknown level. A level above the one the firmware
+/// was built with also lets the newly enabled messages into the RAM log.
+pub fn set_console_level(level: usize) -> bool {
+    let Some(&filter) = LEVELS.get(level) else {
+        return false;
+    };
+    CONSOLE_LEVEL.store(level, Ordering::Relaxed);
+    if filter > log::max_level() {
+        log::set_max_level(filter);
+    }
+    true
+}
+
+/// Hand the console to the supervisor, or back to the firmware.
+///
+/// While the supervisor owns the console, messages only go to the RAM log, so
+/// they do not garble what it prints. Returns whether it owned the console.
+pub fn set_supervisor_owns_console(owns: bool) -> bool {
+    if owns && !SUPERVISOR_OWNS_CONSOLE.load(Ordering::Relaxed) {
+        info!("Console handed over to the supervisor, logging to RAM only");
+    }
+    let owned = SUPERVISOR_OWNS_CONSOLE.swap(owns, Ordering::Relaxed);
+    if owned && !owns {
+        info!("Console handed back to the firmware");
+    }
+    owned
+}
+
+/// Whether the supervisor has taken over the console.
+#[inline]
+pub fn supervisor_owns_console() -> bool {
+    SUPERVISOR_OWNS_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Give back the RAM log lock if the current hart left it held.
+///
+/// # Safety
+///
+/// See [`Mutex::force_unlock`].
+pub unsafe fn force_unlock() -> bool {
+    unsafe { RAM_LOG.force_unlock() }
+}
+
+/// Print the RAM log to the console and empty it, returning the bytes printed.
+pub fn flush_ram_log() -> usize {
+    let mut log = RAM_LOG.lock();
+    let mut start = (log.head + RAM_LOG_SIZE - log.len) % RAM_LOG_SIZE;
+    if log.len == RAM_LOG_SIZE {
+        // The oldest line may have lost its beginning, start at the next one.
+        let skip = (0..log.len)
+            .position(|i| log.buf[(start + i) % RAM_LOG_SIZE] == b'\r')
+            .map_or(0, |i| i + 1);
+        start = (start + skip) % RAM_LOG_SIZE;
+        log.len -= skip;
+    }
+    let (first, second) = if start + log.len <= RAM_LOG_SIZE {
+        (&log.buf[start..start + log.len], &log.buf[..0])
+    } else {
+        (&log.buf[start..], &log.buf[..log.head])
+    };
+    console::write_bytes(first);
+    console::write_bytes(second);
+    let len = log.len;
+    log.len = 0;
+    len
+}
+
+/// Print `record` to the console as colored text.
+#[cfg(not(feature = "binary-log"))]
+fn print_colored(record: &log::Record, prefix: &Prefix) {
+    // ANSI color codes for different log levels
+    const ERROR_COLOR: u8 = 31; // Red
+    const WARN_COLOR: u8 = 93; // Bright yellow
+    const INFO_COLOR: u8 = 32; // Green
+    const DEBUG_COLOR: u8 = 36; // Cyan
+    const TRACE_COLOR: u8 = 90; // Bright black
+
+    let color_code = match record.level() {
+        log::Level::Error => ERROR_COLOR,
+        log::Level::Warn => WARN_COLOR,
+        log::Level::Info => INFO_COLOR,
+        log::Level::Debug => DEBUG_COLOR,
+        log::Level::Trace => TRACE_COLOR,
+    };
+
+    println!(
+        "{}\x1b[1;37m[RustSBI] \x1b[1;{color_code}m{:^5}\x1b[0m - {}",
+        prefix,
+        record.level(),
+        record.args(),
+    );
+}
+
 impl log::Log for Logger {
     // Always enable logging for all log levels
     #[inline]
@@ -27,26 +242,21 @@ impl log::Log for Logger {
     // Log messages with color-coded levels
     #[inline]
     fn log(&self, record: &log::Record) {
-        // ANSI color codes for different log levels
-        const ERROR_COLOR: u8 = 31; // Red
-        const WARN_COLOR: u8 = 93; // Bright yellow
-        const INFO_COLOR: u8 = 32; // Green
-        const DEBUG_COLOR: u8 = 36; // Cyan
-        const TRACE_COLOR: u8 = 90; // Bright black
-
-        let color_code = match record.level() {
-            Level::Error => ERROR_COLOR,
-            Level::Warn => WARN_COLOR,
-            Level::Info => INFO_COLOR,
-            Level::Debug => DEBUG_COLOR,
-            Level::Trace => TRACE_COLOR,
-        };
-
-        println!(
-            "\x1b[1;37m[RustSBI] \x1b[1;{color_code}m{:^5}\x1b[0m - {}",
+        let prefix = Prefix::now(LogPrefix::current());
+        let _ = write!(
+            RAM_LOG.lock(),
+            "{}[RustSBI] {:^5} - {}\n\r",
+            prefix,
             record.level(),
-            record.args(),
+            record.args()
         );
+        if record.level() > console_level() || supervisor_owns_console() {
+            return;
+        }
+        #[cfg(feature = "binary-log")]
+        super::binary_log::log(record, prefix.hart_id, time::current_ticks());
+        #[cfg(not(feature = "binary-log"))]
+        print_colored(record, &prefix);
     }
 
     // No-op flush since we use println! which is already line-buffered
@@ -0,0 +1,201 @@
+//! Emulation of misaligned atomic memory operations.
+//!
+//! Some cores raise an address misaligned or access fault on every AMO that
+//! is not naturally aligned, and the kernel then kills the process. With the
+//! `misaligned-amo` feature the firmware performs those operations itself:
+//! the read-modify-write runs with the containing address bucket locked, so
+//! emulated AMOs to the same location serialize on all harts. Plain stores of
+//! other harts are not ordered against it, so this keeps legacy software
+//! running but is no substitute for aligned atomics, and every hart says so on
+//! its first emulation and at each power of two after that.
+//!
+//! `lr` loads and records a reservation of the address and value; the paired
+//! `sc` stores if the reservation is for its address and the value is
+//! unchanged.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use fast_trap::FlowContext;
+use riscv::register::mepc;
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::guest_mem::{self, AccessError, Mode};
+use crate::sbi::insn::Op;
+use crate::sbi::trap_frame;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+use crate::sync::Mutex;
+
+const AMOADD: u32 = 0b00000;
+const AMOSWAP: u32 = 0b00001;
+const LR: u32 = 0b00010;
+const SC: u32 = 0b00011;
+const AMOXOR: u32 = 0b00100;
+const AMOOR: u32 = 0b01000;
+const AMOAND: u32 = 0b01100;
+const AMOMIN: u32 = 0b10000;
+const AMOMAX: u32 = 0b10100;
+const AMOMINU: u32 = 0b11000;
+const AMOMAXU: u32 = 0b11100;
+
+/// Locks of address buckets, by address bits above the access width.
+const BUCKETS: usize = 16;
+static BUCKET_LOCKS: [Mutex<()>; BUCKETS] = [const { Mutex::named("misaligned amo", ()) }; BUCKETS];
+
+percpu! {
+    /// Misaligned AMOs each hart emulated.
+    static EMULATED: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Per-hart `lr` reservations, address and value, only ever touched by their own hart.
+static mut RESERVATIONS: [Option<(usize, u64)>; NUM_HART_MAX] = [None; NUM_HART_MAX];
+
+/// Result of a trap offered to the emulation.
+pub enum Emulation {
+    /// Performed, `mepc` is past the instruction.
+    Done,
+    /// Not a misaligned AMO the firmware can perform.
+    Unsupported,
+    /// The memory access faulted, with the cause and value to report to the trapped mode.
+    Fault { cause: usize, tval: usize },
+}
+
+/// Offer a load or store fault to the emulation.
+///
+/// `regs` holds the caller-saved registers of the trapped context, the only
+/// ones the fast trap path saves; AMOs using other registers are not emulated.
+/// AMOs with `rd = x0`, such as those of Linux `atomic_add`, are emulated
+/// with the old value dropped.
+pub fn emulate(regs: &mut FlowContext) -> Emulation {
+    let Some(mode) = Mode::trapped() else {
+        return Emulation::Unsupported;
+    };
+    let pc = mepc::read();
+    let insn = match guest_mem::fetch(mode, pc) {
+        Ok(insn) => insn,
+        Err(AccessError::Fault { cause, tval }) => return Emulation::Fault { cause, tval },
+        Err(AccessError::Inaccessible) => return Emulation::Unsupported,
+    };
+    let Op::Amo { width, funct5 } = insn.op() else {
+        return Emulation::Unsupported;
+    };
+    let addr = trap_frame::gpr(regs, insn.rs1());
+    if width > core::mem::size_of::<usize>()
+        || addr % width == 0
+        || ![insn.rd(), insn.rs1(), insn.rs2()]
+            .into_iter()
+            .all(trap_frame::caller_saved)
+    {
+        return Emulation::Unsupported;
+    }
+    let src = trap_frame::gpr(regs, insn.rs2()) as u64;
+    let hart_id = current_hartid();
+    let Some(reservation) = (unsafe { RESERVATIONS.get_mut(hart_id) }) else {
+        return Emulation::Unsupported;
+    };
+
+    let _bucket = BUCKET_LOCKS[(addr / width) % BUCKETS].lock();
+    let old = match load(mode, addr, width) {
+        Ok(old) => old,
+        Err(fault) if funct5 == LR => return fault,
+        Err(fault) => return store_fault(fault),
+    };
+    let result = match funct5 {
+        LR => {
+            *reservation = Some((addr, old));
+            sign_extend(old, width)
+        }
+        SC => {
+            let reserved = reservation.take() == Some((addr, old));
+            if reserved {
+                if let Err(fault) = store(mode, addr, width, src) {
+                    return fault;
+                }
+            }
+            !reserved as usize
+        }
+        funct5 => {
+            let Some(new) = apply(funct5, width, old, src) else {
+                return Emulation::Unsupported;
+            };
+            if let Err(fault) = store(mode, addr, width, new) {
+                return fault;
+            }
+            sign_extend(old, width)
+        }
+    };
+    trap_frame::set_gpr(regs, insn.rd(), result);
+    mepc::write(pc + insn.size());
+
+    let count = EMULATED.local().fetch_add(1, Ordering::Relaxed) + 1;
+    if count.is_power_of_two() {
+        warn!(
+            "Hart {} emulated {} misaligned AMO(s), last at pc {:#x} on {:#x}",
+            hart_id, count, pc, addr
+        );
+    }
+    Emulation::Done
+}
+
+fn load(mode: Mode, addr: usize, width: usize) -> Result<u64, Emulation> {
+    let mut bytes = [0; 8];
+    guest_mem::read(mode, addr, &mut bytes[..width]).map_err(fault)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn store(mode: Mode, addr: usize, width: usize, value: u64) -> Result<(), Emulation> {
+    guest_mem::write(mode, addr, &value.to_le_bytes()[..width]).map_err(fault)
+}
+
+fn fault(err: AccessError) -> Emulation {
+    match err {
+        AccessError::Fault { cause, tval } => Emulation::Fault { cause, tval },
+        AccessError::Inaccessible => Emulation::Unsupported,
+    }
+}
+
+/// An AMO reports faults of its load half as store/AMO faults.
+fn store_fault(fault: Emulation) -> Emulation {
+    match fault {
+        Emulation::Fault { cause, tval } => Emulation::Fault {
+            cause: match cause {
+                // Address misaligned, access fault, page fault and guest page fault
+                4 | 5 | 13 | 21 => cause + 2,
+                cause => cause,
+            },
+            tval,
+        },
+        other => other,
+    }
+}
+
+/// `value` as a register holds a `width` byte load of it.
+fn sign_extend(value: u64, width: usize) -> usize {
+    match width {
+        4 => value as u32 as i32 as isize as usize,
+        _ => value as usize,
+    }
+}
+
+/// New memory value of AMO `funct5` on `old` with source `src`.
+fn apply(funct5: u32, width: usize, old: u64, src: u64) -> Option<u64> {
+    let signed = |value: u64| match width {
+        4 => value as u32 as i32 as i64,
+        _ => value as i64,
+    };
+    let unsigned = |value: u64| match width {
+        4 => value as u32 as u64,
+        _ => value,
+    };
+    Some(match funct5 {
+        AMOADD => old.wrapping_add(src),
+        AMOSWAP => src,
+        AMOXOR => old ^ src,
+        AMOOR => old | src,
+        AMOAND => old & src,
+        AMOMIN => signed(old).min(signed(src)) as u64,
+        AMOMAX => signed(old).max(signed(src)) as u64,
+        AMOMINU => unsigned(old).min(unsigned(src)),
+        AMOMAXU => unsigned(old).max(unsigned(src)),
+        _ => return None,
+    })
+}
@@ -1,18 +1,54 @@
 use rustsbi::RustSBI;
 
 pub mod console;
+pub mod console_dma;
 pub mod hsm;
 pub mod ipi;
 pub mod reset;
 pub mod rfence;
 
+pub mod base_cache;
+#[cfg(feature = "binary-log")]
+pub mod binary_log;
+#[cfg(feature = "sbi-trace")]
+pub mod call_trace;
+pub mod crashdump;
+pub mod debug;
+pub mod domain;
 pub mod early_trap;
+pub mod entropy;
+#[cfg(feature = "exit-dump")]
+pub mod exit_dump;
+pub mod extension_mask;
 pub mod extensions;
+pub mod fence_i;
 pub mod fifo;
+pub mod fwft;
+pub mod guest_mem;
 pub mod hart_context;
+pub mod hart_init;
+pub mod hart_mask;
+pub mod idle_states;
+pub mod inject;
+pub mod insn;
+pub mod irq;
+#[cfg(feature = "legacy-sbi")]
+pub mod legacy;
 pub mod logger;
+#[cfg(feature = "misaligned-amo")]
+pub mod misaligned_amo;
+pub mod quarantine;
+#[cfg(feature = "rate-limit")]
+pub mod rate_limit;
+pub mod rnmi;
+pub mod susp;
+pub mod timer;
+#[cfg(feature = "timer-trace")]
+pub mod timer_trace;
 pub mod trap;
+pub mod trap_frame;
 pub mod trap_stack;
+pub mod update;
 
 use console::{ConsoleDevice, SbiConsole};
 use hsm::SbiHsm;
@@ -0,0 +1,107 @@
+//! Quarantine of harts that keep faulting.
+//!
+//! A trap the firmware cannot handle used to stop the hart in a loop, and
+//! every later remote fence then waited forever for it. Traps taken from a
+//! lower privilege are now handed to the supervisor until the hart has taken
+//! `PROTOTYPER_FAULT_THRESHOLD` of them (default 3); traps taken in M-mode
+//! and panics happen with firmware state in an unknown shape and quarantine
+//! the hart at once.
+//!
+//! A quarantined hart is left out of IPIs, fences and HSM starts from then
+//! on. It keeps acknowledging the requests already on their way to it, so
+//! their senders finish, and runs nothing else.
+//!
+//! A hart that stops answering, stuck before it started or leaving remote
+//! fences unacknowledged, is quarantined by the hart that gave up waiting
+//! for it. It cannot be stopped from there: if it ever comes back to the
+//! firmware it finds itself quarantined and enters the loop below.
+//!
+//! A hart panics with whatever locks it held still taken, and its guards are
+//! never dropped. The locks the panic report and the quarantine loop need,
+//! and the fence queues other harts wait on, are given back first.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::hsm::{local_hsm, remote_hsm};
+use crate::sbi::{console, console_dma, fence_i, ipi, logger, rfence, trap};
+
+const DEFAULT_FAULT_THRESHOLD: usize = 3;
+/// Machine software interrupt enable, the only interrupt a quarantined hart waits for.
+const MIE_MSIE: usize = 1 << 3;
+
+percpu! {
+    /// Unhandled traps each hart has survived.
+    static FAULTS: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Fault threshold selected at build time.
+fn fault_threshold() -> usize {
+    option_env!("PROTOTYPER_FAULT_THRESHOLD")
+        .and_then(|threshold| threshold.trim().parse().ok())
+        .filter(|&threshold| threshold > 0)
+        .unwrap_or(DEFAULT_FAULT_THRESHOLD)
+}
+
+/// Count an unhandled trap on the current hart.
+///
+/// Returns true once the hart reached the threshold and should be quarantined.
+pub fn record_fault() -> bool {
+    FAULTS.local().fetch_add(1, Ordering::Relaxed) + 1 >= fault_threshold()
+}
+
+/// Returns true if the current hart can be quarantined instead of halted.
+///
+/// Harts that never got to handle SBI events hold up nobody.
+#[inline]
+pub fn available() -> bool {
+    hart_init::reached(current_hartid(), InitState::SbiReady)
+}
+
+/// Give back the locks a stopping hart may have left held on its way out.
+///
+/// Called first thing on a panic, before anything is printed.
+pub fn release_locks() {
+    // The hart never returns to the code holding these guards.
+    unsafe {
+        console::force_unlock();
+        console_dma::force_unlock();
+        logger::force_unlock();
+        rfence::force_unlock_queues();
+    }
+}
+
+/// Take `hart_id`, which stopped answering, out of service from another hart.
+pub fn enter_remote(hart_id: usize, reason: &str) {
+    hart_init::mark_failed(hart_id);
+    if let Some(remote) = remote_hsm(hart_id) {
+        remote.quarantine();
+    }
+    error!("Hart {} quarantined, {}", hart_id, reason);
+}
+
+/// Take the current hart out of service for good.
+pub fn enter() -> ! {
+    release_locks();
+    let hart_id = current_hartid();
+    hart_init::mark_failed(hart_id);
+    local_hsm().quarantine();
+    error!(
+        "Hart {} quarantined, the rest of the system keeps running",
+        hart_id
+    );
+    unsafe {
+        riscv::register::mstatus::clear_mie();
+        asm!("csrw mie, {}", in(reg) MIE_MSIE);
+    }
+    loop {
+        // Acknowledge requests sent before the hart left the masks.
+        ipi::clear_msip();
+        ipi::get_and_reset_ipi_type();
+        trap::rfence_handler();
+        fence_i::handle_local();
+        riscv::asm::wfi();
+    }
+}
@@ -0,0 +1,144 @@
+//! Rate limiting of expensive SBI calls.
+//!
+//! With the `rate-limit` feature each hart has a token bucket per class of
+//! costly call: remote fences, which interrupt every target hart, and console
+//! writes, which hold the shared console. Legacy fences and console writes
+//! share the buckets of their successors. A call takes tokens from its
+//! bucket, which refills at a fixed rate up to a burst size.
+//!
+//! A call its bucket cannot pay for is refused with `SBI_ERR_DENIED`, and a
+//! console write only gets to write the bytes it has tokens for. The firmware
+//! never waits for tokens, so a throttled hart still serves IPIs and fences
+//! of others, and a call storm from a buggy or malicious supervisor slows
+//! only its own harts. Every hart reports being throttled on its first
+//! refused call and at each power of two after that.
+
+use sbi_spec::{dbcn, legacy, rfnc};
+
+use crate::riscv_spec::current_hartid;
+use crate::sync::Mutex;
+use crate::time;
+
+/// Kinds of rate limited calls.
+#[derive(Clone, Copy, Debug)]
+pub enum Class {
+    /// Remote fence calls, one token each.
+    RFence = 0,
+    /// Console writes, one token per byte.
+    Console = 1,
+}
+
+const CLASSES: usize = 2;
+
+/// Burst size and refill rate per second of each class.
+const LIMITS: [(u64, u64); CLASSES] = [
+    // Far above what kernels issue under load.
+    (65_536, 200_000),
+    // About a 115200 baud console, after a burst for the boot log.
+    (65_536, 16_384),
+];
+
+/// Token bucket of one class on one hart.
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: u64,
+    /// mtime of the last refill, 0 before the first call.
+    refilled: u64,
+    /// Calls throttled so far.
+    stalls: usize,
+}
+
+percpu! {
+    /// Buckets of each hart, only ever touched by the hart itself.
+    static BUCKETS: Mutex<[Bucket; CLASSES]> = Mutex::named(
+        "rate limit",
+        [Bucket {
+            tokens: 0,
+            refilled: 0,
+            stalls: 0,
+        }; CLASSES],
+    );
+}
+
+/// What a charged call may do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grant {
+    /// The whole call.
+    Full,
+    /// Only the first `n` units, `n` nonzero, as a partial console write.
+    Partial(u64),
+    /// Nothing, the call is refused.
+    Denied,
+}
+
+impl Bucket {
+    /// Add the tokens earned since the last refill at `now`.
+    fn refill(&mut self, now: u64, (burst, rate): (u64, u64)) {
+        if self.refilled == 0 {
+            self.tokens = burst;
+        } else {
+            let earned = (now.saturating_sub(self.refilled) as u128 * rate as u128
+                / time::timebase_frequency() as u128) as u64;
+            if earned == 0 {
+                return;
+            }
+            self.tokens = self.tokens.saturating_add(earned).min(burst);
+        }
+        self.refilled = now;
+    }
+}
+
+/// Take up to `cost` tokens of `class` for the current hart.
+///
+/// With `partial`, a call the bucket cannot pay for in full gets the tokens
+/// left, if any.
+pub fn charge(class: Class, cost: u64, partial: bool) -> Grant {
+    let hart_id = current_hartid();
+    let Some(buckets) = BUCKETS.get(hart_id) else {
+        return Grant::Full;
+    };
+    // No timer yet, nothing to refill with.
+    if time::current_ticks() == 0 {
+        return Grant::Full;
+    }
+    let limit = LIMITS[class as usize];
+    let mut buckets = buckets.lock();
+    let bucket = &mut buckets[class as usize];
+    bucket.refill(time::current_ticks(), limit);
+    if bucket.tokens >= cost {
+        bucket.tokens -= cost;
+        return Grant::Full;
+    }
+    bucket.stalls += 1;
+    if bucket.stalls.is_power_of_two() {
+        warn!(
+            "Hart {} throttled on {:?} calls, {} call(s) so far",
+            hart_id, class, bucket.stalls
+        );
+    }
+    if !partial || bucket.tokens == 0 {
+        return Grant::Denied;
+    }
+    let granted = core::mem::take(&mut bucket.tokens);
+    Grant::Partial(granted)
+}
+
+/// Charge an SBI call of function `fid` of extension `eid`, with `a0` as its first parameter.
+///
+/// Legacy calls are told apart by `eid` alone.
+#[inline]
+pub fn charge_call(eid: usize, fid: usize, a0: usize) -> Grant {
+    match (eid, fid) {
+        (rfnc::EID_RFNC, _) => charge(Class::RFence, 1, false),
+        (dbcn::EID_DBCN, dbcn::CONSOLE_WRITE) => charge(Class::Console, a0 as u64, true),
+        (dbcn::EID_DBCN, dbcn::CONSOLE_WRITE_BYTE) => charge(Class::Console, 1, false),
+        (
+            legacy::LEGACY_REMOTE_FENCE_I
+            | legacy::LEGACY_REMOTE_SFENCE_VMA
+            | legacy::LEGACY_REMOTE_SFENCE_VMA_ASID,
+            _,
+        ) => charge(Class::RFence, 1, false),
+        (legacy::LEGACY_CONSOLE_PUTCHAR, _) => charge(Class::Console, 1, false),
+        _ => Grant::Full,
+    }
+}
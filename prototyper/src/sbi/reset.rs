@@ -1,35 +1,48 @@
-use core::sync::atomic::{AtomicPtr, Ordering::Relaxed};
+use crate::sync::Mutex;
 use rustsbi::SbiRet;
 
-use crate::platform::PLATFORM;
+use crate::platform;
+use crate::sbi::{console_dma, update};
 
 pub trait ResetDevice {
     fn fail(&self, code: u16) -> !;
     fn pass(&self) -> !;
     fn reset(&self) -> !;
+    /// Whether passing or failing ends a simulator run, as the QEMU test finisher does.
+    #[cfg(feature = "exit-dump")]
+    fn is_test_finisher(&self) -> bool {
+        false
+    }
 }
 
 pub struct SbiReset<T: ResetDevice> {
-    pub reset_dev: AtomicPtr<T>,
+    pub reset_dev: Mutex<T>,
 }
 
 impl<T: ResetDevice> SbiReset<T> {
-    pub fn new(reset_dev: AtomicPtr<T>) -> Self {
+    pub fn new(reset_dev: Mutex<T>) -> Self {
         Self { reset_dev }
     }
 
     #[allow(unused)]
     pub fn fail(&self) -> ! {
-        let reset_dev = self.reset_dev.load(Relaxed);
-        if reset_dev.is_null() {
-            trace!("test fail, begin dead loop");
-            loop {
-                core::hint::spin_loop()
-            }
-        } else {
-            trace!("Test fail, invoke process exit procedure on Reset device");
-            unsafe { (*reset_dev).fail(0) }
+        trace!("Test fail, invoke process exit procedure on Reset device");
+        // The same code as a system failure reset, in the dump and the exit status.
+        self.before_exit(u16::MAX);
+        self.reset_dev.lock().fail(u16::MAX)
+    }
+
+    /// Print the exit dump, if built in, before the test finisher ends the run
+    /// with `code`, and let queued console output out.
+    #[inline]
+    fn before_exit(&self, code: u16) {
+        #[cfg(feature = "exit-dump")]
+        if self.reset_dev.lock().is_test_finisher() {
+            crate::sbi::exit_dump::write(code);
         }
+        #[cfg(not(feature = "exit-dump"))]
+        let _ = code;
+        console_dma::drain(console_dma::DRAIN_TIMEOUT_US);
     }
 }
 
@@ -42,19 +55,29 @@ impl<T: ResetDevice> rustsbi::Reset for SbiReset<T> {
         };
         match reset_type {
             RESET_TYPE_SHUTDOWN => match reset_reason {
-                RESET_REASON_NO_REASON => unsafe {
-                    (*self.reset_dev.load(Relaxed)).pass();
-                },
-                RESET_REASON_SYSTEM_FAILURE => unsafe {
-                    (*self.reset_dev.load(Relaxed)).fail(u16::MAX);
-                },
-                value => unsafe {
-                    (*self.reset_dev.load(Relaxed)).fail(value as _);
-                },
-            },
-            RESET_TYPE_COLD_REBOOT | RESET_TYPE_WARM_REBOOT => unsafe {
-                (*self.reset_dev.load(Relaxed)).reset();
+                RESET_REASON_NO_REASON => {
+                    self.before_exit(0);
+                    self.reset_dev.lock().pass()
+                }
+                RESET_REASON_SYSTEM_FAILURE => {
+                    self.before_exit(u16::MAX);
+                    self.reset_dev.lock().fail(u16::MAX)
+                }
+                value => {
+                    self.before_exit(value as _);
+                    self.reset_dev.lock().fail(value as _)
+                }
             },
+            RESET_TYPE_COLD_REBOOT => {
+                console_dma::drain(console_dma::DRAIN_TIMEOUT_US);
+                self.reset_dev.lock().reset()
+            }
+            RESET_TYPE_WARM_REBOOT => {
+                console_dma::drain(console_dma::DRAIN_TIMEOUT_US);
+                // An activated firmware update replaces the platform reset.
+                update::reboot_into_staged();
+                self.reset_dev.lock().reset()
+            }
 
             _ => SbiRet::invalid_param(),
         }
@@ -63,8 +86,11 @@ impl<T: ResetDevice> rustsbi::Reset for SbiReset<T> {
 
 #[allow(unused)]
 pub fn fail() -> ! {
-    match unsafe { PLATFORM.sbi.reset.as_ref() } {
+    match platform::reset() {
         Some(reset) => reset.fail(),
-        None => panic!("SBI or IPI device not initialized"),
+        // Nothing to reset with, stop here.
+        None => loop {
+            core::hint::spin_loop()
+        },
     }
 }
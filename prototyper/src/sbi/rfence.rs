@@ -1,11 +1,12 @@
+use crate::sync::{Backoff, Mutex};
 use rustsbi::{HartMask, SbiRet};
-use spin::Mutex;
 
-use crate::platform::PLATFORM;
+use crate::platform;
 use crate::riscv_spec::current_hartid;
+use crate::sbi::fence_i;
 use crate::sbi::fifo::{Fifo, FifoError};
 use crate::sbi::trap;
-use crate::sbi::trap_stack::ROOT_STACK;
+use crate::sbi::trap_stack::{NUM_HART_MAX, ROOT_STACK};
 
 use core::sync::atomic::{AtomicU32, Ordering};
 
@@ -57,7 +58,7 @@ impl RFenceCell {
     /// Creates a new RFenceCell with empty queue and zero sync count.
     pub fn new() -> Self {
         Self {
-            queue: Mutex::new(Fifo::new()),
+            queue: Mutex::named("rfence queue", Fifo::new()),
             wait_sync_count: AtomicU32::new(0),
         }
     }
@@ -94,6 +95,19 @@ pub(crate) fn local_rfence() -> Option<LocalRFenceCell<'static>> {
     }
 }
 
+/// Give back the fence queue locks of any hart the current hart left held.
+///
+/// # Safety
+///
+/// See [`Mutex::force_unlock`].
+pub(crate) unsafe fn force_unlock_queues() {
+    for hart_id in 0..NUM_HART_MAX {
+        if let Some(cell) = remote_rfence(hart_id) {
+            unsafe { cell.0.queue.force_unlock() };
+        }
+    }
+}
+
 /// Gets the remote fence context for a specific hart.
 pub(crate) fn remote_rfence(hart_id: usize) -> Option<RemoteRFenceCell<'static>> {
     unsafe {
@@ -107,7 +121,14 @@ pub(crate) fn remote_rfence(hart_id: usize) -> Option<RemoteRFenceCell<'static>>
 impl LocalRFenceCell<'_> {
     /// Checks if all synchronization operations are complete.
     pub fn is_sync(&self) -> bool {
-        self.0.wait_sync_count.load(Ordering::Relaxed) == 0
+        // Pairs with the Release in `RemoteRFenceCell::sub`.
+        self.0.wait_sync_count.load(Ordering::Acquire) == 0
+    }
+
+    /// Wait for a remote hart to complete an operation, or about to.
+    pub fn wait(&self, backoff: &mut Backoff) {
+        let count = &self.0.wait_sync_count;
+        backoff.wait_while(count.as_ptr(), count.load(Ordering::Relaxed));
     }
 
     /// Increments the synchronization counter.
@@ -160,9 +181,25 @@ impl RemoteRFenceCell<'_> {
         }
     }
 
+    /// Take back the operations the current hart queued here and the target
+    /// did not take yet, acknowledging them for it.
+    ///
+    /// Returns true if there were any.
+    pub fn withdraw(&self) -> bool {
+        let hart_id = current_hartid();
+        let removed = self.0.queue.lock().retain(|&(_, source)| source != hart_id);
+        if let Some(source) = remote_rfence(hart_id) {
+            for _ in 0..removed {
+                source.sub();
+            }
+        }
+        removed != 0
+    }
+
     /// Decrements the synchronization counter.
     pub fn sub(&self) {
-        self.0.wait_sync_count.fetch_sub(1, Ordering::Relaxed);
+        // Make the completed fence visible to the waiting source hart.
+        self.0.wait_sync_count.fetch_sub(1, Ordering::Release);
     }
 }
 
@@ -187,26 +224,15 @@ fn validate_address_range(start_addr: usize, size: usize) -> Result<usize, SbiRe
 
 /// Processes a remote fence operation by sending IPI to target harts.
 fn remote_fence_process(rfence_ctx: RFenceContext, hart_mask: HartMask) -> SbiRet {
-    let sbi_ret = unsafe { PLATFORM.sbi.ipi.as_ref() }
+    platform::ipi()
         .unwrap()
-        .send_ipi_by_fence(hart_mask, rfence_ctx);
-
-    sbi_ret
+        .send_ipi_by_fence(hart_mask, rfence_ctx)
 }
 
 impl rustsbi::Fence for SbiRFence {
     /// Remote instruction fence for specified harts.
     fn remote_fence_i(&self, hart_mask: HartMask) -> SbiRet {
-        remote_fence_process(
-            RFenceContext {
-                start_addr: 0,
-                size: 0,
-                asid: 0,
-                vmid: 0,
-                op: RFenceType::FenceI,
-            },
-            hart_mask,
-        )
+        fence_i::remote_fence_i(hart_mask)
     }
 
     /// Remote supervisor fence for virtual memory on specified harts.
@@ -0,0 +1,160 @@
+//! Resumable non-maskable interrupts, from Smrnmi.
+//!
+//! Where the hart implements Smrnmi, an RNMI (a bus error reported by the
+//! interconnect on some SoCs, for example) no longer stops the system. The
+//! handler records the cause and interrupted pc of each hart and resumes with
+//! `mnret`; S-mode reads the records back through the debug extension.
+//!
+//! The RNMI vector is fixed by the implementation. Platforms that let it be
+//! configured should point it at `_rnmi_entry`. None of the boards in
+//! `boards/` has a register for it, their manifests say whether RNMIs are
+//! handled at all. `mnstatus.NMIE` is set on Smrnmi harts regardless, as
+//! machine interrupts stay masked without it.
+
+use core::arch::asm;
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::riscv_spec::{current_hartid, rnmi};
+use crate::sbi::extensions::{hart_extension_probe, Extension};
+use crate::sbi::trap_frame::{CallerSaved, XLENB};
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Stack size of the RNMI handler, per hart.
+const LEN_RNMI_STACK_PER_HART: usize = 1024;
+
+// The entry saves a `CallerSaved` frame and calls into Rust on this stack.
+const _: () = assert!(LEN_RNMI_STACK_PER_HART % 16 == 0);
+const _: () = assert!(size_of::<CallerSaved>() <= LEN_RNMI_STACK_PER_HART / 4);
+
+#[repr(C, align(16))]
+struct RnmiStack([u8; LEN_RNMI_STACK_PER_HART]);
+
+/// Stacks of the RNMI handler. An RNMI may arrive while the trap handlers are
+/// switching stacks, so it cannot share the trap stack.
+static mut RNMI_STACK: [RnmiStack; NUM_HART_MAX] =
+    [const { RnmiStack([0; LEN_RNMI_STACK_PER_HART]) }; NUM_HART_MAX];
+
+/// RNMIs seen on one hart.
+pub struct RnmiRecord {
+    /// Number of RNMIs taken.
+    pub count: AtomicUsize,
+    /// `mncause` of the last RNMI.
+    pub cause: AtomicUsize,
+    /// `mnepc` of the last RNMI.
+    pub epc: AtomicUsize,
+}
+
+percpu! {
+    /// RNMIs recorded on each hart.
+    pub static RECORD: RnmiRecord = RnmiRecord {
+        count: AtomicUsize::new(0),
+        cause: AtomicUsize::new(0),
+        epc: AtomicUsize::new(0),
+    };
+}
+
+/// Enable RNMIs on the current hart if it implements Smrnmi.
+pub fn init() {
+    if !hart_extension_probe(current_hartid(), Extension::Smrnmi) {
+        return;
+    }
+    let top = unsafe { RNMI_STACK[current_hartid()].0.as_ptr_range().end as usize };
+    rnmi::write_mnscratch(top);
+    rnmi::set_mnstatus_bits(rnmi::MNSTATUS_NMIE);
+}
+
+/// Address of the RNMI handler.
+#[inline]
+pub fn entry_address() -> usize {
+    rnmi_entry as usize
+}
+
+/// Total RNMIs taken on all harts.
+pub fn total_count() -> usize {
+    (0..NUM_HART_MAX)
+        .filter_map(|hart_id| RECORD.get(hart_id))
+        .map(|record| record.count.load(Ordering::Relaxed))
+        .sum()
+}
+
+/// RNMI entry, switches to the RNMI stack and saves caller-saved registers.
+///
+/// # Safety
+///
+/// This is a naked function that directly manipulates registers and stack.
+#[naked]
+#[export_name = "_rnmi_entry"]
+unsafe extern "C" fn rnmi_entry() -> ! {
+    asm!(
+        ".align 2",
+        "csrrw  sp, 0x740, sp",
+        "addi   sp, sp, -{size}",
+        frame_store!(ra),
+        frame_store!(t0),
+        frame_store!(t1),
+        frame_store!(t2),
+        frame_store!(t3),
+        frame_store!(t4),
+        frame_store!(t5),
+        frame_store!(t6),
+        frame_store!(a0),
+        frame_store!(a1),
+        frame_store!(a2),
+        frame_store!(a3),
+        frame_store!(a4),
+        frame_store!(a5),
+        frame_store!(a6),
+        frame_store!(a7),
+        "call   {handler}",
+        frame_load!(ra),
+        frame_load!(t0),
+        frame_load!(t1),
+        frame_load!(t2),
+        frame_load!(t3),
+        frame_load!(t4),
+        frame_load!(t5),
+        frame_load!(t6),
+        frame_load!(a0),
+        frame_load!(a1),
+        frame_load!(a2),
+        frame_load!(a3),
+        frame_load!(a4),
+        frame_load!(a5),
+        frame_load!(a6),
+        frame_load!(a7),
+        "addi   sp, sp, {size}",
+        "csrrw  sp, 0x740, sp",
+        // mnret
+        ".word  0x70200073",
+        handler = sym rnmi_handler,
+        size = const size_of::<CallerSaved>(),
+        ra = const offset_of!(CallerSaved, ra),
+        t0 = const offset_of!(CallerSaved, t),
+        t1 = const offset_of!(CallerSaved, t) + XLENB,
+        t2 = const offset_of!(CallerSaved, t) + 2 * XLENB,
+        t3 = const offset_of!(CallerSaved, t) + 3 * XLENB,
+        t4 = const offset_of!(CallerSaved, t) + 4 * XLENB,
+        t5 = const offset_of!(CallerSaved, t) + 5 * XLENB,
+        t6 = const offset_of!(CallerSaved, t) + 6 * XLENB,
+        a0 = const offset_of!(CallerSaved, a),
+        a1 = const offset_of!(CallerSaved, a) + XLENB,
+        a2 = const offset_of!(CallerSaved, a) + 2 * XLENB,
+        a3 = const offset_of!(CallerSaved, a) + 3 * XLENB,
+        a4 = const offset_of!(CallerSaved, a) + 4 * XLENB,
+        a5 = const offset_of!(CallerSaved, a) + 5 * XLENB,
+        a6 = const offset_of!(CallerSaved, a) + 6 * XLENB,
+        a7 = const offset_of!(CallerSaved, a) + 7 * XLENB,
+        options(noreturn)
+    )
+}
+
+/// Record an RNMI. The interrupted code may hold any lock, so nothing is printed.
+extern "C" fn rnmi_handler() {
+    let Some(record) = RECORD.get(current_hartid()) else {
+        return;
+    };
+    record.cause.store(rnmi::read_mncause(), Ordering::Relaxed);
+    record.epc.store(rnmi::read_mnepc(), Ordering::Relaxed);
+    record.count.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,96 @@
+//! System suspend extension.
+//!
+//! `sbi_system_suspend` puts the whole platform to sleep once every other hart
+//! is stopped. The calling hart hands the sleep over to the platform's
+//! `SuspendDevice`: `enter` does what the board needs before power goes down,
+//! such as putting DRAM into self-refresh and sequencing PMIC rails, and
+//! returns once the platform woke up; `exit` undoes it. The supervisor then
+//! resumes at its resume address as after a non-retentive hart suspend.
+
+use rustsbi::SbiRet;
+use sbi_spec::hsm::hart_state;
+
+use crate::platform::PLATFORM;
+use crate::riscv_spec::current_hartid;
+use crate::sbi::hsm::{self, remote_hsm};
+use crate::sbi::idle_states::IdleState;
+use crate::sbi::ipi;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Extension ID of the system suspend extension.
+pub const EID_SUSP: usize = 0x5355_5350;
+
+/// Suspend the system, resuming at the address in `a1` with `a2` as opaque.
+pub const SYSTEM_SUSPEND: usize = 0;
+
+/// Sleep type of suspend to RAM, the only one the specification defines.
+pub const SUSPEND_TO_RAM: u32 = 0;
+
+/// First of the platform specific sleep types.
+const PLATFORM_SPECIFIC: u32 = 0x8000_0000;
+
+/// Power management of a platform that can sleep as a whole.
+pub trait SuspendDevice {
+    /// Whether the platform implements `sleep_type`.
+    fn supports(&self, sleep_type: u32) -> bool;
+    /// Bring the platform into `sleep_type` and return once it woke up.
+    ///
+    /// An error leaves the platform running, and is returned to the caller.
+    fn enter(&self, sleep_type: u32) -> Result<(), SbiRet>;
+    /// Restore the platform after waking up from `sleep_type`.
+    fn exit(&self, sleep_type: u32);
+    /// Prepare the calling hart for entering idle `state` through HSM suspend.
+    ///
+    /// Called after the hart is marked suspended, just before it waits for an
+    /// interrupt.
+    fn hart_idle_enter(&self, _state: &IdleState) {}
+    /// Undo `hart_idle_enter` once the hart woke up.
+    fn hart_idle_exit(&self, _state: &IdleState) {}
+}
+
+/// Whether the platform can suspend.
+#[inline]
+pub fn available() -> bool {
+    unsafe { PLATFORM.suspend.is_some() }
+}
+
+fn system_suspend(sleep_type: u32, resume_addr: usize) -> SbiRet {
+    let Some(suspend) = (unsafe { PLATFORM.suspend.as_ref() }) else {
+        return SbiRet::not_supported();
+    };
+    if sleep_type != SUSPEND_TO_RAM && sleep_type < PLATFORM_SPECIFIC {
+        return SbiRet::invalid_param();
+    }
+    let suspend = suspend.lock();
+    if !suspend.supports(sleep_type) {
+        return SbiRet::invalid_param();
+    }
+    if let Err(err) = hsm::check_entry_address(resume_addr) {
+        return err;
+    }
+    let hart_id = current_hartid();
+    let others_stopped = (0..NUM_HART_MAX)
+        .filter(|&hart| hart != hart_id)
+        .filter_map(remote_hsm)
+        .all(|remote| remote.sbi_get_status() == hart_state::STOPPED);
+    if !others_stopped {
+        return SbiRet::denied();
+    }
+    if let Err(err) = suspend.enter(sleep_type) {
+        return err;
+    }
+    ipi::restore_local();
+    suspend.exit(sleep_type);
+    SbiRet::success(0)
+}
+
+/// Dispatch a call to the system suspend extension.
+///
+/// A successful suspend returns success, the trap handler then enters the
+/// supervisor at the resume address instead of returning to the caller.
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    match function {
+        SYSTEM_SUSPEND => system_suspend(param[0] as u32, param[1]),
+        _ => SbiRet::not_supported(),
+    }
+}
@@ -0,0 +1,110 @@
+//! Machine timer shared by the supervisor and the firmware.
+//!
+//! On harts without Sstc the supervisor timer is emulated on mtimecmp, which
+//! the firmware may also need for deadlines of its own. Each user owns one
+//! deadline per hart; mtimecmp is programmed to the earliest of them, and
+//! the machine timer interrupt clears every deadline that passed and notifies
+//! its user.
+
+use crate::platform;
+use crate::riscv_spec::current_hartid;
+use crate::sbi::inject;
+use crate::sync::Mutex;
+use crate::time;
+
+/// Users of the machine timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerClient {
+    /// Supervisor timer of `sbi_set_timer`, raises STIP when due.
+    Supervisor = 0,
+    /// Firmware deadline, runs the handler given to `set_firmware` when due.
+    Firmware = 1,
+}
+
+const CLIENTS: usize = 2;
+/// Deadline that never passes.
+const NEVER: u64 = u64::MAX;
+
+struct Deadlines {
+    at: [u64; CLIENTS],
+    firmware_handler: Option<fn()>,
+}
+
+percpu! {
+    /// Timer deadlines of each hart, only touched by the hart itself.
+    static DEADLINES: Mutex<Deadlines> = Mutex::named(
+        "timer deadlines",
+        Deadlines {
+            at: [NEVER; CLIENTS],
+            firmware_handler: None,
+        },
+    );
+}
+
+/// Program mtimecmp of the current hart to the earliest deadline.
+fn program(deadlines: &Deadlines) {
+    let next = deadlines.at.iter().copied().min().unwrap_or(NEVER);
+    if let Some(ipi) = platform::ipi() {
+        ipi.write_mtimecmp(current_hartid(), next);
+    }
+    if next != NEVER {
+        unsafe { riscv::register::mie::set_mtimer() };
+    }
+}
+
+/// Set the supervisor deadline of the current hart to `stime_value`.
+///
+/// Clears a pending supervisor timer interrupt, as `sbi_set_timer` requires.
+pub fn set_supervisor(stime_value: u64) {
+    let mut deadlines = DEADLINES.local().lock();
+    deadlines.at[TimerClient::Supervisor as usize] = stime_value;
+    inject::clear_timer();
+    program(&deadlines);
+}
+
+/// Run `handler` from the machine timer interrupt once mtime reaches `at`.
+///
+/// Replaces any firmware deadline of the current hart armed before.
+#[allow(unused)]
+pub fn set_firmware(at: u64, handler: fn()) {
+    let mut deadlines = DEADLINES.local().lock();
+    deadlines.at[TimerClient::Firmware as usize] = at;
+    deadlines.firmware_handler = Some(handler);
+    program(&deadlines);
+}
+
+/// Drop the deadline of `client` on the current hart.
+#[allow(unused)]
+pub fn cancel(client: TimerClient) {
+    let mut deadlines = DEADLINES.local().lock();
+    deadlines.at[client as usize] = NEVER;
+    program(&deadlines);
+}
+
+/// Notify the users of every passed deadline of the current hart.
+///
+/// Called from the machine timer interrupt.
+pub extern "C" fn expire() {
+    let now = time::current_ticks();
+    let mut due = [false; CLIENTS];
+    let firmware_handler = {
+        let mut deadlines = DEADLINES.local().lock();
+        for (at, due) in deadlines.at.iter_mut().zip(due.iter_mut()) {
+            if *at <= now {
+                *at = NEVER;
+                *due = true;
+            }
+        }
+        program(&deadlines);
+        deadlines.firmware_handler
+    };
+    if due[TimerClient::Supervisor as usize] {
+        inject::raise_timer();
+    }
+    // Called without the lock held, the handler may arm the next deadline.
+    if due[TimerClient::Firmware as usize] {
+        if let Some(handler) = firmware_handler {
+            handler();
+        }
+    }
+}
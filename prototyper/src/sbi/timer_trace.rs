@@ -0,0 +1,94 @@
+//! Optional per-hart record of `set_timer` calls.
+//!
+//! Enabled by the `timer-trace` feature to debug clock jumps and missed ticks
+//! on platforms where firmware emulates the supervisor timer through mtimecmp.
+
+use crate::platform::PLATFORM;
+use crate::sbi::trap_stack::ROOT_STACK;
+use rustsbi::SbiRet;
+
+/// Number of events kept per hart.
+const TRACE_LEN: usize = 32;
+
+/// A single `set_timer` call.
+#[derive(Clone, Copy, Debug)]
+pub struct TimerEvent {
+    /// Deadline requested by the supervisor.
+    pub stime_value: u64,
+    /// Machine time when the request arrived.
+    pub mtime: u64,
+}
+
+/// Ring buffer of the most recent timer events of one hart.
+pub(crate) struct TimerTrace {
+    events: [TimerEvent; TRACE_LEN],
+    /// Total number of events ever recorded.
+    total: usize,
+}
+
+impl TimerTrace {
+    pub const fn new() -> Self {
+        Self {
+            events: [TimerEvent {
+                stime_value: 0,
+                mtime: 0,
+            }; TRACE_LEN],
+            total: 0,
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, event: TimerEvent) {
+        self.events[self.total % TRACE_LEN] = event;
+        self.total += 1;
+    }
+
+    /// Iterate over the kept events, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &TimerEvent> {
+        let kept = self.total.min(TRACE_LEN);
+        (self.total - kept..self.total).map(|i| &self.events[i % TRACE_LEN])
+    }
+}
+
+/// Record a `set_timer` call on `hart_id`.
+#[inline]
+pub fn record(hart_id: usize, stime_value: u64, mtime: u64) {
+    if let Some(stack) = unsafe { ROOT_STACK.get_mut(hart_id) } {
+        stack
+            .hart_context()
+            .timer_trace
+            .lock()
+            .record(TimerEvent { stime_value, mtime });
+    }
+}
+
+/// Print the trace of `hart_id` to the firmware console.
+///
+/// Returns the total number of events recorded on that hart.
+pub fn dump(hart_id: usize) -> SbiRet {
+    if unsafe {
+        PLATFORM
+            .info
+            .cpu_enabled
+            .is_none_or(|list| list.get(hart_id).is_none_or(|res| !(*res)))
+    } {
+        return SbiRet::invalid_param();
+    }
+    let Some(stack) = (unsafe { ROOT_STACK.get_mut(hart_id) }) else {
+        return SbiRet::invalid_param();
+    };
+    let trace = stack.hart_context().timer_trace.lock();
+    info!(
+        "Timer trace of hart {}: {} events recorded",
+        hart_id, trace.total
+    );
+    for event in trace.iter() {
+        info!(
+            "  stime_value 0x{:016x} mtime 0x{:016x} delta {}",
+            event.stime_value,
+            event.mtime,
+            event.stime_value as i64 - event.mtime as i64
+        );
+    }
+    SbiRet::success(trace.total)
+}
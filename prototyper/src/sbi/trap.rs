@@ -1,17 +1,50 @@
 use core::arch::asm;
-use fast_trap::{trap_entry, FastContext, FastResult};
+use core::mem::{offset_of, size_of};
+use fast_trap::{trap_entry, EntireContext, EntireResult, FastContext, FastResult};
 use riscv::register::{
     mcause::{self, Exception as E, Trap as T},
-    mepc, mie, mstatus, mtval, satp, sstatus,
+    mepc, mie, mstatus, mtval,
 };
-use rustsbi::RustSBI;
+use rustsbi::{RustSBI, SbiRet};
 
+#[cfg(debug_assertions)]
+use crate::firmware;
+use crate::firmware::boot_protocol;
 use crate::platform::PLATFORM;
-use crate::riscv_spec::{current_hartid, CSR_TIME, CSR_TIMEH};
-use crate::sbi::console;
-use crate::sbi::hsm::local_hsm;
+#[cfg(target_arch = "riscv32")]
+use crate::riscv_spec::CSR_TIMEH;
+use crate::riscv_spec::{current_hartid, CSR_TIME};
+#[cfg(target_arch = "riscv64")]
+use crate::riscv_spec::{read_mstatus, MSTATUS_MPELP, MSTATUS_SPELP};
+use crate::sbi::base_cache;
+#[cfg(feature = "sbi-trace")]
+use crate::sbi::call_trace;
+use crate::sbi::crashdump;
+use crate::sbi::debug;
+use crate::sbi::domain;
+use crate::sbi::entropy;
+use crate::sbi::extension_mask;
+use crate::sbi::fence_i;
+use crate::sbi::fwft;
+use crate::sbi::guest_mem;
+use crate::sbi::hsm::{is_quarantined, local_hsm};
+use crate::sbi::idle_states;
+use crate::sbi::inject;
+use crate::sbi::insn::{self, Insn, Op};
 use crate::sbi::ipi;
+use crate::sbi::irq;
+#[cfg(feature = "legacy-sbi")]
+use crate::sbi::legacy;
+use crate::sbi::quarantine;
+#[cfg(feature = "rate-limit")]
+use crate::sbi::rate_limit;
 use crate::sbi::rfence::{self, local_rfence, RFenceType};
+use crate::sbi::susp;
+use crate::sbi::timer;
+use crate::sbi::trap_frame::{self, CallerSaved, InterruptFrame, SupervisorContext, XLENB};
+use crate::sbi::trap_stack;
+use crate::sbi::update;
+use crate::time;
 
 // Constants for page and TLB management
 const PAGE_SIZE: usize = 4096;
@@ -25,7 +58,7 @@ pub(crate) unsafe extern "C" fn trap_vec() {
         ".align 2",
         ".option push",
         ".option norvc",
-        "j {default}", // exception
+        "j {ecall}",   // exception
         "j {default}", // supervisor software
         "j {default}", // reserved
         "j {msoft} ",  // machine    software
@@ -36,106 +69,279 @@ pub(crate) unsafe extern "C" fn trap_vec() {
         "j {default}", // reserved
         "j {default}", // supervisor external
         "j {default}", // reserved
-        "j {default}", // machine    external
+        "j {mext}",    // machine    external
         ".option pop",
         default = sym trap_entry,
+        ecall   = sym ecall_fast,
         msoft   = sym msoft,
         mtimer  = sym mtimer,
+        mext    = sym mext,
         options(noreturn)
     )
 }
 
+/// Body of an interrupt entry saving an `InterruptFrame` around a call to `$handler`.
+macro_rules! interrupt_entry {
+    ($handler: path) => {
+        asm!(
+            // Switch stacks: sp <-> mscratch
+            "   csrrw sp, mscratch, sp",
+            // Save registers to stack
+            "   addi   sp, sp, -{size}",
+            frame_store!(ra),
+            frame_store!(gp),
+            frame_store!(tp),
+            frame_store!(t0),
+            frame_store!(t1),
+            frame_store!(t2),
+            frame_store!(s0),
+            frame_store!(s1),
+            frame_store!(a0),
+            frame_store!(a1),
+            frame_store!(a2),
+            frame_store!(a3),
+            frame_store!(a4),
+            frame_store!(a5),
+            frame_store!(a6),
+            frame_store!(a7),
+            frame_store!(s2),
+            frame_store!(s3),
+            frame_store!(s4),
+            frame_store!(s5),
+            frame_store!(s6),
+            frame_store!(s7),
+            frame_store!(s8),
+            frame_store!(s9),
+            frame_store!(s10),
+            frame_store!(s11),
+            frame_store!(t3),
+            frame_store!(t4),
+            frame_store!(t5),
+            frame_store!(t6),
+            "    call  {handler}",
+            // Restore registers from stack
+            frame_load!(ra),
+            frame_load!(gp),
+            frame_load!(tp),
+            frame_load!(t0),
+            frame_load!(t1),
+            frame_load!(t2),
+            frame_load!(s0),
+            frame_load!(s1),
+            frame_load!(a0),
+            frame_load!(a1),
+            frame_load!(a2),
+            frame_load!(a3),
+            frame_load!(a4),
+            frame_load!(a5),
+            frame_load!(a6),
+            frame_load!(a7),
+            frame_load!(s2),
+            frame_load!(s3),
+            frame_load!(s4),
+            frame_load!(s5),
+            frame_load!(s6),
+            frame_load!(s7),
+            frame_load!(s8),
+            frame_load!(s9),
+            frame_load!(s10),
+            frame_load!(s11),
+            frame_load!(t3),
+            frame_load!(t4),
+            frame_load!(t5),
+            frame_load!(t6),
+            "   addi   sp, sp, {size}",
+            // Switch stacks back: sp <-> mscratch
+            "   csrrw sp, mscratch, sp",
+            // Return from machine mode
+            "   mret",
+            handler = sym $handler,
+            size = const size_of::<InterruptFrame>(),
+            ra = const offset_of!(InterruptFrame, ra),
+            gp = const offset_of!(InterruptFrame, gp),
+            tp = const offset_of!(InterruptFrame, tp),
+            t0 = const offset_of!(InterruptFrame, t0),
+            t1 = const offset_of!(InterruptFrame, t1),
+            t2 = const offset_of!(InterruptFrame, t2),
+            s0 = const offset_of!(InterruptFrame, s0),
+            s1 = const offset_of!(InterruptFrame, s1),
+            a0 = const offset_of!(InterruptFrame, a0),
+            a1 = const offset_of!(InterruptFrame, a1),
+            a2 = const offset_of!(InterruptFrame, a2),
+            a3 = const offset_of!(InterruptFrame, a3),
+            a4 = const offset_of!(InterruptFrame, a4),
+            a5 = const offset_of!(InterruptFrame, a5),
+            a6 = const offset_of!(InterruptFrame, a6),
+            a7 = const offset_of!(InterruptFrame, a7),
+            s2 = const offset_of!(InterruptFrame, s2),
+            s3 = const offset_of!(InterruptFrame, s3),
+            s4 = const offset_of!(InterruptFrame, s4),
+            s5 = const offset_of!(InterruptFrame, s5),
+            s6 = const offset_of!(InterruptFrame, s6),
+            s7 = const offset_of!(InterruptFrame, s7),
+            s8 = const offset_of!(InterruptFrame, s8),
+            s9 = const offset_of!(InterruptFrame, s9),
+            s10 = const offset_of!(InterruptFrame, s10),
+            s11 = const offset_of!(InterruptFrame, s11),
+            t3 = const offset_of!(InterruptFrame, t3),
+            t4 = const offset_of!(InterruptFrame, t4),
+            t5 = const offset_of!(InterruptFrame, t5),
+            t6 = const offset_of!(InterruptFrame, t6),
+            options(noreturn)
+        )
+    };
+}
+
 /// Machine timer interrupt handler.
-/// Saves context, clears mtimecmp, sets STIP bit, and restores context.
+/// Saves context, dispatches passed timer deadlines, and restores context.
 ///
 /// # Safety
 ///
 /// This is a naked function that directly manipulates registers and stack.
 #[naked]
 unsafe extern "C" fn mtimer() {
+    // Notify the supervisor or firmware whose deadline passed
+    interrupt_entry!(timer::expire)
+}
+
+/// Machine external interrupt handler.
+/// Saves context, dispatches firmware owned interrupts, and restores context.
+///
+/// # Safety
+///
+/// This is a naked function that directly manipulates registers and stack.
+#[naked]
+unsafe extern "C" fn mext() {
+    // Claim and handle pending interrupts
+    interrupt_entry!(irq::mext_handler)
+}
+
+/// Exception entry with a fast path for hot SBI calls.
+///
+/// Hart local calls that need no trap state, such as `set_timer` and the
+/// base extension queries, are served after saving only the caller-saved
+/// registers. Everything else leaves through `trap_entry` with all registers
+/// untouched.
+///
+/// # Safety
+///
+/// This is a naked function that directly manipulates registers and stack.
+#[naked]
+unsafe extern "C" fn ecall_fast() {
     asm!(
-        // Switch stacks: sp <-> mscratch
-        "   csrrw sp, mscratch, sp",
-        // Save registers to stack
-        "   addi   sp, sp, -30*8",
-        "   sd     ra, 0*8(sp)
-            sd      gp, 2*8(sp)
-            sd      tp, 3*8(sp)
-            sd      t0, 4*8(sp)
-            sd      t1, 5*8(sp)
-            sd      t2, 6*8(sp)
-            sd      s0, 7*8(sp)
-            sd      s1, 8*8(sp)
-            sd      a0, 9*8(sp)
-            sd      a1, 10*8(sp)
-            sd      a2, 11*8(sp)
-            sd      a3, 12*8(sp)
-            sd      a4, 13*8(sp)
-            sd      a5, 14*8(sp)
-            sd      a6, 15*8(sp)
-            sd      a7, 16*8(sp)
-            sd      s2, 17*8(sp)
-            sd      s3, 18*8(sp)
-            sd      s4, 19*8(sp)
-            sd      s5, 20*8(sp)
-            sd      s6, 21*8(sp)
-            sd      s7, 22*8(sp)
-            sd      s8, 23*8(sp)
-            sd      s9, 24*8(sp)
-            sd     s10, 25*8(sp)
-            sd     s11, 26*8(sp)
-            sd      t3, 27*8(sp)
-            sd      t4, 28*8(sp)
-            sd      t5, 29*8(sp)
-            sd      t6, 1*8(sp)",
-        // Clear machine timer compare register
-        "    call  {clear_mtime}",
-        // Set supervisor timer interrupt pending bit
-        "   li    a0, {mip_stip}
-            csrrs zero, mip, a0
-        ",
-        // Restore registers from stack
-        "   ld     ra, 0*8(sp)
-            ld      gp, 2*8(sp)
-            ld      tp, 3*8(sp)
-            ld      t0, 4*8(sp)
-            ld      t1, 5*8(sp)
-            ld      t2, 6*8(sp)
-            ld      s0, 7*8(sp)
-            ld      s1, 8*8(sp)
-            ld      a0, 9*8(sp)
-            ld      a1, 10*8(sp)
-            ld      a2, 11*8(sp)
-            ld      a3, 12*8(sp)
-            ld      a4, 13*8(sp)
-            ld      a5, 14*8(sp)
-            ld      a6, 15*8(sp)
-            ld      a7, 16*8(sp)
-            ld      s2, 17*8(sp)
-            ld      s3, 18*8(sp)
-            ld      s4, 19*8(sp)
-            ld      s5, 20*8(sp)
-            ld      s6, 21*8(sp)
-            ld      s7, 22*8(sp)
-            ld      s8, 23*8(sp)
-            ld      s9, 24*8(sp)
-            ld     s10, 25*8(sp)
-            ld     s11, 26*8(sp)
-            ld      t3, 27*8(sp)
-            ld      t4, 28*8(sp)
-            ld      t5, 29*8(sp)
-            ld      t6, 1*8(sp)",
-        "   addi   sp, sp, 30*8",
-        // Switch stacks back: sp <-> mscratch
-        "   csrrw sp, mscratch, sp",
-        // Return from machine mode
-        "   mret",
-        mip_stip    = const 1 << 5,
-        clear_mtime = sym ipi::clear_mtime,
+        ".align 2",
+        // Switch stacks
+        "csrrw  sp, mscratch, sp",
+        "addi   sp, sp, -{size}",
+        frame_store!(t0),
+        // Only supervisor environment calls take the fast path
+        "csrr   t0, mcause
+        addi    t0, t0, -9
+        bnez    t0, 2f",
+        frame_store!(t1),
+        frame_store!(t2),
+        frame_store!(t3),
+        frame_store!(t4),
+        frame_store!(t5),
+        frame_store!(t6),
+        frame_store!(ra),
+        frame_store!(a0),
+        frame_store!(a1),
+        frame_store!(a2),
+        frame_store!(a3),
+        frame_store!(a4),
+        frame_store!(a5),
+        frame_store!(a6),
+        frame_store!(a7),
+        "mv     a0, sp",
+        "call   {handler}",
+        "mv     t0, a0",
+        frame_load!(t1),
+        frame_load!(t2),
+        frame_load!(t3),
+        frame_load!(t4),
+        frame_load!(t5),
+        frame_load!(t6),
+        frame_load!(ra),
+        frame_load!(a0),
+        frame_load!(a1),
+        frame_load!(a2),
+        frame_load!(a3),
+        frame_load!(a4),
+        frame_load!(a5),
+        frame_load!(a6),
+        frame_load!(a7),
+        "beqz   t0, 2f",
+        // Handled, step over the ecall
+        "csrr   t0, mepc
+        addi    t0, t0, 4
+        csrw    mepc, t0",
+        frame_load!(t0),
+        "addi   sp, sp, {size}",
+        "csrrw  sp, mscratch, sp",
+        "mret",
+        // Not handled here, take the full trap path
+        "2:",
+        frame_load!(t0),
+        "addi   sp, sp, {size}",
+        "csrrw  sp, mscratch, sp",
+        "j       {trap_entry}",
+        handler    = sym ecall_fast_handler,
+        trap_entry = sym trap_entry,
+        size = const size_of::<CallerSaved>(),
+        t0 = const offset_of!(CallerSaved, t),
+        t1 = const offset_of!(CallerSaved, t) + XLENB,
+        t2 = const offset_of!(CallerSaved, t) + 2 * XLENB,
+        t3 = const offset_of!(CallerSaved, t) + 3 * XLENB,
+        t4 = const offset_of!(CallerSaved, t) + 4 * XLENB,
+        t5 = const offset_of!(CallerSaved, t) + 5 * XLENB,
+        t6 = const offset_of!(CallerSaved, t) + 6 * XLENB,
+        ra = const offset_of!(CallerSaved, ra),
+        a0 = const offset_of!(CallerSaved, a),
+        a1 = const offset_of!(CallerSaved, a) + XLENB,
+        a2 = const offset_of!(CallerSaved, a) + 2 * XLENB,
+        a3 = const offset_of!(CallerSaved, a) + 3 * XLENB,
+        a4 = const offset_of!(CallerSaved, a) + 4 * XLENB,
+        a5 = const offset_of!(CallerSaved, a) + 5 * XLENB,
+        a6 = const offset_of!(CallerSaved, a) + 6 * XLENB,
+        a7 = const offset_of!(CallerSaved, a) + 7 * XLENB,
         options(noreturn)
     )
 }
 
+/// Serve an SBI call from `ecall_fast`, returns false to take the full trap path.
+extern "C" fn ecall_fast_handler(frame: &mut CallerSaved) -> bool {
+    use sbi_spec::{base, time};
+    let [a0, a1, a2, a3, a4, a5, a6, a7] = frame.a;
+    // Calls the build, the boot or the caller's domain filter out are
+    // refused, and logged, by `fast_handler`.
+    if !extension_mask::is_enabled(a7) || !domain::allows(a7) {
+        return false;
+    }
+    let ret = match base_cache::lookup(a7, a6) {
+        // Constant answers skip the dispatcher.
+        Some(value) => SbiRet::success(value),
+        None => {
+            let hot = match a7 {
+                // Probing and the version need the fix-ups done in `fast_handler`.
+                base::EID_BASE => a6 != base::PROBE_EXTENSION && a6 != base::GET_SBI_SPEC_VERSION,
+                time::EID_TIME => a6 == time::SET_TIMER,
+                _ => false,
+            };
+            if !hot {
+                return false;
+            }
+            unsafe { PLATFORM.sbi.handle_ecall(a7, a6, [a0, a1, a2, a3, a4, a5]) }
+        }
+    };
+    #[cfg(feature = "sbi-trace")]
+    call_trace::record(a7, a6, [a0, a1, a2, a3, a4, a5], ret);
+    frame.a[0] = ret.error;
+    frame.a[1] = ret.value;
+    trap_stack::check_canary();
+    true
+}
+
 /// Machine software interrupt handler.
 ///
 /// Handles inter-processor interrupts.
@@ -146,87 +352,120 @@ pub unsafe extern "C" fn msoft() -> ! {
         // Switch stacks
         "csrrw  sp, mscratch, sp",
         // Allocate stack space
-        "addi   sp, sp, -32*8",
+        "addi   sp, sp, -{size}",
         // Save registers
-        "sd     ra, 0*8(sp)
-        sd      gp, 2*8(sp)
-        sd      tp, 3*8(sp)
-        sd      t0, 4*8(sp)
-        sd      t1, 5*8(sp)
-        sd      t2, 6*8(sp)
-        sd      s0, 7*8(sp)
-        sd      s1, 8*8(sp)
-        sd      a0, 9*8(sp)
-        sd      a1, 10*8(sp)
-        sd      a2, 11*8(sp)
-        sd      a3, 12*8(sp)
-        sd      a4, 13*8(sp)
-        sd      a5, 14*8(sp)
-        sd      a6, 15*8(sp)
-        sd      a7, 16*8(sp)
-        sd      s2, 17*8(sp)
-        sd      s3, 18*8(sp)
-        sd      s4, 19*8(sp)
-        sd      s5, 20*8(sp)
-        sd      s6, 21*8(sp)
-        sd      s7, 22*8(sp)
-        sd      s8, 23*8(sp)
-        sd      s9, 24*8(sp)
-        sd     s10, 25*8(sp)
-        sd     s11, 26*8(sp)
-        sd      t3, 27*8(sp)
-        sd      t4, 28*8(sp)
-        sd      t5, 29*8(sp)
-        sd      t6, 30*8(sp)",
+        frame_store!(ra),
+        frame_store!(gp),
+        frame_store!(tp),
+        frame_store!(t0),
+        frame_store!(t1),
+        frame_store!(t2),
+        frame_store!(s0),
+        frame_store!(s1),
+        frame_store!(a0),
+        frame_store!(a1),
+        frame_store!(a2),
+        frame_store!(a3),
+        frame_store!(a4),
+        frame_store!(a5),
+        frame_store!(a6),
+        frame_store!(a7),
+        frame_store!(s2),
+        frame_store!(s3),
+        frame_store!(s4),
+        frame_store!(s5),
+        frame_store!(s6),
+        frame_store!(s7),
+        frame_store!(s8),
+        frame_store!(s9),
+        frame_store!(s10),
+        frame_store!(s11),
+        frame_store!(t3),
+        frame_store!(t4),
+        frame_store!(t5),
+        frame_store!(t6),
         // Save mepc and mscratch
-        "csrr   t0, mepc
-        sd      t0, 31*8(sp)",
+        "csrr   t0, mepc",
+        frame_store!(t0, mepc),
         "csrr   t2, mscratch",
-        "sd     t2, 1*8(sp)",
+        frame_store!(t2, sp),
         // Call handler with context pointer
         "mv     a0, sp",
         "call   {msoft_handler}",
         // Restore mepc
-        "ld     t0, 31*8(sp)
-        csrw    mepc, t0",
+        frame_load!(t0, mepc),
+        "csrw   mepc, t0",
         // Restore registers
-        "ld     ra, 0*8(sp)
-        ld      gp, 2*8(sp)
-        ld      tp, 3*8(sp)
-        ld      t0, 4*8(sp)
-        ld      t1, 5*8(sp)
-        ld      t2, 6*8(sp)
-        ld      s0, 7*8(sp)
-        ld      s1, 8*8(sp)
-        ld      a0, 9*8(sp)
-        ld      a1, 10*8(sp)
-        ld      a2, 11*8(sp)
-        ld      a3, 12*8(sp)
-        ld      a4, 13*8(sp)
-        ld      a5, 14*8(sp)
-        ld      a6, 15*8(sp)
-        ld      a7, 16*8(sp)
-        ld      s2, 17*8(sp)
-        ld      s3, 18*8(sp)
-        ld      s4, 19*8(sp)
-        ld      s5, 20*8(sp)
-        ld      s6, 21*8(sp)
-        ld      s7, 22*8(sp)
-        ld      s8, 23*8(sp)
-        ld      s9, 24*8(sp)
-        ld     s10, 25*8(sp)
-        ld     s11, 26*8(sp)
-        ld      t3, 27*8(sp)
-        ld      t4, 28*8(sp)
-        ld      t5, 29*8(sp)
-        ld      t6, 30*8(sp)",
+        frame_load!(ra),
+        frame_load!(gp),
+        frame_load!(tp),
+        frame_load!(t0),
+        frame_load!(t1),
+        frame_load!(t2),
+        frame_load!(s0),
+        frame_load!(s1),
+        frame_load!(a0),
+        frame_load!(a1),
+        frame_load!(a2),
+        frame_load!(a3),
+        frame_load!(a4),
+        frame_load!(a5),
+        frame_load!(a6),
+        frame_load!(a7),
+        frame_load!(s2),
+        frame_load!(s3),
+        frame_load!(s4),
+        frame_load!(s5),
+        frame_load!(s6),
+        frame_load!(s7),
+        frame_load!(s8),
+        frame_load!(s9),
+        frame_load!(s10),
+        frame_load!(s11),
+        frame_load!(t3),
+        frame_load!(t4),
+        frame_load!(t5),
+        frame_load!(t6),
         // Restore stack pointer
-        "addi   sp, sp, 32*8",
+        "addi   sp, sp, {size}",
         // Switch stacks back
         "csrrw  sp, mscratch, sp",
         // Return from machine mode
         "mret",
         msoft_handler = sym msoft_handler,
+        size = const size_of::<SupervisorContext>(),
+        ra = const offset_of!(SupervisorContext, ra),
+        gp = const offset_of!(SupervisorContext, gp),
+        tp = const offset_of!(SupervisorContext, tp),
+        t0 = const offset_of!(SupervisorContext, t0),
+        t1 = const offset_of!(SupervisorContext, t1),
+        t2 = const offset_of!(SupervisorContext, t2),
+        s0 = const offset_of!(SupervisorContext, s0),
+        s1 = const offset_of!(SupervisorContext, s1),
+        a0 = const offset_of!(SupervisorContext, a0),
+        a1 = const offset_of!(SupervisorContext, a1),
+        a2 = const offset_of!(SupervisorContext, a2),
+        a3 = const offset_of!(SupervisorContext, a3),
+        a4 = const offset_of!(SupervisorContext, a4),
+        a5 = const offset_of!(SupervisorContext, a5),
+        a6 = const offset_of!(SupervisorContext, a6),
+        a7 = const offset_of!(SupervisorContext, a7),
+        s2 = const offset_of!(SupervisorContext, s2),
+        s3 = const offset_of!(SupervisorContext, s3),
+        s4 = const offset_of!(SupervisorContext, s4),
+        s5 = const offset_of!(SupervisorContext, s5),
+        s6 = const offset_of!(SupervisorContext, s6),
+        s7 = const offset_of!(SupervisorContext, s7),
+        s8 = const offset_of!(SupervisorContext, s8),
+        s9 = const offset_of!(SupervisorContext, s9),
+        s10 = const offset_of!(SupervisorContext, s10),
+        s11 = const offset_of!(SupervisorContext, s11),
+        t3 = const offset_of!(SupervisorContext, t3),
+        t4 = const offset_of!(SupervisorContext, t4),
+        t5 = const offset_of!(SupervisorContext, t5),
+        t6 = const offset_of!(SupervisorContext, t6),
+        sp = const offset_of!(SupervisorContext, sp),
+        mepc = const offset_of!(SupervisorContext, mepc),
         options(noreturn)
     );
 }
@@ -237,9 +476,8 @@ pub unsafe extern "C" fn msoft() -> ! {
 pub extern "C" fn msoft_handler(ctx: &mut SupervisorContext) {
     #[inline(always)]
     fn boot(ctx: &mut SupervisorContext, start_addr: usize, opaque: usize) {
-        unsafe {
-            sstatus::clear_sie();
-            satp::write(0);
+        if boot_protocol::prepare(opaque).zero_registers {
+            *ctx = SupervisorContext::default();
         }
         ctx.a0 = current_hartid();
         ctx.a1 = opaque;
@@ -250,6 +488,9 @@ pub extern "C" fn msoft_handler(ctx: &mut SupervisorContext) {
         // Handle HSM Start
         Ok(next_stage) => {
             ipi::clear_msip();
+            // IPIs sent while the hart was stopped are not for the new start.
+            inject::clear_software();
+            fwft::reset_local();
             unsafe {
                 mstatus::set_mpie();
                 mstatus::set_mpp(next_stage.next_mode);
@@ -259,13 +500,16 @@ pub extern "C" fn msoft_handler(ctx: &mut SupervisorContext) {
             boot(ctx, next_stage.start_addr, next_stage.opaque);
         }
         // Handle HSM Stop
-        Err(rustsbi::spec::hsm::HART_STOP) => {
+        Err(rustsbi::spec::hsm::hart_state::STOPPED) => {
             ipi::clear_msip();
             unsafe {
                 mie::set_msoft();
             }
+            trap_stack::check_canary();
             riscv::asm::wfi();
         }
+        // Given up on while starting
+        Err(state) if is_quarantined(state) => quarantine::enter(),
         // Handle RFence
         _ => {
             msoft_ipi_handler();
@@ -341,18 +585,25 @@ pub fn rfence_handler() {
 /// Handle machine software inter-processor interrupts.
 pub fn msoft_ipi_handler() {
     use ipi::get_and_reset_ipi_type;
+    debug::count(debug::Event::IpiReceived);
     ipi::clear_msip();
     let ipi_type = get_and_reset_ipi_type();
     // Handle supervisor software interrupt
     if (ipi_type & ipi::IPI_TYPE_SSOFT) != 0 {
-        unsafe {
-            riscv::register::mip::set_ssoft();
-        }
+        inject::raise_software();
     }
     // Handle fence operation
     if (ipi_type & ipi::IPI_TYPE_FENCE) != 0 {
         rfence_handler();
     }
+    // Handle instruction fences
+    if (ipi_type & ipi::IPI_TYPE_FENCE_I) != 0 {
+        fence_i::handle_local();
+    }
+    // Move into the firmware update stub, this returns only if the update is abandoned
+    if (ipi_type & ipi::IPI_TYPE_UPDATE) != 0 {
+        update::join_update();
+    }
 }
 
 /// Fast trap handler for SBI calls and illegal instructions.
@@ -368,58 +619,167 @@ pub extern "C" fn fast_handler(
 ) -> FastResult {
     #[inline]
     fn resume(mut ctx: FastContext, start_addr: usize, opaque: usize) -> FastResult {
-        unsafe {
-            sstatus::clear_sie();
-            satp::write(0);
+        // Hart set-up first, `a0` and `a1` must be the last registers written.
+        let zero_registers = boot_protocol::prepare(opaque).zero_registers;
+        let regs = ctx.regs();
+        regs.a[0] = current_hartid();
+        regs.a[1] = opaque;
+        regs.pc = start_addr;
+        if zero_registers {
+            regs.ra = 0;
+            regs.t = [0; 7];
+            regs.a[2..].fill(0);
+            regs.s = [0; 12];
+            regs.gp = 0;
+            regs.tp = 0;
+            regs.sp = 0;
+            ctx.restore()
+        } else {
+            ctx.call(2)
         }
-        ctx.regs().a[0] = current_hartid();
-        ctx.regs().a[1] = opaque;
-        ctx.regs().pc = start_addr;
-        ctx.call(2)
     }
+    debug::count(debug::Event::Trap);
     match mcause::read().cause() {
         // Handle SBI calls
         T::Exception(E::SupervisorEnvCall) => {
-            use sbi_spec::{base, hsm, legacy};
-            let mut ret = unsafe {
-                PLATFORM
-                    .sbi
-                    .handle_ecall(a7, a6, [ctx.a0(), a1, a2, a3, a4, a5])
+            use sbi_spec::legacy::{LEGACY_SET_TIMER, LEGACY_SHUTDOWN};
+            use sbi_spec::{base, hsm};
+            let enabled = extension_mask::is_enabled(a7) && domain::check_call(a7);
+            // Unknown functions are refused here, without any side effect.
+            let implemented = extension_mask::is_implemented(a7, a6);
+            let legacy_call = (LEGACY_SET_TIMER..=LEGACY_SHUTDOWN).contains(&a7);
+            // A call the hart has no tokens left for is refused, a console
+            // write only gets the bytes it has tokens for.
+            #[cfg(feature = "rate-limit")]
+            let grant = if enabled && (implemented || legacy_call) {
+                rate_limit::charge_call(a7, a6, ctx.a0())
+            } else {
+                rate_limit::Grant::Full
+            };
+            #[cfg(feature = "rate-limit")]
+            let (denied, a0) = match grant {
+                rate_limit::Grant::Full => (false, ctx.a0()),
+                rate_limit::Grant::Partial(count) => (false, count as usize),
+                rate_limit::Grant::Denied => (true, ctx.a0()),
+            };
+            #[cfg(not(feature = "rate-limit"))]
+            let (denied, a0) = (false, ctx.a0());
+            let mut ret = if !enabled || !implemented {
+                SbiRet::not_supported()
+            } else if denied {
+                SbiRet::denied()
+            } else if a7 == debug::EID_DEBUG {
+                debug::handle_ecall(a6, [a0, a1, a2, a3, a4, a5])
+            } else if a7 == update::EID_UPDATE {
+                update::handle_ecall(a6, [a0, a1, a2, a3, a4, a5])
+            } else if a7 == entropy::EID_ENTROPY {
+                entropy::handle_ecall(a6, [a0, a1, a2, a3, a4, a5])
+            } else if a7 == fwft::EID_FWFT {
+                fwft::handle_ecall(a6, [a0, a1, a2, a3, a4, a5])
+            } else if a7 == susp::EID_SUSP {
+                susp::handle_ecall(a6, [a0, a1, a2, a3, a4, a5])
+            } else {
+                unsafe { PLATFORM.sbi.handle_ecall(a7, a6, [a0, a1, a2, a3, a4, a5]) }
             };
             if ret.is_ok() {
                 match (a7, a6) {
+                    // Report the configured specification version
+                    (base::EID_BASE, base::GET_SBI_SPEC_VERSION) => {
+                        ret.value = extension_mask::spec_version().encoded();
+                    }
+                    // Report disabled extensions as absent
+                    (base::EID_BASE, base::PROBE_EXTENSION)
+                        if !extension_mask::is_enabled(ctx.a0()) || !domain::allows(ctx.a0()) =>
+                    {
+                        ret.value = 0;
+                    }
                     // Handle non-retentive suspend
                     (hsm::EID_HSM, hsm::HART_SUSPEND)
-                        if matches!(ctx.a0() as u32, hsm::suspend_type::NON_RETENTIVE) =>
+                        if idle_states::non_retentive(ctx.a0() as u32) =>
                     {
                         return resume(ctx, a1, a2);
                     }
-                    // Handle legacy console probe
+                    // Enter the supervisor where it asked to resume after system suspend
+                    (susp::EID_SUSP, susp::SYSTEM_SUSPEND) => return resume(ctx, a1, a2),
+                    // Park a stopped hart until it is started again
+                    (hsm::EID_HSM, hsm::HART_STOP) => {
+                        let next_stage = loop {
+                            trap_stack::check_canary();
+                            riscv::asm::wfi();
+                            ipi::clear_msip();
+                            if (ipi::get_and_reset_ipi_type() & ipi::IPI_TYPE_UPDATE) != 0 {
+                                update::join_update();
+                            }
+                            // Acknowledge instruction fences sent before we stopped.
+                            fence_i::handle_local();
+                            match local_hsm().start() {
+                                Ok(next_stage) => break next_stage,
+                                Err(state) if is_quarantined(state) => quarantine::enter(),
+                                Err(_) => {}
+                            }
+                        };
+                        inject::clear_software();
+                        fwft::reset_local();
+                        unsafe {
+                            mstatus::set_mpp(next_stage.next_mode);
+                        }
+                        return resume(ctx, next_stage.start_addr, next_stage.opaque);
+                    }
+                    // Handle legacy extension probe
+                    #[cfg(feature = "legacy-sbi")]
+                    (base::EID_BASE, base::PROBE_EXTENSION) if legacy::probe(ctx.a0()) => {
+                        ret.value = 1;
+                    }
+                    // Handle debug extension probe
+                    (base::EID_BASE, base::PROBE_EXTENSION) if ctx.a0() == debug::EID_DEBUG => {
+                        ret.value = 1;
+                    }
+                    // Handle update extension probe, absent with Smepmp
                     (base::EID_BASE, base::PROBE_EXTENSION)
-                        if matches!(
-                            ctx.a0(),
-                            legacy::LEGACY_CONSOLE_PUTCHAR | legacy::LEGACY_CONSOLE_GETCHAR
-                        ) =>
+                        if ctx.a0() == update::EID_UPDATE && update::available() =>
                     {
                         ret.value = 1;
                     }
-                    _ => {}
-                }
-            } else {
-                match a7 {
-                    legacy::LEGACY_CONSOLE_PUTCHAR => {
-                        ret.error = console::putchar(ctx.a0());
-                        ret.value = a1;
+                    // Handle firmware features extension probe
+                    (base::EID_BASE, base::PROBE_EXTENSION) if ctx.a0() == fwft::EID_FWFT => {
+                        ret.value = 1;
                     }
-                    legacy::LEGACY_CONSOLE_GETCHAR => {
-                        ret.error = console::getchar();
-                        ret.value = a1;
+                    // Handle system suspend extension probe, present only with a suspend device
+                    (base::EID_BASE, base::PROBE_EXTENSION)
+                        if ctx.a0() == susp::EID_SUSP && susp::available() =>
+                    {
+                        ret.value = 1;
+                    }
+                    // Handle entropy extension probe, present only with an entropy source
+                    (base::EID_BASE, base::PROBE_EXTENSION)
+                        if ctx.a0() == entropy::EID_ENTROPY && entropy::available() =>
+                    {
+                        ret.value = 1;
                     }
                     _ => {}
                 }
+            } else if legacy_call {
+                // Legacy calls return their value in a0 and leave a1 untouched,
+                // also when they are not supported or built out.
+                ret.value = a1;
+                if denied {
+                    ret.error = SbiRet::denied().error;
+                }
+                #[cfg(feature = "legacy-sbi")]
+                if enabled && !denied {
+                    if let Some(value) = legacy::handle_ecall(a7, [ctx.a0(), a1, a2, a3, a4, a5]) {
+                        ret.error = value;
+                    }
+                }
+            }
+            if !legacy_call {
+                extension_mask::mask_error(&mut ret);
             }
+            #[cfg(feature = "sbi-trace")]
+            call_trace::record(a7, a6, [ctx.a0(), a1, a2, a3, a4, a5], ret);
             ctx.regs().a = [ret.error, ret.value, a2, a3, a4, a5, a6, a7];
             mepc::write(mepc::read() + 4);
+            trap_stack::check_canary();
             ctx.restore()
         }
         // Handle illegal instructions
@@ -429,23 +789,97 @@ pub extern "C" fn fast_handler(
             }
 
             ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
-            if !illegal_instruction_handler(&mut ctx) {
-                delegate();
+            match illegal_instruction_handler() {
+                Some((rd, value)) if trap_frame::caller_saved(rd) => {
+                    trap_frame::set_gpr(ctx.regs(), rd, value)
+                }
+                // Other registers are live in the hart, the entire path saves
+                // and restores them all.
+                Some(write) => {
+                    trap_stack::check_canary();
+                    return ctx.continue_with(write_register, write);
+                }
+                None => delegate(),
+            }
+            trap_stack::check_canary();
+            ctx.restore()
+        }
+        // Breakpoints are only kept in M-mode while firmware watchpoints are armed.
+        #[cfg(debug_assertions)]
+        T::Exception(E::Breakpoint) => {
+            if firmware::watchpoint::check_hit() {
+                crashdump::write(Some(&*ctx.regs()));
+                error!("-----------------------------");
+                error!("mepc:    {:#018x}", mepc::read());
+                error!("mtval:   {:#018x}", mtval::read());
+                error!("-----------------------------");
+                panic!(
+                    "Store from {:?} mode into watched firmware data",
+                    mstatus::read().mpp()
+                );
+            }
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            delegate();
+            trap_stack::check_canary();
+            ctx.restore()
+        }
+        // Misaligned AMOs the core refuses are performed here.
+        #[cfg(feature = "misaligned-amo")]
+        trap @ T::Exception(
+            E::LoadMisaligned | E::LoadFault | E::StoreMisaligned | E::StoreFault,
+        ) => {
+            use crate::sbi::misaligned_amo::{self, Emulation};
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            match misaligned_amo::emulate(ctx.regs()) {
+                Emulation::Done => {}
+                // The supervisor pages the memory in and runs the AMO again.
+                Emulation::Fault { cause, tval } => unsafe {
+                    asm!("csrw mcause, {}", in(reg) cause);
+                    asm!("csrw mtval, {}", in(reg) tval);
+                    delegate();
+                },
+                Emulation::Unsupported => return unsupported_trap(ctx, trap),
             }
+            trap_stack::check_canary();
             ctx.restore()
         }
         // Handle other traps
         trap => {
-            error!("-----------------------------");
-            error!("trap:    {trap:?}");
-            error!("mepc:    {:#018x}", mepc::read());
-            error!("mtval:   {:#018x}", mtval::read());
-            error!("-----------------------------");
-            panic!("Stopped with unsupported trap")
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            unsupported_trap(ctx, trap)
         }
     }
 }
 
+/// Report a trap the firmware does not handle, then forward it to the
+/// supervisor or stop the hart. `ctx` holds all argument registers.
+fn unsupported_trap(mut ctx: FastContext, trap: T) -> FastResult {
+    crashdump::write(Some(&*ctx.regs()));
+    error!("-----------------------------");
+    error!("trap:    {trap:?}");
+    error!("mepc:    {:#018x}", mepc::read());
+    error!("mtval:   {:#018x}", mtval::read());
+    let insn = match guest_mem::Mode::trapped() {
+        Some(mode) => guest_mem::fetch(mode, mepc::read()).ok(),
+        None => insn::fetch_firmware(mepc::read()),
+    };
+    if let Some(insn) = insn {
+        error!("insn:    {}", insn);
+    }
+    error!("-----------------------------");
+    // Let the supervisor deal with its own faults, up to the threshold.
+    if mstatus::read().mpp() != mstatus::MPP::Machine
+        && quarantine::available()
+        && !quarantine::record_fault()
+    {
+        warn!("Hart {} forwarding unsupported trap", current_hartid());
+        delegate();
+        trap_stack::check_canary();
+        return ctx.restore();
+    }
+    panic!("Stopped with unsupported trap")
+}
+
 /// Delegate trap handling to supervisor mode.
 #[inline]
 fn delegate() {
@@ -461,80 +895,49 @@ fn delegate() {
         } else {
             sstatus::set_spp(sstatus::SPP::User);
         }
+        // Entering the supervisor handler saves the landing pad state, as hardware would.
+        #[cfg(target_arch = "riscv64")]
+        if read_mstatus() & MSTATUS_MPELP != 0 {
+            asm!("csrc mstatus, {}", in(reg) MSTATUS_MPELP);
+            asm!("csrs mstatus, {}", in(reg) MSTATUS_SPELP);
+        } else {
+            asm!("csrc mstatus, {}", in(reg) MSTATUS_SPELP);
+        }
         mstatus::set_mpp(mstatus::MPP::Supervisor);
         mepc::write(stvec::read().address());
     }
 }
 
-/// Handle illegal instructions, particularly CSR access.
+/// Emulate illegal instructions, particularly CSR access, stepping over them.
+///
+/// Returns the register the instruction writes and its value, or `None` if
+/// it is not emulated.
 #[inline]
-fn illegal_instruction_handler(ctx: &mut FastContext) -> bool {
+fn illegal_instruction_handler() -> Option<(u32, usize)> {
     use riscv::register::{mepc, mtval};
-    use riscv_decode::{decode, Instruction};
 
-    let inst = decode(mtval::read() as u32);
-    match inst {
-        Ok(Instruction::Csrrs(csr)) => match csr.csr() {
-            CSR_TIME => {
-                assert!(
-                    10 <= csr.rd() && csr.rd() <= 17,
-                    "Unsupported CSR rd: {}",
-                    csr.rd()
-                );
-                ctx.regs().a[(csr.rd() - 10) as usize] =
-                    unsafe { PLATFORM.sbi.ipi.as_ref() }.unwrap().get_time();
-            }
-            CSR_TIMEH => {
-                assert!(
-                    10 <= csr.rd() && csr.rd() <= 17,
-                    "Unsupported CSR rd: {}",
-                    csr.rd()
-                );
-                ctx.regs().a[(csr.rd() - 10) as usize] =
-                    unsafe { PLATFORM.sbi.ipi.as_ref() }.unwrap().get_timeh();
-            }
-            _ => return false,
+    let insn = Insn::new(mtval::read() as u32);
+    let value = match insn.op() {
+        Op::Csr {
+            funct3: insn::CSRRS,
+        } => match insn.csr() {
+            CSR_TIME => time::current_ticks() as usize,
+            // `timeh` only exists on RV32, RV64 software reads the whole counter from `time`.
+            #[cfg(target_arch = "riscv32")]
+            CSR_TIMEH => (time::current_ticks() >> 32) as usize,
+            _ => return None,
         },
-        _ => return false,
-    }
-    mepc::write(mepc::read() + 4);
-    true
+        _ => return None,
+    };
+    mepc::write(mepc::read() + insn.size());
+    Some((insn.rd(), value))
 }
 
-/// Supervisor context structure containing saved register state.
-#[derive(Debug)]
-#[repr(C)]
-pub struct SupervisorContext {
-    pub ra: usize, // 0
-    pub sp: usize,
-    pub gp: usize,
-    pub tp: usize,
-    pub t0: usize,
-    pub t1: usize,
-    pub t2: usize,
-    pub s0: usize,
-    pub s1: usize,
-    pub a0: usize,
-    pub a1: usize,
-    pub a2: usize,
-    pub a3: usize,
-    pub a4: usize,
-    pub a5: usize,
-    pub a6: usize,
-    pub a7: usize,
-    pub s2: usize,
-    pub s3: usize,
-    pub s4: usize,
-    pub s5: usize,
-    pub s6: usize,
-    pub s7: usize,
-    pub s8: usize,
-    pub s9: usize,
-    pub s10: usize,
-    pub s11: usize,
-    pub t3: usize,
-    pub t4: usize,
-    pub t5: usize,
-    pub t6: usize,   // 30
-    pub mepc: usize, // 31
+/// Entire trap path setting `x<index>` to `value`, for registers the fast
+/// path leaves in the hart.
+extern "C" fn write_register(ctx: EntireContext<(u32, usize)>) -> EntireResult {
+    let (mut ctx, mail) = ctx.split();
+    let (index, value) = mail.get();
+    trap_frame::set_gpr(ctx.regs(), index, value);
+    ctx.restore()
 }
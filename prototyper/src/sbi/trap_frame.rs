@@ -0,0 +1,152 @@
+//! Register frames saved by the trap entries.
+//!
+//! The naked entries store registers at offsets taken from these structures
+//! with `offset_of!`, so the assembly filling a frame and the Rust code reading
+//! it cannot drift apart. Full traps save a `fast_trap::FlowContext`, whose
+//! layout belongs to that crate; `gpr` and `set_gpr` reach its registers by
+//! number for code decoding instructions or dumping state.
+
+use fast_trap::FlowContext;
+
+/// Bytes taken by one register slot.
+pub const XLENB: usize = core::mem::size_of::<usize>();
+
+/// Caller-saved registers, all that a call into Rust may clobber.
+///
+/// Saved by the fast SBI call path and the RNMI entry.
+#[derive(Debug, Default)]
+#[repr(C)]
+#[allow(unused)]
+pub struct CallerSaved {
+    pub t: [usize; 7],
+    pub ra: usize,
+    pub a: [usize; 8],
+}
+
+/// Registers saved by the machine timer and external interrupt entries.
+///
+/// Every general purpose register but `zero` and `sp`, in register order.
+#[derive(Debug, Default)]
+#[repr(C)]
+#[allow(unused)]
+pub struct InterruptFrame {
+    pub ra: usize,
+    pub gp: usize,
+    pub tp: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+}
+
+/// Supervisor context saved by the machine software interrupt entry.
+///
+/// Registers `x1` to `x31` in order, then `mepc`.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct SupervisorContext {
+    pub ra: usize,
+    pub sp: usize,
+    pub gp: usize,
+    pub tp: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub mepc: usize,
+}
+
+// Sizes the entries were written for.
+const _: () = assert!(core::mem::size_of::<SupervisorContext>() == 32 * XLENB);
+const _: () = assert!(core::mem::size_of::<InterruptFrame>() == 30 * XLENB);
+const _: () = assert!(core::mem::size_of::<CallerSaved>() == 16 * XLENB);
+// The entries move `sp` by whole frames, which must keep it 16 byte aligned.
+const _: () = assert!(core::mem::size_of::<SupervisorContext>() % 16 == 0);
+const _: () = assert!(core::mem::size_of::<InterruptFrame>() % 16 == 0);
+const _: () = assert!(core::mem::size_of::<CallerSaved>() % 16 == 0);
+
+/// Whether `x<index>` is saved by the fast trap path, and so readable and
+/// writable in its `FlowContext`: `ra` and the `t` and `a` registers, and
+/// `x0`, read as 0 with writes dropped as `gpr` and `set_gpr` do.
+pub fn caller_saved(index: u32) -> bool {
+    matches!(index, 0 | 1 | 5..=7 | 10..=17 | 28..=31)
+}
+
+/// Register `x<index>` of a trapped context, 0 for `x0` and numbers out of range.
+pub fn gpr(ctx: &FlowContext, index: u32) -> usize {
+    match index {
+        1 => ctx.ra,
+        2 => ctx.sp,
+        3 => ctx.gp,
+        4 => ctx.tp,
+        5..=7 => ctx.t[index as usize - 5],
+        8..=9 => ctx.s[index as usize - 8],
+        10..=17 => ctx.a[index as usize - 10],
+        18..=27 => ctx.s[index as usize - 16],
+        28..=31 => ctx.t[index as usize - 25],
+        _ => 0,
+    }
+}
+
+/// Set register `x<index>` of a trapped context, writes to `x0` are dropped.
+pub fn set_gpr(ctx: &mut FlowContext, index: u32, value: usize) {
+    let slot = match index {
+        1 => &mut ctx.ra,
+        2 => &mut ctx.sp,
+        3 => &mut ctx.gp,
+        4 => &mut ctx.tp,
+        5..=7 => &mut ctx.t[index as usize - 5],
+        8..=9 => &mut ctx.s[index as usize - 8],
+        10..=17 => &mut ctx.a[index as usize - 10],
+        18..=27 => &mut ctx.s[index as usize - 16],
+        28..=31 => &mut ctx.t[index as usize - 25],
+        _ => return,
+    };
+    *slot = value;
+}
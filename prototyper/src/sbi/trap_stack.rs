@@ -1,34 +1,70 @@
 use crate::riscv_spec::current_hartid;
+use crate::sbi::entropy;
 use crate::sbi::hart_context::HartContext;
+use crate::sbi::hart_init::{self, InitState};
 use crate::sbi::trap::fast_handler;
-use core::mem::forget;
+use core::mem::{align_of, forget, size_of};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use fast_trap::FreeTrapStack;
 
 /// Stack size per hart (hardware thread) in bytes.
-const LEN_STACK_PER_HART: usize = 16 * 1024;
-/// Maximum number of supported harts.
-pub const NUM_HART_MAX: usize = 8;
+pub const LEN_STACK_PER_HART: usize = 16 * 1024;
+/// Maximum number of supported harts, `harts.max` in the platform manifest.
+pub const NUM_HART_MAX: usize = crate::config::MAX_HARTS;
 
 /// Root stack array for all harts, placed in uninitialized BSS section.
 #[link_section = ".bss.uninit"]
 pub(crate) static mut ROOT_STACK: [Stack; NUM_HART_MAX] = [Stack::ZERO; NUM_HART_MAX];
 
+/// Offset of the stack canary, the first word above the hart context.
+const CANARY_OFFSET: usize = (size_of::<HartContext>() + 7) & !7;
+
+percpu! {
+    /// Stack canary value of each hart, 0 until armed.
+    static CANARY: AtomicUsize = AtomicUsize::new(0);
+}
+
+// `locate` steps through `ROOT_STACK` by `LEN_STACK_PER_HART`, and the hart
+// context is cast from the bottom of each stack.
+const _: () = assert!(size_of::<Stack>() == LEN_STACK_PER_HART);
+const _: () = assert!(LEN_STACK_PER_HART % align_of::<Stack>() == 0);
+const _: () = assert!(align_of::<HartContext>() <= align_of::<Stack>());
+const _: () = assert!(
+    PAINT_OFFSET <= LEN_STACK_PER_HART / 2,
+    "hart context leaves less than half of the stack"
+);
+const _: () = assert!(
+    LEN_STACK_PER_HART * NUM_HART_MAX < crate::config::PAYLOAD_BASE - crate::config::MEMORY_BASE,
+    "hart stacks alone run into the payload"
+);
+
+/// Fill of unused stack, for finding how deep a stack was ever used.
+const STACK_PAINT: usize = 0x5354_4b50_5354_4b50_u64 as usize;
+/// Offset of the first painted word, right above the canary.
+const PAINT_OFFSET: usize = CANARY_OFFSET + size_of::<usize>();
+
 /// Locates and initializes stack for each hart.
 ///
 /// This is a naked function that sets up the stack pointer based on hart ID.
+/// Harts without a stack, from `NUM_HART_MAX` on, are parked here for good.
 #[naked]
 pub(crate) unsafe extern "C" fn locate() {
     core::arch::asm!(
-        "   la   sp, {stack}            // Load stack base address
+        "   csrr t1, mhartid            // Get current hart ID
+            li   t0, {harts}            // Load number of stacks
+            bgeu t1, t0, 2f             // Park harts without a stack
+            la   sp, {stack}            // Load stack base address
             li   t0, {per_hart_stack_size} // Load stack size per hart
-            csrr t1, mhartid            // Get current hart ID
             addi t1, t1,  1             // Add 1 to hart ID
          1: add  sp, sp, t0             // Calculate stack pointer
             addi t1, t1, -1             // Decrement counter
             bnez t1, 1b                 // Loop if not zero
             call t1, {move_stack}       // Call stack reuse function
             ret                         // Return
+         2: wfi                         // Wait forever
+            j    2b
         ",
+        harts               = const NUM_HART_MAX,
         per_hart_stack_size = const LEN_STACK_PER_HART,
         stack               =   sym ROOT_STACK,
         move_stack          =   sym fast_trap::reuse_stack_for_trap,
@@ -43,12 +79,88 @@ pub(crate) fn prepare_for_trap() {
             .get_unchecked_mut(current_hartid())
             .load_as_stack()
     };
+    hart_init::advance(InitState::StacksReady);
+}
+
+/// Place a random canary between the hart context and the stack of the current hart.
+///
+/// Scrambled `mcycle` is used where no entropy source is available; it still
+/// catches accidental overflows, just not deliberate ones.
+pub(crate) fn arm_canary() {
+    let hart_id = current_hartid();
+    let seed = entropy::random_usize().unwrap_or_else(|_| {
+        (riscv::register::mcycle::read() ^ hart_id).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize)
+    });
+    // A zero low byte stops overflows by unterminated string copies.
+    let value = (seed & !0xff).max(0x100);
+    unsafe {
+        ROOT_STACK
+            .get_unchecked_mut(hart_id)
+            .canary()
+            .write_volatile(value)
+    };
+    CANARY.local().store(value, Ordering::Relaxed);
+}
+
+/// Paint the free part of the current hart's stack, below the stack pointer.
+///
+/// Called first thing in `rust_main`, before anything stores into the hart
+/// context or runs deep, so the high-water mark covers all of the boot.
+pub(crate) fn paint_stack() {
+    let sp: usize;
+    unsafe { core::arch::asm!("mv {}, sp", out(reg) sp, options(nomem, nostack)) };
+    let Some((start, end)) = stack_bounds(current_hartid()) else {
+        return;
+    };
+    let mut word = start + PAINT_OFFSET;
+    while word < sp.min(end) {
+        unsafe { (word as *mut usize).write_volatile(STACK_PAINT) };
+        word += size_of::<usize>();
+    }
+}
+
+/// Deepest stack use of `hart_id` so far, in bytes from the top of its stack.
+pub(crate) fn stack_high_water(hart_id: usize) -> Option<usize> {
+    let (start, end) = stack_bounds(hart_id)?;
+    let lowest_used = (start + PAINT_OFFSET..end)
+        .step_by(size_of::<usize>())
+        .find(|&word| unsafe { (word as *const usize).read_volatile() } != STACK_PAINT)
+        .unwrap_or(end);
+    Some(end - lowest_used)
+}
+
+/// Address range of the stack of `hart_id`, without forming a reference to it.
+fn stack_bounds(hart_id: usize) -> Option<(usize, usize)> {
+    if hart_id >= NUM_HART_MAX {
+        return None;
+    }
+    let start = core::ptr::addr_of!(ROOT_STACK) as usize + hart_id * size_of::<Stack>();
+    Some((start, start + LEN_STACK_PER_HART))
+}
+
+/// Panic if the stack of the current hart ran into its hart context.
+#[inline]
+pub(crate) fn check_canary() {
+    let hart_id = current_hartid();
+    let expected = CANARY.local().load(Ordering::Relaxed);
+    if expected == 0 {
+        return;
+    }
+    let found = unsafe {
+        ROOT_STACK
+            .get_unchecked_mut(hart_id)
+            .canary()
+            .read_volatile()
+    };
+    if found != expected {
+        panic!("Stack overflow on hart {}, canary clobbered", hart_id);
+    }
 }
 
 /// Stack type for each hart.
 ///
 /// Memory layout:
-/// - Bottom: HartContext struct.
+/// - Bottom: HartContext struct, followed by the stack canary.
 /// - Middle: Stack space for the hart.
 /// - Top: Trap handling space.
 ///
@@ -65,6 +177,12 @@ impl Stack {
         unsafe { &mut *self.0.as_mut_ptr().cast() }
     }
 
+    /// Gets pointer to the stack canary right above the hart context.
+    #[inline]
+    fn canary(&mut self) -> *mut usize {
+        unsafe { self.0.as_mut_ptr().add(CANARY_OFFSET).cast() }
+    }
+
     /// Initializes stack for trap handling.
     /// - Sets up hart context.
     /// - Creates and loads FreeTrapStack with the stack range.
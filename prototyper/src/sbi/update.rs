@@ -0,0 +1,574 @@
+//! RustSBI Prototyper firmware update extension.
+//!
+//! The supervisor writes a new firmware image somewhere in RAM, stages it with
+//! `STAGE` and arms it with `ACTIVATE`. Staging copies the image into the
+//! inactive one of two slots in the update window, memory after the firmware
+//! image which the device tree reserves and the PMP keeps from lower
+//! privileges, and checks only that copy. The next warm reboot then replaces
+//! the running image instead of resetting the platform: every hart moves into
+//! a small position independent stub placed in the slot after the staged
+//! copy, one hart copies the image over the firmware, and all harts enter the
+//! new image at its first byte with the original boot arguments. The update
+//! is abandoned if a hart does not reach the stub.
+//!
+//! The new image finds the window again through its device tree reservation
+//! and a slot table in its last page, and boots on trial. Once the supervisor
+//! is satisfied it calls `CONFIRM`. A warm reboot before that counts as a
+//! failed boot and rolls back: to the image of the other slot, or, if the
+//! loader put the previous image in place, to a platform reset that loads it
+//! again. Nothing can be staged during a trial, so the slot rolled back to
+//! stays intact.
+
+use crate::sync::Mutex;
+use core::arch::{asm, global_asm};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use prototyper_common::fdt_reader::FdtReader;
+use rustsbi::SbiRet;
+
+use crate::firmware;
+use crate::firmware::image_header::{ImageHeader, IMAGE_HEADER_VERSION, IMAGE_MAGIC};
+use crate::platform::{self, PLATFORM};
+use crate::riscv_spec::current_hartid;
+use crate::sbi::extensions::{hart_extension_probe, Extension};
+use crate::sbi::guest_mem::{self, Mode};
+use crate::sbi::hart_init::{self, InitState};
+use crate::sbi::ipi;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+use crate::sync::Backoff;
+use crate::time;
+
+/// Extension ID of the update extension, in the firmware specific range.
+pub const EID_UPDATE: usize = 0x0A52_5355;
+
+/// Copy the image at `a0` of `a1` bytes into the update window and validate
+/// the copy against the FNV-1a hash in `a2`.
+pub const STAGE: usize = 0;
+/// Replace the firmware with the staged image on the next warm reboot.
+pub const ACTIVATE: usize = 1;
+/// Forget the staged image.
+pub const CANCEL: usize = 2;
+/// Keep the image entered by the last update, ending its trial boot.
+pub const CONFIRM: usize = 3;
+
+/// Time the copying hart waits for the others to reach the stub.
+const GATHER_TIMEOUT_US: u64 = 100_000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// Control word 0 values: the waiting harts enter the new image on `COPIED`
+/// and return on `ABORTED`.
+const COPIED: u32 = 1;
+const ABORTED: u32 = 2;
+
+/// `RSBISLOT` in little endian, marks the slot table.
+const SLOTS_MAGIC: u64 = u64::from_le_bytes(*b"RSBISLOT");
+/// `Slots::running` of an image the loader put in place.
+const NO_SLOT: u32 = u32::MAX;
+
+/// Image copied into a slot of the update window.
+#[derive(Clone, Copy)]
+struct Staged {
+    slot: usize,
+    activate: bool,
+}
+
+/// The slot table, in the last page of the update window.
+///
+/// It outlives the image that wrote it: the images entered by updates keep
+/// using it and the slots it describes.
+#[repr(C)]
+struct Slots {
+    magic: u64,
+    /// Slot of the running image, `NO_SLOT` if the loader put it in place.
+    running: u32,
+    /// Non-zero until the running image is confirmed.
+    trial: u32,
+    /// Size of the image in each slot, 0 if there is none.
+    size: [u64; 2],
+    /// FNV-1a hash of the image in each slot.
+    hash: [u64; 2],
+}
+
+/// Staged image, also held while the slot table is accessed.
+static STAGED: Mutex<Option<Staged>> = Mutex::named("update staged", None);
+/// Device tree and dynamic information addresses the firmware was booted with.
+static BOOT_ARGS: Mutex<(usize, usize)> = Mutex::named("update boot args", (0, 0));
+/// Start of the update window, valid once `WINDOW_END` is set.
+static WINDOW_START: AtomicUsize = AtomicUsize::new(0);
+/// End of the update window, 0 until the boot hart claims it.
+static WINDOW_END: AtomicUsize = AtomicUsize::new(0);
+/// Stub address shared with harts joining the update, 0 while none is running.
+static STUB: AtomicUsize = AtomicUsize::new(0);
+
+// Entered with a0 = destination, a1 = source, a2 = size, a3 = device tree,
+// a4 = dynamic information, a5 = control words, a6 = 1 on the copying hart.
+// Control word 0 is set once the copy is done or abandoned, word 1 counts
+// waiting harts. The copy moves whole registers, then the remaining bytes.
+global_asm!(
+    ".pushsection .text.update_stub, \"ax\"",
+    ".balign 8",
+    ".global update_stub_start",
+    "update_stub_start:",
+    "   mv      t1, a0",
+    "   beqz    a6, 3f",
+    concat!("   li      t2, ", xlenb!()),
+    "1: bltu    a2, t2, 2f",
+    concat!("   ", reg_l!(), "      t0, 0(a1)"),
+    concat!("   ", reg_s!(), "      t0, 0(a0)"),
+    concat!("   addi    a0, a0, ", xlenb!()),
+    concat!("   addi    a1, a1, ", xlenb!()),
+    concat!("   addi    a2, a2, -", xlenb!()),
+    "   j       1b",
+    "2: beqz    a2, 5f",
+    "   lbu     t0, 0(a1)",
+    "   sb      t0, 0(a0)",
+    "   addi    a0, a0, 1",
+    "   addi    a1, a1, 1",
+    "   addi    a2, a2, -1",
+    "   j       2b",
+    "5: fence   rw, rw",
+    "   li      t0, {copied}",
+    "   sw      t0, 0(a5)",
+    "   j       6f",
+    "3: li      t0, 1",
+    "   addi    t2, a5, 4",
+    "   amoadd.w zero, t0, (t2)",
+    "4: lw      t0, 0(a5)",
+    "   beqz    t0, 4b",
+    "   fence   r, rw",
+    "   li      t2, {copied}",
+    "   bne     t0, t2, 7f",
+    "6: fence.i",
+    "   csrr    a0, mhartid",
+    "   mv      a1, a3",
+    "   mv      a2, a4",
+    "   jr      t1",
+    "7: ret",
+    ".global update_stub_end",
+    "update_stub_end:",
+    ".popsection",
+    copied = const COPIED,
+);
+
+extern "C" {
+    fn update_stub_start();
+    fn update_stub_end();
+}
+
+/// The stub as entered by the copying hart.
+type CopyStub = unsafe extern "C" fn(usize, usize, usize, usize, usize, usize, usize) -> !;
+/// The stub as entered by a waiting hart, which returns if the update is abandoned.
+type JoinStub = unsafe extern "C" fn(usize, usize, usize, usize, usize, usize, usize);
+
+/// Remember how this firmware was entered, so the new image can be entered alike.
+pub fn record_boot_args(fdt_address: usize, nonstandard_a2: usize) {
+    *BOOT_ARGS.lock() = (fdt_address, nonstandard_a2);
+}
+
+/// Claim the memory from the end of the image to the payload address, or to
+/// the next stage at `next_address`, the device tree at `fdt_address` or
+/// memory it reserves if one comes first, as the update window. An image
+/// entered by an update takes over the window of the image before it.
+///
+/// The window is reserved in the device tree and kept from lower privileges
+/// by `firmware::set_pmp`, so it must be claimed before the PMP is set.
+pub fn claim_window(fdt_address: usize, next_address: usize) {
+    if let Some(window) = previous_window(fdt_address) {
+        let slots = unsafe { &*slots(&window) };
+        info!(
+            "{:<30}: 0x{:x} - 0x{:x}, {}",
+            "Firmware Update Window",
+            window.start,
+            window.end,
+            match slots.trial {
+                0 => "confirmed",
+                _ => "on trial",
+            }
+        );
+        WINDOW_START.store(window.start, Ordering::Relaxed);
+        WINDOW_END.store(window.end, Ordering::Release);
+        return;
+    }
+    let start = firmware::firmware_range().end;
+    let mut end = crate::config::PAYLOAD_BASE;
+    if next_address > start {
+        end = end.min(next_address);
+    }
+    match firmware::free_end(start, fdt_address) {
+        Ok(free) => end = end.min(free) & !(PAGE_SIZE - 1),
+        Err(err) => {
+            warn!("Firmware update window: reserved memory unknown, {:?}", err);
+            end = start;
+        }
+    }
+    // A device tree embedded with the `fdt` feature cannot grow the
+    // reservation, and Smepmp rules updates out.
+    // Two slots and the slot table need three pages at least.
+    if cfg!(feature = "fdt") || !available() || end < start + 3 * PAGE_SIZE {
+        end = start;
+    }
+    if end > start {
+        match firmware::fdt_fixup::add_mem_reserve(fdt_address, start..end) {
+            Ok(()) => {
+                info!(
+                    "{:<30}: 0x{:x} - 0x{:x}",
+                    "Firmware Update Window", start, end
+                );
+                unsafe {
+                    slots(&(start..end)).write(Slots {
+                        magic: SLOTS_MAGIC,
+                        running: NO_SLOT,
+                        trial: 0,
+                        size: [0; 2],
+                        hash: [0; 2],
+                    })
+                };
+            }
+            Err(err) => {
+                warn!("Failed to reserve the firmware update window: {:?}", err);
+                end = start;
+            }
+        }
+    }
+    WINDOW_START.store(start, Ordering::Relaxed);
+    WINDOW_END.store(end.max(start), Ordering::Release);
+}
+
+/// The window the image before this one reserved in the device tree at
+/// `fdt_address`, if this image was entered by an update.
+///
+/// It is the reserved range with a slot table in its last page. A window
+/// the image runs into is not taken over, its first slot may be lost.
+fn previous_window(fdt_address: usize) -> Option<Range<usize>> {
+    if cfg!(feature = "fdt") || !available() {
+        return None;
+    }
+    let memory = platform::memory_range()?;
+    let image_end = firmware::firmware_range().end;
+    let fdt = unsafe { FdtReader::from_address(fdt_address) }.ok()?;
+    let mut found = None;
+    fdt.reserved_ranges(|range| {
+        let (Ok(start), Ok(end)) = (usize::try_from(range.start), usize::try_from(range.end))
+        else {
+            return;
+        };
+        if found.is_some()
+            || start < image_end
+            || end > memory.end
+            || end < start + 3 * PAGE_SIZE
+            || (start | end) % PAGE_SIZE != 0
+        {
+            return;
+        }
+        let slots = unsafe { &*slots(&(start..end)) };
+        if slots.magic == SLOTS_MAGIC && (slots.running < 2 || slots.running == NO_SLOT) {
+            found = Some(start..end);
+        }
+    })
+    .ok()?;
+    found
+}
+
+/// The update window, empty without one. Waits for the boot hart to claim it.
+pub fn window() -> Range<usize> {
+    let mut backoff = Backoff::new();
+    loop {
+        match WINDOW_END.load(Ordering::Acquire) {
+            0 => backoff.spin(),
+            end => return WINDOW_START.load(Ordering::Relaxed)..end,
+        }
+    }
+}
+
+/// The slot table of a non-empty `window`.
+#[inline]
+fn slots(window: &Range<usize>) -> *mut Slots {
+    (window.end - PAGE_SIZE) as *mut Slots
+}
+
+/// Slot `slot` of a non-empty `window`, one of the two page aligned halves
+/// below the slot table.
+fn slot_range(window: &Range<usize>, slot: usize) -> Range<usize> {
+    let len = ((window.end - PAGE_SIZE - window.start) / 2) & !(PAGE_SIZE - 1);
+    let start = window.start + slot * len;
+    start..start + len
+}
+
+/// Device tree address the firmware was booted with.
+pub fn boot_fdt_address() -> usize {
+    BOOT_ARGS.lock().0
+}
+
+/// 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[inline]
+fn stub_len() -> usize {
+    update_stub_end as usize - update_stub_start as usize
+}
+
+/// Address of the two control words following the stub at `stub`.
+#[inline]
+fn control_words(stub: usize) -> usize {
+    (stub + stub_len() + 7) & !7
+}
+
+/// Where the stub and its control words go for an image of `size` bytes at
+/// the start of `slot`.
+fn stub_range(slot: &Range<usize>, size: usize) -> Range<usize> {
+    let start = (slot.start + size + 7) & !7;
+    start..control_words(start) + 8
+}
+
+fn stage(image: usize, size: usize, hash: usize) -> SbiRet {
+    if image % 8 != 0 || !guest_mem::phys_accessible(image, size) {
+        return SbiRet::invalid_address();
+    }
+    let window = window();
+    if window.is_empty() {
+        return SbiRet::failed();
+    }
+    let mut staged = STAGED.lock();
+    let slots = unsafe { &mut *slots(&window) };
+    // The other slot holds what a failed boot rolls back to.
+    if slots.trial != 0 {
+        return SbiRet::denied();
+    }
+    let slot = match slots.running {
+        0 => 1,
+        _ => 0,
+    };
+    let target = slot_range(&window, slot);
+    // The copy over the firmware moves forwards, from the window down to the
+    // image start, and stays clear of the stub after the staged copy.
+    if size < core::mem::size_of::<ImageHeader>() || stub_range(&target, size).end > target.end {
+        return SbiRet::invalid_param();
+    }
+    *staged = None;
+    slots.size[slot] = 0;
+    let copy = unsafe { core::slice::from_raw_parts_mut(target.start as *mut u8, size) };
+    if guest_mem::read(Mode::Physical, image, copy).is_err() {
+        return SbiRet::invalid_address();
+    }
+    let header = unsafe { &*(copy.as_ptr() as *const ImageHeader) };
+    if header.magic != IMAGE_MAGIC
+        || header.header_version != IMAGE_HEADER_VERSION
+        || header.load_address != crate::START_ADDRESS as u64
+    {
+        return SbiRet::invalid_param();
+    }
+    if fnv1a(copy) != hash as u64 {
+        return SbiRet::denied();
+    }
+    slots.size[slot] = size as u64;
+    slots.hash[slot] = hash as u64;
+    *staged = Some(Staged {
+        slot,
+        activate: false,
+    });
+    info!(
+        "Staged firmware image from 0x{:x} in slot {}, {} bytes",
+        image, slot, size
+    );
+    SbiRet::success(0)
+}
+
+fn activate() -> SbiRet {
+    match STAGED.lock().as_mut() {
+        Some(staged) => {
+            staged.activate = true;
+            SbiRet::success(0)
+        }
+        None => SbiRet::denied(),
+    }
+}
+
+fn confirm() -> SbiRet {
+    let window = window();
+    if window.is_empty() {
+        return SbiRet::failed();
+    }
+    let _staged = STAGED.lock();
+    let slots = unsafe { &mut *slots(&window) };
+    if slots.trial != 0 {
+        slots.trial = 0;
+        info!("Firmware image in slot {} confirmed", slots.running);
+    }
+    SbiRet::success(0)
+}
+
+/// Whether the firmware can be updated in place.
+///
+/// Not if any hart is locked down by Smepmp, which keeps M-mode from writing
+/// the firmware text and from running the stub in the update window. The
+/// `no-lockdown` feature leaves such harts unlocked to keep updates.
+pub fn available() -> bool {
+    cfg!(feature = "no-lockdown")
+        || !(0..NUM_HART_MAX).any(|hart_id| hart_extension_probe(hart_id, Extension::Smepmp))
+}
+
+/// Dispatch a call to the update extension.
+pub fn handle_ecall(function: usize, param: [usize; 6]) -> SbiRet {
+    if !available() {
+        return SbiRet::not_supported();
+    }
+    match function {
+        STAGE => stage(param[0], param[1], param[2]),
+        ACTIVATE => activate(),
+        CANCEL => {
+            *STAGED.lock() = None;
+            SbiRet::success(0)
+        }
+        CONFIRM => confirm(),
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// The slot to reboot into and whether it boots on trial: the activated
+/// staged image, or the last confirmed one if the running image is on trial.
+///
+/// `None` leaves the reboot to the platform, which also rolls back to an
+/// image the loader put in place.
+fn reboot_slot(window: &Range<usize>) -> Option<(usize, bool)> {
+    let staged = STAGED.lock();
+    let slots = unsafe { &*slots(window) };
+    if let Some(Staged {
+        slot,
+        activate: true,
+    }) = *staged
+    {
+        return Some((slot, true));
+    }
+    if slots.trial == 0 {
+        return None;
+    }
+    let previous = 1 - slots.running as usize;
+    let size = slots.size[previous] as usize;
+    error!("Firmware image in slot {} not confirmed", slots.running);
+    if size == 0 {
+        return None;
+    }
+    let slot = slot_range(window, previous);
+    let image = unsafe { core::slice::from_raw_parts(slot.start as *const u8, size) };
+    if fnv1a(image) != slots.hash[previous] {
+        error!(
+            "Firmware image in slot {} is corrupt, not rolled back to",
+            previous
+        );
+        return None;
+    }
+    info!("Rolling back to the firmware image in slot {}", previous);
+    Some((previous, false))
+}
+
+/// Jump into the staged image if one has been activated, or back into the
+/// last confirmed one if the running image was not confirmed. Otherwise
+/// return.
+///
+/// Also returns, abandoning the update, if a hart does not reach the stub
+/// in time: it would run on while its code is overwritten.
+pub fn reboot_into_staged() {
+    let window = window();
+    if window.is_empty() {
+        return;
+    }
+    let Some((slot, trial)) = reboot_slot(&window) else {
+        return;
+    };
+    let (fdt_address, nonstandard_a2) = *BOOT_ARGS.lock();
+    let size = unsafe { (*slots(&window)).size[slot] } as usize;
+    let source = slot_range(&window, slot);
+    let stub = stub_range(&source, size).start;
+    let control = control_words(stub);
+    unsafe {
+        core::ptr::copy_nonoverlapping(update_stub_start as *const u8, stub as *mut u8, stub_len());
+        (control as *mut u64).write_volatile(0);
+        asm!("fence.i");
+    }
+    STUB.store(stub, Ordering::Release);
+    info!("Rebooting into the firmware image in slot {}", slot);
+
+    // Gather the other harts in the stub before overwriting their code.
+    let current_hart = current_hartid();
+    let mut expected = 0;
+    for hart_id in 0..NUM_HART_MAX {
+        let enabled =
+            unsafe { PLATFORM.info.cpu_enabled }.is_some_and(|cpu_enabled| cpu_enabled[hart_id]);
+        // Harts still setting up or quarantined would never see the event.
+        if hart_id == current_hart
+            || !enabled
+            || !hart_init::reached(hart_id, InitState::SbiReady)
+            || hart_init::failed(hart_id)
+        {
+            continue;
+        }
+        expected += 1;
+        if ipi::set_ipi_type(hart_id, ipi::IPI_TYPE_UPDATE) == 0 {
+            if let Some(ipi) = platform::ipi() {
+                ipi.set_msip(hart_id);
+            }
+        }
+    }
+    let state = unsafe { &*(control as *const AtomicU32) };
+    let arrived = unsafe { &*((control + 4) as *const AtomicU32) };
+    let deadline = time::Deadline::after_us(GATHER_TIMEOUT_US);
+    while (arrived.load(Ordering::Acquire) as usize) < expected && !deadline.expired() {
+        core::hint::spin_loop();
+    }
+    let arrived = arrived.load(Ordering::Acquire) as usize;
+    if arrived < expected {
+        STUB.store(0, Ordering::Release);
+        state.store(ABORTED, Ordering::Release);
+        error!(
+            "Firmware update abandoned, {} of {} harts reached the update stub",
+            arrived, expected
+        );
+        return;
+    }
+
+    {
+        let _staged = STAGED.lock();
+        let slots = unsafe { &mut *slots(&window) };
+        slots.running = slot as u32;
+        slots.trial = trial as u32;
+    }
+    let entry: CopyStub = unsafe { core::mem::transmute(stub) };
+    let destination = firmware::firmware_range().start;
+    unsafe {
+        entry(
+            destination,
+            source.start,
+            size,
+            fdt_address,
+            nonstandard_a2,
+            control,
+            1,
+        )
+    }
+}
+
+/// Join an update started by another hart. Returns if no update is running
+/// or it is abandoned.
+pub fn join_update() {
+    let stub = STUB.load(Ordering::Acquire);
+    if stub == 0 {
+        return;
+    }
+    let (fdt_address, nonstandard_a2) = *BOOT_ARGS.lock();
+    let entry: JoinStub = unsafe { core::mem::transmute(stub) };
+    unsafe {
+        entry(
+            firmware::firmware_range().start,
+            0,
+            0,
+            fdt_address,
+            nonstandard_a2,
+            control_words(stub),
+            0,
+        )
+    }
+}
@@ -0,0 +1,71 @@
+use core::arch::asm;
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::extensions::{hart_extension_probe, Extension};
+
+/// Exponential backoff for spin-wait loops.
+///
+/// Each `spin` waits twice as long as the previous one, up to a cap, so harts
+/// polling a shared cache line keep off the bus while the owner makes progress.
+pub struct Backoff {
+    step: u32,
+}
+
+/// Largest number of pause hints issued in a single `spin`, as a power of two.
+const MAX_STEP: u32 = 8;
+
+impl Backoff {
+    #[inline]
+    pub const fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    #[inline]
+    pub fn spin(&mut self) {
+        for _ in 0..1u32 << self.step {
+            // `pause` with Zihintpause, a no-op hint otherwise.
+            core::hint::spin_loop();
+        }
+        if self.step < MAX_STEP {
+            self.step += 1;
+        }
+    }
+
+    /// Spin proportionally to `distance` waiters ahead of us, without growing.
+    #[inline]
+    pub fn spin_for(&self, distance: u32) {
+        for _ in 0..distance.min(1 << MAX_STEP) * 16 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wait while the 32-bit word at `word` holds `current`.
+    ///
+    /// With Zawrs the hart stalls in `wrs.sto` until another hart stores to
+    /// the word, an interrupt becomes pending or a short timeout passes;
+    /// otherwise it falls back to `spin`. Either way the caller must recheck
+    /// its condition, wake-ups may be spurious.
+    #[inline]
+    pub fn wait_while(&mut self, word: *const u32, current: u32) {
+        if !hart_extension_probe(current_hartid(), Extension::Zawrs) {
+            self.spin();
+            return;
+        }
+        // The reservation of `lr.w` is what a remote store invalidates.
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +zawrs",
+                "lr.w   {value}, ({word})",
+                "bne    {value}, {current}, 1f",
+                "wrs.sto",
+                "1:",
+                ".option pop",
+                word = in(reg) word,
+                current = in(reg) current as i32 as isize,
+                value = out(reg) _,
+                options(nostack),
+            )
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Lightweight lock dependency checking.
+//!
+//! Each `Mutex` instance is a lock class, numbered on first use. Every hart
+//! keeps a stack of the classes it holds. Taking class `B` while holding `A`
+//! records the edge `A -> B`; finding `B -> A` already recorded means two call
+//! paths take the pair in opposite orders and can deadlock against each other.
+//! Problems are logged once per pair instead of hanging silently.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use riscv::register::{mcause, mstatus};
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Number of lock classes tracked, later classes are ignored.
+const MAX_CLASSES: usize = 64;
+/// Number of locks a hart may hold at once while being tracked.
+const MAX_HELD: usize = 8;
+
+/// Next class number to hand out, 0 means unassigned.
+static NEXT_CLASS: AtomicUsize = AtomicUsize::new(1);
+/// `AFTER[a]` bit `b` is set once class `b` was taken while holding `a`.
+static AFTER: [AtomicU64; MAX_CLASSES] = [const { AtomicU64::new(0) }; MAX_CLASSES];
+/// `REPORTED[a]` bit `b` is set once the pair was reported.
+static REPORTED: [AtomicU64; MAX_CLASSES] = [const { AtomicU64::new(0) }; MAX_CLASSES];
+/// Classes taken from an interrupt handler.
+static IN_IRQ: AtomicU64 = AtomicU64::new(0);
+/// Classes taken with machine interrupts enabled.
+static IRQ_ENABLED: AtomicU64 = AtomicU64::new(0);
+/// Classes reported as interrupt unsafe.
+static IRQ_REPORTED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy)]
+struct Held {
+    class: u8,
+    name: &'static str,
+}
+
+struct HeldLocks {
+    depth: usize,
+    locks: [Held; MAX_HELD],
+}
+
+const NO_LOCK: Held = Held { class: 0, name: "" };
+
+static mut HELD: [HeldLocks; NUM_HART_MAX] = [const {
+    HeldLocks {
+        depth: 0,
+        locks: [NO_LOCK; MAX_HELD],
+    }
+}; NUM_HART_MAX];
+/// Set while a hart prints a report, so its console lock is not tracked.
+static REPORTING: [AtomicBool; NUM_HART_MAX] = [const { AtomicBool::new(false) }; NUM_HART_MAX];
+
+/// Per-hart held lock stack, only ever touched by its own hart.
+fn held() -> Option<&'static mut HeldLocks> {
+    unsafe { HELD.get_mut(current_hartid()) }
+}
+
+fn class_of(slot: &AtomicU8) -> Option<u8> {
+    let class = slot.load(Ordering::Acquire);
+    if class != 0 {
+        return Some(class);
+    }
+    let next = NEXT_CLASS.fetch_add(1, Ordering::Relaxed);
+    if next >= MAX_CLASSES {
+        return None;
+    }
+    match slot.compare_exchange(0, next as u8, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => Some(next as u8),
+        // Another hart numbered this lock first, the number we took is wasted.
+        Err(class) => Some(class),
+    }
+}
+
+#[inline]
+fn bit(class: u8) -> u64 {
+    1 << class
+}
+
+fn report(f: impl FnOnce()) {
+    let Some(reporting) = REPORTING.get(current_hartid()) else {
+        return;
+    };
+    reporting.store(true, Ordering::Relaxed);
+    f();
+    reporting.store(false, Ordering::Relaxed);
+}
+
+/// Record that the current hart is about to take the lock numbered in `slot`.
+///
+/// Returns the class to hand back to `release`, 0 if the lock is untracked.
+pub fn acquire(slot: &AtomicU8, name: &'static str) -> u8 {
+    if REPORTING
+        .get(current_hartid())
+        .map_or(true, |reporting| reporting.load(Ordering::Relaxed))
+    {
+        return 0;
+    }
+    let Some(held) = held() else {
+        return 0;
+    };
+    let Some(class) = class_of(slot) else {
+        return 0;
+    };
+
+    for i in 0..held.depth {
+        let outer = held.locks[i];
+        if outer.class == class {
+            report(|| {
+                error!(
+                    "lockdep: hart {} takes `{}` recursively, this deadlocks",
+                    current_hartid(),
+                    name
+                )
+            });
+            continue;
+        }
+        let before = AFTER[outer.class as usize].fetch_or(bit(class), Ordering::Relaxed);
+        let inverted = AFTER[class as usize].load(Ordering::Relaxed) & bit(outer.class) != 0;
+        if before & bit(class) == 0
+            && inverted
+            && REPORTED[outer.class as usize].fetch_or(bit(class), Ordering::Relaxed) & bit(class)
+                == 0
+        {
+            report(|| {
+                warn!(
+                    "lockdep: hart {} takes `{}` while holding `{}`, the opposite order was seen before",
+                    current_hartid(),
+                    name,
+                    outer.name
+                )
+            });
+        }
+    }
+
+    let irq_classes = if mcause::read().is_interrupt() {
+        IN_IRQ.fetch_or(bit(class), Ordering::Relaxed) | bit(class)
+    } else {
+        IN_IRQ.load(Ordering::Relaxed)
+    };
+    let enabled_classes = if mstatus::read().mie() {
+        IRQ_ENABLED.fetch_or(bit(class), Ordering::Relaxed) | bit(class)
+    } else {
+        IRQ_ENABLED.load(Ordering::Relaxed)
+    };
+    if irq_classes & enabled_classes & bit(class) != 0
+        && IRQ_REPORTED.fetch_or(bit(class), Ordering::Relaxed) & bit(class) == 0
+    {
+        report(|| {
+            warn!(
+                "lockdep: `{}` is taken in interrupt handlers and with interrupts enabled",
+                name
+            )
+        });
+    }
+
+    if held.depth < MAX_HELD {
+        held.locks[held.depth] = Held { class, name };
+        held.depth += 1;
+    }
+    class
+}
+
+/// Record that the current hart released a lock of `class`.
+pub fn release(class: u8) {
+    if class == 0 {
+        return;
+    }
+    let Some(held) = held() else {
+        return;
+    };
+    // Guards need not be dropped in order, remove the innermost match.
+    if let Some(i) = (0..held.depth)
+        .rev()
+        .find(|&i| held.locks[i].class == class)
+    {
+        held.locks.copy_within(i + 1..held.depth, i);
+        held.depth -= 1;
+    }
+}
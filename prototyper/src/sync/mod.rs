@@ -0,0 +1,138 @@
+//! Locking primitives shared by firmware subsystems.
+//!
+//! `Mutex` is a fair ticket lock. Spin-wait loops on flags owned by other
+//! harts should use `Backoff`, and per-hart state is declared with `percpu!`.
+//! With the `lockdep` feature every acquisition is checked for lock order
+//! inversions, recursive locking, and locks used both from interrupt handlers
+//! and with machine interrupts enabled.
+
+mod backoff;
+#[cfg(feature = "lockdep")]
+mod lockdep;
+mod percpu;
+mod ticket;
+
+pub use backoff::Backoff;
+pub use percpu::{CacheAligned, PerCpu};
+pub use ticket::{TicketLock, TicketLockGuard};
+
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::sync::atomic::AtomicU8;
+
+/// A spin lock with an optional name used in lock dependency reports.
+pub struct Mutex<T> {
+    inner: TicketLock<T>,
+    #[cfg(feature = "lockdep")]
+    name: &'static str,
+    #[cfg(feature = "lockdep")]
+    class: AtomicU8,
+}
+
+impl<T> Mutex<T> {
+    #[allow(unused)]
+    #[inline]
+    pub const fn new(value: T) -> Self {
+        Self::named("<unnamed>", value)
+    }
+
+    #[allow(unused_variables)]
+    #[inline]
+    pub const fn named(name: &'static str, value: T) -> Self {
+        Self {
+            inner: TicketLock::new(value),
+            #[cfg(feature = "lockdep")]
+            name,
+            #[cfg(feature = "lockdep")]
+            class: AtomicU8::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        let class = lockdep::acquire(&self.class, self.name);
+        MutexGuard {
+            guard: self.inner.lock(),
+            #[cfg(feature = "lockdep")]
+            class,
+        }
+    }
+
+    /// Take the lock if it is free, for paths that must not wait on a
+    /// holder that may never release it, such as a panicking hart.
+    #[inline]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        Some(MutexGuard {
+            guard,
+            #[cfg(feature = "lockdep")]
+            class: lockdep::acquire(&self.class, self.name),
+        })
+    }
+
+    /// Returns true if the current hart holds the lock.
+    #[inline]
+    pub fn held_here(&self) -> bool {
+        self.inner.held_here()
+    }
+
+    /// Raw pointer to the protected data, for a holder without its guard at hand.
+    #[inline]
+    pub fn data_ptr(&self) -> *mut T {
+        self.inner.data_ptr()
+    }
+
+    /// Release the lock if the current hart holds it, for a hart that stops
+    /// for good with guards it will never drop.
+    ///
+    /// # Safety
+    ///
+    /// See [`TicketLock::force_unlock`].
+    #[inline]
+    pub unsafe fn force_unlock(&self) -> bool {
+        let released = unsafe { self.inner.force_unlock() };
+        #[cfg(feature = "lockdep")]
+        if released {
+            lockdep::release(self.class.load(core::sync::atomic::Ordering::Relaxed));
+        }
+        released
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    guard: TicketLockGuard<'a, T>,
+    #[cfg(feature = "lockdep")]
+    class: u8,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl<T> Drop for MutexGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        lockdep::release(self.class);
+    }
+}
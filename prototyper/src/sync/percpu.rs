@@ -0,0 +1,51 @@
+use core::ops::Deref;
+
+use crate::riscv_spec::current_hartid;
+use crate::sbi::trap_stack::NUM_HART_MAX;
+
+/// Pads a value to its own cache line, so harts do not contend on neighbours.
+#[repr(C, align(64))]
+pub struct CacheAligned<T>(T);
+
+impl<T> CacheAligned<T> {
+    #[inline]
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for CacheAligned<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// One instance of `T` per hart, declared with `percpu!`.
+///
+/// Instances are indexed by `mhartid`: `mscratch` would be cheaper, but it
+/// holds the supervisor stack pointer while the firmware runs a trap handler.
+pub struct PerCpu<T> {
+    data: [CacheAligned<T>; NUM_HART_MAX],
+}
+
+impl<T> PerCpu<T> {
+    #[inline]
+    pub const fn new(data: [CacheAligned<T>; NUM_HART_MAX]) -> Self {
+        Self { data }
+    }
+
+    /// Instance of the current hart.
+    #[inline]
+    pub fn local(&self) -> &T {
+        &self.data[current_hartid()]
+    }
+
+    /// Instance of `hart_id`, if the hart is within `NUM_HART_MAX`.
+    #[inline]
+    pub fn get(&self, hart_id: usize) -> Option<&T> {
+        self.data.get(hart_id).map(|value| &**value)
+    }
+}
@@ -0,0 +1,136 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use super::Backoff;
+use crate::riscv_spec::current_hartid;
+
+/// `holder` of a lock nobody holds.
+const NO_HOLDER: usize = usize::MAX;
+
+/// A fair spin lock: harts are served in the order they asked for the lock.
+///
+/// Waiters only read `serving` and back off proportionally to their distance
+/// from the head of the queue, instead of all retrying a compare-exchange on
+/// the same cache line. The holder's hart ID is kept so a hart that stops
+/// for good can give back the locks it held.
+pub struct TicketLock<T> {
+    next: AtomicU32,
+    serving: AtomicU32,
+    holder: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for TicketLock<T> {}
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    #[inline]
+    pub const fn new(value: T) -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            serving: AtomicU32::new(0),
+            holder: AtomicUsize::new(NO_HOLDER),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    #[inline]
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let backoff = Backoff::new();
+        loop {
+            let serving = self.serving.load(Ordering::Acquire);
+            if serving == ticket {
+                break;
+            }
+            backoff.spin_for(ticket.wrapping_sub(serving));
+        }
+        self.holder.store(current_hartid(), Ordering::Relaxed);
+        TicketLockGuard { lock: self }
+    }
+
+    /// Take the lock if nobody holds or waits for it.
+    #[inline]
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        let serving = self.serving.load(Ordering::Acquire);
+        self.next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| {
+                self.holder.store(current_hartid(), Ordering::Relaxed);
+                TicketLockGuard { lock: self }
+            })
+    }
+
+    /// Release the lock if the current hart holds it, returning whether it did.
+    ///
+    /// Only the holder stores its own hart ID, so no other hart can be
+    /// holding the lock when this one finds its ID.
+    ///
+    /// # Safety
+    ///
+    /// The guard of the current hart must never be used or dropped again,
+    /// as after a panic. The data may be left half updated.
+    #[inline]
+    pub unsafe fn force_unlock(&self) -> bool {
+        if !self.held_here() {
+            return false;
+        }
+        self.release();
+        true
+    }
+
+    /// Returns true if the current hart holds the lock.
+    #[inline]
+    pub fn held_here(&self) -> bool {
+        self.holder.load(Ordering::Relaxed) == current_hartid()
+    }
+
+    /// Raw pointer to the protected data, for a holder without its guard at hand.
+    #[inline]
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    #[inline]
+    fn release(&self) {
+        self.holder.store(NO_HOLDER, Ordering::Relaxed);
+        // Only the holder writes `serving`, so a plain increment is enough.
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
+
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.release();
+    }
+}
@@ -0,0 +1,94 @@
+//! Firmware internal time keeping.
+//!
+//! Ticks come from the platform mtime counter of the IPI device and are
+//! converted with the `timebase-frequency` of the `/cpus` device tree node.
+
+use core::cell::Cell;
+
+use crate::platform::{self, PLATFORM};
+
+/// Frequency assumed when the device tree does not provide one (QEMU virt).
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/// Returns the mtime frequency in Hz.
+#[inline]
+pub fn timebase_frequency() -> u64 {
+    unsafe { PLATFORM.info.timebase_frequency }
+        .filter(|&freq| freq != 0)
+        .unwrap_or(DEFAULT_TIMEBASE_FREQUENCY)
+}
+
+/// Returns the current mtime value, or 0 before the IPI device is available.
+#[inline]
+pub fn current_ticks() -> u64 {
+    match platform::ipi() {
+        Some(ipi) => ipi.read_mtime(),
+        None => 0,
+    }
+}
+
+/// Convert mtime ticks to microseconds.
+#[inline]
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    (ticks as u128 * 1_000_000 / timebase_frequency() as u128) as u64
+}
+
+/// Convert microseconds to mtime ticks, rounding up.
+#[inline]
+pub fn us_to_ticks(us: u64) -> u64 {
+    (us as u128 * timebase_frequency() as u128).div_ceil(1_000_000) as u64
+}
+
+/// Checks of a deadline counted as one microsecond before there is a timer.
+///
+/// Far more than a core checks in a microsecond, so early waits last at
+/// least as long as asked, but they end.
+const EARLY_POLLS_PER_US: u64 = 1_000;
+
+/// A point in time after which a wait is considered timed out.
+#[derive(Clone, Debug)]
+pub enum Deadline {
+    /// mtime value the wait ends at.
+    Ticks(u64),
+    /// Checks left before the wait ends, when the deadline was set before
+    /// the IPI device gave a timer.
+    Polls(Cell<u64>),
+}
+
+impl Deadline {
+    /// Deadline `us` microseconds from now.
+    #[inline]
+    pub fn after_us(us: u64) -> Self {
+        match platform::ipi() {
+            Some(ipi) => Deadline::Ticks(ipi.read_mtime().saturating_add(us_to_ticks(us))),
+            None => Deadline::Polls(Cell::new(us.saturating_mul(EARLY_POLLS_PER_US))),
+        }
+    }
+
+    #[inline]
+    pub fn expired(&self) -> bool {
+        match self {
+            Deadline::Ticks(end) => current_ticks() >= *end,
+            Deadline::Polls(left) => match left.get() {
+                0 => true,
+                polls => {
+                    left.set(polls - 1);
+                    false
+                }
+            },
+        }
+    }
+}
+
+/// Busy-wait for at least `us` microseconds.
+///
+/// Returns immediately if no timer is available yet.
+pub fn udelay(us: u64) {
+    if platform::ipi().is_none() {
+        return;
+    }
+    let deadline = Deadline::after_us(us);
+    while !deadline.expired() {
+        core::hint::spin_loop();
+    }
+}
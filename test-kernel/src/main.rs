@@ -6,10 +6,31 @@
 #[macro_use]
 extern crate rcore_console;
 
-use core::{arch::asm, ptr::null};
+use core::{
+    arch::asm,
+    ptr::null,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use prototyper_common::sbi_functions::{Functions, IMPLEMENTED};
 use sbi_testing::sbi;
 use uart16550::Uart16550;
 
+/// Full width register store, for the assembly entries.
+#[cfg(target_arch = "riscv64")]
+macro_rules! reg_s {
+    () => {
+        "sd"
+    };
+}
+#[cfg(target_arch = "riscv32")]
+macro_rules! reg_s {
+    () => {
+        "sw"
+    };
+}
+
+const XLENB: usize = core::mem::size_of::<usize>();
+
 const RISCV_HEAD_FLAGS: u64 = 0;
 const RISCV_HEADER_VERSION: u32 = 0x2;
 const RISCV_IMAGE_MAGIC: u64 = 0x5643534952; /* Magic number, little endian, "RISCV" */
@@ -61,8 +82,8 @@ unsafe extern "C" fn _start(hartid: usize, device_tree_paddr: usize) -> ! {
         "   la      t0, sbss
             la      t1, ebss
         1:  bgeu    t0, t1, 2f
-            sd      zero, 0(t0)
-            addi    t0, t0, 8
+            sw      zero, 0(t0)
+            addi    t0, t0, 4
             j       1b",
         "2:",
         "   la sp, {stack} + {stack_size}",
@@ -103,7 +124,18 @@ extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
         hart_mask_base: 0,
         delay: frequency,
     };
-    if testing.test() {
+    // A fuzzing run of `cargo xtask diff` makes its calls and nothing else.
+    if fuzz_calls() {
+        sbi::system_reset(sbi::Shutdown, sbi::NoReason);
+    }
+    // First, while secondary harts are still parked where the firmware left them.
+    let start_args_ok = hsm_start_args_test(hartid, smp);
+    sbi_behavior_report(hartid, smp, frequency);
+    let sbi_ok = testing.test();
+    let hsm_ok = hsm_stress_test(hartid, smp);
+    let fid_ok = unknown_fid_test();
+    let inject_ok = inject_test(hartid, smp, frequency);
+    if start_args_ok && sbi_ok && hsm_ok && fid_ok && inject_ok {
         sbi::system_reset(sbi::Shutdown, sbi::NoReason);
     } else {
         sbi::system_reset(sbi::Shutdown, sbi::SystemFailure);
@@ -123,6 +155,543 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     loop {}
 }
 
+/// Harts whose start arguments are recorded, the rest are not tested.
+const START_ARGS_HARTS: usize = 32;
+/// Mixed into the hart ID to form the opaque value of each start.
+const START_ARGS_MAGIC: usize = 0x5342_0000 << (usize::BITS - 32);
+
+/// `a0` and `a1` each secondary hart found on entry, `usize::MAX` before.
+static mut START_ARGS: [[usize; 2]; START_ARGS_HARTS] = [[usize::MAX; 2]; START_ARGS_HARTS];
+
+/// Entry of secondary harts during the start argument test.
+///
+/// Records `a0` and `a1` in the slot of the hart ID in `a0`, then stops. No
+/// stack is needed.
+#[naked]
+unsafe extern "C" fn hsm_start_args_entry(_hartid: usize, _opaque: usize) -> ! {
+    asm!(
+        "   li      t1, {harts}",
+        "   bgeu    a0, t1, 1f",
+        "   la      t0, {args}",
+        "   slli    t1, a0, {shift}",
+        "   add     t0, t0, t1",
+        concat!("   ", reg_s!(), "      a0, 0(t0)"),
+        concat!("   ", reg_s!(), "      a1, {xlenb}(t0)"),
+        "   fence   w, w",
+        "1: li      a7, 0x48534D",
+        "   li      a6, 1",
+        "   ecall",
+        "2: wfi",
+        "   j       2b",
+        harts = const START_ARGS_HARTS,
+        args  = sym START_ARGS,
+        shift = const (2 * XLENB).trailing_zeros(),
+        xlenb = const XLENB,
+        options(noreturn)
+    )
+}
+
+/// Start every secondary hart twice, first from where the firmware parked it
+/// at boot and then after a `hart_stop`, checking it enters with its hart ID
+/// in `a0` and the opaque value of `hart_start` in `a1`.
+fn hsm_start_args_test(hartid: usize, smp: usize) -> bool {
+    let mut ok = true;
+    for round in 0..2 {
+        for target in (0..smp.min(START_ARGS_HARTS)).filter(|&id| id != hartid) {
+            let opaque = START_ARGS_MAGIC ^ (round << 16) ^ target;
+            unsafe { core::ptr::write_volatile(&mut START_ARGS[target], [usize::MAX; 2]) };
+            let ret = sbi::hart_start(target, hsm_start_args_entry as usize, opaque);
+            if ret.error != 0 {
+                println!("[hsm-start-args] round {round}: hart_start({target}) failed: {ret:?}");
+                ok = false;
+                continue;
+            }
+            let stopped = (0..HSM_STRESS_TIMEOUT).any(|_| {
+                let status = sbi::hart_get_status(target);
+                status.error == 0 && status.value == HART_STATE_STOPPED
+            });
+            if !stopped {
+                println!("[hsm-start-args] round {round}: hart {target} never stopped");
+                return false;
+            }
+            let [a0, a1] = unsafe { core::ptr::read_volatile(&START_ARGS[target]) };
+            if a0 != target || a1 != opaque {
+                println!(
+                    "[hsm-start-args] round {round}: hart {target} entered with a0 = {a0:#x}, a1 = {a1:#x}, expected {target:#x}, {opaque:#x}"
+                );
+                ok = false;
+            }
+        }
+    }
+    println!(
+        "[hsm-start-args] {} harts: {}",
+        smp.min(START_ARGS_HARTS) - 1,
+        if ok { "pass" } else { "FAILED" }
+    );
+    ok
+}
+
+/// Number of start/stop rounds each secondary hart goes through.
+const HSM_STRESS_ROUNDS: usize = 64;
+/// Number of status polls before a transition is considered stuck.
+const HSM_STRESS_TIMEOUT: usize = 1_000_000;
+
+const HART_STATE_STARTED: usize = 0;
+const HART_STATE_STOPPED: usize = 1;
+const HART_STATE_START_PENDING: usize = 2;
+const HART_STATE_STOP_PENDING: usize = 3;
+const SBI_ERR_ALREADY_AVAILABLE: usize = -6isize as usize;
+
+/// Opaque value of the repeated `hart_start` of each round.
+const HSM_STRESS_REPEAT: usize = 0x5245_5054;
+
+/// Secondary harts that saw themselves in a state other than STARTED.
+static HSM_STRESS_FAILURES: AtomicU32 = AtomicU32::new(0);
+/// Secondary harts entered with the opaque value of a repeated start.
+static HSM_STRESS_REPEATS: AtomicU32 = AtomicU32::new(0);
+
+/// Entry of secondary harts during the HSM stress test.
+///
+/// Counts an entry through the repeated start, checks that the hart
+/// observes itself as STARTED, then stops immediately. No stack is needed.
+#[naked]
+unsafe extern "C" fn hsm_stress_entry(_hartid: usize, _opaque: usize) -> ! {
+    asm!(
+        "   li      t1, 1",
+        "   li      t0, {repeat}",
+        "   bne     a1, t0, 1f",
+        "   la      t0, {repeats}",
+        "   amoadd.w zero, t1, (t0)",
+        "1: li      a7, 0x48534D",
+        "   li      a6, 2",
+        "   ecall",
+        "   bnez    a0, 2f",
+        "   beqz    a1, 3f",
+        "2: la      t0, {failures}",
+        "   li      t1, 1",
+        "   amoadd.w zero, t1, (t0)",
+        "3: li      a7, 0x48534D",
+        "   li      a6, 1",
+        "   ecall",
+        "4: wfi",
+        "   j       4b",
+        repeat   = const HSM_STRESS_REPEAT,
+        repeats  = sym HSM_STRESS_REPEATS,
+        failures = sym HSM_STRESS_FAILURES,
+        options(noreturn)
+    )
+}
+
+/// Hammer hart_start/hart_stop on every secondary hart while checking that
+/// hart_get_status never reports a state outside the start/stop cycle, and
+/// that a repeated start the firmware refused never reaches the hart.
+fn hsm_stress_test(hartid: usize, smp: usize) -> bool {
+    let mut ok = true;
+    let mut repeats = 0;
+    for round in 0..HSM_STRESS_ROUNDS {
+        for target in (0..smp).filter(|&id| id != hartid) {
+            let ret = sbi::hart_start(target, hsm_stress_entry as usize, 0);
+            if ret.error != 0 {
+                println!("[hsm-stress] round {round}: hart_start({target}) failed: {ret:?}");
+                ok = false;
+                continue;
+            }
+            // A second start must be refused until the hart stopped again.
+            let again = sbi::hart_start(target, hsm_stress_entry as usize, HSM_STRESS_REPEAT);
+            match again.error {
+                0 => repeats += 1,
+                SBI_ERR_ALREADY_AVAILABLE => {}
+                _ => {
+                    println!(
+                        "[hsm-stress] round {round}: repeated hart_start({target}): {again:?}"
+                    );
+                    ok = false;
+                }
+            }
+            let mut stopped = false;
+            for _ in 0..HSM_STRESS_TIMEOUT {
+                let status = sbi::hart_get_status(target);
+                match status.value {
+                    _ if status.error != 0 => {
+                        println!("[hsm-stress] hart_get_status({target}) failed: {status:?}");
+                        ok = false;
+                        break;
+                    }
+                    HART_STATE_STOPPED => {
+                        stopped = true;
+                        break;
+                    }
+                    HART_STATE_STARTED | HART_STATE_START_PENDING | HART_STATE_STOP_PENDING => {}
+                    state => {
+                        println!("[hsm-stress] round {round}: hart {target} in state {state}");
+                        ok = false;
+                        break;
+                    }
+                }
+            }
+            if !stopped {
+                println!("[hsm-stress] round {round}: hart {target} never stopped");
+                return false;
+            }
+        }
+    }
+    let failures = HSM_STRESS_FAILURES.load(Ordering::Relaxed);
+    if failures != 0 {
+        println!("[hsm-stress] {failures} harts did not observe themselves as STARTED");
+        ok = false;
+    }
+    let entered = HSM_STRESS_REPEATS.load(Ordering::Relaxed);
+    if entered != repeats {
+        println!("[hsm-stress] {entered} harts entered through {repeats} accepted repeated starts");
+        ok = false;
+    }
+    println!(
+        "[hsm-stress] {} rounds on {} harts: {}",
+        HSM_STRESS_ROUNDS,
+        smp - 1,
+        if ok { "pass" } else { "FAILED" }
+    );
+    ok
+}
+
+const SBI_ERR_NOT_SUPPORTED: usize = -2isize as usize;
+
+fn sbi_call(eid: usize, fid: usize, arg0: usize) -> (usize, usize) {
+    sbi_call3(eid, fid, [arg0, 0, 0])
+}
+
+fn sbi_call3(eid: usize, fid: usize, [arg0, arg1, arg2]: [usize; 3]) -> (usize, usize) {
+    sbi_call6(eid, fid, [arg0, arg1, arg2, 0, 0, 0])
+}
+
+fn sbi_call6(eid: usize, fid: usize, args: [usize; 6]) -> (usize, usize) {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a6") fid,
+            in("a7") eid,
+        )
+    };
+    (error, value)
+}
+
+/// Call function IDs past the implemented ones of every present extension,
+/// they must all return SBI_ERR_NOT_SUPPORTED.
+fn unknown_fid_test() -> bool {
+    let mut ok = true;
+    for Functions { name, eid, count } in IMPLEMENTED {
+        // Base extension function 3 probes for an extension.
+        if sbi_call(0x10, 3, eid) == (0, 0) {
+            continue;
+        }
+        for fid in [count, count + 1, 0x7fff_ffff, usize::MAX] {
+            let (error, _) = sbi_call(eid, fid, 0);
+            if error != SBI_ERR_NOT_SUPPORTED {
+                println!("[unknown-fid] {name} function {fid:#x} returned error {error:#x}");
+                ok = false;
+            }
+        }
+    }
+    println!(
+        "[unknown-fid] {} extensions: {}",
+        IMPLEMENTED.len(),
+        if ok { "pass" } else { "FAILED" }
+    );
+    ok
+}
+
+const SIP_SSIP: usize = 1 << 1;
+const SIP_STIP: usize = 1 << 5;
+
+fn read_sip() -> usize {
+    let sip: usize;
+    unsafe { asm!("csrr {}, sip", out(reg) sip) };
+    sip
+}
+
+/// Wait up to `timeout` ticks for the `bits` of `sip` to read `expected`.
+fn wait_sip(bits: usize, expected: usize, timeout: u64) -> bool {
+    let deadline = read_time() + timeout;
+    loop {
+        if read_sip() & bits == expected {
+            return true;
+        }
+        if read_time() > deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Progress of the secondary hart in the remote IPI check.
+static INJECT_REMOTE: AtomicU32 = AtomicU32::new(0);
+const INJECT_REMOTE_WAITING: u32 = 1;
+const INJECT_REMOTE_SEEN: u32 = 2;
+
+/// Entry of the secondary hart during the remote IPI check.
+///
+/// Clears SSIP, reports it is waiting and polls `sip` until the IPI arrives,
+/// then clears it again, reports it if the bit cleared and stops. Interrupts
+/// stay disabled as `hart_start` left them. No stack is needed.
+#[naked]
+unsafe extern "C" fn inject_remote_entry(_hartid: usize, _opaque: usize) -> ! {
+    asm!(
+        "   csrci   sip, {ssip}",
+        "   la      t0, {state}",
+        "   li      t1, {waiting}",
+        "   fence   rw, w",
+        "   sw      t1, 0(t0)",
+        "   li      t2, {polls}",
+        "1: csrr    t3, sip",
+        "   andi    t3, t3, {ssip}",
+        "   bnez    t3, 2f",
+        "   addi    t2, t2, -1",
+        "   bnez    t2, 1b",
+        "   j       3f",
+        "2: csrci   sip, {ssip}",
+        "   csrr    t3, sip",
+        "   andi    t3, t3, {ssip}",
+        "   bnez    t3, 3f",
+        "   li      t1, {seen}",
+        "   sw      t1, 0(t0)",
+        "3: fence   w, w",
+        "   li      a7, 0x48534D",
+        "   li      a6, 1",
+        "   ecall",
+        "4: wfi",
+        "   j       4b",
+        ssip    = const SIP_SSIP,
+        state   = sym INJECT_REMOTE,
+        waiting = const INJECT_REMOTE_WAITING,
+        seen    = const INJECT_REMOTE_SEEN,
+        polls   = const HSM_STRESS_TIMEOUT,
+        options(noreturn)
+    )
+}
+
+/// Check the supervisor interrupts the firmware injects, with interrupts
+/// masked: an IPI to this hart raises SSIP, which S-mode can clear, and
+/// set_timer raises STIP at the deadline and withdraws it when moved away.
+/// With another hart, an IPI sent to it must raise its SSIP as well.
+///
+/// Run under `aia=aplic-imsic` too, where the firmware hands SSIP to S-mode
+/// through `mvip`.
+fn inject_test(hartid: usize, smp: usize, frequency: u64) -> bool {
+    const SPI: usize = 0x735049;
+    let mut ok = true;
+    let mut check = |what: &str, passed: bool| {
+        if !passed {
+            println!("[inject] {what}: FAILED");
+            ok = false;
+        }
+    };
+    let timeout = frequency / 10;
+    let sie: usize;
+    unsafe { asm!("csrrc {}, sie, {}", out(reg) sie, in(reg) SIP_SSIP | SIP_STIP) };
+
+    // Software interrupt, through mip or mvip depending on the hart.
+    unsafe { asm!("csrc sip, {}", in(reg) SIP_SSIP) };
+    check("SSIP clear at start", read_sip() & SIP_SSIP == 0);
+    let (error, _) = sbi_call3(SPI, 0, [1, hartid, 0]);
+    check("send_ipi to self", error == 0);
+    check("IPI raises SSIP", wait_sip(SIP_SSIP, SIP_SSIP, timeout));
+    unsafe { asm!("csrc sip, {}", in(reg) SIP_SSIP) };
+    check("S-mode clears SSIP", read_sip() & SIP_SSIP == 0);
+    if let Some(target) = (0..smp).find(|&id| id != hartid) {
+        check("send_ipi to another hart", inject_remote(target));
+    }
+
+    // Timer interrupt, through mip or stimecmp depending on the hart.
+    let delay = frequency / 100;
+    let deadline = read_time() + delay;
+    set_timer(deadline);
+    check("STIP idle before deadline", read_sip() & SIP_STIP == 0);
+    check(
+        "set_timer raises STIP",
+        wait_sip(SIP_STIP, SIP_STIP, delay + timeout),
+    );
+    set_timer(deadline + 10 * delay);
+    check("set_timer withdraws STIP", wait_sip(SIP_STIP, 0, timeout));
+    set_timer(u64::MAX);
+
+    unsafe { asm!("csrs sie, {}", in(reg) sie) };
+    println!("[inject] {}", if ok { "pass" } else { "FAILED" });
+    ok
+}
+
+/// Start `target` on the remote IPI entry, send it an IPI once it waits and
+/// tell whether it saw SSIP raised and could clear it.
+fn inject_remote(target: usize) -> bool {
+    const SPI: usize = 0x735049;
+    INJECT_REMOTE.store(0, Ordering::Relaxed);
+    if sbi::hart_start(target, inject_remote_entry as usize, 0).error != 0 {
+        return false;
+    }
+    let waiting = (0..HSM_STRESS_TIMEOUT)
+        .any(|_| INJECT_REMOTE.load(Ordering::Acquire) == INJECT_REMOTE_WAITING);
+    let (error, _) = sbi_call3(SPI, 0, [1, target, 0]);
+    let stopped = (0..HSM_STRESS_TIMEOUT).any(|_| {
+        let status = sbi::hart_get_status(target);
+        status.error == 0 && status.value == HART_STATE_STOPPED
+    });
+    waiting && error == 0 && stopped && INJECT_REMOTE.load(Ordering::Acquire) == INJECT_REMOTE_SEEN
+}
+
+/// Where `cargo xtask diff --fuzz` has QEMU load a call list.
+const FUZZ_LIST: usize = 0x8600_0000;
+/// First word of a call list, "SBIFUZZ" in little endian.
+const FUZZ_MAGIC: u64 = 0x005a_5a55_4649_4253;
+/// Words of each call: extension, function and `a0` to `a5`.
+const FUZZ_CALL_WORDS: usize = 8;
+const FUZZ_CALLS_MAX: usize = 256;
+
+/// Calls the fuzzer may make. None may stop, suspend or reset harts, start
+/// code on them, change how the supervisor runs or have the firmware access
+/// memory on its behalf, or the rest of the run would tell nothing.
+fn fuzz_allowed(eid: usize, fid: usize) -> bool {
+    match eid {
+        // Base; legacy set_timer, console_getchar and clear_ipi; TIME, IPI and RFNC.
+        0x10 | 0x00 | 0x02 | 0x03 | 0x5449_4D45 | 0x0073_5049 | 0x5246_4E43 => true,
+        // The other legacy calls take addresses or shut down.
+        0x01 | 0x04..=0x08 => false,
+        // hart_get_status, console_write_byte and FWFT get.
+        0x0048_534D | 0x4442_434E => fid == 2,
+        0x4657_4654 => fid == 1,
+        // PMU without snapshot_set_shmem, CPPC probe and reads.
+        0x0050_4D55 => fid != 7,
+        0x4350_5043 => fid <= 2,
+        // SRST, SUSP, NACL, STA, DBTR, SSE and MPXY.
+        0x5352_5354 | 0x5355_5350 | 0x4E41_434C | 0x0053_5441 | 0x4442_5452 | 0x0053_5345
+        | 0x4D50_5859 => false,
+        // Vendor and firmware specific extensions.
+        0x0900_0000..=0x0AFF_FFFF => false,
+        // Unknown extensions.
+        _ => true,
+    }
+}
+
+/// Make the calls of the list loaded by `cargo xtask diff --fuzz`, reporting
+/// each as `fuzz.<index>`. Returns false if there is no list.
+fn fuzz_calls() -> bool {
+    let list = FUZZ_LIST as *const u64;
+    let word = |at: usize| unsafe { list.add(at).read_volatile() };
+    if word(0) != FUZZ_MAGIC {
+        return false;
+    }
+    let count = (word(1) as usize).min(FUZZ_CALLS_MAX);
+    for index in 0..count {
+        let call: [usize; FUZZ_CALL_WORDS] =
+            core::array::from_fn(|at| word(2 + index * FUZZ_CALL_WORDS + at) as usize);
+        let [eid, fid, args @ ..] = call;
+        if fuzz_allowed(eid, fid) {
+            report(format_args!("fuzz.{index:03}"), sbi_call6(eid, fid, args));
+        } else {
+            println!("[sbi-diff] fuzz.{index:03} = skipped");
+        }
+    }
+    true
+}
+
+/// Standard extensions probed for the behavior report.
+const REPORT_EXTENSIONS: [(&str, usize); 14] = [
+    ("legacy_set_timer", 0x00),
+    ("legacy_console_putchar", 0x01),
+    ("time", 0x54494D45),
+    ("spi", 0x735049),
+    ("rfnc", 0x52464E43),
+    ("hsm", 0x48534D),
+    ("srst", 0x53525354),
+    ("pmu", 0x504D55),
+    ("dbcn", 0x4442434E),
+    ("susp", 0x53555350),
+    ("cppc", 0x43505043),
+    ("nacl", 0x4E41434C),
+    ("sta", 0x535441),
+    ("fwft", 0x46574654),
+];
+
+/// Print one `[sbi-diff] key = value` line for `cargo xtask diff`.
+fn report(key: impl core::fmt::Display, (error, value): (usize, usize)) {
+    println!("[sbi-diff] {key} = {} {value:#x}", error as isize);
+}
+
+fn read_time() -> u64 {
+    riscv::register::time::read64()
+}
+
+/// Set the timer to `deadline`, its upper half in `a1` on RV32.
+fn set_timer(deadline: u64) {
+    const TIME: usize = 0x54494D45;
+    match usize::BITS {
+        32 => sbi_call3(TIME, 0, [deadline as usize, (deadline >> 32) as usize, 0]),
+        _ => sbi_call(TIME, 0, deadline as usize),
+    };
+}
+
+fn timer_pending() -> usize {
+    (read_sip() >> 5) & 1
+}
+
+/// Print the results of SBI calls whose outcome the specification fixes,
+/// for comparing firmwares with `cargo xtask diff`. Nothing here fails the
+/// test kernel.
+fn sbi_behavior_report(hartid: usize, smp: usize, frequency: u64) {
+    const BASE: usize = 0x10;
+    const HSM: usize = 0x48534D;
+    report("base.spec_version", sbi_call(BASE, 0, 0));
+    report("base.impl_id", sbi_call(BASE, 1, 0));
+    report("base.impl_version", sbi_call(BASE, 2, 0));
+    report("base.probe_unknown", sbi_call(BASE, 3, 0x0BAD_EE00));
+    for (name, eid) in REPORT_EXTENSIONS {
+        report(format_args!("probe.{name}"), sbi_call(BASE, 3, eid));
+    }
+    report("hsm.status_self", sbi_call(HSM, 2, hartid));
+    report("hsm.status_absent", sbi_call(HSM, 2, smp + 100));
+    report(
+        "hsm.start_self",
+        sbi_call3(HSM, 0, [hartid, hsm_start_args_entry as usize, 0]),
+    );
+    report(
+        "hsm.start_absent",
+        sbi_call3(HSM, 0, [smp + 100, hsm_start_args_entry as usize, 0]),
+    );
+    report("hsm.suspend_reserved", sbi_call3(HSM, 3, [0x1, 0, 0]));
+    // Bit XLEN - 1 of a mask addresses a hart that does not exist.
+    let absent_mask = 1 << (usize::BITS - 1);
+    report(
+        "spi.absent_hart",
+        sbi_call3(0x735049, 0, [absent_mask, 0, 0]),
+    );
+    report("spi.empty_mask", sbi_call3(0x735049, 0, [0, 0, 0]));
+    report(
+        "rfnc.fence_i_absent_hart",
+        sbi_call3(0x52464E43, 0, [absent_mask, 0, 0]),
+    );
+    report("srst.reserved_type", sbi_call3(0x53525354, 0, [0x3, 0, 0]));
+    report("dbcn.write_empty", sbi_call3(0x4442434E, 0, [0, 0, 0]));
+    // The timer: no interrupt before the deadline, one after, none once moved away.
+    let delay = frequency / 100;
+    let deadline = read_time() + delay;
+    set_timer(deadline);
+    let before = timer_pending();
+    while read_time() < deadline + delay {
+        core::hint::spin_loop();
+    }
+    let after = timer_pending();
+    set_timer(u64::MAX);
+    let cleared = timer_pending();
+    report("time.pending_before", (0, before));
+    report("time.pending_after", (0, after));
+    report("time.pending_cleared", (0, cleared));
+}
+
 struct BoardInfo {
     smp: usize,
     frequency: u64,
@@ -5,6 +5,7 @@ use std::{
 
 use clap::Args;
 
+use crate::run::image_path;
 use crate::utils::cargo;
 
 #[derive(Debug, Args, Clone)]
@@ -12,6 +13,11 @@ pub struct BenchArg {
     /// Package Prototyper and Test-Kernel
     #[clap(long)]
     pub pack: bool,
+
+    /// Boot the bench kernel under the last built dynamic image on QEMU
+    /// virt with `-icount shift=0`, where a cycle is one instruction.
+    #[clap(long)]
+    pub run: bool,
 }
 
 #[must_use]
@@ -24,6 +30,8 @@ pub fn run(arg: &BenchArg) -> Option<ExitStatus> {
         .join("target")
         .join(arch)
         .join("release");
+    // Before `--pack` changes the directory.
+    let firmware = image_path(arch, "dynamic");
 
     cargo::Cargo::new("build")
         .package("rustsbi-bench-kernel")
@@ -68,5 +76,23 @@ pub fn run(arg: &BenchArg) -> Option<ExitStatus> {
             .ok()?;
         fs::remove_file(env::current_dir().unwrap().join("rustsbi-bench-kernel.its")).ok()?;
     }
+    if arg.run {
+        if !firmware.exists() {
+            eprintln!(
+                "{} not found, build it first with `cargo prototyper`",
+                firmware.display()
+            );
+            return None;
+        }
+        return Command::new("qemu-system-riscv64")
+            .args(["-machine", "virt", "-nographic", "-smp", "4"])
+            .args(["-icount", "shift=0"])
+            .arg("-bios")
+            .arg(&firmware)
+            .arg("-kernel")
+            .arg(target_dir.join("rustsbi-bench-kernel.bin"))
+            .status()
+            .ok();
+    }
     Some(exit_status)
 }
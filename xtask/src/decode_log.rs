@@ -0,0 +1,481 @@
+//! Decoder of the firmware's binary log frames.
+//!
+//! Console output of a firmware built with the `binary-log` feature is read
+//! from a file or standard input. Text passes through; each frame, a zero
+//! byte, the start mark 0xff, the COBS encoded payload and a zero byte, is
+//! printed as the log line it stands for, with static text looked up in the
+//! firmware ELF. Bytes that do not decode are reported and skipped up to the
+//! next frame start.
+
+use std::{
+    env, fs,
+    io::{self, BufReader, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use clap::Args;
+
+/// Byte after the leading zero byte of a frame.
+const FRAME_START: u8 = 0xff;
+/// Longer than any encoded payload the firmware sends, longer ones lost their end.
+const FRAME_MAX: usize = 264;
+
+const PIECE_STATIC: u8 = 0;
+const PIECE_BYTES: u8 = 1;
+const PIECE_TRUNCATED: u8 = 2;
+
+const LEVELS: [&str; 6] = ["?", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+#[derive(Debug, Args, Clone)]
+pub struct DecodeLogArg {
+    /// Firmware ELF the log came from, the dynamic RV64 image by default.
+    #[clap(long)]
+    pub elf: Option<PathBuf>,
+
+    /// Captured console output, standard input by default.
+    pub input: Option<PathBuf>,
+}
+
+/// Loaded sections and the `sbi_start` symbol of an ELF64 little endian file.
+struct Elf {
+    data: Vec<u8>,
+    /// Address, file offset and size of each section with file contents.
+    sections: Vec<(u64, u64, u64)>,
+    sbi_start: u64,
+}
+
+impl Elf {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(
+            self.data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            self.data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+
+    fn u64(&self, offset: usize) -> Option<u64> {
+        Some(u64::from_le_bytes(
+            self.data.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    }
+
+    fn parse(data: Vec<u8>) -> Option<Self> {
+        // 64-bit, little endian.
+        if data.get(..6)? != b"\x7fELF\x02\x01" {
+            return None;
+        }
+        let mut elf = Elf {
+            data,
+            sections: Vec::new(),
+            sbi_start: 0,
+        };
+        let shoff = elf.u64(0x28)? as usize;
+        let shentsize = elf.u16(0x3a)? as usize;
+        let shnum = elf.u16(0x3c)? as usize;
+        let header = |index: usize| shoff + index * shentsize;
+        let mut symtab = None;
+        for index in 0..shnum {
+            let at = header(index);
+            let (kind, addr, offset, size) = (
+                elf.u32(at + 4)?,
+                elf.u64(at + 0x10)?,
+                elf.u64(at + 0x18)?,
+                elf.u64(at + 0x20)?,
+            );
+            match kind {
+                // SHT_PROGBITS
+                1 if addr != 0 => elf.sections.push((addr, offset, size)),
+                // SHT_SYMTAB, with its string table linked
+                2 => symtab = Some((offset, size, elf.u32(at + 0x28)? as usize)),
+                _ => {}
+            }
+        }
+        let (offset, size, link) = symtab?;
+        let strtab = elf.u64(header(link) + 0x18)? as usize;
+        for symbol in (offset..offset + size).step_by(24) {
+            let name = strtab + elf.u32(symbol as usize)? as usize;
+            if elf.data.get(name..name + 10)? == b"sbi_start\0" {
+                elf.sbi_start = elf.u64(symbol as usize + 8)?;
+                return Some(elf);
+            }
+        }
+        None
+    }
+
+    /// Text the firmware had at `offset` from `sbi_start`.
+    fn text(&self, offset: u64, len: u64) -> Option<&[u8]> {
+        let addr = self.sbi_start + offset;
+        let &(start, file_offset, _) = self
+            .sections
+            .iter()
+            .find(|&&(start, _, size)| start <= addr && addr + len <= start + size)?;
+        let at = (file_offset + addr - start) as usize;
+        self.data.get(at..at + len as usize)
+    }
+}
+
+/// Undo the COBS encoding of one frame.
+fn cobs_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut rest = encoded;
+    while let Some((&code, tail)) = rest.split_first() {
+        let len = (code as usize).checked_sub(1)?;
+        decoded.extend_from_slice(tail.get(..len)?);
+        rest = &tail[len..];
+        if code != 0xff && !rest.is_empty() {
+            decoded.push(0);
+        }
+    }
+    Some(decoded)
+}
+
+fn leb128(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The log line a frame payload stands for.
+fn decode_frame(elf: &Elf, payload: &[u8]) -> Option<String> {
+    let (&level, mut rest) = payload.split_first()?;
+    let hart_id = leb128(&mut rest)?;
+    let ticks = leb128(&mut rest)?;
+    let mut message = Vec::new();
+    while let Some((&tag, tail)) = rest.split_first() {
+        rest = tail;
+        match tag {
+            PIECE_STATIC => {
+                let offset = leb128(&mut rest)?;
+                let len = leb128(&mut rest)?;
+                message.extend_from_slice(elf.text(offset, len)?);
+            }
+            PIECE_BYTES => {
+                let len = leb128(&mut rest)? as usize;
+                message.extend_from_slice(rest.get(..len)?);
+                rest = &rest[len..];
+            }
+            PIECE_TRUNCATED => message.extend_from_slice(b"..."),
+            _ => return None,
+        }
+    }
+    Some(format!(
+        "[hart{hart_id}][{ticks}] [RustSBI] {:^5} - {}",
+        LEVELS.get(level as usize).unwrap_or(&"?"),
+        String::from_utf8_lossy(&message)
+    ))
+}
+
+/// What a byte of console output completed.
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(u8),
+    /// The COBS encoded payload of a frame.
+    Frame(Vec<u8>),
+    /// A frame this many bytes long had no end.
+    Lost(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Text,
+    /// After a zero byte, which may start a frame.
+    Zero,
+    Frame,
+}
+
+/// Splits console output into text and frames.
+///
+/// A zero byte ends the frame it is in. A frame only starts at a zero byte
+/// followed by the start mark, so a lost zero byte costs the frame it
+/// belonged to and no more.
+struct Splitter {
+    state: State,
+    frame: Vec<u8>,
+}
+
+impl Splitter {
+    fn new() -> Self {
+        Self {
+            state: State::Text,
+            frame: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<Token> {
+        match (self.state, byte) {
+            (State::Text | State::Zero, 0) => {
+                self.state = State::Zero;
+                None
+            }
+            (State::Zero, FRAME_START) => {
+                self.state = State::Frame;
+                None
+            }
+            (State::Text | State::Zero, byte) => {
+                self.state = State::Text;
+                Some(Token::Text(byte))
+            }
+            (State::Frame, 0) => {
+                self.state = State::Zero;
+                Some(Token::Frame(std::mem::take(&mut self.frame)))
+            }
+            (State::Frame, byte) => {
+                if self.frame.len() == FRAME_MAX {
+                    self.state = State::Text;
+                    let lost = self.frame.len();
+                    self.frame.clear();
+                    return Some(Token::Lost(lost));
+                }
+                self.frame.push(byte);
+                None
+            }
+        }
+    }
+}
+
+fn default_elf() -> PathBuf {
+    env::current_dir()
+        .unwrap()
+        .join("target")
+        .join("riscv64imac-unknown-none-elf")
+        .join("release")
+        .join("rustsbi-prototyper-dynamic.elf")
+}
+
+#[must_use]
+pub fn run(arg: &DecodeLogArg) -> ExitCode {
+    let elf_path = arg.elf.clone().unwrap_or_else(default_elf);
+    let Some(elf) = fs::read(&elf_path).ok().and_then(Elf::parse) else {
+        eprintln!("{} is not a firmware ELF with symbols", elf_path.display());
+        return ExitCode::FAILURE;
+    };
+    let input: Box<dyn Read> = match &arg.input {
+        Some(path) => match fs::File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("Cannot open {}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin()),
+    };
+    let input = BufReader::new(input);
+    let mut stdout = io::stdout().lock();
+    let mut splitter = Splitter::new();
+    for byte in input.bytes() {
+        let Ok(byte) = byte else {
+            break;
+        };
+        match splitter.push(byte) {
+            None => {}
+            Some(Token::Text(byte)) => {
+                let _ = stdout.write_all(&[byte]);
+            }
+            Some(Token::Frame(frame)) => {
+                match cobs_decode(&frame).and_then(|payload| decode_frame(&elf, &payload)) {
+                    Some(line) => {
+                        let _ = writeln!(stdout, "{line}");
+                    }
+                    None => eprintln!("[decode-log] bad frame of {} bytes", frame.len()),
+                }
+            }
+            Some(Token::Lost(len)) => {
+                eprintln!("[decode-log] frame without end, skipped {len} bytes")
+            }
+        }
+    }
+    let _ = stdout.flush();
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// COBS encoding as the firmware does it.
+    fn cobs_encode(payload: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for block in payload.split(|&byte| byte == 0) {
+            let mut chunks = block.chunks(254).peekable();
+            if chunks.peek().is_none() {
+                encoded.push(1);
+            }
+            while let Some(chunk) = chunks.next() {
+                encoded.push(chunk.len() as u8 + 1);
+                encoded.extend_from_slice(chunk);
+                if chunk.len() == 254 && chunks.peek().is_none() {
+                    encoded.push(1);
+                }
+            }
+        }
+        encoded
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0, FRAME_START];
+        frame.extend(cobs_encode(payload));
+        frame.push(0);
+        frame
+    }
+
+    fn split(input: &[u8]) -> Vec<Token> {
+        let mut splitter = Splitter::new();
+        input
+            .iter()
+            .filter_map(|&byte| splitter.push(byte))
+            .collect()
+    }
+
+    /// An ELF with "static text" at offset 0x100 from `sbi_start`.
+    fn elf() -> Elf {
+        Elf {
+            data: b"static text".to_vec(),
+            sections: vec![(0x8000_0100, 0, 11)],
+            sbi_start: 0x8000_0000,
+        }
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        let long: Vec<u8> = (1..=255).cycle().take(600).collect();
+        let cases: [&[u8]; 7] = [
+            b"",
+            &[0],
+            &[0, 0],
+            b"abc",
+            &[1, 0, 2, 0, 0, 3],
+            &long[..254],
+            &long,
+        ];
+        for payload in cases {
+            let encoded = cobs_encode(payload);
+            assert!(!encoded.contains(&0), "{payload:?}");
+            assert_eq!(cobs_decode(&encoded).as_deref(), Some(payload));
+        }
+    }
+
+    #[test]
+    fn cobs_rejects_short_blocks() {
+        assert_eq!(cobs_decode(&[4, 1, 2]), None);
+        assert_eq!(cobs_decode(&[0]), None);
+    }
+
+    #[test]
+    fn leb128_values() {
+        let cases: [(&[u8], u64); 4] = [
+            (&[0], 0),
+            (&[0x7f], 0x7f),
+            (&[0x80, 0x01], 0x80),
+            (&[0xe5, 0x8e, 0x26], 624_485),
+        ];
+        for (encoded, value) in cases {
+            let mut rest = encoded;
+            assert_eq!(leb128(&mut rest), Some(value));
+            assert!(rest.is_empty());
+        }
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(leb128(&mut &max[..]), Some(u64::MAX));
+    }
+
+    #[test]
+    fn leb128_rejects_unterminated() {
+        assert_eq!(leb128(&mut &[0x80, 0x80][..]), None);
+        assert_eq!(leb128(&mut &[0x80; 11][..]), None);
+    }
+
+    #[test]
+    fn decode_pieces() {
+        let mut payload = vec![3, 2, 0x90, 0x4e];
+        payload.extend([PIECE_STATIC, 0x80, 0x02, 6]);
+        payload.extend([PIECE_BYTES, 3, b' ', 0, b'!']);
+        payload.push(PIECE_TRUNCATED);
+        assert_eq!(
+            decode_frame(&elf(), &payload).as_deref(),
+            Some("[hart2][10000] [RustSBI] INFO  - static \0!...")
+        );
+    }
+
+    #[test]
+    fn decode_rejects_bad_pieces() {
+        // Unknown tag, bytes past the end and static text outside the ELF.
+        let cases: [&[u8]; 4] = [
+            &[1, 0, 0, 7],
+            &[1, 0, 0, PIECE_BYTES, 4, b'a'],
+            &[1, 0, 0, PIECE_STATIC, 0x80, 0x02, 12],
+            &[1, 0],
+        ];
+        for payload in cases {
+            assert_eq!(decode_frame(&elf(), payload), None, "{payload:?}");
+        }
+    }
+
+    #[test]
+    fn split_text_and_frames() {
+        let mut input = b"boot\n".to_vec();
+        input.extend(frame(&[3, 0, 0]));
+        input.extend(frame(&[0, 1]));
+        input.extend(b"ok");
+        assert_eq!(
+            split(&input),
+            [
+                Token::Text(b'b'),
+                Token::Text(b'o'),
+                Token::Text(b'o'),
+                Token::Text(b't'),
+                Token::Text(b'\n'),
+                Token::Frame(cobs_encode(&[3, 0, 0])),
+                Token::Frame(cobs_encode(&[0, 1])),
+                Token::Text(b'o'),
+                Token::Text(b'k'),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_resyncs_after_lost_zero() {
+        // The first frame lost its end, which takes the text up to the next frame with it.
+        let mut input = frame(&[3, 0, 0]);
+        input.pop();
+        input.extend(b"text");
+        input.extend(frame(&[4, 0, 0]));
+        // A stray zero byte in text is dropped.
+        input.extend([b'a', 0, b'b']);
+        let mut lost = cobs_encode(&[3, 0, 0]);
+        lost.extend(b"text");
+        assert_eq!(
+            split(&input),
+            [
+                Token::Frame(lost),
+                Token::Frame(cobs_encode(&[4, 0, 0])),
+                Token::Text(b'a'),
+                Token::Text(b'b'),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_gives_up_on_endless_frames() {
+        let mut input = vec![0, FRAME_START];
+        input.extend([1; FRAME_MAX + 1]);
+        input.extend(frame(&[5, 0, 0]));
+        assert_eq!(
+            split(&input),
+            [
+                Token::Lost(FRAME_MAX),
+                Token::Frame(cobs_encode(&[5, 0, 0])),
+            ]
+        );
+    }
+}
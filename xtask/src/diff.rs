@@ -0,0 +1,282 @@
+//! Differential test of Prototyper against OpenSBI.
+//!
+//! The test kernel prints one `[sbi-diff] key = error value` line per SBI
+//! call whose outcome the specification fixes. It is booted on QEMU virt
+//! once under the Prototyper image and once under OpenSBI, QEMU's own
+//! `-bios default`, and the two reports are compared key by key.
+//! Implementation defined keys are shown but do not fail the comparison.
+//!
+//! With `--fuzz`, call lists grown by coverage of the Prototyper are then
+//! compared the same way, see [`fuzz`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+
+use crate::fuzz::{self, Rng};
+use crate::run::image_path;
+use crate::test;
+
+/// Prefix of the report lines of the test kernel.
+const REPORT_PREFIX: &str = "[sbi-diff] ";
+
+/// Keys whose values legitimately differ between implementations.
+const IMPLEMENTATION_DEFINED: [&str; 2] = ["base.impl_id", "base.impl_version"];
+
+#[derive(Debug, Args, Clone)]
+pub struct DiffArg {
+    /// Reference firmware passed to `-bios`, QEMU's bundled OpenSBI by default.
+    #[clap(long, default_value = "default")]
+    pub reference: String,
+
+    #[clap(long, default_value_t = 4)]
+    pub smp: usize,
+
+    /// Seconds one QEMU run may take before it is stopped.
+    #[clap(long, default_value_t = 60)]
+    pub timeout: u64,
+
+    /// Print the full console output of both runs.
+    #[clap(long)]
+    pub verbose: bool,
+
+    /// Also compare this many mutated call lists, guided by the code they
+    /// reach in the Prototyper.
+    #[clap(long, default_value_t = 0)]
+    pub fuzz: usize,
+
+    /// Seed of the mutations, from the clock by default.
+    #[clap(long)]
+    pub seed: Option<u64>,
+}
+
+/// What one firmware reported.
+struct Outcome {
+    report: BTreeMap<String, String>,
+    /// Whether QEMU exited by itself with status 0, the test kernel passing.
+    passed: bool,
+}
+
+/// Boot `kernel` under `bios`, with `extra` QEMU arguments, and collect its report.
+fn boot(
+    name: &str,
+    bios: &Path,
+    kernel: &Path,
+    extra: &[String],
+    arg: &DiffArg,
+) -> Option<Outcome> {
+    let mut child = Command::new("qemu-system-riscv64")
+        .args(["-machine", "virt", "-nographic"])
+        .args(["-smp", &arg.smp.to_string()])
+        .arg("-bios")
+        .arg(bios)
+        .arg("-kernel")
+        .arg(kernel)
+        .args(extra)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| eprintln!("cannot start QEMU: {err}"))
+        .ok()?;
+    let stdout = child.stdout.take()?;
+    let verbose = arg.verbose;
+    let prefix = name.to_string();
+    let reader = thread::spawn(move || {
+        let mut report = BTreeMap::new();
+        // Fuzzed console writes may leave bytes that are no UTF-8.
+        let lines = BufReader::new(stdout)
+            .split(b'\n')
+            .map_while(Result::ok)
+            .map(|line| String::from_utf8_lossy(&line).into_owned());
+        for line in lines {
+            if verbose {
+                println!("{prefix}| {line}");
+            }
+            let line = line.trim_end_matches('\r');
+            if let Some((key, value)) = line
+                .find(REPORT_PREFIX)
+                .and_then(|at| line[at + REPORT_PREFIX.len()..].split_once(" = "))
+            {
+                report.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+        report
+    });
+    let deadline = Instant::now() + Duration::from_secs(arg.timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait().ok()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            eprintln!("{name}: no exit after {} s, stopping QEMU", arg.timeout);
+            child.kill().ok();
+            child.wait().ok();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    Some(Outcome {
+        report: reader.join().ok()?,
+        passed: status.is_some_and(|status: ExitStatus| status.success()),
+    })
+}
+
+pub fn run(arg: &DiffArg) -> ExitCode {
+    match compare(arg) {
+        Some(0) => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+/// Run `arg.fuzz` mutated call lists under both firmwares, keeping those
+/// that reach new Prototyper code, and return the number of distinct
+/// divergences.
+fn fuzz(arg: &DiffArg, image: &Path, reference: &Path, kernel: &Path) -> Option<usize> {
+    let work = image.with_file_name("sbi-fuzz");
+    fs::create_dir_all(&work).ok()?;
+    let list = work.join("calls.bin");
+    let log = work.join("in_asm.log");
+    let loader = vec![
+        "-device".to_string(),
+        format!(
+            "loader,file={},addr={:#x},force-raw=on",
+            list.display(),
+            fuzz::LIST_ADDRESS
+        ),
+    ];
+    let mut traced = loader.clone();
+    traced.extend(["-d", "in_asm", "-D"].map(String::from));
+    traced.push(log.display().to_string());
+
+    let mut rng = Rng::new(arg.seed);
+    // What the boot alone reaches is no news.
+    fs::write(&list, fuzz::encode(&[])).ok()?;
+    boot("prototyper", image, kernel, &traced, arg)?;
+    let mut covered = fuzz::take_coverage(&log);
+    let mut parents = vec![fuzz::seed()];
+    let mut seen = BTreeSet::new();
+    for round in 0..=arg.fuzz {
+        // The seed itself goes first.
+        let calls = match round {
+            0 => parents[0].clone(),
+            _ => {
+                let parent = &parents[rng.below(parents.len())];
+                fuzz::mutate(parent, &mut rng, arg.smp)
+            }
+        };
+        fs::write(&list, fuzz::encode(&calls)).ok()?;
+        let ours = boot("prototyper", image, kernel, &traced, arg)?;
+        let reached = fuzz::take_coverage(&log);
+        let new = reached.difference(&covered).count();
+        if new != 0 {
+            println!(
+                "fuzz {round}: {} calls reach {new} new instructions",
+                calls.len()
+            );
+            covered.extend(reached);
+            parents.push(calls.clone());
+        }
+        let theirs = boot("reference", reference, kernel, &loader, arg)?;
+        for (call, ours, theirs) in fuzz::divergences(&calls, &ours.report, &theirs.report) {
+            if !seen.insert((call.eid, call.fid, ours.clone(), theirs.clone())) {
+                continue;
+            }
+            let saved = work.join(format!("divergence-{}.bin", seen.len()));
+            fs::write(&saved, fuzz::encode(&calls)).ok()?;
+            println!(
+                "DIVERGES eid {:#x} fid {:#x} args {:#x?}: prototyper {ours}, reference {theirs}, list in {}",
+                call.eid,
+                call.fid,
+                call.args,
+                saved.display()
+            );
+        }
+    }
+    println!(
+        "{} call lists fuzzed, {} kept, {} Prototyper instructions reached, {} distinct divergences",
+        arg.fuzz,
+        parents.len(),
+        covered.len(),
+        seen.len()
+    );
+    Some(seen.len())
+}
+
+/// Boot both firmwares and print where they differ, returning the number of
+/// divergences.
+fn compare(arg: &DiffArg) -> Option<usize> {
+    let arch = "riscv64imac-unknown-none-elf";
+    let image = image_path(arch, "dynamic");
+    if !image.exists() {
+        eprintln!(
+            "{} not found, build it first with `cargo prototyper`",
+            image.display()
+        );
+        return None;
+    }
+    if !test::run(&test::TestArg {
+        pack: false,
+        rv32: false,
+    })?
+    .success()
+    {
+        return None;
+    }
+    let kernel = image
+        .with_file_name("rustsbi-test-kernel.bin")
+        .canonicalize()
+        .ok()?;
+
+    let reference_bios = PathBuf::from(&arg.reference);
+    let prototyper = boot("prototyper", &image, &kernel, &[], arg)?;
+    let reference = boot("reference", &reference_bios, &kernel, &[], arg)?;
+
+    let keys: BTreeSet<_> = prototyper
+        .report
+        .keys()
+        .chain(reference.report.keys())
+        .collect();
+    let mut divergences = 0;
+    println!("{:<32} {:<24} {:<24}", "call", "prototyper", "reference");
+    for &key in &keys {
+        let ours = prototyper.report.get(key).map_or("-", String::as_str);
+        let theirs = reference.report.get(key).map_or("-", String::as_str);
+        if ours == theirs {
+            continue;
+        }
+        let note = if IMPLEMENTATION_DEFINED.contains(&key.as_str()) {
+            "(implementation defined)"
+        } else {
+            divergences += 1;
+            "DIVERGES"
+        };
+        println!("{key:<32} {ours:<24} {theirs:<24} {note}");
+    }
+    println!(
+        "{} calls compared, {} divergences; test kernel {} on prototyper, {} on reference",
+        keys.len(),
+        divergences,
+        if prototyper.passed {
+            "passed"
+        } else {
+            "failed"
+        },
+        if reference.passed { "passed" } else { "failed" },
+    );
+    if prototyper.report.is_empty() {
+        eprintln!("the test kernel printed no report under prototyper");
+        return None;
+    }
+    if arg.fuzz != 0 {
+        divergences += fuzz(arg, &image, &reference_bios, &kernel)?;
+    }
+    Some(divergences)
+}
@@ -0,0 +1,297 @@
+use std::{
+    env, fs,
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+use clap::{Args, ValueEnum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Board {
+    /// Allwinner D1, see `--method`.
+    D1,
+    /// StarFive VisionFive 2, written as a bootable SD card image.
+    Visionfive2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Method {
+    /// Load the payload image into DRAM over USB FEL and run it.
+    Xfel,
+    /// Write the image to `--device` at `--offset`.
+    Dd,
+    /// Flash the image into `--partition` over fastboot.
+    Fastboot,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct FlashArg {
+    #[clap(long, value_enum)]
+    pub board: Board,
+
+    /// How to get the image onto a D1 board.
+    #[clap(long, value_enum, default_value = "xfel")]
+    pub method: Method,
+
+    /// Block device to write to, an SD card for VisionFive 2.
+    #[clap(long)]
+    pub device: Option<String>,
+
+    /// Byte offset on `--device` for `--method dd`.
+    #[clap(long)]
+    pub offset: Option<u64>,
+
+    /// Partition for `--method fastboot`.
+    #[clap(long, default_value = "opensbi")]
+    pub partition: String,
+
+    /// U-Boot SPL with the StarFive header (`u-boot-spl.bin.normal.out`).
+    #[clap(long)]
+    pub spl: Option<String>,
+
+    /// U-Boot proper without device tree (`u-boot-nodtb.bin`).
+    #[clap(long)]
+    pub uboot: Option<String>,
+
+    /// Device tree for U-Boot proper.
+    #[clap(long)]
+    pub uboot_dtb: Option<String>,
+}
+
+/// Start of DRAM on D1, matching `prototyper/boards/d1.toml`.
+const D1_DRAM_BASE: &str = "0x40000000";
+
+/// VisionFive 2 SD card layout, in 512 byte sectors, as expected by the boot ROM.
+const VF2_SPL_SECTOR: u64 = 4096;
+const VF2_FIT_SECTOR: u64 = 8192;
+const VF2_FIT_END_SECTOR: u64 = 16384;
+const VF2_IMAGE_SIZE: u64 = 32 * 1024 * 1024;
+const VF2_SPL_TYPE: &str = "2E54B353-1271-4842-806F-E436D6AF6985";
+const VF2_FIT_TYPE: &str = "BC13C2FF-59E6-4262-A352-B275FD6F7172";
+
+/// FIT image of the firmware and U-Boot, loaded by U-Boot SPL.
+const VF2_FIT_SOURCE: &str = r#"/dts-v1/;
+
+/ {
+    description = "RustSBI Prototyper and U-Boot for VisionFive 2";
+    #address-cells = <1>;
+
+    images {
+        firmware {
+            description = "RustSBI Prototyper";
+            data = /incbin/("rustsbi-prototyper-dynamic.bin");
+            type = "firmware";
+            arch = "riscv";
+            os = "opensbi";
+            compression = "none";
+            load = <0x40000000>;
+            entry = <0x40000000>;
+        };
+        uboot {
+            description = "U-Boot";
+            data = /incbin/("u-boot-nodtb.bin");
+            type = "standalone";
+            arch = "riscv";
+            os = "u-boot";
+            compression = "none";
+            load = <0x40200000>;
+        };
+        fdt {
+            description = "U-Boot device tree";
+            data = /incbin/("u-boot.dtb");
+            type = "flat_dt";
+            arch = "riscv";
+            compression = "none";
+        };
+    };
+
+    configurations {
+        default = "config";
+        config {
+            description = "RustSBI Prototyper with U-Boot";
+            firmware = "firmware";
+            loadables = "uboot";
+            fdt = "fdt";
+        };
+    };
+};
+"#;
+
+/// Directory holding the images `cargo prototyper --board <board>` built.
+fn board_dir(board: &str) -> PathBuf {
+    env::current_dir()
+        .unwrap()
+        .join("target")
+        .join("riscv64imac-unknown-none-elf")
+        .join("release")
+        .join(board)
+}
+
+fn require<'a>(value: &'a Option<String>, flag: &str) -> Option<&'a str> {
+    if value.is_none() {
+        eprintln!("{flag} is required for this board or method");
+    }
+    value.as_deref()
+}
+
+fn require_image(image: PathBuf, build: &str) -> Option<PathBuf> {
+    if !image.exists() {
+        eprintln!(
+            "{} not found, build it first with `{build}`",
+            image.display()
+        );
+        return None;
+    }
+    Some(image)
+}
+
+/// Write `image` to the block device `device`, `offset` bytes in.
+fn dd(image: &Path, device: &str, offset: u64) -> Option<ExitStatus> {
+    if offset % 512 != 0 {
+        eprintln!("--offset must be a multiple of 512");
+        return None;
+    }
+    Command::new("dd")
+        .arg(format!("if={}", image.display()))
+        .arg(format!("of={device}"))
+        .args(["bs=512", &format!("seek={}", offset / 512)])
+        .args(["conv=fsync,notrunc", "status=progress"])
+        .status()
+        .ok()
+}
+
+fn flash_d1(arg: &FlashArg) -> Option<ExitStatus> {
+    let dir = board_dir("d1");
+    match arg.method {
+        // FEL starts the image without dynamic information, so it must carry
+        // its payload.
+        Method::Xfel => {
+            let image = require_image(
+                dir.join("rustsbi-prototyper-payload.bin"),
+                "cargo prototyper --board d1 --payload <kernel>",
+            )?;
+            let status = Command::new("xfel").args(["ddr", "d1"]).status().ok()?;
+            if !status.success() {
+                return Some(status);
+            }
+            let status = Command::new("xfel")
+                .args(["write", D1_DRAM_BASE])
+                .arg(&image)
+                .status()
+                .ok()?;
+            if !status.success() {
+                return Some(status);
+            }
+            Command::new("xfel")
+                .args(["exec", D1_DRAM_BASE])
+                .status()
+                .ok()
+        }
+        Method::Dd => {
+            let image = require_image(
+                dir.join("rustsbi-prototyper-dynamic.bin"),
+                "cargo prototyper --board d1",
+            )?;
+            let device = require(&arg.device, "--device")?;
+            let Some(offset) = arg.offset else {
+                eprintln!("--offset is required for --method dd");
+                return None;
+            };
+            dd(&image, device, offset)
+        }
+        Method::Fastboot => {
+            let image = require_image(
+                dir.join("rustsbi-prototyper-dynamic.bin"),
+                "cargo prototyper --board d1",
+            )?;
+            Command::new("fastboot")
+                .args(["flash", &arg.partition])
+                .arg(&image)
+                .status()
+                .ok()
+        }
+    }
+}
+
+/// Copy `source` into `file` at `sector`, failing if it would pass `end`.
+fn place(file: &mut fs::File, source: &Path, sector: u64, end: u64) -> Option<()> {
+    let bytes = fs::read(source).ok()?;
+    if sector * 512 + bytes.len() as u64 > end * 512 {
+        eprintln!("{} does not fit into its partition", source.display());
+        return None;
+    }
+    file.seek(SeekFrom::Start(sector * 512)).ok()?;
+    file.write_all(&bytes).ok()
+}
+
+fn flash_visionfive2(arg: &FlashArg) -> Option<ExitStatus> {
+    let dir = board_dir("visionfive2");
+    require_image(
+        dir.join("rustsbi-prototyper-dynamic.bin"),
+        "cargo prototyper --board visionfive2",
+    )?;
+    let spl = require(&arg.spl, "--spl")?;
+    let uboot = require(&arg.uboot, "--uboot")?;
+    let uboot_dtb = require(&arg.uboot_dtb, "--uboot-dtb")?;
+
+    fs::copy(uboot, dir.join("u-boot-nodtb.bin")).ok()?;
+    fs::copy(uboot_dtb, dir.join("u-boot.dtb")).ok()?;
+    fs::write(dir.join("visionfive2-fit.its"), VF2_FIT_SOURCE).ok()?;
+    let status = Command::new("mkimage")
+        .current_dir(&dir)
+        .args(["-f", "visionfive2-fit.its"])
+        .arg("visionfive2_fw_payload.img")
+        .status()
+        .ok()?;
+    if !status.success() {
+        return Some(status);
+    }
+
+    let sdcard = dir.join("sdcard.img");
+    let mut file = fs::File::create(&sdcard).ok()?;
+    file.set_len(VF2_IMAGE_SIZE).ok()?;
+    let status = Command::new("sgdisk")
+        .arg("--clear")
+        .arg(format!("--new=1:{VF2_SPL_SECTOR}:{}", VF2_FIT_SECTOR - 1))
+        .args([
+            "--change-name=1:spl",
+            &format!("--typecode=1:{VF2_SPL_TYPE}"),
+        ])
+        .arg(format!(
+            "--new=2:{VF2_FIT_SECTOR}:{}",
+            VF2_FIT_END_SECTOR - 1
+        ))
+        .args([
+            "--change-name=2:uboot",
+            &format!("--typecode=2:{VF2_FIT_TYPE}"),
+        ])
+        .arg(&sdcard)
+        .status()
+        .ok()?;
+    if !status.success() {
+        return Some(status);
+    }
+    place(&mut file, Path::new(spl), VF2_SPL_SECTOR, VF2_FIT_SECTOR)?;
+    place(
+        &mut file,
+        &dir.join("visionfive2_fw_payload.img"),
+        VF2_FIT_SECTOR,
+        VF2_FIT_END_SECTOR,
+    )?;
+    drop(file);
+    println!("SD card image written to {}", sdcard.display());
+
+    match &arg.device {
+        Some(device) => dd(&sdcard, device, 0),
+        None => Some(status),
+    }
+}
+
+#[must_use]
+pub fn run(arg: &FlashArg) -> Option<ExitStatus> {
+    match arg.board {
+        Board::D1 => flash_d1(arg),
+        Board::Visionfive2 => flash_visionfive2(arg),
+    }
+}
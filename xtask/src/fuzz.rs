@@ -0,0 +1,295 @@
+//! Coverage guided call lists for `cargo xtask diff --fuzz`.
+//!
+//! A call list is loaded into guest memory with QEMU's generic loader, and
+//! the test kernel makes its calls in order, reporting each result as
+//! `fuzz.<index>`. The test kernel skips calls that would stop, reset or
+//! otherwise derail the run.
+//!
+//! Coverage is the set of Prototyper instruction addresses QEMU translated,
+//! read from its `-d in_asm` log. A list is kept as a parent for further
+//! mutations when it reaches firmware code no list reached before, counting
+//! the boot itself. Each list is also run under the reference firmware, and
+//! calls whose results differ are reported.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Guest physical address the call list is loaded at, see the test kernel.
+pub const LIST_ADDRESS: u64 = 0x8600_0000;
+/// First word of a call list, "SBIFUZZ" in little endian.
+const MAGIC: u64 = 0x005a_5a55_4649_4253;
+/// Longest list generated.
+const CALLS_MAX: usize = 128;
+/// Where the Prototyper image runs, below the test kernel.
+const FIRMWARE: std::ops::Range<u64> = 0x8000_0000..0x8020_0000;
+
+/// Extensions calls are generated for: the standard ones the test kernel may
+/// call, the legacy calls and two unknown extension IDs.
+const EXTENSIONS: [u64; 14] = [
+    0x10,
+    0x5449_4D45,
+    0x0073_5049,
+    0x5246_4E43,
+    0x0048_534D,
+    0x4442_434E,
+    0x4657_4654,
+    0x0050_4D55,
+    0x4350_5043,
+    0x00,
+    0x02,
+    0x03,
+    0x0800_0000,
+    0x0BAD_EE00,
+];
+/// Function IDs of the base extension whose results are implementation defined.
+const IMPLEMENTATION_DEFINED: [u64; 2] = [1, 2];
+
+/// One SBI call: extension, function and `a0` to `a5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Call {
+    pub eid: u64,
+    pub fid: u64,
+    pub args: [u64; 6],
+}
+
+/// Xorshift64*, enough to pick mutations.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |time| time.as_nanos() as u64)
+        });
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// An argument, mostly one of the values firmware checks are made of.
+    fn value(&mut self, smp: usize) -> u64 {
+        let smp = smp as u64;
+        let interesting = [
+            0,
+            1,
+            2,
+            3,
+            smp - 1,
+            smp,
+            smp + 100,
+            u64::MAX,
+            1 << 63,
+            0x7fff_ffff,
+            0x8000_0000,
+            0x8020_0000,
+            0x1000,
+            0x20_0000,
+        ];
+        match self.below(4) {
+            0 => self.next(),
+            _ => interesting[self.below(interesting.len())],
+        }
+    }
+
+    fn call(&mut self, smp: usize) -> Call {
+        Call {
+            eid: EXTENSIONS[self.below(EXTENSIONS.len())],
+            fid: match self.below(8) {
+                0 => self.value(smp),
+                _ => self.below(10) as u64,
+            },
+            args: std::array::from_fn(|_| self.value(smp)),
+        }
+    }
+}
+
+/// The first parent: every extension with function IDs 0 to 8 and no arguments.
+pub fn seed() -> Vec<Call> {
+    EXTENSIONS
+        .iter()
+        .flat_map(|&eid| (0..9).map(move |fid| (eid, fid)))
+        .take(CALLS_MAX)
+        .map(|(eid, fid)| Call {
+            eid,
+            fid,
+            args: [0; 6],
+        })
+        .collect()
+}
+
+/// A child of `parent` with one to three mutations.
+pub fn mutate(parent: &[Call], rng: &mut Rng, smp: usize) -> Vec<Call> {
+    let mut calls = parent.to_vec();
+    for _ in 0..1 + rng.below(3) {
+        let at = rng.below(calls.len().max(1));
+        match rng.below(5) {
+            0 | 1 if calls.len() < CALLS_MAX => calls.insert(at, rng.call(smp)),
+            2 if calls.len() > 1 => {
+                calls.remove(at);
+            }
+            3 if !calls.is_empty() => {
+                let arg = rng.below(6);
+                calls[at].args[arg] = rng.value(smp);
+            }
+            4 if !calls.is_empty() && calls.len() < CALLS_MAX => calls.insert(at, calls[at]),
+            _ if !calls.is_empty() => calls[at].fid = rng.below(10) as u64,
+            _ => calls.push(rng.call(smp)),
+        }
+    }
+    calls
+}
+
+/// The call list as the test kernel reads it.
+pub fn encode(calls: &[Call]) -> Vec<u8> {
+    let mut words = vec![MAGIC, calls.len() as u64];
+    for call in calls {
+        words.extend([call.eid, call.fid]);
+        words.extend(call.args);
+    }
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Firmware instruction addresses in a QEMU `-d in_asm` log.
+pub fn coverage(log: &str) -> BTreeSet<u64> {
+    log.lines()
+        .filter_map(|line| {
+            let (address, _) = line.trim_start().strip_prefix("0x")?.split_once(':')?;
+            u64::from_str_radix(address, 16).ok()
+        })
+        .filter(|address| FIRMWARE.contains(address))
+        .collect()
+}
+
+/// Read and remove the coverage log at `path`.
+pub fn take_coverage(path: &Path) -> BTreeSet<u64> {
+    let log = fs::read(path).unwrap_or_default();
+    fs::remove_file(path).ok();
+    coverage(&String::from_utf8_lossy(&log))
+}
+
+/// Calls of `calls` whose reported results differ between two runs, with
+/// both results.
+pub fn divergences<'a>(
+    calls: &'a [Call],
+    ours: &BTreeMap<String, String>,
+    theirs: &BTreeMap<String, String>,
+) -> Vec<(&'a Call, String, String)> {
+    let mut found = Vec::new();
+    for (index, call) in calls.iter().enumerate() {
+        if call.eid == 0x10 && IMPLEMENTATION_DEFINED.contains(&call.fid) {
+            continue;
+        }
+        let key = format!("fuzz.{index:03}");
+        let ours = ours.get(&key).map_or("-", String::as_str);
+        let theirs = theirs.get(&key).map_or("-", String::as_str);
+        if ours != theirs {
+            found.push((call, ours.to_string(), theirs.to_string()));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let call = Call {
+            eid: 0x10,
+            fid: 3,
+            args: [1, 2, 3, 4, 5, 6],
+        };
+        let bytes = encode(&[call]);
+        assert_eq!(bytes.len(), 10 * 8);
+        assert_eq!(&bytes[..8], b"SBIFUZZ\0");
+        let word = |at: usize| u64::from_le_bytes(bytes[at * 8..at * 8 + 8].try_into().unwrap());
+        assert_eq!(
+            (1..10).map(word).collect::<Vec<_>>(),
+            [1, 0x10, 3, 1, 2, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn mutations_stay_in_bounds() {
+        let mut rng = Rng::new(Some(7));
+        let mut calls = seed();
+        assert!(calls.len() <= CALLS_MAX);
+        for _ in 0..10_000 {
+            calls = mutate(&calls, &mut rng, 4);
+            assert!(!calls.is_empty() && calls.len() <= CALLS_MAX);
+        }
+        assert_ne!(calls, seed());
+        // The empty list gets a call.
+        assert!(!mutate(&[], &mut rng, 4).is_empty());
+    }
+
+    #[test]
+    fn coverage_lines() {
+        let log = "\
+----------------
+IN: _start
+0x80000000:  00000297          auipc                   t0,0
+0x0000000080000004:  02028293  addi                    t0,t0,32
+
+Priv: 1; Virt: 0
+0x80200000:  00000297          auipc                   t0,0
+0x1000:  00000297          auipc                   t0,0
+";
+        assert_eq!(
+            coverage(log).into_iter().collect::<Vec<_>>(),
+            [0x8000_0000, 0x8000_0004]
+        );
+    }
+
+    #[test]
+    fn divergent_calls() {
+        let calls = [
+            Call {
+                eid: 0x10,
+                fid: 1,
+                args: [0; 6],
+            },
+            Call {
+                eid: 0x10,
+                fid: 3,
+                args: [0; 6],
+            },
+            Call {
+                eid: 0x0048_534D,
+                fid: 2,
+                args: [0; 6],
+            },
+        ];
+        let report = |values: [&str; 3]| {
+            values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| (format!("fuzz.{index:03}"), value.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let ours = report(["0 0x1", "0 0x0", "0 0x0"]);
+        let theirs = report(["0 0x2", "0 0x1", "0 0x0"]);
+        let found = divergences(&calls, &ours, &theirs);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, &calls[1]);
+        assert_eq!(
+            (found[0].1.as_str(), found[0].2.as_str()),
+            ("0 0x0", "0 0x1")
+        );
+    }
+}
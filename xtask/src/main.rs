@@ -4,11 +4,22 @@ use std::process::ExitCode;
 #[macro_use]
 mod utils;
 mod bench;
+mod decode_log;
+mod diff;
+mod flash;
+mod fuzz;
 mod prototyper;
+mod run;
+mod size;
 mod test;
 
 use crate::bench::BenchArg;
+use crate::decode_log::DecodeLogArg;
+use crate::diff::DiffArg;
+use crate::flash::FlashArg;
 use crate::prototyper::PrototyperArg;
+use crate::run::RunArg;
+use crate::size::SizeCheckArg;
 use crate::test::TestArg;
 
 #[derive(Parser)]
@@ -27,6 +38,16 @@ enum Cmd {
     Prototyper(PrototyperArg),
     Test(TestArg),
     Bench(BenchArg),
+    /// Boot a built image on QEMU virt.
+    Run(RunArg),
+    /// Put a built image onto a development board.
+    Flash(FlashArg),
+    /// Turn console output of a `binary-log` build back into text.
+    DecodeLog(DecodeLogArg),
+    /// Compare the SBI behavior seen by the test kernel with OpenSBI's.
+    Diff(DiffArg),
+    /// Check that the smallest firmware build fits a small SRAM part.
+    SizeCheck(SizeCheckArg),
 }
 
 fn main() -> ExitCode {
@@ -34,6 +55,11 @@ fn main() -> ExitCode {
         Cmd::Prototyper(ref arg) => prototyper::run(arg),
         Cmd::Test(ref arg) => test::run(arg),
         Cmd::Bench(ref arg) => bench::run(arg),
+        Cmd::Run(ref arg) => run::run(arg),
+        Cmd::Flash(ref arg) => flash::run(arg),
+        Cmd::DecodeLog(ref arg) => return decode_log::run(arg),
+        Cmd::Diff(ref arg) => return diff::run(arg),
+        Cmd::SizeCheck(ref arg) => size::check(arg),
     } {
         if code.success() {
             return ExitCode::SUCCESS;
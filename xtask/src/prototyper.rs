@@ -1,10 +1,12 @@
 use std::{
     env, fs,
+    path::{Path, PathBuf},
     process::{Command, ExitStatus},
 };
 
 use clap::Args;
 
+use crate::size;
 use crate::utils::cargo;
 use crate::utils::CmdOptional;
 
@@ -18,12 +20,142 @@ pub struct PrototyperArg {
 
     #[clap(long, env = "PROTOTYPER_PAYLOAD_PATH")]
     pub payload: Option<String>,
+
+    /// Embed the payload gzip compressed, for the firmware to inflate at boot.
+    /// The compressed payload must fit in the firmware image.
+    #[clap(long, requires = "payload")]
+    pub compress_payload: bool,
+
+    /// Build for RV32 (riscv32imac) instead of RV64.
+    #[clap(long)]
+    pub rv32: bool,
+
+    /// Build images for these boards, named after `prototyper/boards/<board>.toml`,
+    /// or `all` for every board there.
+    #[clap(long, value_delimiter = ',')]
+    pub board: Vec<String>,
+
+    /// Print the size of each firmware module after building.
+    #[clap(long)]
+    pub size_report: bool,
+
+    /// Fail if the firmware, `.bss` and stacks included, exceeds this size,
+    /// for example `128K`.
+    #[clap(long, value_parser = size::parse_size)]
+    pub size_limit: Option<u64>,
+
+    /// Leave out the default features, such as the legacy SBI extensions.
+    #[clap(long)]
+    pub no_default_features: bool,
+}
+
+/// Compress `payload` with the host `gzip` into `target_dir`, returning the
+/// compressed file.
+fn compress_payload(payload: &str, target_dir: &Path) -> Option<PathBuf> {
+    fs::create_dir_all(target_dir).ok()?;
+    let packed = target_dir.join("payload.gz");
+    let output = Command::new("gzip")
+        .args(["-9", "-n", "-c"])
+        .arg(payload)
+        .output()
+        .map_err(|err| eprintln!("cannot run gzip: {err}"))
+        .ok()?;
+    if !output.status.success() {
+        eprintln!("gzip failed on {payload}");
+        return None;
+    }
+    fs::write(&packed, &output.stdout).ok()?;
+    println!(
+        "Compressed payload {payload} to {} bytes",
+        output.stdout.len()
+    );
+    Some(packed)
+}
+
+/// Print the report asked for by `arg` and enforce its size limit.
+fn check_size(arg: &PrototyperArg, map: &Path) -> Option<()> {
+    if !arg.size_report && arg.size_limit.is_none() {
+        return Some(());
+    }
+    let Some(report) = size::report(map) else {
+        eprintln!("Cannot read linker map {}", map.display());
+        return None;
+    };
+    if arg.size_report {
+        for (module, bytes) in &report.modules {
+            println!("{bytes:>10}  {module}");
+        }
+        if let Some(total) = report.total {
+            println!("{total:>10}  total, with .bss and stacks");
+        }
+    }
+    if let Some(limit) = arg.size_limit {
+        let Some(total) = report.total else {
+            eprintln!("No sbi_start and sbi_end in {}", map.display());
+            return None;
+        };
+        if total > limit {
+            eprintln!("Firmware is {total} bytes, over the limit of {limit} bytes");
+            return None;
+        }
+    }
+    Some(())
+}
+
+/// Manifests of the boards selected by `names`, or `None` if one is unknown.
+fn board_manifests(names: &[String]) -> Option<Vec<(String, PathBuf)>> {
+    let boards_dir = env::current_dir().ok()?.join("prototyper").join("boards");
+    let mut boards = Vec::new();
+    if names.iter().any(|name| name == "all") {
+        for entry in fs::read_dir(&boards_dir).ok()? {
+            let path = entry.ok()?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let name = path.file_stem()?.to_string_lossy().into_owned();
+                boards.push((name, path));
+            }
+        }
+        boards.sort();
+        return Some(boards);
+    }
+    for name in names {
+        let path = boards_dir.join(format!("{name}.toml"));
+        if !path.exists() {
+            eprintln!("Unknown board `{name}`, no {}", path.display());
+            return None;
+        }
+        boards.push((name.clone(), path));
+    }
+    Some(boards)
 }
 
 #[must_use]
-#[rustfmt::skip] // "export_env!("PROTOTYPER_FDT_PATH" ?= fdt.unwrap());" is a macro, rustfmt will not format it correctly
 pub fn run(arg: &PrototyperArg) -> Option<ExitStatus> {
-    let arch = "riscv64imac-unknown-none-elf";
+    if arg.board.is_empty() {
+        return build(arg, None);
+    }
+    let mut last = None;
+    for (name, manifest) in board_manifests(&arg.board)? {
+        let status = build(arg, Some((&name, &manifest)))?;
+        if !status.success() {
+            return Some(status);
+        }
+        last = Some(status);
+    }
+    last
+}
+
+/// Build one image, with the platform manifest of `board` if given.
+///
+/// Board images are copied into a directory named after the board, so
+/// builds for several boards don't overwrite each other.
+#[must_use]
+#[rustfmt::skip] // "export_env!("PROTOTYPER_FDT_PATH" ?= fdt.unwrap());" is a macro, rustfmt will not format it correctly
+pub fn build(arg: &PrototyperArg, board: Option<(&str, &Path)>) -> Option<ExitStatus> {
+    let (arch, binary_arch) = if arg.rv32 {
+        ("riscv32imac-unknown-none-elf", "riscv32")
+    } else {
+        ("riscv64imac-unknown-none-elf", "riscv64")
+    };
     let fdt = arg.fdt.clone();
     let payload = arg.payload.clone();
     let current_dir = env::current_dir();
@@ -33,13 +165,30 @@ pub fn run(arg: &PrototyperArg) -> Option<ExitStatus> {
         .join("target")
         .join(arch)
         .join("release");
+    let image_dir = match board {
+        Some((name, _)) => target_dir.join(name),
+        None => target_dir.clone(),
+    };
+    let map = target_dir.join("rustsbi-prototyper.map");
+    let packed = match (&payload, arg.compress_payload) {
+        (Some(payload), true) => Some(compress_payload(payload, &target_dir)?),
+        _ => None,
+    };
+    let rustflags = format!(
+        "-C relocation-model=pie -C link-arg=-pie -C link-arg=-Map={}",
+        map.display()
+    );
 
     let status = cargo::Cargo::new("build")
         .package("rustsbi-prototyper")
         .target(arch)
         .unstable("build-std", ["core"])
-        .env("RUSTFLAGS", "-C relocation-model=pie -C link-arg=-pie")
+        .env("RUSTFLAGS", &rustflags)
         .features(&arg.features)
+        .optional(arg.no_default_features, |cargo| cargo.no_default_features())
+        .optional(board.is_some(), |cargo| {
+            cargo.env("PROTOTYPER_PLATFORM", board.unwrap().1)
+        })
         .optional(arg.fdt.is_some(), |cargo| {
             export_env!("PROTOTYPER_FDT_PATH" ?= fdt.unwrap());
             cargo.features(["fdt".to_string()])
@@ -48,39 +197,46 @@ pub fn run(arg: &PrototyperArg) -> Option<ExitStatus> {
             export_env!("PROTOTYPER_PAYLOAD_PATH" ?= payload.unwrap());
             cargo.features(["payload".to_string()])
         })
+        .optional(packed.is_some(), |cargo| {
+            cargo
+                .env("PROTOTYPER_PAYLOAD_PATH", packed.as_ref().unwrap())
+                .features(["payload-gzip".to_string()])
+        })
         .release()
         .status()
         .ok()?;
 
     if status.success() {
+        fs::create_dir_all(&image_dir).ok()?;
         let exit_status = Command::new("rust-objcopy")
             .args(["-O", "binary"])
-            .arg("--binary-architecture=riscv64")
+            .arg(format!("--binary-architecture={binary_arch}"))
             .arg(target_dir.join("rustsbi-prototyper"))
             .arg(target_dir.join("rustsbi-prototyper.bin"))
             .status()
             .ok()?;
+        check_size(arg, &map)?;
 
         if arg.payload.is_some() {
             fs::copy(
                 target_dir.join("rustsbi-prototyper"),
-                target_dir.join("rustsbi-prototyper-payload.elf"),
+                image_dir.join("rustsbi-prototyper-payload.elf"),
             )
             .ok()?;
             fs::copy(
                 target_dir.join("rustsbi-prototyper.bin"),
-                target_dir.join("rustsbi-prototyper-payload.bin"),
+                image_dir.join("rustsbi-prototyper-payload.bin"),
             )
             .ok()?;
         } else {
             fs::copy(
                 target_dir.join("rustsbi-prototyper"),
-                target_dir.join("rustsbi-prototyper-dynamic.elf"),
+                image_dir.join("rustsbi-prototyper-dynamic.elf"),
             )
             .ok()?;
             fs::copy(
                 target_dir.join("rustsbi-prototyper.bin"),
-                target_dir.join("rustsbi-prototyper-dynamic.bin"),
+                image_dir.join("rustsbi-prototyper-dynamic.bin"),
             ).ok()?;
         }
         return Some(exit_status);
@@ -0,0 +1,122 @@
+use std::{
+    env,
+    path::PathBuf,
+    process::{Command, ExitStatus},
+};
+
+use clap::Args;
+
+#[derive(Debug, Args, Clone)]
+pub struct RunArg {
+    /// Run the RV32 image on qemu-system-riscv32.
+    #[clap(long)]
+    pub rv32: bool,
+
+    /// Boot the image built with `--payload` instead of the dynamic one.
+    #[clap(long)]
+    pub payload: bool,
+
+    /// Supervisor image handed over through the dynamic firmware information.
+    #[clap(long)]
+    pub kernel: Option<String>,
+
+    /// Hand over the test kernel built by `cargo test-kernel` instead.
+    #[clap(long, conflicts_with = "kernel")]
+    pub test_kernel: bool,
+
+    /// Give QEMU virt an APLIC with IMSICs and the harts Smaia and Ssaia.
+    #[clap(long)]
+    pub aia: bool,
+
+    #[clap(long, default_value_t = 4)]
+    pub smp: usize,
+
+    #[clap(long, short = 'm', default_value = "256M")]
+    pub memory: String,
+
+    /// Wait for a debugger on tcp::1234 before starting.
+    #[clap(long)]
+    pub gdb: bool,
+
+    /// Extra arguments passed to QEMU as is.
+    #[clap(last = true)]
+    pub qemu_args: Vec<String>,
+}
+
+/// Image built by `cargo prototyper` for `arch`, `payload` or `dynamic`.
+pub fn image_path(arch: &str, kind: &str) -> PathBuf {
+    env::current_dir()
+        .unwrap()
+        .join("target")
+        .join(arch)
+        .join("release")
+        .join(format!("rustsbi-prototyper-{kind}.bin"))
+}
+
+/// Test kernel ELF built by `cargo test-kernel` for `arch`.
+fn test_kernel_path(arch: &str) -> PathBuf {
+    env::current_dir()
+        .unwrap()
+        .join("target")
+        .join(arch)
+        .join("release")
+        .join("rustsbi-test-kernel")
+}
+
+#[must_use]
+pub fn run(arg: &RunArg) -> Option<ExitStatus> {
+    let (arch, qemu) = if arg.rv32 {
+        ("riscv32imac-unknown-none-elf", "qemu-system-riscv32")
+    } else {
+        ("riscv64imac-unknown-none-elf", "qemu-system-riscv64")
+    };
+    let image = image_path(arch, if arg.payload { "payload" } else { "dynamic" });
+    if !image.exists() {
+        eprintln!(
+            "{} not found, build it first with `cargo prototyper{}{}`",
+            image.display(),
+            if arg.rv32 { " --rv32" } else { "" },
+            if arg.payload {
+                " --payload <image>"
+            } else {
+                ""
+            },
+        );
+        return None;
+    }
+
+    // The ELF, QEMU loads it where it is linked on RV32 as well.
+    let test_kernel = test_kernel_path(arch);
+    if arg.test_kernel && !test_kernel.exists() {
+        eprintln!(
+            "{} not found, build it first with `cargo test-kernel{}`",
+            test_kernel.display(),
+            if arg.rv32 { " --rv32" } else { "" },
+        );
+        return None;
+    }
+
+    let mut qemu = Command::new(qemu);
+    if arg.aia {
+        let cpu = if arg.rv32 { "rv32" } else { "rv64" };
+        qemu.args(["-machine", "virt,aia=aplic-imsic"])
+            .args(["-cpu", &format!("{cpu},smaia=on,ssaia=on")]);
+    } else {
+        qemu.args(["-machine", "virt"]);
+    }
+    qemu.arg("-nographic")
+        .args(["-smp", &arg.smp.to_string()])
+        .args(["-m", &arg.memory])
+        .arg("-bios")
+        .arg(&image);
+    if let Some(kernel) = &arg.kernel {
+        qemu.args(["-kernel", kernel]);
+    }
+    if arg.test_kernel {
+        qemu.arg("-kernel").arg(&test_kernel);
+    }
+    if arg.gdb {
+        qemu.args(["-s", "-S"]);
+    }
+    qemu.args(&arg.qemu_args).status().ok()
+}
@@ -0,0 +1,249 @@
+//! Size accounting of the firmware image from the linker map.
+
+use std::{collections::BTreeMap, env, fs, path::Path, process::ExitStatus};
+
+use clap::Args;
+
+use crate::prototyper::{self, PrototyperArg};
+
+/// Crate whose sizes are broken down by module instead of reported whole.
+const FIRMWARE_CRATE: &str = "rustsbi_prototyper";
+/// Module depth of the breakdown, counting the crate itself.
+const MODULE_DEPTH: usize = 3;
+
+/// Name of the manifest and image directory of the size check build.
+const CHECK_BUILD: &str = "size-check";
+
+#[derive(Debug, Args, Clone)]
+pub struct SizeCheckArg {
+    /// Harts with a firmware stack, as `harts.max` of a board manifest.
+    #[clap(long, default_value_t = 1)]
+    pub harts: usize,
+
+    /// Largest firmware allowed, `.bss` and stacks included.
+    #[clap(long, default_value = "128K", value_parser = parse_size)]
+    pub limit: u64,
+
+    /// Check the RV32 (riscv32imac) build instead of RV64.
+    #[clap(long)]
+    pub rv32: bool,
+}
+
+pub struct SizeReport {
+    /// Bytes per module or crate, largest first.
+    pub modules: Vec<(String, u64)>,
+    /// Bytes from `sbi_start` to `sbi_end`, including `.bss` and the stacks.
+    pub total: Option<u64>,
+}
+
+/// Parse an lld map file.
+pub fn report(map: &Path) -> Option<SizeReport> {
+    let text = fs::read_to_string(map).ok()?;
+    let mut sections = Vec::new();
+    let (mut start, mut end) = (None, None);
+    for line in text.lines() {
+        // VMA, LMA, Size and Align columns, then the output section,
+        // input section or symbol.
+        let mut columns = line.split_whitespace();
+        let (Some(vma), Some(_), Some(size), Some(_)) = (
+            columns.next(),
+            columns.next(),
+            columns.next(),
+            columns.next(),
+        ) else {
+            continue;
+        };
+        let (Ok(vma), Ok(size)) = (u64::from_str_radix(vma, 16), u64::from_str_radix(size, 16))
+        else {
+            continue;
+        };
+        let rest: Vec<_> = columns.collect();
+        match rest.as_slice() {
+            ["sbi_start"] => start = Some(vma),
+            ["sbi_end"] => end = Some(vma),
+            [input] if size != 0 => {
+                if let Some((object, section)) = input.split_once(":(") {
+                    // The payload is linked in, but is not part of the firmware.
+                    if section.starts_with(".payload") {
+                        continue;
+                    }
+                    sections.push((vma, size, owner(object, section)));
+                }
+            }
+            _ => {}
+        }
+    }
+    // Sections outside the firmware, or not loaded at all, don't count.
+    let firmware = start.unwrap_or(1)..end.unwrap_or(u64::MAX);
+    let mut modules = BTreeMap::<String, u64>::new();
+    for (vma, size, owner) in sections {
+        if firmware.contains(&vma) {
+            *modules.entry(owner).or_default() += size;
+        }
+    }
+    let mut modules: Vec<_> = modules.into_iter().collect();
+    modules.sort_by(|a, b| b.1.cmp(&a.1));
+    let total = start.zip(end).map(|(start, end)| end - start);
+    Some(SizeReport { modules, total })
+}
+
+/// Module or crate an input section belongs to.
+fn owner(object: &str, section: &str) -> String {
+    let section = section.trim_end_matches(')');
+    if let Some(path) = section
+        .find("_ZN")
+        .and_then(|index| demangle(&section[index + 3..]))
+    {
+        if path.first().is_some_and(|name| name == FIRMWARE_CRATE) {
+            let modules = path[..path.len() - 1]
+                .iter()
+                .take_while(|name| name.starts_with(|c: char| c.is_ascii_lowercase()))
+                .take(MODULE_DEPTH);
+            return modules.cloned().collect::<Vec<_>>().join("::");
+        }
+        if let Some(name) = path.first() {
+            return name.clone();
+        }
+    }
+    // Objects are named `<crate>-<hash>.<unit>.o`, possibly inside `lib<crate>-<hash>.rlib(...)`.
+    let name = object.rsplit(['/', '(']).next().unwrap_or(object);
+    name.split(['-', '.']).next().unwrap_or(name).to_string()
+}
+
+/// Path segments of a legacy mangled symbol, after the `_ZN` prefix.
+fn demangle(mut mangled: &str) -> Option<Vec<String>> {
+    let mut path = Vec::new();
+    while !mangled.starts_with('E') {
+        let digits = mangled.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = mangled[..digits].parse().ok()?;
+        let segment = mangled.get(digits..digits + len)?;
+        // The trailing `h<hash>` segment carries no path information.
+        if !(segment.len() == 17 && segment.starts_with('h')) {
+            path.push(segment.to_string());
+        }
+        mangled = &mangled[digits + len..];
+    }
+    (!path.is_empty()).then_some(path)
+}
+
+/// Parse a size like `131072`, `0x20000` or `128K`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (digits, scale) = match value.strip_suffix(['K', 'k']) {
+        Some(digits) => (digits, 1024),
+        None => match value.strip_suffix(['M', 'm']) {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (value, 1),
+        },
+    };
+    let number = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    number
+        .map(|number| number * scale)
+        .map_err(|_| format!("invalid size `{value}`"))
+}
+
+/// Build the smallest firmware, without default features and with stacks
+/// for `arg.harts` harts only, and fail if it is larger than `arg.limit`.
+#[must_use]
+pub fn check(arg: &SizeCheckArg) -> Option<ExitStatus> {
+    let target_dir = env::current_dir().ok()?.join("target");
+    fs::create_dir_all(&target_dir).ok()?;
+    let manifest = target_dir.join(format!("{CHECK_BUILD}.toml"));
+    fs::write(&manifest, format!("[harts]\nmax = {}\n", arg.harts)).ok()?;
+    let build = PrototyperArg {
+        features: Vec::new(),
+        fdt: None,
+        payload: None,
+        compress_payload: false,
+        rv32: arg.rv32,
+        board: Vec::new(),
+        size_report: true,
+        size_limit: Some(arg.limit),
+        no_default_features: true,
+    };
+    let status = prototyper::build(&build, Some((CHECK_BUILD, &manifest)))?;
+    if status.success() {
+        println!(
+            "Firmware for {} harts fits in {} bytes",
+            arg.harts, arg.limit
+        );
+    }
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        let cases = [
+            ("131072", 131_072),
+            ("0x20000", 0x20000),
+            ("128K", 128 * 1024),
+            ("128k", 128 * 1024),
+            ("2M", 2 * 1024 * 1024),
+            ("0x10K", 16 * 1024),
+        ];
+        for (value, size) in cases {
+            assert_eq!(parse_size(value), Ok(size), "{value}");
+        }
+    }
+
+    #[test]
+    fn bad_sizes() {
+        for value in ["", "K", "12G", "0x", "-1", "1.5M", "0xZ"] {
+            assert!(parse_size(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn demangle_paths() {
+        assert_eq!(
+            demangle("18rustsbi_prototyper3sbi4trap12fast_handler17h0123456789abcdefE"),
+            Some(vec![
+                "rustsbi_prototyper".to_string(),
+                "sbi".to_string(),
+                "trap".to_string(),
+                "fast_handler".to_string(),
+            ])
+        );
+        assert_eq!(
+            demangle("4core3fmt5write17h0000000000000000E"),
+            Some(vec![
+                "core".to_string(),
+                "fmt".to_string(),
+                "write".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn demangle_rejects_garbage() {
+        // Only a hash, a length past the end, no length and no terminator.
+        for mangled in ["17h0123456789abcdefE", "9core", "coreE", "4core"] {
+            assert_eq!(demangle(mangled), None, "{mangled}");
+        }
+    }
+
+    #[test]
+    fn owners() {
+        assert_eq!(
+            owner(
+                "rustsbi_prototyper-0a1b2c.rustsbi_prototyper.1234-cgu.0.rcgu.o",
+                ".text._ZN18rustsbi_prototyper3sbi4trap12fast_handler17h0123456789abcdefE)"
+            ),
+            "rustsbi_prototyper::sbi::trap"
+        );
+        assert_eq!(
+            owner(
+                "libcore-0a1b2c.rlib(core-0a1b2c.core.1234-cgu.0.rcgu.o",
+                ".text._ZN4core3fmt5write17h0123456789abcdefE)"
+            ),
+            "core"
+        );
+        assert_eq!(owner("/build/boot.o", ".text.entry)"), "boot");
+    }
+}
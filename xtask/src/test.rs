@@ -12,11 +12,19 @@ pub struct TestArg {
     /// Package Prototyper and Test-Kernel
     #[clap(long)]
     pub pack: bool,
+
+    /// Build the test kernel for riscv32imac.
+    #[clap(long)]
+    pub rv32: bool,
 }
 
 #[must_use]
 pub fn run(arg: &TestArg) -> Option<ExitStatus> {
-    let arch = "riscv64imac-unknown-none-elf";
+    let (arch, binary_arch) = if arg.rv32 {
+        ("riscv32imac-unknown-none-elf", "riscv32")
+    } else {
+        ("riscv64imac-unknown-none-elf", "riscv64")
+    };
     let current_dir = env::current_dir();
     let target_dir = current_dir
         .as_ref()
@@ -34,7 +42,7 @@ pub fn run(arg: &TestArg) -> Option<ExitStatus> {
 
     let exit_status = Command::new("rust-objcopy")
         .args(["-O", "binary"])
-        .arg("--binary-architecture=riscv64")
+        .arg(format!("--binary-architecture={binary_arch}"))
         .arg(target_dir.join("rustsbi-test-kernel"))
         .arg(target_dir.join("rustsbi-test-kernel.bin"))
         .status()